@@ -92,16 +92,21 @@ mod writer;
 #[cfg(any(test, feature = "mocks"))]
 pub mod mock;
 pub mod restore;
-#[cfg(any(test))]
+#[cfg(any(test, feature = "rocksdb"))]
 pub mod rocksdb_store;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 use bytes32ext::Bytes32Ext;
 pub use iterator::JellyfishMerkleIterator;
 #[cfg(feature = "ics23")]
 pub use tree::ics23_impl::ics23_spec;
-pub use tree::JellyfishMerkleTree;
 #[cfg(any(test, feature = "sha2"))]
 pub use tree::Sha256Jmt;
+pub use tree::{
+    compute_root, compute_version_commitment, stores_equal_at, ChangeKind, DepthStats,
+    JellyfishMerkleTree, ProvenLeaf,
+};
 
 use types::nibble::ROOT_NIBBLE_HEIGHT;
 pub use types::proof;
@@ -110,12 +115,17 @@ pub use types::Version;
 /// Contains types used to bridge a [`JellyfishMerkleTree`](crate::JellyfishMerkleTree)
 /// to the backing storage recording the tree's internal data.
 pub mod storage {
-    pub use node_type::{LeafNode, Node, NodeKey};
+    pub use node_type::{LeafNode, Node, NodeKey, OrderedNodeKey};
     pub use reader::HasPreimage;
-    pub use reader::TreeReader;
-    pub use types::nibble::nibble_path::NibblePath;
+    #[cfg(feature = "std")]
+    pub use reader::TtlCachingReader;
+    pub use reader::{
+        NodeWithValue, OverlayReader, TieredTreeStore, TreeReader, VersionCappedReader,
+    };
+    pub use types::nibble::nibble_path::{nibble_path_of, NibblePath};
     pub use writer::{
-        NodeBatch, NodeStats, StaleNodeIndex, StaleNodeIndexBatch, TreeUpdateBatch, TreeWriter,
+        MirrorTreeWriter, NodeBatch, NodeStats, StaleNodeIndex, StaleNodeIndexBatch,
+        TreeUpdateBatch, TreeUpdateWriter, TreeWriter,
     };
 
     use super::*;
@@ -146,6 +156,83 @@ impl core::fmt::Display for MissingRootError {
     }
 }
 
+/// An error returned by a [`JellyfishMerkleTree`] set to
+/// [`reject_duplicate_keys`](JellyfishMerkleTree::reject_duplicate_keys) when a value set holds
+/// the same key hash more than once.
+#[derive(Debug)]
+#[cfg_attr(feature = "std", derive(Error))]
+#[cfg_attr(
+    feature = "std",
+    error("Duplicate key hashes in the value set of version {version}: {key_hashes:?}")
+)]
+pub struct DuplicateKeysError {
+    pub version: Version,
+    /// The key hashes given more than once, each listed once, in order.
+    pub key_hashes: alloc::vec::Vec<KeyHash>,
+}
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Display for DuplicateKeysError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "Duplicate key hashes in the value set of version {}: {:?}",
+            self.version, self.key_hashes
+        )
+    }
+}
+
+/// An error returned by a [`JellyfishMerkleTree`] set to
+/// [`verify_on_read`](JellyfishMerkleTree::verify_on_read) when a node it reads does not hash to
+/// the hash its parent records for it, which means the node is corrupted in storage.
+#[derive(Debug)]
+#[cfg_attr(feature = "std", derive(Error))]
+#[cfg_attr(
+    feature = "std",
+    error(
+        "Corrupted node {node_key:?}: it hashes to {}, but its parent records {}",
+        hex::encode(found),
+        hex::encode(expected)
+    )
+)]
+pub struct CorruptedNodeError {
+    pub node_key: storage::NodeKey,
+    /// The hash the parent of the node records for it.
+    pub expected: [u8; 32],
+    /// The hash of the node read.
+    pub found: [u8; 32],
+}
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Display for CorruptedNodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "Corrupted node {:?}: it hashes to {}, but its parent records {}",
+            self.node_key,
+            hex::encode(self.found),
+            hex::encode(self.expected)
+        )
+    }
+}
+
+/// An error returned by the strict exports, such as
+/// [`iter_with_proofs_and_preimages_strict`](JellyfishMerkleTree::iter_with_proofs_and_preimages_strict),
+/// when the reader has no preimage of a key hash they export.
+#[derive(Debug)]
+#[cfg_attr(feature = "std", derive(Error))]
+#[cfg_attr(feature = "std", error("Missing preimage of key hash {key_hash:?}"))]
+pub struct MissingPreimageError {
+    pub key_hash: KeyHash,
+}
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Display for MissingPreimageError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Missing preimage of key hash {:?}", self.key_hash)
+    }
+}
+
 // TODO: reorg
 
 const SPARSE_MERKLE_PLACEHOLDER_HASH: [u8; 32] = *b"SPARSE_MERKLE_PLACEHOLDER_HASH__";
@@ -16,7 +16,10 @@ use std::collections::{hash_map::Entry, HashMap};
 
 use crate::{
     node_type::{LeafNode, Node, NodeKey},
-    storage::{HasPreimage, NodeBatch, StaleNodeIndex, TreeReader, TreeUpdateBatch, TreeWriter},
+    storage::{
+        HasPreimage, NodeBatch, StaleNodeIndex, TreeReader, TreeUpdateBatch, TreeUpdateWriter,
+        TreeWriter,
+    },
     types::Version,
     KeyHash, OwnedValue,
 };
@@ -116,6 +119,12 @@ impl TreeWriter for MockTreeStore {
     }
 }
 
+impl TreeUpdateWriter for MockTreeStore {
+    fn write_tree_update_batch(&self, batch: &TreeUpdateBatch) -> Result<()> {
+        MockTreeStore::write_tree_update_batch(self, batch.clone())
+    }
+}
+
 /// Place a value into the provided value history map. Versions must be pushed in non-decreasing order per key.
 pub fn put_value(
     value_history: &mut HashMap<KeyHash, Vec<(Version, Option<OwnedValue>)>>,
@@ -26,7 +26,7 @@ use serde::{Deserialize, Serialize};
 use crate::proof::SparseMerkleNode;
 use crate::{
     types::{
-        nibble::{nibble_path::NibblePath, Nibble},
+        nibble::{nibble_path::NibblePath, Nibble, ROOT_NIBBLE_HEIGHT},
         proof::{SparseMerkleInternalNode, SparseMerkleLeafNode},
         Version,
     },
@@ -102,6 +102,99 @@ impl NodeKey {
     }
 }
 
+/// A [`NodeKey`] ordered, and encoded into bytes sorting, in the breadth-first order of the
+/// tree: by the length of the nibble path, then by the nibble path, then by version.
+///
+/// The nodes of a level of the tree thus come before those of the levels below it, those of a
+/// level in the order of their paths, which is the order of their keys, and the versions of a
+/// node oldest first. [`NodeKey`] itself orders by version first, and its serde encodings do not
+/// sort like it either, so stores iterating over their encoded node keys should encode them with
+/// [`OrderedNodeKey::encode`].
+///
+/// The encoding is the number of nibbles of the path on a byte, the nibbles of the path two to a
+/// byte, the last half byte being zero for an odd number of nibbles, and the version on eight
+/// big-endian bytes.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct OrderedNodeKey(NodeKey);
+
+impl OrderedNodeKey {
+    /// Returns the node key.
+    pub fn node_key(&self) -> &NodeKey {
+        &self.0
+    }
+
+    /// Encodes the key into bytes sorting as the key does.
+    pub fn encode(&self) -> Vec<u8> {
+        let nibble_path = self.0.nibble_path();
+        let mut bytes = Vec::with_capacity(1 + nibble_path.bytes().len() + 8);
+        bytes.push(nibble_path.num_nibbles() as u8);
+        bytes.extend_from_slice(nibble_path.bytes());
+        bytes.extend_from_slice(&self.0.version().to_be_bytes());
+        bytes
+    }
+
+    /// Decodes a key encoded by [`encode`](OrderedNodeKey::encode).
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let (&num_nibbles, rest) = bytes
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("empty ordered node key"))?;
+        let num_nibbles = usize::from(num_nibbles);
+        anyhow::ensure!(
+            num_nibbles <= ROOT_NIBBLE_HEIGHT,
+            "ordered node key of {} nibbles",
+            num_nibbles
+        );
+        let path_len = num_nibbles.div_ceil(2);
+        anyhow::ensure!(
+            rest.len() == path_len + 8,
+            "ordered node key of {} bytes for {} nibbles",
+            bytes.len(),
+            num_nibbles
+        );
+        let (path, version) = rest.split_at(path_len);
+        let path = path.to_vec();
+        let nibble_path = if num_nibbles % 2 == 0 {
+            NibblePath::new(path)
+        } else {
+            anyhow::ensure!(
+                path[path_len - 1] & 0x0f == 0,
+                "ordered node key with a nonzero half byte past its path"
+            );
+            NibblePath::new_odd(path)
+        };
+        let version = Version::from_be_bytes(version.try_into().expect("8 bytes"));
+        Ok(OrderedNodeKey(NodeKey::new(version, nibble_path)))
+    }
+}
+
+impl Ord for OrderedNodeKey {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        let (path, other_path) = (self.0.nibble_path(), other.0.nibble_path());
+        path.num_nibbles()
+            .cmp(&other_path.num_nibbles())
+            .then_with(|| path.nibbles().cmp(other_path.nibbles()))
+            .then_with(|| self.0.version().cmp(&other.0.version()))
+    }
+}
+
+impl PartialOrd for OrderedNodeKey {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<NodeKey> for OrderedNodeKey {
+    fn from(node_key: NodeKey) -> Self {
+        OrderedNodeKey(node_key)
+    }
+}
+
+impl From<OrderedNodeKey> for NodeKey {
+    fn from(ordered: OrderedNodeKey) -> Self {
+        ordered.0
+    }
+}
+
 #[derive(
     Clone,
     Debug,
@@ -652,6 +745,33 @@ impl InternalNode {
         unreachable!("Impossible to get here without returning even at the lowest level.")
     }
 
+    /// Returns, for each sibling
+    /// [`get_only_child_with_siblings`](InternalNode::get_only_child_with_siblings) returns for the
+    /// `n`-th child and in the same order, whether it is a non-null node, without reading or
+    /// hashing any of them.
+    pub(crate) fn only_child_sibling_existence(&self, n: Nibble) -> Vec<bool> {
+        let mut existence = vec![];
+        let (existence_bitmap, leaf_bitmap) = self.generate_bitmaps();
+
+        // Nibble height from 3 to 0, as in `get_child_with_siblings_helper`.
+        for h in (0..4).rev() {
+            let width = 1 << h;
+            let (child_half_start, sibling_half_start) = get_child_and_sibling_half_start(n, h);
+            let (sibling_existence_bitmap, _) =
+                Self::range_bitmaps(sibling_half_start, width, (existence_bitmap, leaf_bitmap));
+            existence.push(sibling_existence_bitmap != 0);
+
+            let (range_existence_bitmap, range_leaf_bitmap) =
+                Self::range_bitmaps(child_half_start, width, (existence_bitmap, leaf_bitmap));
+            if range_existence_bitmap == 0
+                || has_only_child(width, range_existence_bitmap, range_leaf_bitmap)
+            {
+                return existence;
+            }
+        }
+        unreachable!("Impossible to get here without returning even at the lowest level.")
+    }
+
     /// Gets the child and its corresponding siblings that are necessary to generate the proof for
     /// the `n`-th child. This function will **either** return the child that matches the nibble n or the only
     /// child in the largest width range pointed by n. If it is an existence proof, the returned child must be the `n`-th
@@ -2,7 +2,19 @@ use alloc::vec::Vec;
 use anyhow::{format_err, Result};
 
 use crate::node_type::{LeafNode, Node, NodeKey};
+use crate::storage::{NodeBatch, TreeWriter};
 use crate::{KeyHash, OwnedValue, Version};
+#[cfg(feature = "std")]
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// A node with the value of its key if it is a leaf whose value is stored inline, as returned
+/// by [`TreeReader::get_node_with_inline_value`].
+pub type NodeWithValue<N = Node> = (N, Option<OwnedValue>);
 
 /// Defines the interface between a
 /// [`JellyfishMerkleTree`](crate::JellyfishMerkleTree)
@@ -17,6 +29,16 @@ pub trait TreeReader {
     /// Gets node given a node key. Returns `None` if the node does not exist.
     fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>>;
 
+    /// Same as [`get_node_option`](TreeReader::get_node_option), also returning the value of the
+    /// key of the node if it is a leaf whose value the storage keeps inline with it, so that
+    /// reading the value takes no second lookup.
+    ///
+    /// The value is the one the leaf commits to, that of its key at the version of the leaf. The
+    /// default implementation keeps no value inline.
+    fn get_node_with_inline_value(&self, node_key: &NodeKey) -> Result<Option<NodeWithValue>> {
+        Ok(self.get_node_option(node_key)?.map(|node| (node, None)))
+    }
+
     /// Gets a value by identifier, returning the newest value whose version is *less than or
     /// equal to* the specified version. Returns an error if the value does not exist.
     fn get_value(&self, max_version: Version, key_hash: KeyHash) -> Result<OwnedValue> {
@@ -46,3 +68,356 @@ pub trait HasPreimage {
     /// Gets the preimage of a key hash, if it is present in the tree.
     fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>>;
 }
+
+/// A [`TreeReader`] over an empty tree whose last version is the one before `next_version`, for
+/// a tree to build the tree of `next_version` from scratch.
+pub(crate) struct EmptyTreeReader {
+    pub(crate) next_version: Version,
+}
+
+impl TreeReader for EmptyTreeReader {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        let is_previous_root = self
+            .next_version
+            .checked_sub(1)
+            .is_some_and(|version| *node_key == NodeKey::new_empty_path(version));
+        Ok(is_previous_root.then(Node::new_null))
+    }
+
+    fn get_value_option(
+        &self,
+        _max_version: Version,
+        _key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        Ok(None)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        Ok(None)
+    }
+}
+
+/// A [`TreeReader`] reading a [`NodeBatch`] that is not written yet on top of a base reader, as if
+/// the batch had been applied to it.
+///
+/// The nodes and values of the batch shadow those of the base, which serves everything else, so
+/// a [`JellyfishMerkleTree`](crate::JellyfishMerkleTree) over the overlay reads, and proves, the
+/// pending state without committing it. The batch is expected to be newer than the base: a value
+/// of the batch wins over any value of the base for the same key.
+pub struct OverlayReader<'a, R> {
+    base: &'a R,
+    overlay: &'a NodeBatch,
+}
+
+impl<'a, R: TreeReader> OverlayReader<'a, R> {
+    /// Creates a reader of `overlay` on top of `base`.
+    pub fn new(base: &'a R, overlay: &'a NodeBatch) -> Self {
+        OverlayReader { base, overlay }
+    }
+}
+
+impl<R: TreeReader> TreeReader for OverlayReader<'_, R> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        match self.overlay.get_node(node_key) {
+            Some(node) => Ok(Some(node.clone())),
+            None => self.base.get_node_option(node_key),
+        }
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        let overlaid = self
+            .overlay
+            .values()
+            .range(..=(max_version, KeyHash([u8::MAX; 32])))
+            .rev()
+            .find(|((_, overlaid_key_hash), _)| *overlaid_key_hash == key_hash);
+        match overlaid {
+            Some((_, value)) => Ok(value.clone()),
+            None => self.base.get_value_option(max_version, key_hash),
+        }
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        let overlaid = self
+            .overlay
+            .nodes()
+            .iter()
+            .filter_map(|(node_key, node)| match node {
+                Node::Leaf(leaf_node) => Some((node_key.clone(), leaf_node.clone())),
+                _ => None,
+            });
+        Ok(overlaid
+            .chain(self.base.get_rightmost_leaf()?)
+            .max_by_key(|(_, leaf_node)| leaf_node.key_hash()))
+    }
+}
+
+impl<R: HasPreimage> HasPreimage for OverlayReader<'_, R> {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        // Node batches carry no preimages.
+        self.base.preimage(key_hash)
+    }
+}
+
+/// A store reading from a fast hot tier first and from a cold archive tier on a miss, and writing
+/// to the hot tier only.
+///
+/// This keeps the versions pruned from the hot tier readable as long as the cold tier archived
+/// them: the nodes and values the hot tier no longer holds are served by the cold one. A value
+/// the hot tier does not find is looked up in the cold tier, which cannot tell a key the hot
+/// tier deleted from one it never held, so reading the values of the tree through
+/// [`JellyfishMerkleTree`](crate::JellyfishMerkleTree), which only looks a value up once a leaf
+/// proves it live, is what keeps the reads consistent.
+#[derive(Debug)]
+pub struct TieredTreeStore<Hot, Cold> {
+    hot: Hot,
+    cold: Cold,
+}
+
+impl<Hot: TreeReader, Cold: TreeReader> TieredTreeStore<Hot, Cold> {
+    /// Creates a store over the hot tier `hot` and the cold tier `cold`.
+    pub fn new(hot: Hot, cold: Cold) -> Self {
+        TieredTreeStore { hot, cold }
+    }
+
+    /// Returns the hot tier.
+    pub fn hot(&self) -> &Hot {
+        &self.hot
+    }
+
+    /// Returns the cold tier.
+    pub fn cold(&self) -> &Cold {
+        &self.cold
+    }
+}
+
+impl<Hot: TreeReader, Cold: TreeReader> TreeReader for TieredTreeStore<Hot, Cold> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        match self.hot.get_node_option(node_key)? {
+            Some(node) => Ok(Some(node)),
+            None => self.cold.get_node_option(node_key),
+        }
+    }
+
+    fn get_node_with_inline_value(&self, node_key: &NodeKey) -> Result<Option<NodeWithValue>> {
+        match self.hot.get_node_with_inline_value(node_key)? {
+            Some(node) => Ok(Some(node)),
+            None => self.cold.get_node_with_inline_value(node_key),
+        }
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        match self.hot.get_value_option(max_version, key_hash)? {
+            Some(value) => Ok(Some(value)),
+            None => self.cold.get_value_option(max_version, key_hash),
+        }
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        match self.hot.get_rightmost_leaf()? {
+            Some(leaf) => Ok(Some(leaf)),
+            None => self.cold.get_rightmost_leaf(),
+        }
+    }
+}
+
+impl<Hot: HasPreimage, Cold: HasPreimage> HasPreimage for TieredTreeStore<Hot, Cold> {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        match self.hot.preimage(key_hash)? {
+            Some(preimage) => Ok(Some(preimage)),
+            None => self.cold.preimage(key_hash),
+        }
+    }
+}
+
+impl<Hot: TreeWriter, Cold> TreeWriter for TieredTreeStore<Hot, Cold> {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        self.hot.write_node_batch(node_batch)
+    }
+}
+
+/// A [`TreeReader`] reading another one as it was at a version, ignoring everything written at
+/// the versions after it.
+///
+/// The nodes of later versions read as missing and the values are read at the capped version at
+/// most, so a [`JellyfishMerkleTree`](crate::JellyfishMerkleTree) over the reader proves the
+/// versions up to the cap as the base does, and fails to find the roots of the later ones. The
+/// rightmost leaf cannot be told apart from the nodes of later versions, so it is only served if
+/// it was written at the capped version or before.
+#[derive(Debug)]
+pub struct VersionCappedReader<'a, R> {
+    base: &'a R,
+    version: Version,
+}
+
+impl<'a, R: TreeReader> VersionCappedReader<'a, R> {
+    /// Creates a reader of `base` as it was at `version`.
+    pub fn new(base: &'a R, version: Version) -> Self {
+        VersionCappedReader { base, version }
+    }
+
+    /// Returns the version the reads are capped at.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+}
+
+impl<R: TreeReader> TreeReader for VersionCappedReader<'_, R> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        if node_key.version() > self.version {
+            return Ok(None);
+        }
+        self.base.get_node_option(node_key)
+    }
+
+    fn get_node_with_inline_value(&self, node_key: &NodeKey) -> Result<Option<NodeWithValue>> {
+        if node_key.version() > self.version {
+            return Ok(None);
+        }
+        self.base.get_node_with_inline_value(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.base
+            .get_value_option(max_version.min(self.version), key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        match self.base.get_rightmost_leaf()? {
+            Some((node_key, _)) if node_key.version() > self.version => Err(format_err!(
+                "the rightmost leaf was written at version {}, after version {}",
+                node_key.version(),
+                self.version
+            )),
+            leaf => Ok(leaf),
+        }
+    }
+}
+
+impl<R: HasPreimage> HasPreimage for VersionCappedReader<'_, R> {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        // Preimages are not versioned.
+        self.base.preimage(key_hash)
+    }
+}
+
+/// A [`TreeReader`] caching the nodes and values read from another one for a bounded time.
+///
+/// Every node found and every value lookup, by key hash and version, is kept for `ttl` after it
+/// was read, then read again from the wrapped reader the next time it is asked for. Expired
+/// entries are only dropped once they are looked up or room is needed for new ones, so that a
+/// reader left idle frees its memory at its next reads. Each of the two caches holds at most
+/// `max_entries` entries, and once full does not cache further reads until some expire.
+///
+/// Nodes never change once written, but the value of a key at a version not written yet does
+/// when that version is: such a value may be served stale for up to `ttl`.
+#[cfg(feature = "std")]
+pub struct TtlCachingReader<R> {
+    inner: R,
+    ttl: Duration,
+    max_entries: usize,
+    nodes: TtlCache<NodeKey, Node>,
+    values: TtlCache<(Version, KeyHash), Option<OwnedValue>>,
+}
+
+/// Entries of a [`TtlCachingReader`] keyed by `K`, with the time they were read at.
+#[cfg(feature = "std")]
+type TtlCache<K, T> = Mutex<HashMap<K, (Instant, T)>>;
+
+#[cfg(feature = "std")]
+impl<R: TreeReader> TtlCachingReader<R> {
+    /// Creates a reader caching up to `max_entries` nodes and as many values read from `inner`,
+    /// for `ttl` each.
+    pub fn new(inner: R, ttl: Duration, max_entries: usize) -> Self {
+        TtlCachingReader {
+            inner,
+            ttl,
+            max_entries,
+            nodes: Mutex::default(),
+            values: Mutex::default(),
+        }
+    }
+
+    /// Returns the reader whose reads are cached.
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns the cached entry of `key` in `cache` if it has not expired yet, dropping it if it
+    /// has.
+    fn cached<K: Eq + Hash, T: Clone>(&self, cache: &TtlCache<K, T>, key: &K) -> Option<T> {
+        let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+        match cache.get(key) {
+            Some((read_at, entry)) if read_at.elapsed() < self.ttl => Some(entry.clone()),
+            Some(_) => {
+                cache.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Caches `entry` as just read for `key` in `cache`, first dropping the expired entries if
+    /// the cache is full.
+    fn insert<K: Eq + Hash, T>(&self, cache: &TtlCache<K, T>, key: K, entry: T) {
+        let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+        if cache.len() >= self.max_entries {
+            cache.retain(|_, (read_at, _)| read_at.elapsed() < self.ttl);
+        }
+        if cache.len() < self.max_entries {
+            cache.insert(key, (Instant::now(), entry));
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: TreeReader> TreeReader for TtlCachingReader<R> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        if let Some(node) = self.cached(&self.nodes, node_key) {
+            return Ok(Some(node));
+        }
+        let node = self.inner.get_node_option(node_key)?;
+        // A node missing now may be written later, so only the nodes found are cached.
+        if let Some(node) = &node {
+            self.insert(&self.nodes, node_key.clone(), node.clone());
+        }
+        Ok(node)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        let key = (max_version, key_hash);
+        if let Some(value) = self.cached(&self.values, &key) {
+            return Ok(value);
+        }
+        let value = self.inner.get_value_option(max_version, key_hash)?;
+        self.insert(&self.values, key, value.clone());
+        Ok(value)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.inner.get_rightmost_leaf()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: HasPreimage> HasPreimage for TtlCachingReader<R> {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        self.inner.preimage(key_hash)
+    }
+}
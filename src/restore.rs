@@ -11,7 +11,7 @@ use alloc::boxed::Box;
 use alloc::vec;
 use alloc::{sync::Arc, vec::Vec};
 
-use anyhow::{bail, ensure, Result};
+use anyhow::{bail, ensure, format_err, Result};
 use mirai_annotations::*;
 
 use crate::{
@@ -604,6 +604,89 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
         )
     }
 
+    /// Checks the subtrees restored so far against `expected_subtree_roots`, pairs of a nibble
+    /// prefix (one nibble per byte) and the expected hash of the subtree at that prefix, with
+    /// [`SPARSE_MERKLE_PLACEHOLDER_HASH`] standing for an empty subtree.
+    ///
+    /// Only subtrees that are completely restored are checked, i.e. those whose keys all sort
+    /// before the most recently added key. Prefixes the restoration has not moved past yet are
+    /// skipped, so the same expectations can be checked after every chunk. Returns `false` on the
+    /// first mismatch, which lets a syncing node abort a bad peer mid-transfer instead of only
+    /// noticing once the root hash is known.
+    ///
+    /// Restored subtrees are checked from the in-memory state of the restoration, which only
+    /// keeps their roots up to the first nibble at which they branch off the path to the most
+    /// recently added key. Checking a deeper prefix of an internal subtree whose nodes have
+    /// already been written to storage fails with an error.
+    pub fn verify_partial(&self, expected_subtree_roots: &[(Vec<u8>, RootHash)]) -> Result<bool> {
+        let previous_key = match self.previous_leaf {
+            Some(ref previous_leaf) => previous_leaf.key_hash(),
+            // Nothing has been restored yet.
+            None => return Ok(true),
+        };
+
+        for (prefix, expected_root_hash) in expected_subtree_roots {
+            ensure!(
+                prefix.len() <= ROOT_NIBBLE_HEIGHT && prefix.iter().all(|nibble| *nibble < 16),
+                "Invalid nibble prefix {:?}.",
+                prefix,
+            );
+            // Find where the prefix branches off the path to the previous key. The subtree is
+            // complete only if it branches off to the left.
+            let branch_depth = match prefix
+                .iter()
+                .enumerate()
+                .find(|(depth, nibble)| **nibble != u8::from(previous_key.0.get_nibble(*depth)))
+            {
+                Some((depth, nibble)) if *nibble < u8::from(previous_key.0.get_nibble(depth)) => {
+                    depth
+                }
+                _ => continue,
+            };
+            if self.restored_subtree_hash(prefix, branch_depth)? != expected_root_hash.0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns the hash of the completely restored subtree at `prefix`, which branches off the
+    /// path to the previous key at `branch_depth`.
+    fn restored_subtree_hash(&self, prefix: &[u8], branch_depth: usize) -> Result<[u8; 32]> {
+        // Below the lowest partial node, the path to the previous key only holds its leaf.
+        let partial_node = match self.partial_nodes.get(branch_depth) {
+            Some(partial_node) => partial_node,
+            None => return Ok(SPARSE_MERKLE_PLACEHOLDER_HASH),
+        };
+        match partial_node.children[prefix[branch_depth] as usize] {
+            None => Ok(SPARSE_MERKLE_PLACEHOLDER_HASH),
+            // A leaf is the only key below its position, so the subtree at a deeper prefix is
+            // either that leaf or empty.
+            Some(ChildInfo::Leaf { ref node }) => {
+                let key = node.key_hash();
+                if prefix
+                    .iter()
+                    .enumerate()
+                    .all(|(depth, nibble)| u8::from(key.0.get_nibble(depth)) == *nibble)
+                {
+                    Ok(node.hash::<H>())
+                } else {
+                    Ok(SPARSE_MERKLE_PLACEHOLDER_HASH)
+                }
+            }
+            Some(ChildInfo::Internal { hash, .. }) => {
+                ensure!(
+                    prefix.len() == branch_depth + 1,
+                    "The subtree at prefix {:?} has already been written to storage.",
+                    prefix,
+                );
+                hash.ok_or_else(|| {
+                    format_err!("The subtree at prefix {:?} is not restored yet.", prefix)
+                })
+            }
+        }
+    }
+
     /// Computes the sibling on the left for the `n`-th child.
     fn compute_left_sibling(partial_node: &InternalInfo, n: Nibble, height: u8) -> [u8; 32] {
         assert!(height < 4);
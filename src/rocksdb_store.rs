@@ -5,146 +5,3789 @@
 
 use crate::{
     node_type::{LeafNode, Node, NodeKey},
-    storage::{HasPreimage, TreeReader, TreeUpdateBatch, TreeWriter},
-    types::Version,
-    KeyHash, OwnedValue,
+    proof::SparseMerkleProof,
+    storage::{
+        HasPreimage, NodeBatch, NodeStats, NodeWithValue, StaleNodeIndex, TreeReader,
+        TreeUpdateBatch, TreeUpdateWriter, TreeWriter, VersionCappedReader,
+    },
+    types::{
+        nibble::{nibble_path::NibblePath, ROOT_NIBBLE_HEIGHT},
+        Version,
+    },
+    JellyfishMerkleTree, KeyHash, MissingRootError, OwnedValue, RootHash, SimpleHasher, ValueHash,
 };
-use anyhow::Result;
-use rocksdb::{DB, Options, WriteBatch};
-use std::sync::Arc;
+use anyhow::{bail, ensure, Context, Result};
+use auto_prune::AutoPruner;
+use corruption::CorruptionHook;
+use group_commit::WalSyncer;
+#[cfg(feature = "latency_histogram")]
+use latency::LatencyHistogram;
+use lazy_prune::ReclaimableNodes;
+use node_cache::NodeCache;
+use proof_cache::ProofCache;
+use record::{frame, unframe};
+use rocksdb::{
+    BlockBasedOptions, Cache, ColumnFamily, ColumnFamilyDescriptor, DBIteratorWithThreadMode,
+    DBRawIteratorWithThreadMode, ErrorKind, IteratorMode, Options, ReadOptions, SliceTransform,
+    WriteBatch, DB,
+};
+use sha2::Sha256;
+use stats::Statistics;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Write as _};
+use std::io;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::Duration;
+
+mod auto_prune;
+mod buffered;
+mod bulk_verifier;
+mod codec;
+mod corruption;
+mod cursor;
+mod group_commit;
+#[cfg(feature = "latency_histogram")]
+mod latency;
+mod lazy_prune;
+mod merge;
+mod migrate;
+mod node_cache;
+#[cfg(any(test, feature = "parallel"))]
+mod parallel;
+mod proof_batcher;
+mod proof_cache;
+mod record;
+mod rehash;
+mod retrying_writer;
+mod sharded;
+mod snapshot;
+mod staleness;
+mod stats;
+mod strict_scan;
+mod swap;
+mod trace;
+mod transaction;
+mod ttl;
+mod wal;
+mod writer_handle;
+
+pub use buffered::BufferedTreeStore;
+pub use bulk_verifier::{BulkVerifier, MAX_CACHED_LEVELS};
+pub use codec::{BincodeCodec, Codec, OrderedKeyBincodeCodec, SortableKeyBincodeCodec};
+pub use corruption::{CorruptionCause, CorruptionEvent};
+pub use cursor::{ExportedLeaf, ValueCursor, ValuePage};
+pub use merge::ValueMergeOperator;
+pub use node_cache::NodeCachePolicy;
+pub use proof_batcher::ProofBatcher;
+pub use record::{Corruption, RecordKind, FORMAT_VERSION};
+pub use retrying_writer::{is_transient, RetryingWriter};
+pub use sharded::ShardedTreeStore;
+pub use snapshot::ImportProgress;
+pub use staleness::BoundedStalenessReader;
+#[cfg(feature = "latency_histogram")]
+pub use stats::WriteLatency;
+pub use stats::{BatchStats, ColumnFamilyUsage, DiskUsage, QuickStats, RocksStats, StoreMetrics};
+pub use strict_scan::UndecodableRecord;
+pub use transaction::Transaction;
+pub use wal::WalFileInfo;
+pub use writer_handle::WriterHandle;
+
+/// Column family holding the nodes, keyed by the encoded [`NodeKey`].
+///
+/// Every key and value the store encodes in its column families starts with a header giving
+/// its format version and [`RecordKind`], see [`record`].
+const NODES_CF: &str = "nodes";
+
+/// Column family holding the preimages of key hashes, keyed by the key hash.
+const PREIMAGES_CF: &str = "preimages";
+
+/// Column family holding store-level bookkeeping, such as the latest written version.
+const METADATA_CF: &str = "metadata";
+
+/// Key in [`METADATA_CF`] under which the highest version written so far is stored, as a
+/// big-endian `u64` record.
+const LATEST_VERSION_KEY: &[u8] = b"latest_version";
+
+/// Key in [`METADATA_CF`] under which the fingerprint of the hasher of the store is stored, see
+/// [`hasher_fingerprint`].
+const HASHER_FINGERPRINT_KEY: &[u8] = b"hasher_fingerprint";
+
+/// Key in [`METADATA_CF`] under which the version set by
+/// [`RocksDbTreeStore::set_min_readable_version`] is stored, as a big-endian `u64` record.
+const MIN_READABLE_VERSION_KEY: &[u8] = b"min_readable_version";
+
+/// Key in [`METADATA_CF`] under which the lowest version sealed by
+/// [`RocksDbTreeStore::seal_version`] is stored, as a big-endian `u64` record.
+const SEALED_VERSION_KEY: &[u8] = b"sealed_version";
+
+/// Key in [`METADATA_CF`] under which, with [`RocksDbStoreConfig::refcount_nodes`],
+/// [`write_node_batch`](TreeWriter::write_node_batch) records the digest of the batch it wrote
+/// last, as a [`RecordKind::BatchDigest`] record. Any other write of nodes removes it.
+const LAST_NODE_BATCH_KEY: &[u8] = b"last_node_batch";
+
+/// Key in [`METADATA_CF`] under which an interrupted
+/// [`import_snapshot`](RocksDbTreeStore::import_snapshot) records how far it got, as a
+/// [`RecordKind::ImportCheckpoint`] record.
+const IMPORT_CHECKPOINT_KEY: &[u8] = b"import_checkpoint";
+
+/// Key in [`METADATA_CF`] under which
+/// [`migrate_layout`](RocksDbTreeStore::migrate_layout) records the version of the layout it
+/// migrated the store to, as a [`RecordKind::LayoutVersion`] record.
+const LAYOUT_VERSION_KEY: &[u8] = b"layout_version";
+
+/// Column family holding the values of every version. Keys are the key hash followed by the
+/// big-endian version, so that the entries of a key are adjacent and sorted by version. Values are
+/// the encoded `Option<OwnedValue>`, with `None` marking a deletion.
+const VALUES_CF: &str = "values";
+
+/// Column family indexing stale nodes by the version since which they are stale. Keys are the
+/// big-endian `stale_since_version` followed by the encoded [`NodeKey`], so that iterating
+/// the column family visits the oldest stale nodes first. Values are empty.
+const STALE_NODES_CF: &str = "stale_nodes";
+
+/// Column family mapping version labels to the big-endian version record they name.
+const LABELS_CF: &str = "labels";
+
+/// Column family counting, in refcount mode, how many times each node was written. Keys are the
+/// encoded [`NodeKey`], values the big-endian `u64` count.
+const REFCOUNTS_CF: &str = "refcounts";
+
+/// Column family indexing the versions that have a root node. Keys are the big-endian version
+/// records, so that iterating the column family visits the versions in ascending order. Values are
+/// empty.
+const ROOTS_CF: &str = "roots";
+
+/// Column family holding, with [`RocksDbStoreConfig::dedup_nodes`], the node records the pointers
+/// of [`NODES_CF`] point to, keyed by the hash of the record. Values are the encoded [`Node`].
+const NODE_CONTENTS_CF: &str = "node_contents";
+
+/// Column family holding the metadata of [`RocksDbTreeStore::set_version_metadata`]. Keys are
+/// the big-endian version records, values [`RecordKind::VersionMetadata`] records.
+const VERSION_METADATA_CF: &str = "version_metadata";
+
+/// Column family indexing the versions that have a root node by the hash of the root. Keys are
+/// [`RecordKind::RootVersion`] records, so that the versions of a root hash are adjacent and in
+/// ascending order. Values are empty.
+const ROOT_VERSIONS_CF: &str = "root_versions";
+
+/// The column families holding the data of a store. The default column family is left unused, so
+/// that a store can live in a database shared with other data (see
+/// [`RocksDbTreeStore::from_db`]).
+const COLUMN_FAMILIES: [&str; 11] = [
+    NODES_CF,
+    PREIMAGES_CF,
+    VALUES_CF,
+    METADATA_CF,
+    STALE_NODES_CF,
+    LABELS_CF,
+    REFCOUNTS_CF,
+    ROOTS_CF,
+    NODE_CONTENTS_CF,
+    VERSION_METADATA_CF,
+    ROOT_VERSIONS_CF,
+];
+
+/// A column family of a store, as passed to [`RocksDbTreeStore::compact_cf`] and
+/// [`RocksDbTreeStore::scan_strict`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CfKind {
+    /// The nodes, or with [`RocksDbStoreConfig::dedup_nodes`] the pointers to their contents.
+    Nodes,
+    /// The preimages of key hashes.
+    Preimages,
+    /// The values of every version.
+    Values,
+    /// Store-level bookkeeping, such as the latest written version.
+    Metadata,
+    /// The stale node index.
+    StaleNodes,
+    /// The version labels.
+    Labels,
+    /// The node refcounts of [`RocksDbStoreConfig::refcount_nodes`].
+    Refcounts,
+    /// The index of the versions that have a root node.
+    Roots,
+    /// The node contents of [`RocksDbStoreConfig::dedup_nodes`].
+    NodeContents,
+    /// The metadata of [`RocksDbTreeStore::set_version_metadata`].
+    VersionMetadata,
+    /// The index of the versions that have a root node by root hash.
+    RootVersions,
+}
+
+impl CfKind {
+    /// Returns the name of the column family, without the prefix of the store.
+    pub fn name(self) -> &'static str {
+        match self {
+            CfKind::Nodes => NODES_CF,
+            CfKind::Preimages => PREIMAGES_CF,
+            CfKind::Values => VALUES_CF,
+            CfKind::Metadata => METADATA_CF,
+            CfKind::StaleNodes => STALE_NODES_CF,
+            CfKind::Labels => LABELS_CF,
+            CfKind::Refcounts => REFCOUNTS_CF,
+            CfKind::Roots => ROOTS_CF,
+            CfKind::NodeContents => NODE_CONTENTS_CF,
+            CfKind::VersionMetadata => VERSION_METADATA_CF,
+            CfKind::RootVersions => ROOT_VERSIONS_CF,
+        }
+    }
+}
+
+/// A problem found by [`RocksDbTreeStore::verify_consistency`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The version has no root node.
+    MissingRoot { version: Version },
+    /// The internal node at `parent` references a child at `nibble` that is not in the store.
+    MissingChild {
+        parent: NodeKey,
+        nibble: u8,
+        child: NodeKey,
+    },
+    /// The leaf at `node_key` has no value entry readable at the checked version.
+    MissingValue {
+        node_key: NodeKey,
+        key_hash: KeyHash,
+    },
+    /// The version has a root but is newer than the latest version recorded in the metadata.
+    LatestVersionBehind {
+        version: Version,
+        latest_version: Option<Version>,
+    },
+}
+
+/// The error of [`RocksDbTreeStore::get_with_proof_against`] when the root of the store at a
+/// version is not the root the caller expected, such as when the store is on another fork.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "root of version {version} is {}, expected {}",
+    hex::encode(actual.0),
+    hex::encode(expected.0)
+)]
+pub struct RootMismatch {
+    /// The version the proof was requested at.
+    pub version: Version,
+    /// The root the caller expected.
+    pub expected: RootHash,
+    /// The root of the store at `version`.
+    pub actual: RootHash,
+}
+
+/// The error of [`RocksDbTreeStore::commit_optimistic`] when the store moved past the version
+/// a batch was computed against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "batch computed against version {base_version:?}, but the latest version is {latest_version:?}"
+)]
+pub struct Conflict {
+    /// The version the batch was computed against.
+    pub base_version: Option<Version>,
+    /// The latest version of the store when the batch was committed.
+    pub latest_version: Option<Version>,
+}
+
+/// The nodes and the value entries written after a version, as returned by
+/// [`RocksDbTreeStore::changes_since`].
+pub type Changes = (
+    Vec<(NodeKey, Node)>,
+    Vec<((Version, KeyHash), Option<OwnedValue>)>,
+);
 
-/// A RocksDB-backed tree store.
-pub struct RocksDbTreeStore {
+/// A value, or `None` for an absent key, with the proof of it, as returned by
+/// [`RocksDbTreeStore::get_with_proof_against`].
+pub type ProofResult<H> = (Option<OwnedValue>, SparseMerkleProof<H>);
+
+/// A RocksDB-backed tree store, with the bincode [`Codec`] and the hasher `H`, [`Sha256`] by
+/// default.
+///
+/// See [`GenericTreeStore`] for stores with other primitives. The default stays [`BincodeCodec`]
+/// so that the stores already written keep opening as they are, the codec not being recorded in
+/// the database; [`OrderedKeyBincodeCodec`], which keeps the nodes in tree order, is opted into
+/// through [`GenericTreeStore`], and its documentation tells how to migrate a store to it.
+pub type RocksDbTreeStore<H = Sha256> = GenericTreeStore<BincodeCodec, H>;
+
+/// A RocksDB-backed tree store, parameterized by the [`Codec`] `C` encoding the nodes and values
+/// it persists and the hasher `H` of the tree it holds.
+///
+/// The hasher is used wherever the store computes hashes itself, such as in
+/// [`verify_at`](GenericTreeStore::verify_at), and its fingerprint is persisted so that a store
+/// cannot be reopened with another hasher. Stores with other primitives than those of
+/// [`RocksDbTreeStore`] are opened with [`with_hasher`](GenericTreeStore::with_hasher) or
+/// [`from_db_with_hasher`](GenericTreeStore::from_db_with_hasher).
+///
+/// A store is `Send + Sync`, and cloning it returns a handle on the same database, sharing its
+/// caches and commit callbacks, which is cheap enough to hand a clone to every worker thread.
+///
+/// Reads take `&self` and never wait for writes: RocksDB serves concurrent readers next to a writer
+/// without locking them out, and the store holds no lock of its own on the read path, its metrics
+/// being atomic counters, except for the proof cache of
+/// [`RocksDbStoreConfig::proof_cache_capacity`], whose lookups share a read lock that only the
+/// insertions of new proofs and the writes clearing it take exclusively, for as long as a `HashMap`
+/// operation, and the node cache of [`RocksDbStoreConfig::node_cache_capacity`], whose lookups each
+/// take its lock as briefly. Every batch is written atomically and a version is never rewritten
+/// once committed, so a read at a committed version never sees part of a write.
+pub struct GenericTreeStore<C: Codec, H: SimpleHasher> {
     db: Arc<DB>,
+    /// Prepended to the name of every column family the store uses.
+    cf_prefix: String,
+    config: RocksDbStoreConfig,
+    /// Shared with the clones of the store, whose writes invalidate it.
+    proof_cache: Option<Arc<ProofCache>>,
+    /// The proofs of absence of [`RocksDbStoreConfig::absence_cache_capacity`], shared with the
+    /// clones of the store, whose writes invalidate them.
+    absence_cache: Option<Arc<ProofCache>>,
+    /// The nodes of [`RocksDbStoreConfig::node_cache_capacity`], shared with the clones of the
+    /// store.
+    node_cache: Option<Arc<NodeCache>>,
+    /// The callbacks registered by [`on_commit`](GenericTreeStore::on_commit), in registration
+    /// order, shared with the clones of the store.
+    commit_hooks: Arc<RwLock<Vec<CommitHook>>>,
+    /// The metadata set by [`set_version_metadata`](GenericTreeStore::set_version_metadata) for
+    /// versions not committed yet, shared with the clones of the store.
+    pending_version_metadata: Arc<Mutex<BTreeMap<Version, Vec<u8>>>>,
+    /// The callbacks registered by [`on_corruption`](GenericTreeStore::on_corruption), in
+    /// registration order, shared with the clones of the store.
+    corruption_hooks: Arc<RwLock<Vec<CorruptionHook>>>,
+    /// The nodes the compaction filter drops, with [`RocksDbStoreConfig::lazy_pruning`].
+    reclaimable_nodes: Option<Arc<ReclaimableNodes>>,
+    /// The thread of [`RocksDbStoreConfig::wal_sync_interval`], shared with the clones of the
+    /// store.
+    wal_syncer: Option<Arc<WalSyncer>>,
+    /// The thread of [`RocksDbStoreConfig::auto_prune_stale_bytes`], started by
+    /// [`set_least_readable_version`](GenericTreeStore::set_least_readable_version) and shared
+    /// with the clones of the store.
+    auto_pruner: Arc<Mutex<Option<AutoPruner>>>,
+    /// Held from reading the latest version to writing the batches checked against it, shared with
+    /// the clones of the store, see [`commit_optimistic`](GenericTreeStore::commit_optimistic).
+    write_lock: Arc<Mutex<()>>,
+    /// The root of the latest version, see [`cached_root`](GenericTreeStore::cached_root),
+    /// shared with the clones of the store.
+    cached_root: Arc<RwLock<Option<(NodeKey, Node)>>>,
+    /// The durations of the writes of node batches, shared with the clones of the store.
+    #[cfg(feature = "latency_histogram")]
+    write_latency: Arc<LatencyHistogram>,
+    /// The statistics of [`RocksDbStoreConfig::enable_statistics`], shared with the clones of
+    /// the store.
+    statistics: Option<Arc<Statistics>>,
+    _phantom: PhantomData<(C, H)>,
+}
+
+// Deriving `Clone` would require the codec and the hasher to be `Clone`.
+impl<C: Codec, H: SimpleHasher> Clone for GenericTreeStore<C, H> {
+    fn clone(&self) -> Self {
+        GenericTreeStore {
+            db: Arc::clone(&self.db),
+            cf_prefix: self.cf_prefix.clone(),
+            config: self.config.clone(),
+            proof_cache: self.proof_cache.clone(),
+            absence_cache: self.absence_cache.clone(),
+            node_cache: self.node_cache.clone(),
+            commit_hooks: Arc::clone(&self.commit_hooks),
+            pending_version_metadata: Arc::clone(&self.pending_version_metadata),
+            corruption_hooks: Arc::clone(&self.corruption_hooks),
+            reclaimable_nodes: self.reclaimable_nodes.clone(),
+            wal_syncer: self.wal_syncer.clone(),
+            auto_pruner: Arc::clone(&self.auto_pruner),
+            write_lock: Arc::clone(&self.write_lock),
+            cached_root: Arc::clone(&self.cached_root),
+            #[cfg(feature = "latency_histogram")]
+            write_latency: Arc::clone(&self.write_latency),
+            statistics: self.statistics.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<C: Codec, H: SimpleHasher> Drop for GenericTreeStore<C, H> {
+    fn drop(&mut self) {
+        // The other handles on the database flush when the last of them goes.
+        if !self.config.flush_on_drop || Arc::strong_count(&self.db) > 1 {
+            return;
+        }
+        if let Err(err) = self.flush_all() {
+            tracing::error!(
+                target: "jmt::rocksdb_store",
+                error = %format!("{:#}", err),
+                "failed to flush the store on drop"
+            );
+        }
+    }
+}
+
+/// A callback invoked with the version and root hash of every commit, see
+/// [`GenericTreeStore::on_commit`].
+type CommitHook = Box<dyn Fn(Version, RootHash) + Send + Sync>;
+
+/// The length of a [`RocksDbStoreConfig::namespace`].
+pub const NAMESPACE_LEN: usize = 8;
+
+/// Tuning options for opening a [`RocksDbTreeStore`].
+///
+/// The [`Default`] configuration matches the behaviour of [`RocksDbTreeStore::new`].
+#[derive(Clone, Default)]
+pub struct RocksDbStoreConfig {
+    /// Read SST files through `mmap` instead of `pread`, mapping to
+    /// [`Options::set_allow_mmap_reads`]. Defaults to `false`.
+    ///
+    /// This can cut syscall overhead on read-heavy workloads whose data fits in RAM, since
+    /// node reads are then served straight from the page cache. Mapped pages are not accounted
+    /// for in the block cache, which makes memory usage harder to bound, and on 32-bit targets
+    /// the address space limits how much of the store can be mapped at all. RocksDB rejects
+    /// mmap reads combined with direct I/O for reads (`use_direct_reads`), so the database will
+    /// fail to open if both are enabled.
+    pub allow_mmap_reads: bool,
+    /// When set, every [`RocksDbTreeStore::write_tree_update_batch`] prunes the store so that
+    /// only the latest `retain_versions` versions stay readable, as if by calling
+    /// [`RocksDbTreeStore::prune`]. `Some(0)` is treated like `Some(1)`, since the latest version
+    /// is always kept. Labeled and sealed versions are kept regardless (see
+    /// [`RocksDbTreeStore::seal_version`]). Defaults to `None`, which never prunes automatically.
+    pub retain_versions: Option<u64>,
+    /// When set, the values column family gets a bloom filter with this many bits per key,
+    /// built over the full `(key_hash, version)` key. Defaults to `None`, which builds no filter.
+    ///
+    /// The filter only helps point reads at an exact version, such as the fast path of
+    /// [`RocksDbTreeStore::get_latest_value`]: a key that was not written at that version is
+    /// usually rejected without touching the data blocks. Reads at an arbitrary version seek
+    /// through the values instead, and gain nothing from it. Around 10 bits per key gives a false
+    /// positive rate of about 1%.
+    pub value_full_bloom_bits: Option<f64>,
+    /// When set, the values column family gets a fixed-length prefix extractor taking the
+    /// namespace and the key hash of each key, and the reads of the values of a key hash only
+    /// look at the keys sharing its prefix. Defaults to `false`.
+    ///
+    /// The bloom filter of the column family, with
+    /// [`value_full_bloom_bits`](RocksDbStoreConfig::value_full_bloom_bits) or
+    /// [`bloom_filter_bits`](RocksDbStoreConfig::bloom_filter_bits), then also holds the prefixes,
+    /// so that the seek of a read at an arbitrary version skips the files holding no value of
+    /// its key hash, which the filter over full keys cannot tell, and the memtable gets a prefix
+    /// bloom filter of its own. This relies on the keys of a key hash sorting next to each other,
+    /// which value keys do: the key hash comes first and the version after it, big-endian. Scans
+    /// of the whole column family are unaffected. The extractor is only installed on stores
+    /// opened from a path, not by [`RocksDbTreeStore::from_db`].
+    pub value_prefix_extractor: bool,
+    /// When set, the nodes and values column families get a bloom filter with this many bits per
+    /// key, built over the full keys. Defaults to `None`, which builds no filter.
+    ///
+    /// Nodes are always read by their exact key, so a read of an absent node, such as the root of
+    /// a version that was never written or was pruned, is usually rejected without touching the
+    /// data blocks. On the values column family the filter behaves as described for
+    /// [`value_full_bloom_bits`](RocksDbStoreConfig::value_full_bloom_bits), which takes
+    /// precedence there when both are set.
+    pub bloom_filter_bits: Option<f64>,
+    /// When set, the store counts the writes of every node, and [`RocksDbTreeStore::prune`] only
+    /// deletes a node once it has been marked stale as many times as it was written. Defaults to
+    /// `false`.
+    ///
+    /// This guards against a stale index that marks a node stale while a later write still
+    /// relies on it, for instance when a version is written again after being reverted: the
+    /// rewritten node survives the prune of its first copy. The cost is one extra entry per node,
+    /// made of its bincode-encoded key (16 bytes plus the packed nibble path) and an 8-byte
+    /// count, and one extra read per node written. Nodes written while the mode was off count as
+    /// written once, so the mode can be turned on for an existing store; turning it off leaves
+    /// the counts behind, to be trusted again only if no node was written in between.
+    pub refcount_nodes: bool,
+    /// When set, writes holding a value longer than this many bytes are rejected as a whole,
+    /// before anything is written. Defaults to `None`, which accepts values of any size.
+    ///
+    /// Values are read back into memory in one piece, so a bound keeps a single faulty write
+    /// from making later reads run out of memory.
+    pub max_value_size: Option<usize>,
+    /// When set, [`RocksDbTreeStore::write_tree_update_batch`] accepts batches of versions that
+    /// are not newer than the latest version written, for backfilling history on purpose.
+    /// Defaults to `false`, which rejects such batches.
+    ///
+    /// A batch of an old version is usually an integration bug, such as a replayed or reordered
+    /// write, and rewriting history under later versions leaves the stale index describing nodes
+    /// that later versions may still reach. Backfilled batches never move the latest version back.
+    pub allow_backfill: bool,
+    /// When set, the column families of the store cache their data blocks in this cache instead
+    /// of a cache of their own. Defaults to `None`, which gives each column family RocksDB's
+    /// default block cache.
+    ///
+    /// A [`Cache`] is a handle that can be cloned into the configurations of several stores, so
+    /// that the stores of a process, such as its shards, share a single bounded amount of memory
+    /// for their blocks.
+    pub block_cache: Option<Cache>,
+    /// When set, [`RocksDbTreeStore::get_with_proof`] caches up to this many proofs, keyed by
+    /// key hash and version. Defaults to `None`, which caches nothing.
+    ///
+    /// This serves repeated queries for the same keys without walking the tree again. The cache
+    /// is emptied whenever the store is written to or pruned, so that it only holds proofs
+    /// computed since the latest write; once full, further proofs are not cached until then.
+    /// Its hits and misses are reported by [`RocksDbTreeStore::metrics`].
+    pub proof_cache_capacity: Option<usize>,
+    /// When set, [`RocksDbTreeStore::get_with_proof`] caches up to this many proofs of absence,
+    /// of the keys that have no value at the version queried, keyed by key hash and version.
+    /// Defaults to `None`, which caches nothing.
+    ///
+    /// A proof is dropped once its key becomes present, when a write writes the key, or once a
+    /// version newer than its own is committed, so that the cache serves the absent keys probed
+    /// over and over between two commits. Only the proofs at versions above all of those a
+    /// backfill writes survive it. Pruning and the other rewrites of history empty it, and once
+    /// full, further proofs are not cached until then. Its hits and misses are reported by
+    /// [`RocksDbTreeStore::metrics`].
+    pub absence_cache_capacity: Option<usize>,
+    /// When set, the store caches up to this many of the nodes it reads, keyed by node key.
+    /// Defaults to `None`, which caches nothing.
+    ///
+    /// Nodes are immutable once written, so writes of new versions leave the cache as it is, only
+    /// dropping the nodes they overwrite; pruning and the other rewrites of history empty it. Once
+    /// full, each node cached drops the one picked by
+    /// [`node_cache_policy`](RocksDbStoreConfig::node_cache_policy). Its hits and misses are
+    /// reported by [`RocksDbTreeStore::metrics`].
+    pub node_cache_capacity: Option<usize>,
+    /// How the node cache of [`node_cache_capacity`](RocksDbStoreConfig::node_cache_capacity)
+    /// picks the node to drop once full. Defaults to [`NodeCachePolicy::Lru`].
+    ///
+    /// [`NodeCachePolicy::Lfu`] keeps the nodes read most often, such as those near the root,
+    /// through scans reading many nodes once, which would flush them out of an LRU cache.
+    pub node_cache_policy: NodeCachePolicy,
+    /// The number of write-ahead log files RocksDB keeps around to reuse instead of creating new
+    /// ones. Defaults to `0`, RocksDB's default, which recycles none.
+    ///
+    /// Reusing log files saves the file creation and allocation of every new log, which adds up
+    /// under write-heavy workloads.
+    pub recycle_log_file_num: usize,
+    /// The total size in bytes the write-ahead logs may reach before RocksDB flushes the column
+    /// families holding the oldest of them. Defaults to `0`, RocksDB's default, which derives the
+    /// limit from the sizes of the write buffers.
+    pub max_total_wal_size: u64,
+    /// The number of files RocksDB keeps open at most, mapping to
+    /// [`Options::set_max_open_files`]. Defaults to `None`, RocksDB's default, which keeps every
+    /// file it opened open.
+    ///
+    /// RocksDB caches the open SST files in its table cache, whose capacity derives from this
+    /// limit, so that several stores opened by one process can exhaust its file descriptors
+    /// without one. A file evicted from the cache is opened again on the next read hitting it.
+    /// RocksDB raises limits below 20 to 20.
+    pub max_open_files: Option<u32>,
+    /// The table cache is split into `2^table_cache_num_shard_bits` shards, each with its own
+    /// lock, mapping to [`Options::set_table_cache_num_shard_bits`]. Defaults to `None`,
+    /// RocksDB's default of 6 bits.
+    ///
+    /// Fewer shards leave more of a small table cache to each of them, more shards cut the
+    /// contention of many reader threads.
+    pub table_cache_num_shard_bits: Option<u32>,
+    /// When set, RocksDB writes at most this many bytes per second to its files, mapping to
+    /// [`Options::set_ratelimiter`] with RocksDB's default refill period of 100ms and fairness of
+    /// 10. Defaults to `None`, which does not limit writes.
+    ///
+    /// This is meant to keep a bulk import from starving the reads served next to it. The limit
+    /// covers the flushes of memtables and the compactions, which share it, flushes going first,
+    /// but not the writes to the write-ahead log, so that writes keep landing in memtables at
+    /// full speed until RocksDB stalls them for the flushes and compactions to catch up. A limit
+    /// below the write rate of a workload thus ends up slowing its writes down too, as does a
+    /// compaction backlog building up under it.
+    pub rate_limit_bytes_per_sec: Option<u64>,
+    /// When set, the nodes column family gets a compaction filter dropping the nodes that
+    /// [`RocksDbTreeStore::set_min_readable_version`] made unreadable, so that RocksDB reclaims
+    /// them while compacting instead of [`RocksDbTreeStore::prune`] deleting them. Defaults to
+    /// `false`.
+    ///
+    /// This spares the write of one deletion per pruned node, at the cost of keeping the keys of
+    /// the nodes to drop in memory, and of their space being reclaimed only once compactions get
+    /// to them. The filter is only installed on stores opened from a path, not by
+    /// [`RocksDbTreeStore::from_db`].
+    pub lazy_pruning: bool,
+    /// When set, values and preimages are stamped with the time they are written at, and the
+    /// values and preimages column families get a compaction filter dropping those written more
+    /// than this long ago, in whole seconds. The nodes never expire. Defaults to `None`, under
+    /// which nothing expires.
+    ///
+    /// This is meant for stores used as an expiring cache of payloads next to a permanent tree.
+    /// An expired entry stays readable until a compaction gets to it, and once dropped its key
+    /// reads as absent, or with an older value, at every version, so proofs for the versions
+    /// that wrote it no longer match the values read. Entries written without a TTL carry no
+    /// time and are kept. The filter is only installed on stores opened from a path, not by
+    /// [`RocksDbTreeStore::from_db`].
+    pub value_ttl: Option<Duration>,
+    /// When set, the nodes written are stored once per distinct content: each node key maps to
+    /// a pointer to the encoded node, keyed by its hash, and a node whose encoding is already
+    /// stored under another key is not stored again. Defaults to `false`.
+    ///
+    /// This shrinks stores whose updates keep rewriting identical nodes, such as leaves set back
+    /// to an earlier value. A pointer takes 34 bytes, and every node written costs one extra read
+    /// to look its content up. Reading a node written in this mode takes two reads instead of
+    /// one, the pointer and then the content, which roughly doubles the cost of walking the
+    /// tree when the contents are not cached. Pruning deletes the pointers but never the
+    /// contents, which other node keys may still point to. Nodes written while the mode was off
+    /// stay readable either way.
+    pub dedup_nodes: bool,
+    /// When set, every key the store writes, in every column family, starts with these bytes,
+    /// and the store only reads and iterates over the keys starting with them. Defaults to
+    /// `None`, which prefixes nothing.
+    ///
+    /// This lets several stores share the same column families of one database, each under a
+    /// namespace of its own, see [`RocksDbTreeStore::from_db_in_namespace`]. Namespaces all have
+    /// the same length so that none is a prefix of another, but the keys of a store without one
+    /// may start with any namespace: the stores sharing column families must all have one. The
+    /// namespace of a store cannot change once it is written to. The statistics of
+    /// [`rocks_stats`](RocksDbTreeStore::rocks_stats),
+    /// [`quick_stats`](RocksDbTreeStore::quick_stats) and
+    /// [`disk_usage`](RocksDbTreeStore::disk_usage) are those of the whole column families, every
+    /// namespace included.
+    pub namespace: Option<[u8; NAMESPACE_LEN]>,
+    /// When set, the values longer than this many bytes are compressed with zstd one by one
+    /// before being stored, and decompressed as they are read. Defaults to `None`, which
+    /// compresses no value.
+    ///
+    /// Unlike the compression of whole column families, this leaves the small values as they
+    /// are, sparing them the cost of compressing and decompressing for little gain, while large
+    /// ones shrink on their own, whatever the values around them. Compressed values are marked
+    /// by the kind of their record, so values written with any setting stay readable.
+    pub compress_values_over: Option<usize>,
+    /// When set, a background thread syncs the write-ahead log to disk at this interval, as
+    /// [`RocksDbTreeStore::sync_now`] does. Defaults to `None`, which leaves the syncs to RocksDB.
+    ///
+    /// Writes are appended to the write-ahead log without syncing it, which survives a crash of
+    /// the process but not always one of the machine. Syncing after every write would cost one
+    /// sync per batch; this groups the syncs of the batches written within an interval into one,
+    /// at the cost of a crash of the machine losing at most the writes of the last interval.
+    pub wal_sync_interval: Option<Duration>,
+    /// When set, once the nodes made stale by the writes since the last automatic prune add up
+    /// to more than this many bytes, a background thread prunes the store to the version set by
+    /// [`RocksDbTreeStore::set_least_readable_version`], as [`RocksDbTreeStore::prune`] does.
+    /// Defaults to `None`, which never prunes automatically.
+    ///
+    /// Nothing is pruned until a least readable version is set. The writes only hand the keys of
+    /// the nodes they make stale over to the thread, which reads their sizes and prunes without
+    /// holding them up, so that the stale nodes are removed some time after the write crossing
+    /// the threshold returns. Sealed versions are kept regardless (see
+    /// [`RocksDbTreeStore::seal_version`]). Each prune holds the write lock, as any prune does,
+    /// so writes wait for it to finish, and it combines safely with
+    /// [`refcount_nodes`](RocksDbStoreConfig::refcount_nodes).
+    pub auto_prune_stale_bytes: Option<u64>,
+    /// When set, the leaves whose value is no longer than this many bytes are stored with the
+    /// value inline, so that [`get_with_proof`](crate::JellyfishMerkleTree::get_with_proof)
+    /// reads the value along with the leaf instead of looking it up in the values column family
+    /// afterwards. Defaults to `None`, which stores every value apart.
+    ///
+    /// The values are still written to the values column family, which every other read goes
+    /// through, so that this only trades the size of the small values for one lookup per proof,
+    /// and leaves written with any setting stay readable. A leaf moved by a write that did not
+    /// change its value is stored without it, as is every leaf with
+    /// [`value_ttl`](RocksDbStoreConfig::value_ttl), whose values only expire from the values
+    /// column family.
+    pub inline_values_up_to: Option<usize>,
+    /// Flush the memtables and sync the write-ahead log when the last handle on the store is
+    /// dropped, as [`RocksDbTreeStore::close`] does. Defaults to `false`.
+    ///
+    /// `Drop` cannot return an error, so a failed flush is only logged through `tracing`, at the
+    /// error level: callers that need to know whether the flush succeeded should call `close`,
+    /// after which dropping does not flush again. The handles sharing the database, such as the
+    /// clones of the store, only flush once the last of them goes, and a database also held
+    /// through the [`Arc`] passed to [`from_db`](RocksDbTreeStore::from_db) is not flushed.
+    pub flush_on_drop: bool,
+    /// Flush the memtables of all the column families together, mapping to
+    /// [`Options::set_atomic_flush`]. Defaults to `false`.
+    ///
+    /// Every write of the store lands in all of its column families at once, through one
+    /// write-ahead log, which RocksDB replays on opening: a crash cannot leave the nodes, values,
+    /// stale index and roots of a version at different points as long as the log survives. With
+    /// this, they also never are in the SST files, so that a database opened after losing the
+    /// unflushed part of its log, or repaired by [`RocksDbTreeStore::repair`], still holds every
+    /// column family as of the same write. The cost is a little throughput: a column family whose
+    /// memtable fills up gets all the others flushed along with it, into more and smaller files,
+    /// and its flush waits for theirs.
+    pub atomic_flush: bool,
+    /// When set, the memtables of all the column families of the database share a budget of
+    /// this many bytes, mapping to [`Options::set_db_write_buffer_size`]. Defaults to `None`,
+    /// which bounds each column family on its own, by RocksDB's default write buffer size.
+    ///
+    /// RocksDB backs the budget with a write buffer manager of the database, flushing the
+    /// oldest memtable once the memtables together reach it, so that the memory of the
+    /// memtables stays bounded however many column families the store has. A small budget keeps
+    /// the memory of the store low at the cost of more frequent flushes into smaller files, and
+    /// thus more compactions. The budget is that of one database: the stores of a process opened
+    /// on databases of their own each get one, and the stores sharing a database through
+    /// [`RocksDbTreeStore::from_db`] share the budget it was opened with.
+    pub db_write_buffer_size: Option<usize>,
+    /// When set, the values column family gets a merge operator combining the value of a key
+    /// with the deltas merged into it by [`RocksDbTreeStore::merge_value`], oldest first, and
+    /// called whenever the value is read or compacted. Defaults to `None`, under which merging
+    /// fails.
+    ///
+    /// This lets values such as counters be updated without reading them first. Merging only
+    /// touches the values column family, bypassing the tree: the leaf of the key still commits
+    /// to the value it was written with, so that a merged value is read back by the plain value
+    /// reads but is outside the guarantees of the proofs, which no longer verify against it,
+    /// until it is written through the tree again. The operator is only installed on stores
+    /// opened from a path, not by [`RocksDbTreeStore::from_db`], and must be set on every open
+    /// of a store holding unmerged deltas, which cannot be read without it.
+    pub value_merge_operator: Option<ValueMergeOperator>,
+    /// Collect RocksDB's internal statistics, such as the counts of cache hits and of bytes
+    /// written, and the histograms of the durations of reads, mapping to
+    /// [`Options::enable_statistics`]. Defaults to `false`.
+    ///
+    /// They are read with [`RocksDbTreeStore::take_statistics`]. Collecting them costs a little
+    /// on every operation of the database, and they are only collected by stores opened from a
+    /// path, not by [`RocksDbTreeStore::from_db`], which takes the options of the database as
+    /// they are.
+    pub enable_statistics: bool,
+    /// When set, the SST files older than this are compacted again even if nothing else would
+    /// compact them, mapping to RocksDB's `periodic_compaction_seconds` option. Defaults to
+    /// `None`, which leaves RocksDB's default.
+    ///
+    /// Deletions, such as those of the stale nodes pruned, only free their space once compacted
+    /// together with the records they delete, which may sit in files of the lower levels that
+    /// writes rarely reach: this bounds how long they linger without a manual
+    /// [`compact_cf`](RocksDbTreeStore::compact_cf). The bindings do not expose the option when
+    /// opening, so it is set on every column family right after, and only by stores opened
+    /// from a path for writing, not by [`RocksDbTreeStore::open_read_only`] and
+    /// [`RocksDbTreeStore::from_db`]. The period is rounded down to whole seconds.
+    pub periodic_compaction: Option<Duration>,
+}
+
+// `Cache` and the merge operator are not `Debug`.
+impl fmt::Debug for RocksDbStoreConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RocksDbStoreConfig")
+            .field("allow_mmap_reads", &self.allow_mmap_reads)
+            .field("retain_versions", &self.retain_versions)
+            .field("value_full_bloom_bits", &self.value_full_bloom_bits)
+            .field("value_prefix_extractor", &self.value_prefix_extractor)
+            .field("bloom_filter_bits", &self.bloom_filter_bits)
+            .field("refcount_nodes", &self.refcount_nodes)
+            .field("max_value_size", &self.max_value_size)
+            .field("allow_backfill", &self.allow_backfill)
+            .field(
+                "block_cache",
+                &self.block_cache.as_ref().map(|_| "Cache { .. }"),
+            )
+            .field("proof_cache_capacity", &self.proof_cache_capacity)
+            .field("absence_cache_capacity", &self.absence_cache_capacity)
+            .field("node_cache_capacity", &self.node_cache_capacity)
+            .field("node_cache_policy", &self.node_cache_policy)
+            .field("recycle_log_file_num", &self.recycle_log_file_num)
+            .field("max_total_wal_size", &self.max_total_wal_size)
+            .field("max_open_files", &self.max_open_files)
+            .field(
+                "table_cache_num_shard_bits",
+                &self.table_cache_num_shard_bits,
+            )
+            .field("rate_limit_bytes_per_sec", &self.rate_limit_bytes_per_sec)
+            .field("lazy_pruning", &self.lazy_pruning)
+            .field("value_ttl", &self.value_ttl)
+            .field("dedup_nodes", &self.dedup_nodes)
+            .field("namespace", &self.namespace.map(hex::encode))
+            .field("compress_values_over", &self.compress_values_over)
+            .field("wal_sync_interval", &self.wal_sync_interval)
+            .field("auto_prune_stale_bytes", &self.auto_prune_stale_bytes)
+            .field("inline_values_up_to", &self.inline_values_up_to)
+            .field("flush_on_drop", &self.flush_on_drop)
+            .field("atomic_flush", &self.atomic_flush)
+            .field("db_write_buffer_size", &self.db_write_buffer_size)
+            .field(
+                "value_merge_operator",
+                &self.value_merge_operator.as_ref().map(|_| "Fn { .. }"),
+            )
+            .field("enable_statistics", &self.enable_statistics)
+            .field("periodic_compaction", &self.periodic_compaction)
+            .finish()
+    }
+}
+
+impl RocksDbStoreConfig {
+    /// Builds the RocksDB options described by this configuration.
+    fn options(&self) -> Options {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        opts.set_allow_mmap_reads(self.allow_mmap_reads);
+        opts.set_recycle_log_file_num(self.recycle_log_file_num);
+        opts.set_max_total_wal_size(self.max_total_wal_size);
+        opts.set_atomic_flush(self.atomic_flush);
+        if let Some(size) = self.db_write_buffer_size {
+            opts.set_db_write_buffer_size(size);
+        }
+        if let Some(max_open_files) = self.max_open_files {
+            opts.set_max_open_files(i32::try_from(max_open_files).unwrap_or(i32::MAX));
+        }
+        if let Some(bits) = self.table_cache_num_shard_bits {
+            opts.set_table_cache_num_shard_bits(i32::try_from(bits).unwrap_or(i32::MAX));
+        }
+        if let Some(rate) = self.rate_limit_bytes_per_sec {
+            opts.set_ratelimiter(i64::try_from(rate).unwrap_or(i64::MAX), 100_000, 10);
+        }
+        if self.enable_statistics {
+            opts.enable_statistics();
+        }
+        opts
+    }
+
+    /// Builds the descriptors of the column families opened besides the default one, with the
+    /// compaction filter dropping `reclaimable_nodes` on the nodes column family if any, the one
+    /// expiring values and preimages with [`value_ttl`](RocksDbStoreConfig::value_ttl), and the
+    /// merge operator of [`value_merge_operator`](RocksDbStoreConfig::value_merge_operator)
+    /// encoding its values with `C`.
+    fn column_family_descriptors<C: Codec>(
+        &self,
+        reclaimable_nodes: Option<&Arc<ReclaimableNodes>>,
+    ) -> Vec<ColumnFamilyDescriptor> {
+        COLUMN_FAMILIES
+            .iter()
+            .map(|&name| {
+                let mut opts = Options::default();
+                let bloom_bits = match name {
+                    NODES_CF => self.bloom_filter_bits,
+                    VALUES_CF => self.value_full_bloom_bits.or(self.bloom_filter_bits),
+                    _ => None,
+                };
+                if bloom_bits.is_some() || self.block_cache.is_some() {
+                    let mut table_opts = BlockBasedOptions::default();
+                    if let Some(bits) = bloom_bits {
+                        table_opts.set_bloom_filter(bits, false);
+                        table_opts.set_whole_key_filtering(true);
+                    }
+                    if let Some(cache) = &self.block_cache {
+                        table_opts.set_block_cache(cache);
+                    }
+                    opts.set_block_based_table_factory(&table_opts);
+                }
+                if name == VALUES_CF && self.value_prefix_extractor {
+                    let namespace_len = self.namespace.map_or(0, |namespace| namespace.len());
+                    opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(
+                        namespace_len + value_key_prefix(KeyHash([0; 32])).len(),
+                    ));
+                    opts.set_memtable_prefix_bloom_ratio(0.1);
+                }
+                if let (NODES_CF, Some(reclaimable_nodes)) = (name, reclaimable_nodes) {
+                    opts.set_compaction_filter(
+                        lazy_prune::FILTER_NAME,
+                        Arc::clone(reclaimable_nodes).compaction_filter(),
+                    );
+                }
+                if let (VALUES_CF | PREIMAGES_CF, Some(ttl)) = (name, self.value_ttl) {
+                    opts.set_compaction_filter(ttl::FILTER_NAME, ttl::compaction_filter(ttl));
+                }
+                if let (VALUES_CF, Some(operator)) = (name, &self.value_merge_operator) {
+                    opts.set_merge_operator(
+                        merge::OPERATOR_NAME,
+                        merge::full_merge(
+                            Arc::clone(operator),
+                            decode_value::<C>,
+                            C::encode::<Option<OwnedValue>>,
+                            self.value_ttl,
+                        ),
+                        merge::partial_merge,
+                    );
+                }
+                ColumnFamilyDescriptor::new(name, opts)
+            })
+            .collect()
+    }
+
+    /// Sets the options of `db`, just opened with the column families of the store, that its
+    /// options could not set.
+    fn set_mutable_options(&self, db: &DB) -> Result<()> {
+        if let Some(period) = self.periodic_compaction {
+            let seconds = period.as_secs().to_string();
+            let options = [("periodic_compaction_seconds", seconds.as_str())];
+            db.set_options(&options)?;
+            for name in COLUMN_FAMILIES {
+                let cf = db
+                    .cf_handle(name)
+                    .ok_or_else(|| anyhow::anyhow!("missing column family {}", name))?;
+                db.set_options_cf(cf, &options)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RocksDbTreeStore {
+    /// Creates a new RocksDB tree store with the given database path.
+    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        Self::with_config(path, RocksDbStoreConfig::default())
+    }
+
+    /// Same as [`new`](RocksDbTreeStore::new), trying to open the database up to `attempts`
+    /// times, `delay` apart, while opening fails on a transient error.
+    ///
+    /// This is for stores on networked volumes, whose directory may be unavailable for a moment,
+    /// during a failover for instance. I/O errors, timeouts and busy resources are taken as
+    /// transient, including the lock of the database still being held by a process on its way
+    /// out; any other error, such as a corrupted database, fails at once, since trying again
+    /// would fail the same way. The error of the last attempt is returned once all of them fail.
+    pub fn open_with_retry<P: AsRef<std::path::Path>>(
+        path: P,
+        attempts: u32,
+        delay: Duration,
+    ) -> Result<Self> {
+        ensure!(attempts > 0, "opening a store takes at least one attempt");
+        let mut attempt = 1;
+        loop {
+            match Self::new(path.as_ref()) {
+                Ok(store) => return Ok(store),
+                Err(err) if attempt < attempts && is_transient_open_error(&err) => {
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                Err(err) => {
+                    return Err(err.context(format!(
+                        "failed to open the store on attempt {} of {}",
+                        attempt, attempts
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Creates a new RocksDB tree store with the given database path, opened according to
+    /// `config`.
+    pub fn with_config<P: AsRef<std::path::Path>>(
+        path: P,
+        config: RocksDbStoreConfig,
+    ) -> Result<Self> {
+        Self::with_hasher(path, config)
+    }
+
+    /// Repairs the database at `path` so that a store can be opened on it again, after a crash
+    /// left it in a state RocksDB refuses to open, such as during a compaction.
+    ///
+    /// This runs RocksDB's repairer with the options stores are opened with, salvaging what it
+    /// can from the SST files. Repair may lose the most recent writes, those that were only in
+    /// the write-ahead log and not flushed yet, and it must not run while the database is open.
+    /// If the database still opens, its memtables are flushed first, so that repairing a healthy
+    /// database leaves its data as it is.
+    pub fn repair<P: AsRef<std::path::Path>>(path: P) -> Result<()> {
+        let config = RocksDbStoreConfig::default();
+        let mut opts = config.options();
+        opts.create_if_missing(false);
+        if let Ok(db) = DB::open_cf_descriptors(
+            &opts,
+            path.as_ref(),
+            config.column_family_descriptors::<BincodeCodec>(None),
+        ) {
+            for name in COLUMN_FAMILIES {
+                let cf = db
+                    .cf_handle(name)
+                    .ok_or_else(|| anyhow::anyhow!("missing column family {}", name))?;
+                db.flush_cf(cf)?;
+            }
+        }
+        DB::repair(&opts, path)?;
+        Ok(())
+    }
+
+    /// Creates a tree store inside `db`, an already opened database, using the column families
+    /// named by [`column_family_names`](RocksDbTreeStore::column_family_names) for `cf_prefix`.
+    ///
+    /// This lets the tree share a database with other data instead of opening a second one, and
+    /// lets its writes be committed together with unrelated ones. The database must have been
+    /// opened with all of those column families, or this fails naming the missing ones. The store
+    /// uses the default [`RocksDbStoreConfig`], since the options of the database are already
+    /// set. Several stores can share the same column families under namespaces of their own, see
+    /// [`from_db_in_namespace`](RocksDbTreeStore::from_db_in_namespace).
+    pub fn from_db(db: Arc<DB>, cf_prefix: &str) -> Result<Self> {
+        Self::from_db_with_hasher(db, cf_prefix)
+    }
+
+    /// Returns the names of the column families a store created by
+    /// [`from_db`](RocksDbTreeStore::from_db) with `cf_prefix` expects the database to have.
+    pub fn column_family_names(cf_prefix: &str) -> Vec<String> {
+        COLUMN_FAMILIES
+            .iter()
+            .map(|name| format!("{}{}", cf_prefix, name))
+            .collect()
+    }
+
+    /// create a new RocksDB tree store in the current diretory and child directory name is state_db
+    pub fn default() -> Result<Self> {
+        let current_dir = std::env::current_dir()?;
+        let db_path = current_dir.join("state_db");
+        Self::new(db_path)
+    }
+
+    /// Creates a new temporary RocksDB tree store.
+    pub fn new_temporary() -> Result<Self> {
+        let temp_dir = tempfile::TempDir::new()?;
+        Self::new(temp_dir.path())
+    }
+}
+
+/// Returns whether `err`, an error opening a database, may go away by trying again: a
+/// [transient](is_transient) error, or any RocksDB I/O error, the directory of the database
+/// being unavailable for a moment or its lock still held.
+fn is_transient_open_error(err: &anyhow::Error) -> bool {
+    is_transient(err)
+        || err
+            .downcast_ref::<rocksdb::Error>()
+            .is_some_and(|err| err.kind() == ErrorKind::IOError)
+}
+
+impl<C: Codec, H: SimpleHasher> GenericTreeStore<C, H> {
+    /// Same as [`with_config`](RocksDbTreeStore::with_config), for a tree hashed with `H`.
+    ///
+    /// Fails if the store at `path` was created with another hasher.
+    pub fn with_hasher<P: AsRef<std::path::Path>>(
+        path: P,
+        config: RocksDbStoreConfig,
+    ) -> Result<Self> {
+        let reclaimable_nodes = config.lazy_pruning.then(Arc::default);
+        let descriptors = config.column_family_descriptors::<C>(reclaimable_nodes.as_ref());
+        let opts = config.options();
+        let db = DB::open_cf_descriptors(&opts, path, descriptors)?;
+        config.set_mutable_options(&db)?;
+        Self::open(
+            Arc::new(db),
+            String::new(),
+            config,
+            reclaimable_nodes,
+            Some(opts),
+        )
+    }
+
+    /// Same as [`with_hasher`](GenericTreeStore::with_hasher), opening the store at `path` for
+    /// reading only.
+    ///
+    /// RocksDB rejects every write to the store, and several processes may open it at once, also
+    /// while another one writes to it; the store only sees what was written before it was
+    /// opened. Fails if there is no store at `path`, or if it was created with another hasher.
+    pub fn open_read_only<P: AsRef<std::path::Path>>(
+        path: P,
+        config: RocksDbStoreConfig,
+    ) -> Result<Self> {
+        let descriptors = config.column_family_descriptors::<C>(None);
+        let opts = config.options();
+        let db = DB::open_cf_descriptors_read_only(&opts, path, descriptors, false)?;
+        Self::open(Arc::new(db), String::new(), config, None, Some(opts))
+    }
+
+    /// Same as [`with_hasher`](GenericTreeStore::with_hasher), opening the store at
+    /// `primary_path` as a secondary instance keeping its own logs under `secondary_path`.
+    ///
+    /// As with [`open_read_only`](GenericTreeStore::open_read_only), RocksDB rejects every write,
+    /// and the store only sees what was written before it was opened, but it can be brought up
+    /// to date with the primary, which keeps writing meanwhile, by
+    /// [`try_catch_up_with_primary`](GenericTreeStore::try_catch_up_with_primary). RocksDB
+    /// requires a secondary to keep every file open, so that
+    /// [`RocksDbStoreConfig::max_open_files`] is ignored. Fails if there is no store at
+    /// `primary_path`, or if it was created with another hasher.
+    pub fn open_as_secondary<P: AsRef<std::path::Path>>(
+        primary_path: P,
+        secondary_path: P,
+        config: RocksDbStoreConfig,
+    ) -> Result<Self> {
+        let descriptors = config.column_family_descriptors::<C>(None);
+        let mut opts = config.options();
+        opts.set_max_open_files(-1);
+        let db =
+            DB::open_cf_descriptors_as_secondary(&opts, primary_path, secondary_path, descriptors)?;
+        Self::open(Arc::new(db), String::new(), config, None, Some(opts))
+    }
+
+    /// Brings a store opened by [`open_as_secondary`](GenericTreeStore::open_as_secondary) up to
+    /// date with the writes of its primary, and clears every cache, which the writes caught up
+    /// with may have left stale.
+    ///
+    /// See [`BoundedStalenessReader`] for reads catching up on their own. Fails on a store that
+    /// is not a secondary.
+    pub fn try_catch_up_with_primary(&self) -> Result<()> {
+        self.db.try_catch_up_with_primary()?;
+        self.invalidate_proofs();
+        if let Some(latest_version) = self.latest_version()? {
+            self.cache_root(&[], latest_version);
+        }
+        Ok(())
+    }
+
+    /// Same as [`from_db`](RocksDbTreeStore::from_db), for a tree hashed with `H`.
+    ///
+    /// Fails if the store in `db` was created with another hasher.
+    pub fn from_db_with_hasher(db: Arc<DB>, cf_prefix: &str) -> Result<Self> {
+        Self::from_db_with_config(db, cf_prefix, RocksDbStoreConfig::default())
+    }
+
+    /// Same as [`from_db_with_hasher`](RocksDbTreeStore::from_db_with_hasher), for a store under
+    /// `namespace` in the column families for `cf_prefix`, see
+    /// [`RocksDbStoreConfig::namespace`].
+    ///
+    /// Stores under different namespaces hold independent trees, down to their latest versions,
+    /// labels and preimages, while sharing the column families and their caches.
+    pub fn from_db_in_namespace(
+        db: Arc<DB>,
+        cf_prefix: &str,
+        namespace: [u8; NAMESPACE_LEN],
+    ) -> Result<Self> {
+        let config = RocksDbStoreConfig {
+            namespace: Some(namespace),
+            ..RocksDbStoreConfig::default()
+        };
+        Self::from_db_with_config(db, cf_prefix, config)
+    }
+
+    /// Creates a tree store with `config` inside `db`, after checking that `db` has the column
+    /// families of the store.
+    fn from_db_with_config(
+        db: Arc<DB>,
+        cf_prefix: &str,
+        config: RocksDbStoreConfig,
+    ) -> Result<Self> {
+        let missing: Vec<String> = RocksDbTreeStore::column_family_names(cf_prefix)
+            .into_iter()
+            .filter(|name| db.cf_handle(name).is_none())
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "database is missing the column families {} required by the tree store",
+                missing.join(", ")
+            );
+        }
+        Self::open(db, cf_prefix.to_owned(), config, None, None)
+    }
+
+    /// Wraps `db` and checks the hasher fingerprint of the store, recording it if the store has
+    /// none yet. With `reclaimable_nodes`, the filter of the database drops the nodes below the
+    /// minimum readable version stored in the metadata again. `opts` are the options `db` was
+    /// opened with, if the store opened it, which hold its statistics.
+    fn open(
+        db: Arc<DB>,
+        cf_prefix: String,
+        config: RocksDbStoreConfig,
+        reclaimable_nodes: Option<Arc<ReclaimableNodes>>,
+        opts: Option<Options>,
+    ) -> Result<Self> {
+        let proof_cache = config
+            .proof_cache_capacity
+            .map(|capacity| Arc::new(ProofCache::new(capacity)));
+        let absence_cache = config
+            .absence_cache_capacity
+            .map(|capacity| Arc::new(ProofCache::new(capacity)));
+        let node_cache = config
+            .node_cache_capacity
+            .map(|capacity| Arc::new(NodeCache::new(capacity, config.node_cache_policy)));
+        let wal_syncer = config
+            .wal_sync_interval
+            .map(|interval| WalSyncer::spawn(Arc::downgrade(&db), interval).map(Arc::new))
+            .transpose()?;
+        let statistics = opts
+            .filter(|_| config.enable_statistics)
+            .map(|opts| Arc::new(Statistics::new(opts)));
+        let store = Self {
+            db,
+            cf_prefix,
+            config,
+            proof_cache,
+            absence_cache,
+            node_cache,
+            commit_hooks: Arc::default(),
+            pending_version_metadata: Arc::default(),
+            corruption_hooks: Arc::default(),
+            reclaimable_nodes,
+            wal_syncer,
+            auto_pruner: Arc::default(),
+            write_lock: Arc::default(),
+            cached_root: Arc::default(),
+            #[cfg(feature = "latency_histogram")]
+            write_latency: Arc::default(),
+            statistics,
+            _phantom: PhantomData,
+        };
+        let metadata_cf = store.metadata_cf()?;
+        let fingerprint = hasher_fingerprint::<H>();
+        match store
+            .db
+            .get_cf(metadata_cf, store.key(HASHER_FINGERPRINT_KEY))?
+        {
+            Some(stored) => {
+                let stored = unframe(RecordKind::HasherFingerprint, &stored)?;
+                ensure!(
+                    stored == fingerprint,
+                    "the store was created with another hasher (fingerprint {}, expected {})",
+                    hex::encode(stored),
+                    hex::encode(fingerprint)
+                )
+            }
+            None => store.db.put_cf(
+                metadata_cf,
+                store.key(HASHER_FINGERPRINT_KEY),
+                frame(RecordKind::HasherFingerprint, &fingerprint),
+            )?,
+        }
+        if let Some(reclaimable_nodes) = &store.reclaimable_nodes {
+            let key = store.key(MIN_READABLE_VERSION_KEY);
+            if let Some(bytes) = store.db.get_cf(metadata_cf, key)? {
+                let min_readable_version = decode_version_record(&bytes)?;
+                reclaimable_nodes.replace(store.reclaimable_node_keys(min_readable_version)?);
+            }
+        }
+        if let Some(latest_version) = store.latest_version()? {
+            store.cache_root(&[], latest_version);
+        }
+        Ok(store)
+    }
+
+    /// Returns the value of `key_hash` at the latest version written to the store, or `None` if
+    /// the key is absent or deleted there.
+    ///
+    /// Keys written by the latest batch are served by a point read at that exact version, which
+    /// [`RocksDbStoreConfig::value_full_bloom_bits`] can answer from the bloom filter for every
+    /// other key. Those then fall back to the same seek as
+    /// [`get_value_option`](TreeReader::get_value_option).
+    pub fn get_latest_value(&self, key_hash: KeyHash) -> Result<Option<OwnedValue>> {
+        let latest_version = match self.latest_version()? {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+        let exact_key = self.key(&encode_value_key(key_hash, latest_version));
+        match self.db.get_pinned_cf(self.cf(VALUES_CF)?, exact_key)? {
+            Some(value) => decode_value::<C>(&value),
+            None => self.get_value_option(latest_version, key_hash),
+        }
+    }
+
+    /// Returns the value of `key_hash` at `max_version` together with the version that set it,
+    /// or `None` if the key is absent or deleted there.
+    ///
+    /// This is [`get_value_option`](TreeReader::get_value_option), which only returns the value,
+    /// plus the version of the entry the value was read from, the latest one at or below
+    /// `max_version`. That version changes exactly when the value is rewritten, which makes it
+    /// usable as a cache key.
+    pub fn get_value_with_version(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<(Version, OwnedValue)>> {
+        let iter = self
+            .db
+            .raw_iterator_cf_opt(self.cf(VALUES_CF)?, self.value_read_options(key_hash));
+        seek_value::<C>(
+            iter,
+            self.namespace(),
+            max_version,
+            key_hash,
+            |key, decoded| self.check_decoded(CfKind::Values, key, decoded),
+        )
+    }
+
+    /// Returns the value of the key `raw_key` at `max_version`, as
+    /// [`get_value_option`](TreeReader::get_value_option) does for its key hash, `raw_key` being
+    /// hashed with the hasher of the store.
+    pub fn get_value_by_raw_key(
+        &self,
+        max_version: Version,
+        raw_key: &[u8],
+    ) -> Result<Option<OwnedValue>> {
+        self.get_value_option(max_version, KeyHash::with::<H>(raw_key))
+    }
+
+    /// Returns the highest version written through [`write_tree_update_batch`], or `None` if
+    /// nothing has been written to this store yet.
+    ///
+    /// The value is persisted, so after reopening a store it tells where to resume committing.
+    ///
+    /// [`write_tree_update_batch`]: RocksDbTreeStore::write_tree_update_batch
+    pub fn latest_version(&self) -> Result<Option<Version>> {
+        self.db
+            .get_cf(self.metadata_cf()?, self.key(LATEST_VERSION_KEY))?
+            .map(|bytes| decode_version_record(&bytes))
+            .transpose()
+    }
+
+    /// Returns the key and the root node of the
+    /// [`latest_version`](GenericTreeStore::latest_version) of the store, kept in memory, or
+    /// `None` if nothing has been written to the store yet.
+    ///
+    /// The root is read when the store is opened and replaced by
+    /// [`write_tree_update_batch`](RocksDbTreeStore::write_tree_update_batch) and
+    /// [`commit_versions`](RocksDbTreeStore::commit_versions) under their write lock, once their
+    /// write has landed, so that a write that fails leaves the root of the version before it.
+    /// The proofs of the latest version start from it, their walks reading its root from
+    /// memory, while [`get_node_option`](TreeReader::get_node_option) still reads the database.
+    /// Like the proof cache, it is shared with the clones of the store only: another
+    /// store opened on the same database does not see their writes, nor they its.
+    pub fn cached_root(&self) -> Option<(NodeKey, Node)> {
+        self.cached_root
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Caches the root of `latest_version`, taken from `batches` if one of them holds it and read
+    /// otherwise, or nothing if it cannot be read.
+    fn cache_root(&self, batches: &[TreeUpdateBatch], latest_version: Version) {
+        let root_key = NodeKey::new_empty_path(latest_version);
+        let root = match batches
+            .iter()
+            .rev()
+            .find_map(|batch| batch.node_batch.get_node(&root_key))
+        {
+            Some(root) => Some(root.clone()),
+            None => self
+                .read_node(&root_key)
+                .ok()
+                .flatten()
+                .map(|(root, _)| root),
+        };
+        *self
+            .cached_root
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = root.map(|root| (root_key, root));
+    }
+
+    /// Lists the write-ahead log files of the store, ordered by log number, with their sizes and
+    /// the range of sequence numbers they log.
+    ///
+    /// Data that has been written but not yet flushed to SST files only lives in these files,
+    /// which makes the listing useful to diagnose durability issues. The sequence ranges are read
+    /// from the files themselves and are `None` for files that do not hold a complete write batch
+    /// yet, such as a freshly created log.
+    pub fn wal_file_info(&self) -> Result<Vec<WalFileInfo>> {
+        wal::wal_files(self.db.path())
+    }
+
+    /// Returns RocksDB statistics of the store, such as its number of SST files per level and the
+    /// memory used by its memtables and block caches, summed over its column families.
+    ///
+    /// Unlike the text of the `rocksdb.stats` property, the fields are typed and can be exported
+    /// to a monitoring system as they are.
+    pub fn rocks_stats(&self) -> Result<RocksStats> {
+        let column_families = COLUMN_FAMILIES
+            .iter()
+            .map(|name| self.cf(name))
+            .collect::<Result<Vec<_>>>()?;
+        stats::rocks_stats(&self.db, &column_families)
+    }
+
+    /// Returns the text of RocksDB's internal statistics accumulated since the last call, or since
+    /// the store was opened, and starts accumulating anew, or `None` unless the store was opened
+    /// with [`RocksDbStoreConfig::enable_statistics`].
+    ///
+    /// The counters and the counts and sums of the histograms start again from 0 after every
+    /// call and [`reset_statistics`](GenericTreeStore::reset_statistics), so that each benchmark
+    /// run only sees its own operations. RocksDB cannot reset the percentiles of its histograms
+    /// through its C API, which thus cover every operation since the store was opened. The
+    /// statistics are those of the database, shared by the stores and clones on it.
+    pub fn take_statistics(&self) -> Option<String> {
+        self.statistics.as_ref()?.take()
+    }
+
+    /// Starts accumulating the statistics returned by
+    /// [`take_statistics`](GenericTreeStore::take_statistics) anew, dropping those accumulated so
+    /// far. Does nothing unless the store was opened with
+    /// [`RocksDbStoreConfig::enable_statistics`].
+    pub fn reset_statistics(&self) {
+        if let Some(statistics) = &self.statistics {
+            statistics.take();
+        }
+    }
+
+    /// Returns RocksDB's estimates of the number of keys in each column family of the store and
+    /// of the size of its live data.
+    ///
+    /// Unlike [`rocks_stats`](GenericTreeStore::rocks_stats) and
+    /// [`disk_usage`](GenericTreeStore::disk_usage), this reads a couple of properties RocksDB
+    /// keeps up to date, without touching any file, so it is cheap enough to serve a frequently
+    /// polled health check. The estimates are coarse and may lag behind recent writes and
+    /// deletions.
+    pub fn quick_stats(&self) -> Result<QuickStats> {
+        let column_families = COLUMN_FAMILIES
+            .iter()
+            .map(|&name| Ok((name, self.cf(name)?)))
+            .collect::<Result<Vec<_>>>()?;
+        stats::quick_stats(&self.db, &column_families)
+    }
+
+    /// Flushes the memtables of every column family of the store to SST files, syncs the
+    /// write-ahead log and closes the store, failing if any of it fails.
+    ///
+    /// Dropping a store closes it as well, flushing it only with
+    /// [`RocksDbStoreConfig::flush_on_drop`] and without any way to report a failed flush, so
+    /// this is to be preferred. The database is closed, and its lock released, once no other
+    /// handle on it is left, such as the store of a [`WriterHandle`] or the [`Arc`] passed to
+    /// [`from_db`](RocksDbTreeStore::from_db).
+    pub fn close(mut self) -> Result<()> {
+        self.config.flush_on_drop = false;
+        self.flush_all()
+    }
+
+    /// Flushes the memtables of every column family and syncs the write-ahead log.
+    fn flush_all(&self) -> Result<()> {
+        for name in COLUMN_FAMILIES {
+            self.db.flush_cf(self.cf(name)?)?;
+        }
+        self.db.flush_wal(true)?;
+        Ok(())
+    }
+
+    /// Compacts the entries of the column family `cf` whose keys are between `start`, included,
+    /// and `end`, excluded, or all of its entries in the namespace of the store if neither is
+    /// given, blocking until done.
+    ///
+    /// The keys are the encoded keys of the column family, starting with their record header, in
+    /// the namespace of the store. RocksDB compacts in the background on its own; this lets a
+    /// caller reclaim the space of overwritten and deleted entries of one column family at a time
+    /// of its choosing, such as a low-traffic window, leaving the others alone.
+    pub fn compact_cf(&self, cf: CfKind, start: Option<&[u8]>, end: Option<&[u8]>) -> Result<()> {
+        let start = self.key(start.unwrap_or_default());
+        let end = match end {
+            Some(end) => Some(self.key(end)),
+            None => prefix_successor(self.namespace()),
+        };
+        self.db
+            .compact_range_cf(self.cf(cf.name())?, Some(start), end);
+        Ok(())
+    }
+
+    /// Syncs the write-ahead log to disk, so that every write made so far survives a crash of the
+    /// machine, without waiting for the next sync of [`RocksDbStoreConfig::wal_sync_interval`].
+    pub fn sync_now(&self) -> Result<()> {
+        self.db.flush_wal(true)?;
+        Ok(())
+    }
+
+    /// Returns the disk space taken by the store, per category of data: nodes, values,
+    /// preimages, the stale node index, and the remaining bookkeeping.
+    ///
+    /// Each category reports the size of its SST files and RocksDB's estimate of the live data in
+    /// them. Writes still in the memtables are not on disk yet and therefore not counted.
+    pub fn disk_usage(&self) -> Result<DiskUsage> {
+        let usage = |names: &[&str]| -> Result<ColumnFamilyUsage> {
+            let column_families = names
+                .iter()
+                .map(|name| self.cf(name))
+                .collect::<Result<Vec<_>>>()?;
+            stats::column_family_usage(&self.db, &column_families)
+        };
+        Ok(DiskUsage {
+            nodes: usage(&[NODES_CF, NODE_CONTENTS_CF])?,
+            values: usage(&[VALUES_CF])?,
+            preimages: usage(&[PREIMAGES_CF])?,
+            stale_nodes: usage(&[STALE_NODES_CF])?,
+            other: usage(&[
+                METADATA_CF,
+                LABELS_CF,
+                REFCOUNTS_CF,
+                ROOTS_CF,
+                VERSION_METADATA_CF,
+                ROOT_VERSIONS_CF,
+            ])?,
+        })
+    }
+
+    /// Stores `preimage` as the preimage of `key_hash`, to be returned by
+    /// [`preimage`](HasPreimage::preimage).
+    pub fn put_key_preimage(&self, key_hash: KeyHash, preimage: &[u8]) -> Result<()> {
+        self.db.put_cf(
+            self.cf(PREIMAGES_CF)?,
+            self.key(&frame(RecordKind::KeyHash, &key_hash.0)),
+            ttl::frame_expiring(RecordKind::Preimage, preimage, self.config.value_ttl),
+        )?;
+        Ok(())
+    }
+
+    /// Returns the preimages of `keys`, in the same order, as
+    /// [`preimage`](HasPreimage::preimage) does for each of them, with a single `multi_get`.
+    pub fn preimages(&self, keys: &[KeyHash]) -> Result<Vec<Option<Vec<u8>>>> {
+        let preimages_cf = self.cf(PREIMAGES_CF)?;
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key_hash| self.key(&frame(RecordKind::KeyHash, &key_hash.0)))
+            .collect();
+        self.db
+            .multi_get_cf(keys.iter().map(|key| (preimages_cf, key)))
+            .into_iter()
+            .map(|value| match value? {
+                Some(value) => Ok(Some(
+                    ttl::unframe_expiring(RecordKind::Preimage, &value)?.to_vec(),
+                )),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    /// Returns metrics of the store itself, as opposed to the RocksDB statistics of
+    /// [`rocks_stats`](RocksDbTreeStore::rocks_stats). With the `latency_histogram` feature,
+    /// these include percentiles of the durations of the writes of node batches since the store
+    /// was opened.
+    pub fn metrics(&self) -> StoreMetrics {
+        let (proof_cache_hits, proof_cache_misses, proof_cache_entries) = self
+            .proof_cache
+            .as_ref()
+            .map_or((0, 0, 0), |cache| cache.stats());
+        let (absence_cache_hits, absence_cache_misses, absence_cache_entries) = self
+            .absence_cache
+            .as_ref()
+            .map_or((0, 0, 0), |cache| cache.stats());
+        let (node_cache_hits, node_cache_misses, node_cache_entries) = self
+            .node_cache
+            .as_ref()
+            .map_or((0, 0, 0), |cache| cache.stats());
+        StoreMetrics {
+            proof_cache_hits,
+            proof_cache_misses,
+            proof_cache_entries,
+            absence_cache_hits,
+            absence_cache_misses,
+            absence_cache_entries,
+            node_cache_hits,
+            node_cache_misses,
+            node_cache_entries,
+            #[cfg(feature = "latency_histogram")]
+            write_latency: self.write_latency.percentiles(),
+        }
+    }
+
+    /// Returns the handle of the column family `name`, under the prefix of the store.
+    fn cf(&self, name: &str) -> Result<&ColumnFamily> {
+        let name = format!("{}{}", self.cf_prefix, name);
+        self.db
+            .cf_handle(&name)
+            .ok_or_else(|| anyhow::anyhow!("missing column family {}", name))
+    }
+
+    /// Returns the [`namespace`](RocksDbStoreConfig::namespace) of the store, empty if it has
+    /// none.
+    fn namespace(&self) -> &[u8] {
+        self.config
+            .namespace
+            .as_ref()
+            .map_or(&[], |namespace| namespace.as_slice())
+    }
+
+    /// Returns `key` under the namespace of the store, the key it is stored at.
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        [self.namespace(), key].concat()
+    }
+
+    /// Returns `key`, read from an iterator bounded to the namespace of the store, without the
+    /// namespace.
+    fn unprefixed<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[self.namespace().len()..]
+    }
+
+    /// Returns an iterator over the entries of the column family `name` in the namespace of the
+    /// store, from the first one. The keys it yields still start with the namespace.
+    fn iterator(&self, name: &str) -> Result<DBIteratorWithThreadMode<'_, DB>> {
+        let opts = prefix_read_options(self.namespace());
+        Ok(self
+            .db
+            .iterator_cf_opt(self.cf(name)?, opts, IteratorMode::Start))
+    }
+
+    /// Returns an iterator over the entries of the column family `name` whose keys start with
+    /// `prefix` in the namespace of the store, left unpositioned.
+    ///
+    /// The prefix is set as the iterate bounds of the read, so RocksDB itself stops at the end of
+    /// the range: seeks and scans never step onto the entries, or the deletions, of the keys
+    /// around it, and SST files holding none of the range are skipped.
+    fn prefix_iterator(
+        &self,
+        name: &str,
+        prefix: &[u8],
+    ) -> Result<DBRawIteratorWithThreadMode<'_, DB>> {
+        let opts = prefix_read_options(&self.key(prefix));
+        Ok(self.db.raw_iterator_cf_opt(self.cf(name)?, opts))
+    }
+
+    /// Returns the options of a read of the values of `key_hash`, bounded to their keys and, with
+    /// [`RocksDbStoreConfig::value_prefix_extractor`], to the prefix of their key hash.
+    fn value_read_options(&self, key_hash: KeyHash) -> ReadOptions {
+        let mut opts = prefix_read_options(&self.key(&value_key_prefix(key_hash)));
+        if self.config.value_prefix_extractor {
+            opts.set_total_order_seek(false);
+            opts.set_prefix_same_as_start(true);
+        }
+        opts
+    }
+
+    /// Returns the handle of the metadata column family.
+    fn metadata_cf(&self) -> Result<&ColumnFamily> {
+        self.cf(METADATA_CF)
+    }
+
+    /// Labels `version` as `label`, replacing any version previously labeled `label`.
+    ///
+    /// Labeled versions are checkpoints that [`prune`](RocksDbTreeStore::prune) never removes:
+    /// a labeled version and every node reachable from it stay readable until the label is
+    /// removed, even once the version falls below the pruning watermark. Every label therefore
+    /// pins the storage of its version's tree. Fails with [`MissingRootError`] if `version`
+    /// is not readable.
+    pub fn set_label(&self, label: &str, version: Version) -> Result<()> {
+        if self
+            .get_node_option(&NodeKey::new_empty_path(version))?
+            .is_none()
+        {
+            return Err(anyhow::anyhow!(MissingRootError { version }));
+        }
+        self.db.put_cf(
+            self.cf(LABELS_CF)?,
+            self.key(label.as_bytes()),
+            encode_version_record(version),
+        )?;
+        Ok(())
+    }
+
+    /// Returns the version labeled `label`, if any.
+    pub fn label_version(&self, label: &str) -> Result<Option<Version>> {
+        self.db
+            .get_cf(self.cf(LABELS_CF)?, self.key(label.as_bytes()))?
+            .map(|bytes| decode_version_record(&bytes))
+            .transpose()
+    }
+
+    /// Removes `label`, unpinning its version so that a later prune may remove it.
+    pub fn remove_label(&self, label: &str) -> Result<()> {
+        self.db
+            .delete_cf(self.cf(LABELS_CF)?, self.key(label.as_bytes()))?;
+        Ok(())
+    }
+
+    /// Attaches `metadata` to `version`, such as the hash or the time of the block it commits,
+    /// replacing any metadata attached to it before. The store keeps the bytes as they are.
+    ///
+    /// For a version not committed yet, the metadata is held until the batch committing the
+    /// version is written, and written atomically with it, so that a version is never committed
+    /// without its metadata nor its metadata stored without the version; it is lost if the store
+    /// is closed first. For a version already committed, it is written right away. The metadata
+    /// is removed with its version by [`prune`](RocksDbTreeStore::prune), and kept as long as
+    /// the version is labeled.
+    pub fn set_version_metadata(
+        &self,
+        version: Version,
+        metadata: impl Into<Vec<u8>>,
+    ) -> Result<()> {
+        let metadata = metadata.into();
+        // Under the write lock, the version cannot be committed between the check and the
+        // staging of its metadata.
+        let _write_guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let committed = self
+            .latest_version()?
+            .is_some_and(|latest_version| version <= latest_version);
+        if committed {
+            self.db.put_cf(
+                self.cf(VERSION_METADATA_CF)?,
+                self.key(&encode_version_record(version)),
+                frame(RecordKind::VersionMetadata, &metadata),
+            )?;
+        } else {
+            self.pending_version_metadata
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(version, metadata);
+        }
+        Ok(())
+    }
+
+    /// Returns the metadata attached to `version` by
+    /// [`set_version_metadata`](GenericTreeStore::set_version_metadata), or `None` if the
+    /// version has none or is not committed yet.
+    pub fn get_version_metadata(&self, version: Version) -> Result<Option<Vec<u8>>> {
+        let key = self.key(&encode_version_record(version));
+        match self.db.get_cf(self.cf(VERSION_METADATA_CF)?, key)? {
+            Some(record) => {
+                let metadata = unframe(RecordKind::VersionMetadata, &record)?;
+                Ok(Some(metadata.to_vec()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns every version that carries at least one label.
+    fn labeled_versions(&self) -> Result<BTreeSet<Version>> {
+        self.iterator(LABELS_CF)?
+            .map(|item| decode_version_record(&item?.1))
+            .collect()
+    }
 }
 
-impl RocksDbTreeStore {
-    /// Creates a new RocksDB tree store with the given database path.
-    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
-        let mut opts = Options::default();
-        opts.create_if_missing(true);
-        opts.create_missing_column_families(true);
-        
-        let db = DB::open(&opts, path)?;
-        Ok(Self { db: Arc::new(db) })
+impl<C: Codec, H: SimpleHasher> TreeReader for GenericTreeStore<C, H> {
+    fn get_node(&self, node_key: &NodeKey) -> Result<Node> {
+        if let Some(node) = self.get_node_option(node_key)? {
+            return Ok(node);
+        }
+        // Only the root of a version may be looked for without a parent referencing it.
+        if !node_key.nibble_path().is_empty() {
+            self.report_corruption(|| CorruptionEvent {
+                column_family: CfKind::Nodes,
+                key: encode_node_key::<C>(node_key).unwrap_or_default(),
+                cause: CorruptionCause::MissingChild {
+                    node_key: node_key.clone(),
+                },
+            });
+        }
+        bail!("Missing node at {:?}.", node_key)
+    }
+
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        Ok(self.read_node(node_key)?.map(|(node, _)| node))
+    }
+
+    fn get_node_with_inline_value(&self, node_key: &NodeKey) -> Result<Option<NodeWithValue>> {
+        if node_key.nibble_path().is_empty() {
+            if let Some((root_key, root)) = &*self
+                .cached_root
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+            {
+                if root_key == node_key {
+                    return Ok(Some((root.clone(), None)));
+                }
+            }
+        }
+        self.read_node(node_key)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        // This is a simplified implementation. In practice, you'd want to maintain
+        // an index for efficient retrieval of the rightmost leaf.
+        // For now, we'll just return None to avoid complex iterator handling.
+        Ok(None)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        let started = trace::start();
+        let value = self
+            .get_value_with_version(max_version, key_hash)?
+            .map(|(_, value)| value);
+        trace::read(
+            "get_value_option",
+            key_hash.0.len(),
+            value.as_ref().map(Vec::len),
+            started,
+        );
+        Ok(value)
+    }
+}
+
+impl<C: Codec, H: SimpleHasher> HasPreimage for GenericTreeStore<C, H> {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        let raw_key = frame(RecordKind::KeyHash, &key_hash.0);
+        match self.db.get_cf(self.cf(PREIMAGES_CF)?, self.key(&raw_key))? {
+            Some(value) => {
+                let preimage = ttl::unframe_expiring(RecordKind::Preimage, &value)
+                    .map(|preimage| preimage.to_vec())
+                    .map_err(anyhow::Error::from);
+                let preimage = self.check_decoded(CfKind::Preimages, &raw_key, preimage)?;
+                Ok(Some(preimage))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl<C: Codec, H: SimpleHasher> TreeWriter for GenericTreeStore<C, H> {
+    /// Writes the nodes and values of `node_batch`.
+    ///
+    /// Fails, writing nothing, if the versions of the nodes and values of the batch do not form
+    /// a contiguous range, as those computed for one version or a run of consecutive versions
+    /// do: a batch mixing unrelated versions is a bug of the code that built it.
+    ///
+    /// Writing the same batch again right after it was written changes nothing, so a write that
+    /// failed without telling whether it landed can be retried: the entries of the batch are put
+    /// under their own keys, replacing themselves, and with
+    /// [`RocksDbStoreConfig::refcount_nodes`] the digest of the last batch written is kept, so
+    /// that a retry does not count its nodes twice. A batch written again after other nodes were
+    /// written in between counts again, as a rewrite of its nodes. With
+    /// [`RocksDbStoreConfig::value_ttl`], a retry restarts the lifetime of the values.
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        check_contiguous_versions(node_batch)?;
+        // The node counts are read and written back under the write lock, as prunes do.
+        let _write_guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let started = trace::start();
+        #[cfg(feature = "latency_histogram")]
+        let latency_started = std::time::Instant::now();
+        let digest = if self.config.refcount_nodes {
+            let digest = frame(
+                RecordKind::BatchDigest,
+                &H::hash(borsh::to_vec(node_batch)?),
+            );
+            let last = self
+                .db
+                .get_cf(self.metadata_cf()?, self.key(LAST_NODE_BATCH_KEY))?;
+            if last.as_deref() == Some(digest.as_slice()) {
+                return Ok(());
+            }
+            Some(digest)
+        } else {
+            None
+        };
+        let mut batch = WriteBatch::default();
+        self.stage_node_batch(&mut batch, node_batch)?;
+        if let Some(digest) = digest {
+            batch.put_cf(self.metadata_cf()?, self.key(LAST_NODE_BATCH_KEY), digest);
+        }
+        let batch_len = batch.size_in_bytes();
+        self.db.write(batch)?;
+        #[cfg(feature = "latency_histogram")]
+        self.write_latency.record(latency_started.elapsed());
+        self.invalidate_proofs_after([node_batch]);
+        // The latest version stays as it is, but a repair may write its root again.
+        if let Some((root_key, _)) = self.cached_root() {
+            if let Some(root) = node_batch.get_node(&root_key) {
+                *self
+                    .cached_root
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner) = Some((root_key, root.clone()));
+            }
+        }
+        trace::write(
+            node_batch.nodes().len(),
+            node_batch.values().len(),
+            batch_len,
+            started,
+        );
+        Ok(())
+    }
+}
+
+impl<C: Codec, H: SimpleHasher> TreeUpdateWriter for GenericTreeStore<C, H> {
+    fn write_tree_update_batch(&self, batch: &TreeUpdateBatch) -> Result<()> {
+        self.write_tree_update_batch_ref(batch)
+    }
+}
+
+impl<C: Codec, H: SimpleHasher> GenericTreeStore<C, H> {
+    /// Writes a tree update batch to the database.
+    ///
+    /// The nodes, values and the updated latest version are written atomically. Fails, writing
+    /// nothing, if the batch holds a version that is not newer than the latest version written,
+    /// unless [`RocksDbStoreConfig::allow_backfill`] is set, or if the versions of its nodes and
+    /// values are not contiguous, as [`write_node_batch`](TreeWriter::write_node_batch) checks.
+    pub fn write_tree_update_batch(&self, batch: TreeUpdateBatch) -> Result<()> {
+        self.write_tree_update_batch_ref(&batch)
+    }
+
+    /// Writes a tree update batch to the database as
+    /// [`write_tree_update_batch`](RocksDbTreeStore::write_tree_update_batch) does, borrowing it,
+    /// so that it can still be inspected or written elsewhere afterwards without cloning it.
+    pub fn write_tree_update_batch_ref(&self, batch: &TreeUpdateBatch) -> Result<()> {
+        self.write_update_batches(std::slice::from_ref(batch))
+    }
+
+    /// Writes `value_set`, keyed by raw keys rather than key hashes, as version `version` of the
+    /// tree, and returns the root hash of the version.
+    ///
+    /// The keys are hashed with the hasher of the store, the update is computed as
+    /// [`JellyfishMerkleTree::put_value_set`] does and written as
+    /// [`write_tree_update_batch`](RocksDbTreeStore::write_tree_update_batch) does, and every key
+    /// is stored as the preimage of its hash, to be returned by
+    /// [`preimage`](HasPreimage::preimage). The preimages are written first: a failed update
+    /// leaves them behind, unreferenced, and is written again in full by a retry.
+    pub fn put_raw<K: AsRef<[u8]>>(
+        &self,
+        value_set: impl IntoIterator<Item = (K, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<RootHash> {
+        let mut hashed_value_set = Vec::new();
+        for (raw_key, value) in value_set {
+            let key_hash = KeyHash::with::<H>(raw_key.as_ref());
+            self.put_key_preimage(key_hash, raw_key.as_ref())?;
+            hashed_value_set.push((key_hash, value));
+        }
+        let (root_hash, batch) =
+            JellyfishMerkleTree::<_, H>::new(self).put_value_set(hashed_value_set, version)?;
+        self.write_tree_update_batch(batch)?;
+        Ok(root_hash)
+    }
+
+    /// Writes entries already encoded as the store encodes them, as they were read from the
+    /// `nodes` and `values` column families of a store with the same codec, without decoding
+    /// them or encoding them again.
+    ///
+    /// **Advanced, for trusted input only.** This is meant for restoring data the store itself
+    /// produced, where encoding it again would only double the work. Keys are given without the
+    /// namespace of the store. Only the node keys and the roots are decoded, to index the roots and
+    /// update the latest version; the records are written as given. The caller guarantees they are
+    /// valid: a wrong encoding is not detected here, only by the reads that later fail on it, and
+    /// an inconsistent set of entries breaks the tree as a wrong batch would. Nodes must be stored
+    /// in full rather than as pointers to deduplicated contents, and the limits and expiry of
+    /// values are not enforced. Fails if [`RocksDbStoreConfig::refcount_nodes`] is set, since the
+    /// counts could not be maintained.
+    ///
+    /// The entries are written atomically, under the write lock, and every cache is cleared
+    /// afterwards. No commit callback is called.
+    pub fn write_raw_batch(
+        &self,
+        raw_nodes: Vec<(Vec<u8>, Vec<u8>)>,
+        raw_values: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        ensure!(
+            !self.config.refcount_nodes,
+            "raw batches cannot be written to a store counting node references"
+        );
+        let write_guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut write_batch = WriteBatch::default();
+        let nodes_cf = self.cf(NODES_CF)?;
+        let roots_cf = self.cf(ROOTS_CF)?;
+        let root_versions_cf = self.cf(ROOT_VERSIONS_CF)?;
+        let mut max_version = None;
+        for (key, value) in raw_nodes {
+            let node_key = decode_node_key::<C>(&key)?;
+            if node_key.nibble_path().is_empty() {
+                let root = encode_version_record(node_key.version());
+                write_batch.put_cf(roots_cf, self.key(&root), []);
+                let root_hash = self.decode_node(&value)?.hash::<H>();
+                let root_version = encode_root_version_key(root_hash, node_key.version());
+                write_batch.put_cf(root_versions_cf, self.key(&root_version), []);
+            }
+            max_version = max_version.max(Some(node_key.version()));
+            write_batch.put_cf(nodes_cf, self.key(&key), value);
+        }
+        let values_cf = self.cf(VALUES_CF)?;
+        for (key, value) in raw_values {
+            write_batch.put_cf(values_cf, self.key(&key), value);
+        }
+        if let Some(max_version) = max_version {
+            self.stage_latest_version(&mut write_batch, max_version)?;
+        }
+        self.db.write(write_batch)?;
+        self.invalidate_proofs();
+        if let Some(latest_version) = self.latest_version()? {
+            self.cache_root(&[], latest_version);
+        }
+        drop(write_guard);
+        Ok(())
+    }
+
+    /// Sets `key` to `value` at version `version`, or deletes it if `value` is `None`, and returns
+    /// the root hash of the version.
+    ///
+    /// This writes the same version as writing the batch of
+    /// [`JellyfishMerkleTree::put_value_set`] with that single entry, through
+    /// [`write_tree_update_batch`](RocksDbTreeStore::write_tree_update_batch), so that the latest
+    /// version, the caches and the write hooks are maintained alike, but computes the update
+    /// with [`JellyfishMerkleTree::put_value`], skipping the collection of a value set.
+    pub fn put_one(
+        &self,
+        key: KeyHash,
+        value: Option<OwnedValue>,
+        version: Version,
+    ) -> Result<RootHash> {
+        let (root_hash, batch) =
+            JellyfishMerkleTree::<_, H>::new(self).put_value(key, value, version)?;
+        self.write_tree_update_batch(batch)?;
+        Ok(root_hash)
+    }
+
+    /// Returns the number of entries and bytes that writing `batch` would add to the store,
+    /// without writing anything.
+    ///
+    /// The bytes are those of the keys and values of the node, value and stale node index
+    /// entries (and node refcounts with [`RocksDbStoreConfig::refcount_nodes`]), encoded as
+    /// [`write_tree_update_batch`](RocksDbTreeStore::write_tree_update_batch) does, so that
+    /// oversized batches can be rejected before they are committed. With
+    /// [`RocksDbStoreConfig::dedup_nodes`], every node counts its pointer and its content, as if
+    /// no content of the batch were stored yet.
+    pub fn estimate_batch(&self, batch: &TreeUpdateBatch) -> Result<BatchStats> {
+        let mut serialized_bytes = 0u64;
+        let namespace_bytes = self.namespace().len() as u64;
+        for (node_key, node) in batch.node_batch.nodes() {
+            let key_bytes = namespace_bytes + record::encoded_size::<C, _>(node_key)?;
+            serialized_bytes += key_bytes + record::encoded_size::<C, _>(node)?;
+            if self.config.refcount_nodes {
+                serialized_bytes += key_bytes + encode_refcount(0).len() as u64;
+            }
+            if self.config.dedup_nodes {
+                // The pointer, and the key of the content.
+                serialized_bytes +=
+                    namespace_bytes + 2 * frame(RecordKind::NodePointer, &[0; 32]).len() as u64;
+            }
+        }
+        for ((version, key_hash), value) in batch.node_batch.values() {
+            let key = encode_value_key(*key_hash, *version);
+            serialized_bytes +=
+                namespace_bytes + key.len() as u64 + self.encode_value(value)?.len() as u64;
+        }
+        for stale_node_index in &batch.stale_node_index_batch {
+            serialized_bytes +=
+                namespace_bytes + encode_stale_node_index::<C>(stale_node_index)?.len() as u64;
+        }
+        Ok(BatchStats {
+            node_count: batch.node_batch.nodes().len(),
+            value_count: batch.node_batch.values().len(),
+            stale_node_count: batch.stale_node_index_batch.len(),
+            serialized_bytes,
+        })
+    }
+
+    /// Returns the root hash the tree would have at the version after `base_version` once
+    /// `values` are written there, without writing anything.
+    ///
+    /// This is the root hash [`JellyfishMerkleTree::put_value_set`] returns for `values` at
+    /// `base_version + 1`, computed against the tree at `base_version` as stored, so that it can
+    /// be agreed on before the batch is committed. Fails with [`MissingRootError`] if
+    /// `base_version` is not readable.
+    pub fn preview_root(
+        &self,
+        base_version: Version,
+        values: Vec<(KeyHash, Option<OwnedValue>)>,
+    ) -> Result<RootHash> {
+        if self
+            .get_node_option(&NodeKey::new_empty_path(base_version))?
+            .is_none()
+        {
+            return Err(anyhow::anyhow!(MissingRootError {
+                version: base_version
+            }));
+        }
+        let version = base_version
+            .checked_add(1)
+            .ok_or_else(|| anyhow::anyhow!("no version follows version {}", base_version))?;
+        let (root_hash, _) =
+            JellyfishMerkleTree::<_, H>::new(self).put_value_set(values, version)?;
+        Ok(root_hash)
+    }
+
+    /// Starts a thread writing the batches submitted to the returned [`WriterHandle`] to this
+    /// store, with room for `capacity` batches waiting to be written.
+    ///
+    /// The thread shares the database of the store, which stays usable for reads and writes
+    /// meanwhile. Submitting blocks while `capacity` batches are queued, so that producers are
+    /// held back instead of buffering batches faster than RocksDB absorbs them.
+    pub fn spawn_writer(&self, capacity: usize) -> Result<WriterHandle>
+    where
+        C: Send + 'static,
+        H: Send + 'static,
+    {
+        WriterHandle::spawn(self.clone(), capacity)
+    }
+
+    /// Starts a thread serving the proof requests submitted to the returned [`ProofBatcher`]
+    /// from this store, coalescing those arriving within `window` of each other into one
+    /// batched pass per version.
+    ///
+    /// A longer window shares more reads between requests at the cost of their latency, each
+    /// request waiting up to `window` for others to join its batch.
+    pub fn spawn_proof_batcher(&self, window: Duration) -> Result<ProofBatcher<H>>
+    where
+        C: Send + 'static,
+        H: Send + 'static,
+    {
+        ProofBatcher::spawn(self.clone(), window)
+    }
+
+    /// Starts a [`Transaction`] staging writes on top of this store until it is committed.
+    pub fn transaction(&self) -> Transaction<'_, C, H> {
+        Transaction::new(self)
+    }
+
+    /// Writes every batch of `batches`, in order, as a single atomic RocksDB write.
+    ///
+    /// Each batch is checked against the versions written before it, those of the store and of
+    /// the previous batches, as [`write_tree_update_batch`] checks a single batch. Readers see
+    /// either none of the versions of `batches` or all of them: if any batch is rejected or fails
+    /// to encode, nothing is written. The commit callbacks are called for each batch in turn once
+    /// the write is durable.
+    ///
+    /// [`write_tree_update_batch`]: RocksDbTreeStore::write_tree_update_batch
+    pub fn commit_versions(&self, batches: Vec<TreeUpdateBatch>) -> Result<()> {
+        self.write_update_batches(&batches)
+    }
+
+    /// Writes `batch`, computed against the tree at `base_version`, or against the empty tree
+    /// for `None`, as [`write_tree_update_batch`](RocksDbTreeStore::write_tree_update_batch)
+    /// does, unless another batch was written since, in which case this fails with a
+    /// [`Conflict`] and writes nothing.
+    ///
+    /// This lets several writers compute batches from the same store concurrently without any
+    /// of them clobbering another: the latest version of the store is checked to still be
+    /// `base_version` and the batch written under a lock shared with the clones of the store, so
+    /// that of two batches computed against the same base, the second one to commit conflicts.
+    /// Its writer is then to recompute it against the new latest version and commit it again.
+    /// The check only covers the writes of this store and its clones through
+    /// [`write_tree_update_batch`](RocksDbTreeStore::write_tree_update_batch),
+    /// [`commit_versions`](RocksDbTreeStore::commit_versions) and this method.
+    pub fn commit_optimistic(
+        &self,
+        base_version: Option<Version>,
+        batch: TreeUpdateBatch,
+    ) -> Result<()> {
+        self.write_update_batches_if(std::slice::from_ref(&batch), |latest_version| {
+            if latest_version != base_version {
+                return Err(anyhow::anyhow!(Conflict {
+                    base_version,
+                    latest_version,
+                }));
+            }
+            Ok(())
+        })
+    }
+
+    /// Writes `batches` to the database at once, see
+    /// [`commit_versions`](RocksDbTreeStore::commit_versions).
+    fn write_update_batches(&self, batches: &[TreeUpdateBatch]) -> Result<()> {
+        self.write_update_batches_if(batches, |_| Ok(()))
+    }
+
+    /// Same as [`write_update_batches`](GenericTreeStore::write_update_batches), once `check`
+    /// accepts the latest version of the store, read under the write lock.
+    fn write_update_batches_if(
+        &self,
+        batches: &[TreeUpdateBatch],
+        check: impl FnOnce(Option<Version>) -> Result<()>,
+    ) -> Result<()> {
+        for batch in batches {
+            check_contiguous_versions(&batch.node_batch)?;
+        }
+        let write_guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        #[cfg(feature = "latency_histogram")]
+        let latency_started = std::time::Instant::now();
+        let mut write_batch = WriteBatch::default();
+        let stale_nodes_cf = self.cf(STALE_NODES_CF)?;
+        let mut latest_version = self.latest_version()?;
+        check(latest_version)?;
+        for batch in batches {
+            if !self.config.allow_backfill {
+                if let (Some(batch_version), Some(latest_version)) =
+                    (min_version(&batch.node_batch), latest_version)
+                {
+                    ensure!(
+                        batch_version > latest_version,
+                        "batch of version {} is not newer than the latest version {}; set \
+                         allow_backfill to write it anyway",
+                        batch_version,
+                        latest_version
+                    );
+                }
+            }
+            self.stage_node_batch(&mut write_batch, &batch.node_batch)?;
+            for stale_node_index in &batch.stale_node_index_batch {
+                write_batch.put_cf(
+                    stale_nodes_cf,
+                    self.key(&encode_stale_node_index::<C>(stale_node_index)?),
+                    [],
+                );
+            }
+            latest_version = latest_version.max(max_version(&batch.node_batch));
+        }
+        if let Some(latest_version) = latest_version {
+            self.stage_latest_version(&mut write_batch, latest_version)?;
+        }
+        let mut pending_version_metadata = self
+            .pending_version_metadata
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut committed_metadata = Vec::new();
+        for batch in batches {
+            let node_batch = &batch.node_batch;
+            if let (Some(first), Some(last)) = (min_version(node_batch), max_version(node_batch)) {
+                for (version, metadata) in pending_version_metadata.range(first..=last) {
+                    write_batch.put_cf(
+                        self.cf(VERSION_METADATA_CF)?,
+                        self.key(&encode_version_record(*version)),
+                        frame(RecordKind::VersionMetadata, metadata),
+                    );
+                    committed_metadata.push(*version);
+                }
+            }
+        }
+        self.db.write(write_batch)?;
+        for version in committed_metadata {
+            pending_version_metadata.remove(&version);
+        }
+        drop(pending_version_metadata);
+        #[cfg(feature = "latency_histogram")]
+        self.write_latency.record(latency_started.elapsed());
+        if let Some(latest_version) = latest_version {
+            self.cache_root(batches, latest_version);
+        }
+        drop(write_guard);
+        self.invalidate_proofs_after(batches.iter().map(|batch| &batch.node_batch));
+        for batch in batches {
+            if let Some(batch_version) = max_version(&batch.node_batch) {
+                self.run_commit_hooks(batch, batch_version)?;
+            }
+        }
+        if let Some(auto_pruner) = self
+            .auto_pruner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+        {
+            let stale_nodes = batches
+                .iter()
+                .flat_map(|batch| &batch.stale_node_index_batch)
+                .map(|stale_node_index| stale_node_index.node_key.clone())
+                .collect();
+            auto_pruner.record_stale(stale_nodes);
+        }
+
+        if let Some(retain_versions) = self.config.retain_versions {
+            if let Some(latest_version) = self.latest_version()? {
+                let mut min_readable_version =
+                    (latest_version + 1).saturating_sub(retain_versions.max(1));
+                // Sealed versions are kept, as labeled versions are.
+                if let Some(sealed_version) = self.sealed_version()? {
+                    min_readable_version = min_readable_version.min(sealed_version);
+                }
+                self.prune(min_readable_version)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers `f` to be called with the version and root hash of every batch committed by
+    /// [`write_tree_update_batch`](RocksDbTreeStore::write_tree_update_batch), including through
+    /// a [`WriterHandle`] or a [`BatchAccumulator`], once the write is durable.
+    ///
+    /// Callbacks run in registration order on the thread that wrote the batch, before the write
+    /// returns, and are passed the newest version of the batch. They cannot be unregistered, and
+    /// must not register callbacks themselves.
+    pub fn on_commit(&self, f: impl Fn(Version, RootHash) + Send + Sync + 'static) {
+        self.commit_hooks
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Box::new(f));
+    }
+
+    /// Calls the commit callbacks for `version`, just written from `batch`.
+    fn run_commit_hooks(&self, batch: &TreeUpdateBatch, version: Version) -> Result<()> {
+        let hooks = self
+            .commit_hooks
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if hooks.is_empty() {
+            return Ok(());
+        }
+        let root_key = NodeKey::new_empty_path(version);
+        let root_hash = match batch.node_batch.get_node(&root_key) {
+            Some(root) => RootHash(root.hash::<H>()),
+            None => RootHash(self.get_node(&root_key)?.hash::<H>()),
+        };
+        for hook in hooks.iter() {
+            hook(version, root_hash);
+        }
+        Ok(())
+    }
+
+    /// Seals `version`, so that it stays readable for good: making it unreadable, as
+    /// [`prune`](RocksDbTreeStore::prune) and
+    /// [`set_min_readable_version`](RocksDbTreeStore::set_min_readable_version) would for any
+    /// minimum readable version above it, then fails instead, writing nothing.
+    ///
+    /// This is meant for the versions finalized by consensus, whose state must stay available.
+    /// Every version sealed stays so, so only the lowest one, the
+    /// [`sealed_version`](RocksDbTreeStore::sealed_version), is recorded. Sealing cannot be undone.
+    /// The seal is recorded under the write lock, which the prunes hold while they check it, so a
+    /// prune either completes before the seal or sees it.
+    pub fn seal_version(&self, version: Version) -> Result<()> {
+        let _write_guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self
+            .sealed_version()?
+            .is_some_and(|sealed_version| sealed_version <= version)
+        {
+            return Ok(());
+        }
+        self.db.put_cf(
+            self.metadata_cf()?,
+            self.key(SEALED_VERSION_KEY),
+            encode_version_record(version),
+        )?;
+        Ok(())
+    }
+
+    /// Returns the lowest version sealed by [`seal_version`](RocksDbTreeStore::seal_version), if
+    /// any.
+    pub fn sealed_version(&self) -> Result<Option<Version>> {
+        self.db
+            .get_cf(self.metadata_cf()?, self.key(SEALED_VERSION_KEY))?
+            .map(|bytes| decode_version_record(&bytes))
+            .transpose()
+    }
+
+    /// Fails if making the versions below `min_readable_version` unreadable would make a sealed
+    /// version unreadable.
+    fn check_unsealed(&self, min_readable_version: Version) -> Result<()> {
+        if let Some(sealed_version) = self.sealed_version()? {
+            ensure!(
+                min_readable_version <= sealed_version,
+                "version {} is sealed and cannot be made unreadable by a minimum readable version \
+                 of {}",
+                sealed_version,
+                min_readable_version
+            );
+        }
+        Ok(())
+    }
+
+    /// Removes the nodes that are only needed to read versions older than
+    /// `min_readable_version`.
+    ///
+    /// A node is removed once it has become stale at or before `min_readable_version`, unless a
+    /// labeled version still reaches it (see [`set_label`](RocksDbTreeStore::set_label)): labeled
+    /// versions stay fully readable however far below `min_readable_version` they are. The stale
+    /// index entries of pinned nodes are kept, so the nodes are removed by the first prune after
+    /// their labels are gone. Values are not pruned, see
+    /// [`compact_tombstones`](RocksDbTreeStore::compact_tombstones).
+    ///
+    /// With [`RocksDbStoreConfig::refcount_nodes`], each stale index entry removed only drops the
+    /// count of its node by one, and the node is deleted when the count reaches zero. The prune
+    /// holds the write lock throughout, so that the counts it reads are not changed by a write
+    /// or another prune before it writes them back.
+    pub fn prune(&self, min_readable_version: Version) -> Result<()> {
+        let _write_guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.check_unsealed(min_readable_version)?;
+        let labeled_versions = self.labeled_versions()?;
+        let nodes_cf = self.cf(NODES_CF)?;
+        let stale_nodes_cf = self.cf(STALE_NODES_CF)?;
+        let refcounts_cf = self.cf(REFCOUNTS_CF)?;
+        let roots_cf = self.cf(ROOTS_CF)?;
+        let root_versions_cf = self.cf(ROOT_VERSIONS_CF)?;
+        // Counts already decremented by this prune, which the database does not reflect yet.
+        let mut refcounts: HashMap<Vec<u8>, u64> = HashMap::new();
+        let mut write_batch = WriteBatch::default();
+        for item in self.iterator(STALE_NODES_CF)? {
+            let (key, _) = item?;
+            let stale_node_index = decode_stale_node_index::<C>(self.unprefixed(&key))?;
+            if stale_node_index.stale_since_version > min_readable_version {
+                break;
+            }
+            // The node is part of the trees of versions `node_key.version()` up to, but
+            // excluding, `stale_since_version`.
+            let node_key = &stale_node_index.node_key;
+            if labeled_versions
+                .range(node_key.version()..stale_node_index.stale_since_version)
+                .next()
+                .is_some()
+            {
+                continue;
+            }
+            write_batch.delete_cf(stale_nodes_cf, key);
+            if node_key.nibble_path().is_empty() {
+                let root = encode_version_record(node_key.version());
+                write_batch.delete_cf(roots_cf, self.key(&root));
+                if let Some(root_node) = self.get_node_option(node_key)? {
+                    let root_version =
+                        encode_root_version_key(root_node.hash::<H>(), node_key.version());
+                    write_batch.delete_cf(root_versions_cf, self.key(&root_version));
+                }
+            }
+            let node_key = self.key(&encode_node_key::<C>(node_key)?);
+            if self.config.refcount_nodes {
+                let refcount = match refcounts.get(&node_key) {
+                    Some(refcount) => *refcount,
+                    None => self.node_refcount(&node_key)?.unwrap_or(1),
+                };
+                let refcount = refcount.saturating_sub(1);
+                refcounts.insert(node_key.clone(), refcount);
+                if refcount > 0 {
+                    write_batch.put_cf(refcounts_cf, &node_key, encode_refcount(refcount));
+                    continue;
+                }
+                write_batch.delete_cf(refcounts_cf, &node_key);
+            }
+            write_batch.delete_cf(nodes_cf, node_key);
+        }
+        let version_metadata_cf = self.cf(VERSION_METADATA_CF)?;
+        for item in self.iterator(VERSION_METADATA_CF)? {
+            let (key, _) = item?;
+            let version = decode_version_record(self.unprefixed(&key))?;
+            if version >= min_readable_version {
+                break;
+            }
+            if !labeled_versions.contains(&version) {
+                write_batch.delete_cf(version_metadata_cf, key);
+            }
+        }
+        self.db.write(write_batch)?;
+        self.invalidate_proofs();
+        Ok(())
+    }
+
+    /// Prunes the store so that only the `k` newest of its
+    /// [`committed_versions`](RocksDbTreeStore::committed_versions) stay readable, as
+    /// [`prune`](RocksDbTreeStore::prune) does for the oldest of them, and returns the number of
+    /// versions removed.
+    ///
+    /// A `k` of 0 is treated like 1, since the latest version is always kept, and a `k` of at
+    /// least the number of versions in the store prunes nothing. Labeled versions are kept
+    /// regardless, and not counted as removed.
+    pub fn prune_keep_last(&self, k: u64) -> Result<usize> {
+        let versions = self.committed_versions()?.collect::<Result<Vec<_>>>()?;
+        let kept = usize::try_from(k.max(1)).unwrap_or(usize::MAX);
+        if kept >= versions.len() {
+            return Ok(0);
+        }
+        self.prune(versions[versions.len() - kept])?;
+        Ok(versions.len() - self.committed_versions()?.count())
+    }
+
+    /// Makes the versions below `min_readable_version` unreadable, leaving the removal of the
+    /// nodes only they need to the compaction filter of [`RocksDbStoreConfig::lazy_pruning`].
+    ///
+    /// The nodes are those [`prune`](RocksDbTreeStore::prune) would delete, labeled versions and
+    /// refcounts included, and they disappear from the database as compactions rewrite the files
+    /// holding them. The version is persisted, so the filter keeps dropping the same nodes after
+    /// the store is reopened; the stale index entries of the nodes are left for a later `prune`
+    /// to clear. Fails if lazy pruning is not enabled.
+    pub fn set_min_readable_version(&self, min_readable_version: Version) -> Result<()> {
+        // Under the write lock, a version cannot be sealed between the check and the write.
+        let _write_guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.check_unsealed(min_readable_version)?;
+        let reclaimable_nodes = self
+            .reclaimable_nodes
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("lazy pruning is not enabled for this store"))?;
+        let keys = self.reclaimable_node_keys(min_readable_version)?;
+        self.db.put_cf(
+            self.metadata_cf()?,
+            self.key(MIN_READABLE_VERSION_KEY),
+            encode_version_record(min_readable_version),
+        )?;
+        reclaimable_nodes.replace(keys);
+        self.invalidate_proofs();
+        Ok(())
+    }
+
+    /// Sets the version the background prunes of [`RocksDbStoreConfig::auto_prune_stale_bytes`]
+    /// prune to, starting them on the first call.
+    ///
+    /// The versions below `least_readable_version` become unreadable at the next automatic
+    /// prune, which happens once enough nodes have gone stale since the previous one. The version
+    /// is not persisted, and must be set again after the store is reopened for automatic prunes
+    /// to resume. Fails if `least_readable_version` is above a sealed version, or if automatic
+    /// pruning is not enabled.
+    pub fn set_least_readable_version(&self, least_readable_version: Version) -> Result<()>
+    where
+        C: Send + 'static,
+        H: Send + 'static,
+    {
+        self.check_unsealed(least_readable_version)?;
+        let threshold = self
+            .config
+            .auto_prune_stale_bytes
+            .ok_or_else(|| anyhow::anyhow!("automatic pruning is not enabled for this store"))?;
+        let mut auto_pruner = self
+            .auto_pruner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match auto_pruner.as_ref() {
+            Some(auto_pruner) => auto_pruner.set_least_readable_version(least_readable_version),
+            None => {
+                // The thread gets a store of its own, which would otherwise keep the pruner, and
+                // so itself, alive.
+                let mut store = self.clone();
+                store.auto_pruner = Arc::default();
+                *auto_pruner = Some(AutoPruner::spawn(store, threshold, least_readable_version)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the keys, under the namespace of the store, of the nodes that
+    /// [`prune`](RocksDbTreeStore::prune) would delete for `min_readable_version`.
+    fn reclaimable_node_keys(&self, min_readable_version: Version) -> Result<HashSet<Vec<u8>>> {
+        let labeled_versions = self.labeled_versions()?;
+        // The number of stale index entries of each node, which refcounts are compared to.
+        let mut stale_counts: HashMap<Vec<u8>, u64> = HashMap::new();
+        for item in self.iterator(STALE_NODES_CF)? {
+            let (key, _) = item?;
+            let stale_node_index = decode_stale_node_index::<C>(self.unprefixed(&key))?;
+            if stale_node_index.stale_since_version > min_readable_version {
+                break;
+            }
+            let node_key = &stale_node_index.node_key;
+            if labeled_versions
+                .range(node_key.version()..stale_node_index.stale_since_version)
+                .next()
+                .is_none()
+            {
+                *stale_counts
+                    .entry(self.key(&encode_node_key::<C>(node_key)?))
+                    .or_default() += 1;
+            }
+        }
+        let mut keys = HashSet::new();
+        for (node_key, stale_count) in stale_counts {
+            if !self.config.refcount_nodes
+                || stale_count >= self.node_refcount(&node_key)?.unwrap_or(1)
+            {
+                keys.insert(node_key);
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Returns the number of writes counted for the node stored under `node_key`, its encoded key
+    /// under the namespace of the store, or `None` if it has no count.
+    fn node_refcount(&self, node_key: &[u8]) -> Result<Option<u64>> {
+        match self.db.get_cf(self.cf(REFCOUNTS_CF)?, node_key)? {
+            Some(record) => {
+                let bytes = unframe(RecordKind::Refcount, &record)?;
+                let bytes: [u8; 8] = bytes.try_into().map_err(|_| {
+                    anyhow::anyhow!("corrupted node refcount: {}", hex::encode(bytes))
+                })?;
+                Ok(Some(u64::from_be_bytes(bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Physically removes the value entries of keys whose latest entry is a deletion older than
+    /// `before`, and returns the number of keys removed.
+    ///
+    /// Setting and then deleting a key otherwise leaves its value entries behind forever. Once the
+    /// deletion is older than `before`, every version from `before` on reads the key as absent,
+    /// which it still does without any entry. `before` is meant to be the pruning watermark
+    /// passed to [`prune`](RocksDbTreeStore::prune): versions below it lose the values of the
+    /// compacted keys, except labeled versions, whose reads are preserved by leaving alone the
+    /// keys they could still read a value of. A key that was set again after its deletion has a
+    /// live latest entry and is never compacted. Fails, removing nothing, if `before` would make
+    /// a sealed version lose values.
+    pub fn compact_tombstones(&self, before: Version) -> Result<usize> {
+        let _write_guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.check_unsealed(before)?;
+        let labeled_versions = self.labeled_versions()?;
+        let values_cf = self.cf(VALUES_CF)?;
+        let mut write_batch = WriteBatch::default();
+        let mut compacted = 0;
+
+        // The entries of a key are adjacent and sorted by version, so each run of entries
+        // sharing a key hash ends with the latest one.
+        let mut entries: Vec<(Version, Box<[u8]>)> = Vec::new();
+        let mut current_key_hash = None;
+        let mut iter = self.iterator(VALUES_CF)?.peekable();
+        while let Some(item) = iter.next() {
+            let (key, value) = item?;
+            let (key_hash, version) = decode_value_key(self.unprefixed(&key))?;
+            if current_key_hash != Some(key_hash) {
+                entries.clear();
+                current_key_hash = Some(key_hash);
+            }
+            entries.push((version, key));
+            let is_last_entry = match iter.peek() {
+                Some(Ok((next_key, _))) => !self
+                    .unprefixed(next_key)
+                    .starts_with(&value_key_prefix(key_hash)),
+                _ => true,
+            };
+            if !is_last_entry {
+                continue;
+            }
+
+            let is_tombstone = decode_value::<C>(&value)?.is_none();
+            if !is_tombstone || version >= before {
+                continue;
+            }
+            // A labeled version between the first entry and the deletion may still read a value.
+            let first_version = entries[0].0;
+            if labeled_versions
+                .range(first_version..version)
+                .next()
+                .is_some()
+            {
+                continue;
+            }
+            for (_, key) in entries.drain(..) {
+                write_batch.delete_cf(values_cf, key);
+            }
+            compacted += 1;
+        }
+        self.db.write(write_batch)?;
+        self.invalidate_proofs();
+        Ok(compacted)
+    }
+
+    /// Removes the value entries of `key_hash` older than the one read at
+    /// `least_readable_version`, and returns the number of entries removed.
+    ///
+    /// A key updated at many versions otherwise keeps every one of its values until a global
+    /// [`prune`](RocksDbTreeStore::prune), which only removes nodes. The entry read at
+    /// `least_readable_version`, the newest one at or below it, and every newer one are kept, so
+    /// that reads of the key at `least_readable_version` and above are unchanged, while older
+    /// versions lose its value, except labeled versions, whose entries are kept as
+    /// [`compact_tombstones`](RocksDbTreeStore::compact_tombstones) keeps them. Fails, removing
+    /// nothing, if `least_readable_version` would make a sealed version unreadable.
+    pub fn compact_key_history(
+        &self,
+        key_hash: KeyHash,
+        least_readable_version: Version,
+    ) -> Result<usize> {
+        self.check_unsealed(least_readable_version)?;
+        let labeled_versions = self.labeled_versions()?;
+        let versions = self
+            .history_of(key_hash)?
+            .map(|entry| entry.map(|(version, _)| version))
+            .collect::<Result<Vec<_>>>()?;
+        let values_cf = self.cf(VALUES_CF)?;
+        let mut write_batch = WriteBatch::default();
+        let mut removed = 0;
+        // Each entry is read by the versions from its own up to that of the next entry.
+        for pair in versions.windows(2) {
+            let (version, next_version) = (pair[0], pair[1]);
+            if next_version > least_readable_version
+                || labeled_versions
+                    .range(version..next_version)
+                    .next()
+                    .is_some()
+            {
+                continue;
+            }
+            write_batch.delete_cf(values_cf, self.key(&encode_value_key(key_hash, version)));
+            removed += 1;
+        }
+        self.db.write(write_batch)?;
+        self.invalidate_proofs();
+        Ok(removed)
+    }
+
+    /// Merges `delta` into the value of `key_hash` written at `version`, with the
+    /// [`value_merge_operator`](RocksDbStoreConfig::value_merge_operator) of the store, without
+    /// reading the value.
+    ///
+    /// The delta is applied to the value entry of `key_hash` at exactly `version`, starting from
+    /// no value if there is none or if it is a deletion, once the value is read. Reads at later
+    /// versions see it as long as no later version writes the key. The merged value bypasses the
+    /// tree, and proofs do not verify against it, see
+    /// [`find_orphan_values`](RocksDbTreeStore::find_orphan_values) to find such values. Fails
+    /// if the store has no merge operator.
+    pub fn merge_value(&self, key_hash: KeyHash, version: Version, delta: &[u8]) -> Result<()> {
+        ensure!(
+            self.config.value_merge_operator.is_some(),
+            "the store has no value merge operator to merge with"
+        );
+        self.db.merge_cf(
+            self.cf(VALUES_CF)?,
+            self.key(&encode_value_key(key_hash, version)),
+            delta,
+        )?;
+        self.invalidate_proofs();
+        Ok(())
+    }
+
+    /// Adds the nodes and values of `node_batch` to `write_batch`.
+    ///
+    /// Fails if a value is longer than [`RocksDbStoreConfig::max_value_size`], in which case
+    /// `write_batch` is to be discarded.
+    fn stage_node_batch(&self, write_batch: &mut WriteBatch, node_batch: &NodeBatch) -> Result<()> {
+        self.stage_node_batch_with_dedup(write_batch, node_batch, self.config.dedup_nodes)
+    }
+
+    /// Same as [`stage_node_batch`](GenericTreeStore::stage_node_batch), storing the nodes once
+    /// per distinct content as with [`RocksDbStoreConfig::dedup_nodes`] if `dedup` is set,
+    /// whatever the configuration.
+    fn stage_node_batch_with_dedup(
+        &self,
+        write_batch: &mut WriteBatch,
+        node_batch: &NodeBatch,
+        dedup: bool,
+    ) -> Result<()> {
+        // Write nodes
+        let nodes_cf = self.cf(NODES_CF)?;
+        let refcounts_cf = self.cf(REFCOUNTS_CF)?;
+        let roots_cf = self.cf(ROOTS_CF)?;
+        let root_versions_cf = self.cf(ROOT_VERSIONS_CF)?;
+        let contents_cf = self.cf(NODE_CONTENTS_CF)?;
+        if self.config.refcount_nodes {
+            write_batch.delete_cf(self.metadata_cf()?, self.key(LAST_NODE_BATCH_KEY));
+        }
+        // The contents staged by this call, which the database does not hold yet.
+        let mut staged_contents = HashSet::new();
+        // Entries are staged in the order of their encoded keys rather than that of the maps of
+        // the batch, which differs, so that the write batch follows the order RocksDB stores
+        // them in and is the same for the same batch.
+        let mut nodes = node_batch
+            .nodes()
+            .iter()
+            .map(|(node_key, node)| {
+                Ok((self.key(&encode_node_key::<C>(node_key)?), node_key, node))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        nodes.sort_unstable_by(|(a, _, _), (b, _, _)| a.cmp(b));
+        for (key, node_key, node) in nodes {
+            if node_key.nibble_path().is_empty() {
+                let root = encode_version_record(node_key.version());
+                write_batch.put_cf(roots_cf, self.key(&root), []);
+                let root_version = encode_root_version_key(node.hash::<H>(), node_key.version());
+                write_batch.put_cf(root_versions_cf, self.key(&root_version), []);
+            }
+            let mut value = match self.inline_value(node_batch, node_key, node) {
+                Some(inline_value) => {
+                    record::encode::<C, _>(RecordKind::InlineLeaf, &(node, inline_value))?
+                }
+                None => record::encode::<C, _>(RecordKind::Node, node)?,
+            };
+            if self.config.refcount_nodes {
+                let refcount = self.node_refcount(&key)?.unwrap_or(0) + 1;
+                write_batch.put_cf(refcounts_cf, &key, encode_refcount(refcount));
+            }
+            if dedup {
+                let content_hash = H::hash(&value);
+                let content_key = self.key(&frame(RecordKind::ContentHash, &content_hash));
+                if staged_contents.insert(content_hash)
+                    && self.db.get_pinned_cf(contents_cf, &content_key)?.is_none()
+                {
+                    write_batch.put_cf(contents_cf, content_key, value);
+                }
+                value = frame(RecordKind::NodePointer, &content_hash);
+            }
+            write_batch.put_cf(nodes_cf, key, value);
+        }
+
+        // Write values
+        let values_cf = self.cf(VALUES_CF)?;
+        let mut values: Vec<_> = node_batch
+            .values()
+            .iter()
+            .map(|(&(version, key_hash), value)| {
+                let key = self.key(&encode_value_key(key_hash, version));
+                (key, version, key_hash, value)
+            })
+            .collect();
+        values.sort_unstable_by(|(a, ..), (b, ..)| a.cmp(b));
+        for (key, version, key_hash, value) in values {
+            if let (Some(value), Some(max_value_size)) = (value, self.config.max_value_size) {
+                ensure!(
+                    value.len() <= max_value_size,
+                    "value of key {} at version {} is {} bytes long, more than the maximum of {} \
+                     bytes; nothing of the batch was written",
+                    hex::encode(key_hash.0),
+                    version,
+                    value.len(),
+                    max_value_size
+                );
+            }
+            let serialized_value = self.encode_value(value)?;
+            write_batch.put_cf(values_cf, key, serialized_value);
+        }
+        Ok(())
+    }
+
+    /// Returns the value written along with `node`, at `node_key` in `node_batch`, if it is a
+    /// leaf to be stored with its value inline, see [`RocksDbStoreConfig::inline_values_up_to`].
+    fn inline_value<'a>(
+        &self,
+        node_batch: &'a NodeBatch,
+        node_key: &NodeKey,
+        node: &Node,
+    ) -> Option<&'a OwnedValue> {
+        let (Some(threshold), None, Node::Leaf(leaf_node)) =
+            (self.config.inline_values_up_to, self.config.value_ttl, node)
+        else {
+            return None;
+        };
+        let value = node_batch
+            .values()
+            .get(&(node_key.version(), leaf_node.key_hash()))?
+            .as_ref()?;
+        (value.len() <= threshold).then_some(value)
+    }
+
+    /// Encodes `value` as a record of [`VALUES_CF`], compressed with
+    /// [`RocksDbStoreConfig::compress_values_over`] and stamped with the current time with
+    /// [`RocksDbStoreConfig::value_ttl`].
+    fn encode_value(&self, value: &Option<OwnedValue>) -> Result<Vec<u8>> {
+        let mut payload = C::encode(value)?;
+        let mut kind = RecordKind::Value;
+        if let (Some(value), Some(threshold)) = (value, self.config.compress_values_over) {
+            if value.len() > threshold {
+                payload = zstd::encode_all(payload.as_slice(), 0)?;
+                kind = RecordKind::CompressedValue;
+            }
+        }
+        Ok(ttl::frame_expiring(kind, &payload, self.config.value_ttl))
+    }
+
+    /// Adds an update of the latest version to `write_batch` if `version` is higher than the
+    /// latest version currently stored, so that batches written out of order never move it back.
+    fn stage_latest_version(&self, write_batch: &mut WriteBatch, version: Version) -> Result<()> {
+        if self.latest_version()?.is_none_or(|latest| version > latest) {
+            write_batch.put_cf(
+                self.metadata_cf()?,
+                self.key(LATEST_VERSION_KEY),
+                encode_version_record(version),
+            );
+        }
+        Ok(())
+    }
+    
+    /// Returns the value of `key` at `version` and the proof of it, as
+    /// [`JellyfishMerkleTree::get_with_proof`] does, serving repeated queries from the proof cache
+    /// when [`RocksDbStoreConfig::proof_cache_capacity`] is set, and repeated queries for absent
+    /// keys from the absence cache when [`RocksDbStoreConfig::absence_cache_capacity`] is set.
+    ///
+    /// [`JellyfishMerkleTree::get_with_proof`]: crate::JellyfishMerkleTree::get_with_proof
+    pub fn get_with_proof(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<(Option<OwnedValue>, SparseMerkleProof<H>)> {
+        if let Some(cache) = &self.absence_cache {
+            if let Some(cached) = cache.get(key, version) {
+                return Ok((None, C::decode(&cached)?));
+            }
+        }
+        let value_and_proof = match &self.proof_cache {
+            Some(cache) => match cache.get(key, version) {
+                Some(cached) => return C::decode(&cached),
+                None => {
+                    let value_and_proof =
+                        JellyfishMerkleTree::<_, H>::new(self).get_with_proof(key, version)?;
+                    cache.insert(key, version, C::encode(&value_and_proof)?);
+                    value_and_proof
+                }
+            },
+            None => JellyfishMerkleTree::<_, H>::new(self).get_with_proof(key, version)?,
+        };
+        if let (None, Some(cache)) = (&value_and_proof.0, &self.absence_cache) {
+            cache.insert(key, version, C::encode(&value_and_proof.1)?);
+        }
+        Ok(value_and_proof)
+    }
+
+    /// Same as [`get_with_proof`](GenericTreeStore::get_with_proof), with the proof encoded as
+    /// JSON by [`SparseMerkleProof::to_json`], for verifiers that do not read bincode.
+    #[cfg(feature = "json")]
+    pub fn get_with_proof_json(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<(Option<OwnedValue>, String)> {
+        let (value, proof) = self.get_with_proof(key, version)?;
+        Ok((value, proof.to_json()))
+    }
+
+    /// Same as [`get_with_proof`](GenericTreeStore::get_with_proof) at the
+    /// [`latest_version`](GenericTreeStore::latest_version) of the store.
+    ///
+    /// Fails if nothing has been written to the store yet.
+    pub fn get_latest_with_proof(
+        &self,
+        key: KeyHash,
+    ) -> Result<(Option<OwnedValue>, SparseMerkleProof<H>)> {
+        let version = self
+            .latest_version()?
+            .ok_or_else(|| anyhow::anyhow!("no version has been written to the store"))?;
+        self.get_with_proof(key, version)
+    }
+
+    /// Returns the value of `key` at `version` and the proof of it, as
+    /// [`get_with_proof`](GenericTreeStore::get_with_proof) does, checked against
+    /// `expected_root`, a root of `version` trusted from elsewhere.
+    ///
+    /// Fails with a [`RootMismatch`] if the root of the store at `version` is not
+    /// `expected_root`, telling a store that diverged from the trusted history apart from a
+    /// proof that does not verify, which fails with the error of
+    /// [`SparseMerkleProof::verify`].
+    pub fn get_with_proof_against(
+        &self,
+        key: KeyHash,
+        version: Version,
+        expected_root: RootHash,
+    ) -> Result<ProofResult<H>> {
+        let (value, proof) = self.get_with_proof(key, version)?;
+        let actual = JellyfishMerkleTree::<_, H>::new(self).get_root_hash(version)?;
+        if actual != expected_root {
+            return Err(anyhow::anyhow!(RootMismatch {
+                version,
+                expected: expected_root,
+                actual,
+            }));
+        }
+        proof.verify(expected_root, key, value.as_ref())?;
+        Ok((value, proof))
+    }
+
+    /// Empties the proof and node caches, if any, after a write that may change what they hold.
+    fn invalidate_proofs(&self) {
+        if let Some(cache) = &self.proof_cache {
+            cache.clear();
+        }
+        if let Some(cache) = &self.absence_cache {
+            cache.clear();
+        }
+        if let Some(cache) = &self.node_cache {
+            cache.clear();
+        }
+    }
+
+    /// Same as [`invalidate_proofs`](GenericTreeStore::invalidate_proofs) after writing
+    /// `node_batches`, but for the proofs of absence of the keys they do not write, at versions
+    /// newer than those they write, and the nodes they do not overwrite, which stay valid.
+    fn invalidate_proofs_after<'a>(&self, node_batches: impl IntoIterator<Item = &'a NodeBatch>) {
+        if let Some(cache) = &self.proof_cache {
+            cache.clear();
+        }
+        let node_batches: Vec<_> = node_batches.into_iter().collect();
+        if let Some(cache) = &self.node_cache {
+            cache.remove(node_batches.iter().flat_map(|batch| batch.nodes().keys()));
+        }
+        let Some(cache) = &self.absence_cache else {
+            return;
+        };
+        let mut written_keys = HashSet::new();
+        let mut max_written_version = None;
+        for node_batch in node_batches {
+            for (version, key_hash) in node_batch.values().keys() {
+                written_keys.insert(*key_hash);
+                max_written_version = max_written_version.max(Some(*version));
+            }
+            for node_key in node_batch.nodes().keys() {
+                max_written_version = max_written_version.max(Some(node_key.version()));
+            }
+        }
+        // A version newer than a proof makes it stale, as does the key becoming present.
+        cache.retain(|key, version| {
+            Some(version) > max_written_version && !written_keys.contains(&key)
+        });
+    }
+
+    /// Looks up every key in `keys` at `version` and returns the value and proof for each, in the
+    /// same order as `keys`.
+    ///
+    /// This is equivalent to calling [`JellyfishMerkleTree::get_with_proof`] once per key, but the
+    /// nodes the lookups need are prefetched level by level: every internal node on the path of
+    /// at least one key is read once, together with all of its children, in a single
+    /// `multi_get` per tree level. Paths that share a prefix therefore share the reads of their
+    /// common ancestors and of the siblings the proofs are built from.
+    ///
+    /// [`JellyfishMerkleTree::get_with_proof`]: crate::JellyfishMerkleTree::get_with_proof
+    pub fn get_with_proof_batch(
+        &self,
+        keys: &[KeyHash],
+        version: Version,
+    ) -> Result<Vec<(Option<OwnedValue>, SparseMerkleProof<H>)>> {
+        let reader = self.prefetch_paths(keys, version)?;
+        let tree: JellyfishMerkleTree<_, H> = JellyfishMerkleTree::new(&reader);
+        keys.iter()
+            .map(|key| tree.get_with_proof(*key, version))
+            .collect()
+    }
+
+    /// Reads the nodes of the tree at `version` down to `depth` nibbles below the root, level by
+    /// level, and returns the number of nodes read.
+    ///
+    /// The nodes are discarded: reading them brings their blocks into the block cache and the page
+    /// cache of the OS, so that the first lookups after startup do not pay for cold reads of the
+    /// upper levels, which every lookup goes through. Each level is read with a single
+    /// `multi_get`. A `depth` of 0 reads the root alone, and a `depth` beyond the tree reads all
+    /// of it. Fails with [`MissingRootError`] if `version` is not readable.
+    pub fn warm_cache(&self, version: Version, depth: usize) -> Result<usize> {
+        let root_key = NodeKey::new_empty_path(version);
+        let root = self
+            .get_node_option(&root_key)?
+            .ok_or_else(|| anyhow::anyhow!(MissingRootError { version }))?;
+        let mut touched = 1;
+        let mut level = vec![(root_key, root)];
+        for _ in 0..depth {
+            let mut child_keys = Vec::new();
+            for (node_key, node) in &level {
+                if let Node::Internal(internal_node) = node {
+                    for (nibble, child) in internal_node.children_sorted() {
+                        child_keys.push(node_key.gen_child_node_key(child.version, nibble));
+                    }
+                }
+            }
+            if child_keys.is_empty() {
+                break;
+            }
+            let children = self.multi_get_nodes(&child_keys)?;
+            level = child_keys
+                .into_iter()
+                .zip(children)
+                .filter_map(|(child_key, child)| Some((child_key, child?)))
+                .collect();
+            touched += level.len();
+        }
+        Ok(touched)
+    }
+
+    /// Reads the nodes on the paths of `keys` at `version`, and all of their children, into a
+    /// [`PrefetchedReader`].
+    fn prefetch_paths(
+        &self,
+        keys: &[KeyHash],
+        version: Version,
+    ) -> Result<PrefetchedReader<'_, C, H>> {
+        let root_key = NodeKey::new_empty_path(version);
+        let mut nodes = HashMap::new();
+        let root = self.get_node_option(&root_key)?;
+        nodes.insert(root_key.clone(), root);
+
+        let nibble_paths: Vec<NibblePath> = keys
+            .iter()
+            .map(|key| NibblePath::new(key.0.to_vec()))
+            .collect();
+        let mut cursors: Vec<Option<NodeKey>> = vec![Some(root_key); keys.len()];
+
+        for depth in 0..ROOT_NIBBLE_HEIGHT {
+            let mut to_fetch = BTreeSet::new();
+            for (cursor, nibble_path) in cursors.iter_mut().zip(&nibble_paths) {
+                let node_key = match cursor.take() {
+                    Some(node_key) => node_key,
+                    None => continue,
+                };
+                if let Some(Some(Node::Internal(internal_node))) = nodes.get(&node_key) {
+                    for (nibble, child) in internal_node.children_sorted() {
+                        let child_key = node_key.gen_child_node_key(child.version, nibble);
+                        if !nodes.contains_key(&child_key) {
+                            to_fetch.insert(child_key);
+                        }
+                    }
+                    *cursor = internal_node
+                        .get_only_child_without_siblings(&node_key, nibble_path.get_nibble(depth));
+                }
+            }
+            if cursors.iter().all(Option::is_none) {
+                break;
+            }
+
+            let to_fetch: Vec<NodeKey> = to_fetch.into_iter().collect();
+            let fetched = self.multi_get_nodes(&to_fetch)?;
+            nodes.extend(to_fetch.into_iter().zip(fetched));
+        }
+
+        Ok(PrefetchedReader { store: self, nodes })
+    }
+
+    /// Reads the nodes stored under `node_keys` with a single `multi_get`.
+    fn multi_get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        let keys = node_keys
+            .iter()
+            .map(|node_key| Ok(self.key(&encode_node_key::<C>(node_key)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let nodes_cf = self.cf(NODES_CF)?;
+        self.db
+            .multi_get_cf(keys.iter().map(|key| (nodes_cf, key)))
+            .into_iter()
+            .map(|value| match value? {
+                Some(value) => Ok(Some(self.decode_node(&value)?)),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    /// Returns the node stored under `node_key` together with the length in bytes of the record
+    /// it was decoded from, or `None` if there is no such node.
+    ///
+    /// The length is that of the record as written, header included and before RocksDB
+    /// compresses it, so that caches can be sized without encoding the node again. For a node
+    /// written with [`RocksDbStoreConfig::dedup_nodes`], it is the length of the content the
+    /// node points to, not of the pointer.
+    pub fn get_node_sized(&self, node_key: &NodeKey) -> Result<Option<(Node, usize)>> {
+        let raw_key = encode_node_key::<C>(node_key)?;
+        let record = match self.db.get_cf(self.cf(NODES_CF)?, self.key(&raw_key))? {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+        let mut size = record.len();
+        let contents_cf = self.cf(NODE_CONTENTS_CF)?;
+        let node = decode_node_record::<C>(&record, |key| {
+            let content = self.db.get_cf(contents_cf, self.key(key))?;
+            if let Some(content) = &content {
+                size = content.len();
+            }
+            Ok(content)
+        });
+        let node = self.check_decoded(CfKind::Nodes, &raw_key, node)?;
+        Ok(Some((node, size)))
+    }
+
+    /// Returns whether a node is stored under `node_key`, without reading or decoding it when
+    /// RocksDB can tell it is absent.
+    ///
+    /// [`DB::key_may_exist`] answers from the memtables and the bloom filters of
+    /// [`RocksDbStoreConfig::bloom_filter_bits`], and is only confirmed by a read of the record
+    /// when it cannot rule the key out. The record is never decoded, nor its content read with
+    /// [`RocksDbStoreConfig::dedup_nodes`].
+    pub fn has_node(&self, node_key: &NodeKey) -> Result<bool> {
+        let nodes_cf = self.cf(NODES_CF)?;
+        let key = self.key(&encode_node_key::<C>(node_key)?);
+        if !self.db.key_may_exist_cf(nodes_cf, &key) {
+            return Ok(false);
+        }
+        Ok(self.db.get_pinned_cf(nodes_cf, &key)?.is_some())
+    }
+
+    /// Decodes `record`, read from [`NODES_CF`], reading the content it points to if it is a
+    /// pointer written with [`RocksDbStoreConfig::dedup_nodes`].
+    fn decode_node(&self, record: &[u8]) -> Result<Node> {
+        Ok(self.decode_node_with_value(record)?.0)
+    }
+
+    /// Reads the node of `node_key` from the database, with its value if it is inline.
+    fn read_node(&self, node_key: &NodeKey) -> Result<Option<NodeWithValue>> {
+        if let Some(cache) = &self.node_cache {
+            if let Some(node) = cache.get(node_key) {
+                return Ok(Some(node));
+            }
+        }
+        let raw_key = encode_node_key::<C>(node_key)?;
+        let key = self.key(&raw_key);
+        let started = trace::start();
+        let value = self.db.get_cf(self.cf(NODES_CF)?, &key)?;
+        trace::read(
+            "get_node_option",
+            key.len(),
+            value.as_ref().map(Vec::len),
+            started,
+        );
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        let node =
+            self.check_decoded(CfKind::Nodes, &raw_key, self.decode_node_with_value(&value))?;
+        // A node missing now may be written later, so only the nodes found are cached.
+        if let Some(cache) = &self.node_cache {
+            cache.insert(node_key, node.clone());
+        }
+        Ok(Some(node))
+    }
+
+    /// Same as [`decode_node`](GenericTreeStore::decode_node), also returning the value of the
+    /// node if it is inline.
+    fn decode_node_with_value(&self, record: &[u8]) -> Result<NodeWithValue> {
+        let contents_cf = self.cf(NODE_CONTENTS_CF)?;
+        decode_node_record_with_value::<C>(record, |key| {
+            Ok(self.db.get_cf(contents_cf, self.key(key))?)
+        })
+    }
+
+    /// Verifies that `proof` proves `key` to have `value` (or, for `None`, to be absent) in the
+    /// tree at `version`, against the root hash persisted for that version.
+    ///
+    /// Only the root node of `version` is read. Fails with [`MissingRootError`] if `version` is
+    /// not readable, and with an error naming the version and its root hash if the proof does not
+    /// verify.
+    pub fn verify_at<V: AsRef<[u8]>>(
+        &self,
+        version: Version,
+        key: KeyHash,
+        value: Option<V>,
+        proof: &SparseMerkleProof<H>,
+    ) -> Result<()> {
+        let root = self
+            .get_node_option(&NodeKey::new_empty_path(version))?
+            .ok_or_else(|| anyhow::anyhow!(MissingRootError { version }))?;
+        let root_hash = RootHash(root.hash::<H>());
+        proof.verify(root_hash, key, value).with_context(|| {
+            format!(
+                "proof for key {} does not verify against root {} of version {}",
+                hex::encode(key.0),
+                hex::encode(root_hash.0),
+                version
+            )
+        })
+    }
+
+    /// Walks the tree at `version` from its root and reports every inconsistency found: internal
+    /// nodes referencing missing children, leaves without a readable value, and metadata that
+    /// does not account for the version.
+    ///
+    /// This is meant to be run after a crash, when a half-applied batch or a corrupted file may
+    /// have left dangling pointers behind. The walk does not stop at the first problem, and an
+    /// empty result means the tree at `version` is fully readable.
+    pub fn verify_consistency(&self, version: Version) -> Result<Vec<Inconsistency>> {
+        let root_key = NodeKey::new_empty_path(version);
+        let root = match self.get_node_option(&root_key)? {
+            Some(root) => root,
+            None => return Ok(vec![Inconsistency::MissingRoot { version }]),
+        };
+
+        let mut inconsistencies = Vec::new();
+        let latest_version = self.latest_version()?;
+        if latest_version.is_none_or(|latest| latest < version) {
+            inconsistencies.push(Inconsistency::LatestVersionBehind {
+                version,
+                latest_version,
+            });
+        }
+
+        check_subtree(self, version, root_key, root, &mut inconsistencies)?;
+        Ok(inconsistencies)
+    }
+
+    /// Walks the tree at `version` and returns the keys of the internal nodes that have a leaf as
+    /// their only child.
+    ///
+    /// A correct tree never has such a node: the leaf is moved up in its place, and removing a
+    /// sibling collapses the path the same way. An internal node whose only child is itself an
+    /// internal node is legitimate, since paths shared by several keys are not compressed, and is
+    /// not reported. Children missing from the store are skipped; see
+    /// [`verify_consistency`](RocksDbTreeStore::verify_consistency) to find those. Fails with
+    /// [`MissingRootError`] if `version` is not readable.
+    pub fn check_no_single_child_internals(&self, version: Version) -> Result<Vec<NodeKey>> {
+        let root_key = NodeKey::new_empty_path(version);
+        if self.get_node_option(&root_key)?.is_none() {
+            return Err(anyhow::anyhow!(MissingRootError { version }));
+        }
+
+        let mut offending = Vec::new();
+        let mut stack = vec![root_key];
+        while let Some(node_key) = stack.pop() {
+            let internal_node = match self.get_node_option(&node_key)? {
+                Some(Node::Internal(internal_node)) => internal_node,
+                _ => continue,
+            };
+            let children = internal_node.children_sorted().collect::<Vec<_>>();
+            if let [(_, child)] = children.as_slice() {
+                if child.is_leaf() {
+                    offending.push(node_key.clone());
+                }
+            }
+            for (nibble, child) in children {
+                if !child.is_leaf() {
+                    stack.push(node_key.gen_child_node_key(child.version, nibble));
+                }
+            }
+        }
+        Ok(offending)
+    }
+
+    /// Returns the version and key hash of every value entry read at `version` that no leaf of
+    /// the tree at `version` accounts for, in key hash order.
+    ///
+    /// The values live at `version`, the last entry of each key at or below it that is not a
+    /// deletion, are cross-referenced against the leaves reachable from the root of `version`: an
+    /// entry is reported if no leaf holds its key, or if the leaf holding it commits to another
+    /// value. Such entries are left behind by bugs or partial writes of values without their
+    /// nodes, and are served by the plain value reads while proofs disagree with them. Children
+    /// missing from the store are skipped; see
+    /// [`verify_consistency`](RocksDbTreeStore::verify_consistency) to find those, and leaves
+    /// without a value. Fails with [`MissingRootError`] if `version` is not readable.
+    pub fn find_orphan_values(&self, version: Version) -> Result<Vec<(Version, KeyHash)>> {
+        let root_key = NodeKey::new_empty_path(version);
+        if self.get_node_option(&root_key)?.is_none() {
+            return Err(anyhow::anyhow!(MissingRootError { version }));
+        }
+
+        let mut leaves = HashMap::new();
+        let mut stack = vec![root_key];
+        while let Some(node_key) = stack.pop() {
+            match self.get_node_option(&node_key)? {
+                Some(Node::Internal(internal_node)) => {
+                    for (nibble, child) in internal_node.children_unsorted() {
+                        stack.push(node_key.gen_child_node_key(child.version, nibble));
+                    }
+                }
+                Some(Node::Leaf(leaf_node)) => {
+                    leaves.insert(leaf_node.key_hash(), leaf_node.value_hash());
+                }
+                Some(Node::Null) | None => {}
+            }
+        }
+
+        let mut orphans = Vec::new();
+        self.for_each_live_value(version, |key_hash, written_version, value| {
+            if leaves.get(&key_hash) != Some(&ValueHash::with::<H>(&value)) {
+                orphans.push((written_version, key_hash));
+            }
+            Ok(())
+        })?;
+        Ok(orphans)
+    }
+
+    /// Renders the tree at `version` as a Graphviz DOT graph.
+    ///
+    /// The walk starts at the root node of `version`. Internal nodes are labelled with their nibble
+    /// path and leaf count, edges with the nibble of the child they lead to, and leaves with their
+    /// key hash and value hash. A child that is referenced by its parent but absent from the store
+    /// is drawn as a dashed `missing` node instead of aborting the export, so dangling pointers
+    /// show up in the rendered graph.
+    pub fn export_dot(&self, version: Version) -> Result<String> {
+        let root_key = NodeKey::new_empty_path(version);
+        if self.get_node_option(&root_key)?.is_none() {
+            return Err(anyhow::anyhow!(MissingRootError { version }));
+        }
+
+        let mut dot = String::from("digraph jmt {\n    node [shape=box, fontname=monospace];\n");
+        let mut stack = vec![root_key];
+        while let Some(node_key) = stack.pop() {
+            let id = dot_node_id(&node_key);
+            match self.get_node_option(&node_key)? {
+                Some(Node::Internal(internal_node)) => {
+                    writeln!(
+                        dot,
+                        "    \"{}\" [label=\"internal\\npath: {:?}\\nversion: {}\\nleaves: {}\"];",
+                        id,
+                        node_key.nibble_path(),
+                        node_key.version(),
+                        internal_node.leaf_count(),
+                    )?;
+                    let children: Vec<_> = internal_node
+                        .children_sorted()
+                        .map(|(nibble, child)| {
+                            (nibble, node_key.gen_child_node_key(child.version, nibble))
+                        })
+                        .collect();
+                    for (nibble, child_key) in &children {
+                        writeln!(
+                            dot,
+                            "    \"{}\" -> \"{}\" [label=\"{:x}\"];",
+                            id,
+                            dot_node_id(child_key),
+                            nibble,
+                        )?;
+                    }
+                    // Push in reverse so that children are visited in ascending nibble order.
+                    stack.extend(children.into_iter().rev().map(|(_, child_key)| child_key));
+                }
+                Some(Node::Leaf(leaf_node)) => {
+                    writeln!(
+                        dot,
+                        "    \"{}\" [shape=ellipse, label=\"leaf\\nkey: {}\\nvalue: {}\"];",
+                        id,
+                        hex::encode(leaf_node.key_hash().0),
+                        hex::encode(leaf_node.value_hash().0),
+                    )?;
+                }
+                Some(Node::Null) => {
+                    writeln!(dot, "    \"{}\" [shape=plaintext, label=\"empty\"];", id)?;
+                }
+                None => {
+                    writeln!(
+                        dot,
+                        "    \"{}\" [style=dashed, label=\"missing\\npath: {:?}\\nversion: {}\"];",
+                        id,
+                        node_key.nibble_path(),
+                        node_key.version(),
+                    )?;
+                }
+            }
+        }
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+
+    /// Writes the keys live at `version` to `writer` as CSV, one
+    /// `key_hash_hex,value_hex,written_version` row per key after a header row.
+    ///
+    /// Rows are sorted by key hash, and `written_version` is the version that set the value
+    /// readable at `version`. The value entries are streamed from the store, so memory use does
+    /// not grow with the size of the state. Fails with [`MissingRootError`] if `version` is not
+    /// readable.
+    pub fn export_csv(&self, version: Version, mut writer: impl io::Write) -> Result<()> {
+        writeln!(writer, "key_hash_hex,value_hex,written_version")?;
+        self.for_each_live_value(version, |key_hash, written_version, value| {
+            writeln!(
+                writer,
+                "{},{},{}",
+                hex::encode(key_hash.0),
+                hex::encode(value),
+                written_version
+            )?;
+            Ok(())
+        })?;
+        writer.flush()?;
+        Ok(())
     }
 
-    /// create a new RocksDB tree store in the current diretory and child directory name is state_db
-    pub fn default() -> Result<Self> {
-        let current_dir = std::env::current_dir()?;
-        let db_path = current_dir.join("state_db");
-        Self::new(db_path)
+    /// Returns the versions that have a root node in the store, in ascending order.
+    ///
+    /// These are the versions written and not yet pruned: a version whose root is deleted by
+    /// [`prune`](RocksDbTreeStore::prune) is no longer listed. Versions made unreadable by
+    /// [`set_min_readable_version`](RocksDbTreeStore::set_min_readable_version) stay listed until
+    /// the next prune.
+    pub fn committed_versions(&self) -> Result<impl Iterator<Item = Result<Version>> + '_> {
+        Ok(self
+            .iterator(ROOTS_CF)?
+            .map(|item| decode_version_record(self.unprefixed(&item?.0))))
     }
 
-    /// Creates a new temporary RocksDB tree store.
-    pub fn new_temporary() -> Result<Self> {
-        let temp_dir = tempfile::TempDir::new()?;
-        Self::new(temp_dir.path())
+    /// Returns whether `version` has a root node in the store, that is whether it is one of the
+    /// [`committed_versions`](RocksDbTreeStore::committed_versions).
+    ///
+    /// This is a single point read of the root index rather than a scan, cheap enough to reject a
+    /// version that was never written or is pruned before serving a request for it.
+    pub fn version_exists(&self, version: Version) -> Result<bool> {
+        let key = self.key(&encode_version_record(version));
+        Ok(self.db.get_pinned_cf(self.cf(ROOTS_CF)?, key)?.is_some())
     }
-}
 
-impl TreeReader for RocksDbTreeStore {
-    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
-        let key = bincode::serialize(node_key)?;
-        match self.db.get(key)? {
-            Some(value) => {
-                let node = bincode::deserialize(&value)?;
-                Ok(Some(node))
+    /// Returns the earliest version whose tree has the root hash `root_hash`, or `None` if no
+    /// version in the store has it.
+    ///
+    /// This tells which version produced a root hash observed elsewhere, such as in a header of
+    /// a peer, with a single seek of the index of the versions by root hash that every write of
+    /// a root maintains. Several versions share a root hash when the later ones change nothing,
+    /// in which case the first of them is returned. As with
+    /// [`committed_versions`](RocksDbTreeStore::committed_versions), the versions pruned by
+    /// [`prune`](RocksDbTreeStore::prune) are no longer found, and neither are those written
+    /// before the store had the index.
+    pub fn version_of_root(&self, root_hash: RootHash) -> Result<Option<Version>> {
+        let prefix = frame(RecordKind::RootVersion, &root_hash.0);
+        let mut iter = self.prefix_iterator(ROOT_VERSIONS_CF, &prefix)?;
+        iter.seek_to_first();
+        match iter.key() {
+            Some(key) => {
+                let version = &self.unprefixed(key)[prefix.len()..];
+                Ok(Some(decode_version(version)?))
+            }
+            None => {
+                iter.status()?;
+                Ok(None)
             }
-            None => Ok(None),
         }
     }
 
-    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
-        // This is a simplified implementation. In practice, you'd want to maintain
-        // an index for efficient retrieval of the rightmost leaf.
-        // For now, we'll just return None to avoid complex iterator handling.
-        Ok(None)
+    /// Returns a read-only view of the store as it was at `version`, for replaying the reads of
+    /// a past state, see [`VersionCappedReader`].
+    ///
+    /// RocksDB cannot read a database as of an earlier sequence number once later writes have
+    /// landed, short of a snapshot taken at the time, but the versions of the store never change
+    /// once committed: capping every read at `version` shows the nodes and values it held then,
+    /// as long as `version` is not pruned.
+    pub fn as_of(&self, version: Version) -> VersionCappedReader<'_, Self> {
+        VersionCappedReader::new(self, version)
     }
 
-    fn get_value_option(
+    /// Returns the entries of the stale node index, the version since which each node is stale
+    /// and the key of the node, in ascending version order.
+    ///
+    /// These are the nodes pending reclamation: a node is listed from the write that replaced it
+    /// until the [`prune`](RocksDbTreeStore::prune) deleting it, so that an operator can see what
+    /// the next prune to a given version would reclaim, and which nodes a label keeps around.
+    pub fn iter_stale_nodes(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<(Version, NodeKey)>> + '_> {
+        Ok(self.iterator(STALE_NODES_CF)?.map(|item| {
+            let index = decode_stale_node_index::<C>(self.unprefixed(&item?.0))?;
+            Ok((index.stale_since_version, index.node_key))
+        }))
+    }
+
+    /// Returns every value entry stored for `key_hash`, in version order: the version that wrote
+    /// it and the value, or `None` for a deletion.
+    ///
+    /// The entries of a key are adjacent in the values column family and sorted by version, so
+    /// this is a single seek followed by a scan of just those entries. Entries removed by
+    /// [`compact_tombstones`](RocksDbTreeStore::compact_tombstones) are gone from the history.
+    pub fn history_of(
         &self,
-        max_version: Version,
         key_hash: KeyHash,
-    ) -> Result<Option<OwnedValue>> {
-        // Store values with composite key: (key_hash, version)
-        // Retrieve the latest version <= max_version
-        // For simplicity, we'll iterate through all keys and find the matching ones.
-        // This is inefficient but works for testing purposes.
-        
-        let mut iter = self.db.iterator(rocksdb::IteratorMode::Start);
-        let mut latest_value: Option<OwnedValue> = None;
-        let mut latest_version: Option<Version> = None;
-        
-        for item in iter {
-            let (key, value) = item?;
-            
-            // Try to deserialize the key as (KeyHash, Version)
-            if let Ok((stored_key_hash, version)) = bincode::deserialize::<(KeyHash, Version)>(&key) {
-                if stored_key_hash == key_hash && version <= max_version {
-                    if latest_version.is_none() || version > latest_version.unwrap() {
-                        latest_version = Some(version);
-                        // Deserialize the value as Option<Vec<u8>>
-                        if let Ok(deserialized_value) = bincode::deserialize::<Option<Vec<u8>>>(&value) {
-                            latest_value = deserialized_value;
-                        }
-                    }
+    ) -> Result<impl Iterator<Item = Result<(Version, Option<OwnedValue>)>> + '_> {
+        let mut iter = self
+            .db
+            .raw_iterator_cf_opt(self.cf(VALUES_CF)?, self.value_read_options(key_hash));
+        iter.seek_to_first();
+        let mut done = false;
+        Ok(std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let entry = match (iter.key(), iter.value()) {
+                (Some(key), Some(value)) => decode_value_key(self.unprefixed(key))
+                    .and_then(|(_, version)| Ok((version, decode_value::<C>(value)?))),
+                _ => {
+                    done = true;
+                    return iter.status().err().map(|err| Err(err.into()));
                 }
+            };
+            iter.next();
+            Some(entry)
+        }))
+    }
+
+    /// Returns every key hash with a value entry in the store, at any version, once each in
+    /// ascending order, including the keys deleted since.
+    ///
+    /// Value keys sort by key hash first, so the entries of a key are adjacent and a scan of the
+    /// values column family only has to skip the repeats of the key before. Keys whose every
+    /// entry was pruned or removed by
+    /// [`compact_tombstones`](RocksDbTreeStore::compact_tombstones) are not listed.
+    pub fn all_key_hashes(&self) -> Result<impl Iterator<Item = Result<KeyHash>> + '_> {
+        let mut last_key_hash = None;
+        Ok(self.iterator(VALUES_CF)?.filter_map(move |item| {
+            let key_hash = match item {
+                Ok((key, _)) => match decode_value_key(self.unprefixed(&key)) {
+                    Ok((key_hash, _)) => key_hash,
+                    Err(err) => return Some(Err(err)),
+                },
+                Err(err) => return Some(Err(err.into())),
+            };
+            if last_key_hash == Some(key_hash) {
+                return None;
             }
+            last_key_hash = Some(key_hash);
+            Some(Ok(key_hash))
+        }))
+    }
+
+    /// Rebuilds the [`TreeUpdateBatch`] that wrote `version`, from the nodes and values stamped
+    /// with `version` and the stale index entries of the nodes it made stale, for debugging a
+    /// store whose contents disagree with what was meant to be written.
+    ///
+    /// The batch holds the same nodes, values and stale index entries as the one written, as long
+    /// as none of them was pruned, and a single [`NodeStats`] counted from them. A batch that
+    /// wrote several versions is rebuilt one version at a time. Every node and value key of the
+    /// store is read, so this is not meant for a hot path. Fails with [`MissingRootError`] if
+    /// `version` has no root, and fails if a node made stale by `version` is gone.
+    pub fn batch_at(&self, version: Version) -> Result<TreeUpdateBatch> {
+        if self
+            .get_node_option(&NodeKey::new_empty_path(version))?
+            .is_none()
+        {
+            return Err(anyhow::anyhow!(MissingRootError { version }));
         }
-        
-        Ok(latest_value)
+        let mut node_stats = NodeStats::default();
+
+        let mut nodes = BTreeMap::new();
+        for item in self.iterator(NODES_CF)? {
+            let (key, _) = item?;
+            let node_key = decode_node_key::<C>(self.unprefixed(&key))?;
+            if node_key.version() == version {
+                let node = self.get_node(&node_key)?;
+                node_stats.new_leaves += usize::from(node.is_leaf());
+                nodes.insert(node_key, node);
+            }
+        }
+        node_stats.new_nodes = nodes.len();
+
+        let mut values = BTreeMap::new();
+        for item in self.iterator(VALUES_CF)? {
+            let (key, value) = item?;
+            let (key_hash, entry_version) = decode_value_key(self.unprefixed(&key))?;
+            if entry_version == version {
+                values.insert((version, key_hash), decode_value::<C>(&value)?);
+            }
+        }
+
+        let mut stale_node_index_batch = BTreeSet::new();
+        let prefix = frame(RecordKind::StaleNodeIndex, &version.to_be_bytes());
+        let mut iter = self.prefix_iterator(STALE_NODES_CF, &prefix)?;
+        iter.seek_to_first();
+        while let Some(key) = iter.key() {
+            let stale_node_index = decode_stale_node_index::<C>(self.unprefixed(key))?;
+            let stale_node = self.get_node(&stale_node_index.node_key)?;
+            node_stats.stale_leaves += usize::from(stale_node.is_leaf());
+            stale_node_index_batch.insert(stale_node_index);
+            iter.next();
+        }
+        iter.status()?;
+        node_stats.stale_nodes = stale_node_index_batch.len();
+
+        Ok(TreeUpdateBatch {
+            node_batch: NodeBatch::new(nodes, values),
+            stale_node_index_batch,
+            node_stats: vec![node_stats],
+        })
     }
-}
 
-impl HasPreimage for RocksDbTreeStore {
-    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
-        let key = bincode::serialize(&(key_hash, "preimage"))?;
-        match self.db.get(key)? {
-            Some(value) => Ok(Some(value)),
-            None => Ok(None),
+    /// Returns every node and value entry of the store written at a version newer than
+    /// `version`, in the order of their keys, the payload of an incremental backup of the store
+    /// taken after a backup at `version`.
+    ///
+    /// Writing them to a store holding the versions up to `version`, as a [`NodeBatch`] in a
+    /// [`TreeUpdateBatch`], brings it to the latest version of this store. The stale node index
+    /// is not part of the changes: the copy prunes on its own schedule. Entries pruned or
+    /// compacted away are missing, and every node and value key of the store is read, so this
+    /// is meant for periodic backups rather than a hot path.
+    pub fn changes_since(&self, version: Version) -> Result<Changes> {
+        let mut nodes = Vec::new();
+        for item in self.iterator(NODES_CF)? {
+            let (key, value) = item?;
+            let node_key = decode_node_key::<C>(self.unprefixed(&key))?;
+            if node_key.version() > version {
+                nodes.push((node_key, self.decode_node(&value)?));
+            }
+        }
+
+        let mut values = Vec::new();
+        for item in self.iterator(VALUES_CF)? {
+            let (key, value) = item?;
+            let (key_hash, entry_version) = decode_value_key(self.unprefixed(&key))?;
+            if entry_version > version {
+                values.push(((entry_version, key_hash), decode_value::<C>(&value)?));
+            }
         }
+        Ok((nodes, values))
     }
-}
 
-impl TreeWriter for RocksDbTreeStore {
-    fn write_node_batch(&self, node_batch: &crate::storage::NodeBatch) -> Result<()> {
-        let mut batch = WriteBatch::default();
-        
-        // Write nodes
-        for (node_key, node) in node_batch.nodes() {
-            let key = bincode::serialize(node_key)?;
-            let value = bincode::serialize(node)?;
-            batch.put(key, value);
+    /// Applies `f` to the key hash and value of every key live at `version`, in key hash order,
+    /// and returns the results.
+    ///
+    /// The values are streamed from the store and handed to `f` as raw bytes, so callers can
+    /// decode them into their own types without collecting them first. The first error returned
+    /// by `f` stops the iteration and is returned. Fails with [`MissingRootError`] if `version`
+    /// is not readable.
+    pub fn map_values_at_version<T>(
+        &self,
+        version: Version,
+        mut f: impl FnMut(KeyHash, &[u8]) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let mut results = Vec::new();
+        self.for_each_live_value(version, |key_hash, _written_version, value| {
+            results.push(f(key_hash, &value)?);
+            Ok(())
+        })?;
+        Ok(results)
+    }
+
+    /// Returns the number of keys live at `version`.
+    ///
+    /// Every internal node stores the number of leaves below it, which the tree keeps up to date
+    /// as keys are inserted and deleted, so this is a single read of the root of `version` rather
+    /// than a walk of the tree. Fails with [`MissingRootError`] if `version` is not readable.
+    pub fn leaf_count(&self, version: Version) -> Result<u64> {
+        match self.get_node_option(&NodeKey::new_empty_path(version))? {
+            Some(root) => Ok(root.leaf_count() as u64),
+            None => Err(anyhow::anyhow!(MissingRootError { version })),
         }
-        
-        // Write values
-        for ((version, key_hash), value) in node_batch.values() {
-            let key = bincode::serialize(&(*key_hash, *version))?;
-            let serialized_value = bincode::serialize(value)?;
-            batch.put(key, serialized_value);
+    }
+
+    /// Returns a digest of the state of the store at `version`: the root hash of the tree, which
+    /// commits to every key and value, and the preimages stored for the keys live at `version`.
+    ///
+    /// Two stores built from the same inputs have the same digest, which is cheaper to exchange
+    /// than the tree, while a preimage missing from one of them or differing between them changes
+    /// it. The digest is the `H` hash of `JMT::RocksDbTreeStore::StateDigest`, followed by the
+    /// big-endian `version`, the root hash and, for each live key with a preimage in key hash
+    /// order, the key hash, the big-endian `u64` length of the preimage and the preimage. Fails
+    /// with [`MissingRootError`] if `version` is not readable.
+    pub fn state_digest(&self, version: Version) -> Result<[u8; 32]> {
+        let root = self
+            .get_node_option(&NodeKey::new_empty_path(version))?
+            .ok_or_else(|| anyhow::anyhow!(MissingRootError { version }))?;
+        let mut hasher = H::new();
+        hasher.update(b"JMT::RocksDbTreeStore::StateDigest");
+        hasher.update(&version.to_be_bytes());
+        hasher.update(&root.hash::<H>());
+        self.for_each_live_value(version, |key_hash, _written_version, _value| {
+            if let Some(preimage) = self.preimage(key_hash)? {
+                hasher.update(&key_hash.0);
+                hasher.update(&(preimage.len() as u64).to_be_bytes());
+                hasher.update(&preimage);
+            }
+            Ok(())
+        })?;
+        Ok(hasher.finalize())
+    }
+
+    /// Returns the bytes of every node and value entry of the store divided by the bytes of the
+    /// entries making up the state at `latest_version`: the nodes reachable from its root and the
+    /// value entry read for each key live there.
+    ///
+    /// A ratio close to 1.0 means that history and stale nodes take little room beyond the live
+    /// state; [`prune`](RocksDbTreeStore::prune) and
+    /// [`compact_tombstones`](RocksDbTreeStore::compact_tombstones) bring it down. Sizes are those
+    /// of the keys and values as stored, before RocksDB compression. Fails with
+    /// [`MissingRootError`] if `latest_version` is not readable.
+    pub fn amplification_ratio(&self, latest_version: Version) -> Result<f64> {
+        let root_key = NodeKey::new_empty_path(latest_version);
+        if self.get_node_option(&root_key)?.is_none() {
+            return Err(anyhow::anyhow!(MissingRootError {
+                version: latest_version
+            }));
         }
-        
-        self.db.write(batch)?;
-        Ok(())
+
+        let nodes_cf = self.cf(NODES_CF)?;
+        let mut live_bytes = 0u64;
+        let mut stack = vec![root_key];
+        while let Some(node_key) = stack.pop() {
+            let key = self.key(&encode_node_key::<C>(&node_key)?);
+            let value = match self.db.get_pinned_cf(nodes_cf, &key)? {
+                Some(value) => value,
+                None => continue,
+            };
+            live_bytes += (key.len() + value.len()) as u64;
+            let node = self.decode_node(&value)?;
+            if let Node::Internal(internal_node) = node {
+                for (nibble, child) in internal_node.children_sorted() {
+                    stack.push(node_key.gen_child_node_key(child.version, nibble));
+                }
+            }
+        }
+        self.for_each_live_value(latest_version, |key_hash, written_version, value| {
+            let key = self.key(&encode_value_key(key_hash, written_version));
+            live_bytes += key.len() as u64 + self.encode_value(&Some(value))?.len() as u64;
+            Ok(())
+        })?;
+
+        let mut total_bytes = 0u64;
+        for name in [NODES_CF, VALUES_CF] {
+            for item in self.iterator(name)? {
+                let (key, value) = item?;
+                total_bytes += (key.len() + value.len()) as u64;
+            }
+        }
+        Ok(total_bytes as f64 / live_bytes as f64)
     }
-}
 
-impl RocksDbTreeStore {
-    /// Writes a tree update batch to the database.
-    pub fn write_tree_update_batch(&self, batch: TreeUpdateBatch) -> Result<()> {
-        self.write_node_batch(&batch.node_batch)?;
-        // Note: stale nodes are typically handled separately in a real implementation
-        // For simplicity, we're ignoring the stale_node_index_batch here
+    /// Returns the number of nodes in the stale index divided by the number of the other nodes
+    /// of the store, which the versions written last still reach.
+    ///
+    /// Stale nodes stay in the index until [`prune`](RocksDbTreeStore::prune) deletes them, so a
+    /// ratio that keeps rising with every write means pruning is overdue; a prune of every stale
+    /// node brings it back to 0. The live nodes are estimated as the nodes stored minus the stale
+    /// ones, both counted by scanning their column families. An empty store has a ratio of 0.
+    pub fn stale_ratio(&self) -> Result<f64> {
+        let count = |name| -> Result<u64> {
+            self.iterator(name)?
+                .try_fold(0, |count, item| item.map(|_| count + 1))
+                .map_err(Into::into)
+        };
+        let stale_nodes = count(STALE_NODES_CF)?;
+        let live_nodes = count(NODES_CF)?.saturating_sub(stale_nodes);
+        Ok(stale_nodes as f64 / live_nodes.max(1) as f64)
+    }
+
+    /// Calls `f` with the key hash, the version that wrote the value, and the value of every key
+    /// live at `version`, in key hash order.
+    fn for_each_live_value(
+        &self,
+        version: Version,
+        mut f: impl FnMut(KeyHash, Version, OwnedValue) -> Result<()>,
+    ) -> Result<()> {
+        if self
+            .get_node_option(&NodeKey::new_empty_path(version))?
+            .is_none()
+        {
+            return Err(anyhow::anyhow!(MissingRootError { version }));
+        }
+
+        // Entries of a key are adjacent and sorted by version, so the last one at or below
+        // `version` is known once the next key starts.
+        let mut current: Option<(KeyHash, Version, Option<OwnedValue>)> = None;
+        for item in self.iterator(VALUES_CF)? {
+            let (key, value) = item?;
+            let (key_hash, entry_version) = decode_value_key(self.unprefixed(&key))?;
+            if let Some((current_key, written_version, current_value)) = current.take() {
+                if current_key != key_hash {
+                    if let Some(current_value) = current_value {
+                        f(current_key, written_version, current_value)?;
+                    }
+                } else {
+                    current = Some((current_key, written_version, current_value));
+                }
+            }
+            if entry_version <= version {
+                current = Some((key_hash, entry_version, decode_value::<C>(&value)?));
+            }
+        }
+        if let Some((key_hash, written_version, Some(value))) = current {
+            f(key_hash, written_version, value)?;
+        }
         Ok(())
     }
-    
+
     /// Prints the contents of the database for visualization purposes.
     /// This is useful for debugging and understanding what's stored in the database.
     #[cfg(test)]
     pub fn print_database_contents(&self) -> Result<()> {
         println!("Database contents:");
-        let iter = self.db.iterator(rocksdb::IteratorMode::Start);
+        let iter = self.iterator(NODES_CF)?;
         let mut count = 0;
         
         for item in iter {
@@ -152,23 +3795,16 @@ impl RocksDbTreeStore {
             count += 1;
             
             // Try to deserialize as a NodeKey
-            if let Ok(node_key) = bincode::deserialize::<crate::node_type::NodeKey>(&key) {
-                if let Ok(node) = bincode::deserialize::<crate::node_type::Node>(&value) {
+            let key = self.unprefixed(&key);
+            if let Ok(node_key) = decode_node_key::<C>(key) {
+                if let Ok(node) = record::decode::<C, Node>(RecordKind::Node, &value) {
                     println!("  {}: NodeKey({:?}) -> Node({:?})", count, node_key, node);
                 } else {
                     println!("  {}: NodeKey({:?}) -> Raw Value({} bytes)", count, node_key, value.len());
                 }
             } 
-            // Try to deserialize as (KeyHash, Version)
-            else if let Ok((key_hash, version)) = bincode::deserialize::<(KeyHash, Version)>(&key) {
-                if let Ok(option_value) = bincode::deserialize::<Option<Vec<u8>>>(&value) {
-                    println!("  {}: (KeyHash({:?}), Version({})) -> {:?}", count, key_hash, version, option_value);
-                } else {
-                    println!("  {}: (KeyHash({:?}), Version({})) -> Raw Value({} bytes)", count, key_hash, version, value.len());
-                }
-            }
             // Try to deserialize as (KeyHash, "preimage")
-            else if let Ok((key_hash, _)) = bincode::deserialize::<(KeyHash, &str)>(&key) {
+            else if let Ok((key_hash, _)) = C::decode::<(KeyHash, String)>(key) {
                 println!("  {}: KeyHash({:?}) preimage -> {} bytes", count, key_hash, value.len());
             }
             else {
@@ -176,6 +3812,18 @@ impl RocksDbTreeStore {
             }
         }
         
+
+        for item in self.iterator(VALUES_CF)? {
+            let (key, value) = item?;
+            count += 1;
+            let (key_hash, version) = decode_value_key(self.unprefixed(&key))?;
+            if let Ok(option_value) = decode_value::<C>(&value) {
+                println!("  {}: (KeyHash({:?}), Version({})) -> {:?}", count, key_hash, version, option_value);
+            } else {
+                println!("  {}: (KeyHash({:?}), Version({})) -> Raw Value({} bytes)", count, key_hash, version, value.len());
+            }
+        }
+
         if count == 0 {
             println!("  Database is empty");
         } else {
@@ -185,6 +3833,14 @@ impl RocksDbTreeStore {
         Ok(())
     }
     
+    /// Returns the versions at which `key_hash` has a value entry, deletions included.
+    #[cfg(test)]
+    pub(crate) fn value_versions(&self, key_hash: KeyHash) -> Result<Vec<Version>> {
+        self.history_of(key_hash)?
+            .map(|entry| entry.map(|(version, _)| version))
+            .collect()
+    }
+
     /// Returns the underlying RocksDB database for advanced operations.
     /// This is primarily for testing and debugging purposes.
     #[cfg(test)]
@@ -192,3 +3848,427 @@ impl RocksDbTreeStore {
         &self.db
     }
 }
+
+/// Collects the [`TreeUpdateBatch`]es of several versions and writes them to a
+/// [`RocksDbTreeStore`] as a single atomic RocksDB write.
+///
+/// This avoids one write per version when replaying history. The accumulator is itself a
+/// [`TreeReader`] that sees the pending batches on top of the store, so each version can be
+/// computed by a [`JellyfishMerkleTree`] reading from the accumulator before the previous
+/// versions are flushed.
+///
+/// Reads of values assume that the pending versions are newer than the ones already in the
+/// store, as is the case when replaying history in order.
+pub struct BatchAccumulator<'a, H: SimpleHasher = Sha256, C: Codec = BincodeCodec> {
+    store: &'a GenericTreeStore<C, H>,
+    pending: TreeUpdateBatch,
+}
+
+impl<'a, H: SimpleHasher, C: Codec> BatchAccumulator<'a, H, C> {
+    /// Creates an empty accumulator writing to `store`.
+    pub fn new(store: &'a GenericTreeStore<C, H>) -> Self {
+        Self {
+            store,
+            pending: TreeUpdateBatch::default(),
+        }
+    }
+
+    /// Adds `batch` to the pending batches.
+    pub fn add(&mut self, batch: TreeUpdateBatch) {
+        merge_update_batch(&mut self.pending, batch);
+    }
+
+    /// Returns `true` if there is nothing to flush.
+    pub fn is_empty(&self) -> bool {
+        self.pending.node_batch.is_empty() && self.pending.stale_node_index_batch.is_empty()
+    }
+
+    /// Returns the reader of the pending batches on top of the store.
+    fn overlay(&self) -> PendingOverlay<'_, C, H> {
+        PendingOverlay {
+            store: self.store,
+            pending: &self.pending,
+        }
+    }
+
+    /// Writes all pending batches to the store in a single atomic write, exactly as
+    /// [`RocksDbTreeStore::write_tree_update_batch`] would write their union, and clears them.
+    ///
+    /// The pending batches are kept if the write fails, so that the flush can be retried.
+    pub fn flush(&mut self) -> Result<()> {
+        self.store.write_tree_update_batch_ref(&self.pending)?;
+        self.pending = TreeUpdateBatch::default();
+        Ok(())
+    }
+}
+
+impl<H: SimpleHasher, C: Codec> TreeReader for BatchAccumulator<'_, H, C> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.overlay().get_node_option(node_key)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.overlay().get_rightmost_leaf()
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.overlay().get_value_option(max_version, key_hash)
+    }
+}
+
+/// Adds the nodes, values, stale node index entries and stats of `batch` to `pending`.
+fn merge_update_batch(pending: &mut TreeUpdateBatch, batch: TreeUpdateBatch) {
+    let TreeUpdateBatch {
+        node_batch,
+        stale_node_index_batch,
+        node_stats,
+    } = batch;
+    pending.node_batch.merge(node_batch);
+    pending
+        .stale_node_index_batch
+        .extend(stale_node_index_batch);
+    pending.node_stats.extend(node_stats);
+}
+
+/// A [`TreeReader`] serving the nodes and values of `pending` batches, not written yet, on top of
+/// those of `store`, assuming that the pending versions are newer than the ones in the store.
+struct PendingOverlay<'a, C: Codec, H: SimpleHasher> {
+    store: &'a GenericTreeStore<C, H>,
+    pending: &'a TreeUpdateBatch,
+}
+
+impl<C: Codec, H: SimpleHasher> TreeReader for PendingOverlay<'_, C, H> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        match self.pending.node_batch.get_node(node_key) {
+            Some(node) => Ok(Some(node.clone())),
+            None => self.store.get_node_option(node_key),
+        }
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        let pending = self
+            .pending
+            .node_batch
+            .nodes()
+            .iter()
+            .filter_map(|(node_key, node)| match node {
+                Node::Leaf(leaf_node) => Some((node_key.clone(), leaf_node.clone())),
+                _ => None,
+            });
+        Ok(pending
+            .chain(self.store.get_rightmost_leaf()?)
+            .max_by_key(|(_, leaf_node)| leaf_node.key_hash()))
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        let pending = self
+            .pending
+            .node_batch
+            .values()
+            .range(..=(max_version, KeyHash([u8::MAX; 32])))
+            .rev()
+            .find(|((_, pending_key_hash), _)| *pending_key_hash == key_hash);
+        match pending {
+            Some((_, value)) => Ok(value.clone()),
+            None => self.store.get_value_option(max_version, key_hash),
+        }
+    }
+}
+
+/// A [`TreeReader`] serving nodes prefetched by [`RocksDbTreeStore::get_with_proof_batch`],
+/// falling back to the store for anything that was not prefetched.
+struct PrefetchedReader<'a, C: Codec, H: SimpleHasher> {
+    store: &'a GenericTreeStore<C, H>,
+    nodes: HashMap<NodeKey, Option<Node>>,
+}
+
+impl<C: Codec, H: SimpleHasher> TreeReader for PrefetchedReader<'_, C, H> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        match self.nodes.get(node_key) {
+            Some(node) => Ok(node.clone()),
+            None => self.store.get_node_option(node_key),
+        }
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.store.get_rightmost_leaf()
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.store.get_value_option(max_version, key_hash)
+    }
+}
+
+/// Walks the subtree of `node_key` at `version` from `node`, read from `reader`, and appends
+/// the inconsistencies found to `inconsistencies`, see [`GenericTreeStore::verify_consistency`].
+fn check_subtree(
+    reader: &impl TreeReader,
+    version: Version,
+    node_key: NodeKey,
+    node: Node,
+    inconsistencies: &mut Vec<Inconsistency>,
+) -> Result<()> {
+    let mut stack = vec![(node_key, node)];
+    while let Some((node_key, node)) = stack.pop() {
+        match node {
+            Node::Internal(internal_node) => {
+                for (nibble, child) in internal_node.children_sorted() {
+                    let child_key = node_key.gen_child_node_key(child.version, nibble);
+                    match reader.get_node_option(&child_key)? {
+                        Some(child_node) => stack.push((child_key, child_node)),
+                        None => inconsistencies.push(Inconsistency::MissingChild {
+                            parent: node_key.clone(),
+                            nibble: u8::from(nibble),
+                            child: child_key,
+                        }),
+                    }
+                }
+            }
+            Node::Leaf(leaf_node) => {
+                let key_hash = leaf_node.key_hash();
+                if reader.get_value_option(version, key_hash)?.is_none() {
+                    inconsistencies.push(Inconsistency::MissingValue { node_key, key_hash });
+                }
+            }
+            Node::Null => {}
+        }
+    }
+    Ok(())
+}
+
+/// Returns the value identifying the hasher `H` in the metadata of a store: the hash of a fixed
+/// string, which differs between hashers.
+fn hasher_fingerprint<H: SimpleHasher>() -> [u8; 32] {
+    H::hash(b"JMT::RocksDbTreeStore::HasherFingerprint")
+}
+
+/// Returns the identifier of a node in the DOT output of [`RocksDbTreeStore::export_dot`].
+fn dot_node_id(node_key: &NodeKey) -> String {
+    format!("{}:{:?}", node_key.version(), node_key.nibble_path())
+}
+
+/// Decodes a big-endian version, as stored in the metadata and labels column families.
+fn decode_version(bytes: &[u8]) -> Result<Version> {
+    let bytes: [u8; 8] = bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("corrupted version: {}", hex::encode(bytes)))?;
+    Ok(Version::from_be_bytes(bytes))
+}
+
+/// Encodes `version` as a [`RecordKind::Version`] record, as stored in the metadata and the
+/// labels.
+fn encode_version_record(version: Version) -> Vec<u8> {
+    frame(RecordKind::Version, &version.to_be_bytes())
+}
+
+/// Decodes a record written by [`encode_version_record`].
+fn decode_version_record(record: &[u8]) -> Result<Version> {
+    decode_version(unframe(RecordKind::Version, record)?)
+}
+
+/// Encodes the key of the entry of the index by root hash of the root of `version`, which
+/// hashes to `root_hash`, as a [`RecordKind::RootVersion`] record.
+fn encode_root_version_key(root_hash: [u8; 32], version: Version) -> Vec<u8> {
+    frame(
+        RecordKind::RootVersion,
+        &[&root_hash[..], &version.to_be_bytes()].concat(),
+    )
+}
+
+/// Decodes `record`, read from [`NODES_CF`], reading the content it points to with
+/// `read_content`, given the key of the content in [`NODE_CONTENTS_CF`], if it is a pointer.
+fn decode_node_record<C: Codec>(
+    record: &[u8],
+    read_content: impl FnOnce(&[u8]) -> Result<Option<Vec<u8>>>,
+) -> Result<Node> {
+    Ok(decode_node_record_with_value::<C>(record, read_content)?.0)
+}
+
+/// Same as [`decode_node_record`], also returning the value of a
+/// [`RecordKind::InlineLeaf`] record.
+fn decode_node_record_with_value<C: Codec>(
+    record: &[u8],
+    read_content: impl FnOnce(&[u8]) -> Result<Option<Vec<u8>>>,
+) -> Result<NodeWithValue> {
+    if !record.starts_with(&record::header(RecordKind::NodePointer)) {
+        return decode_node_content::<C>(record);
+    }
+    let content_hash = unframe(RecordKind::NodePointer, record)?;
+    let content = read_content(&frame(RecordKind::ContentHash, content_hash))?
+        .ok_or_else(|| anyhow::anyhow!("missing node content {}", hex::encode(content_hash)))?;
+    decode_node_content::<C>(&content)
+}
+
+/// Decodes a [`RecordKind::Node`] or [`RecordKind::InlineLeaf`] record, returning the value of
+/// the latter.
+fn decode_node_content<C: Codec>(record: &[u8]) -> Result<NodeWithValue> {
+    if record.starts_with(&record::header(RecordKind::InlineLeaf)) {
+        let (node, value) = record::decode::<C, _>(RecordKind::InlineLeaf, record)?;
+        return Ok((node, Some(value)));
+    }
+    Ok((record::decode::<C, _>(RecordKind::Node, record)?, None))
+}
+
+/// Encodes `refcount` as a [`RecordKind::Refcount`] record, as stored in [`REFCOUNTS_CF`].
+fn encode_refcount(refcount: u64) -> Vec<u8> {
+    frame(RecordKind::Refcount, &refcount.to_be_bytes())
+}
+
+/// Decodes a record of [`VALUES_CF`], expiring or not, compressed or not.
+fn decode_value<C: Codec>(record: &[u8]) -> Result<Option<OwnedValue>> {
+    match ttl::unframe_expiring(RecordKind::CompressedValue, record) {
+        Ok(compressed) => C::decode(&zstd::decode_all(compressed)?),
+        Err(_) => C::decode(ttl::unframe_expiring(RecordKind::Value, record)?),
+    }
+}
+
+/// Encodes `node_key` as the key of its node in [`NODES_CF`] and of its count in
+/// [`REFCOUNTS_CF`].
+fn encode_node_key<C: Codec>(node_key: &NodeKey) -> Result<Vec<u8>> {
+    Ok(frame(RecordKind::NodeKey, &C::encode_node_key(node_key)?))
+}
+
+/// Decodes a key of [`NODES_CF`] or [`REFCOUNTS_CF`].
+fn decode_node_key<C: Codec>(key: &[u8]) -> Result<NodeKey> {
+    C::decode_node_key(unframe(RecordKind::NodeKey, key)?)
+}
+
+/// Returns the read options bounding iterators to the keys starting with `prefix`, see
+/// [`GenericTreeStore::prefix_iterator`].
+fn prefix_read_options(prefix: &[u8]) -> ReadOptions {
+    let mut opts = ReadOptions::default();
+    // A seek otherwise checks the prefix bloom filters of the values column family, if it has a
+    // prefix extractor, for the prefix of the sought key, skipping the files that hold the keys
+    // after it under other prefixes.
+    opts.set_total_order_seek(true);
+    opts.set_iterate_lower_bound(prefix);
+    if let Some(upper_bound) = prefix_successor(prefix) {
+        opts.set_iterate_upper_bound(upper_bound);
+    }
+    opts
+}
+
+/// Reads the value of `key_hash` at `max_version` and the version that set it from `iter`, an
+/// iterator over [`VALUES_CF`] bounded to the entries of `key_hash` under `namespace`. The entry
+/// found is decoded and passed to `check` with its key, without the namespace, which returns it.
+fn seek_value<C: Codec>(
+    mut iter: DBRawIteratorWithThreadMode<'_, DB>,
+    namespace: &[u8],
+    max_version: Version,
+    key_hash: KeyHash,
+    check: impl FnOnce(
+        &[u8],
+        Result<Option<(Version, OwnedValue)>>,
+    ) -> Result<Option<(Version, OwnedValue)>>,
+) -> Result<Option<(Version, OwnedValue)>> {
+    // The entry of the latest version <= max_version is the last one of key_hash sorting before
+    // the key of max_version.
+    iter.seek_for_prev([namespace, &encode_value_key(key_hash, max_version)].concat());
+    match (iter.key(), iter.value()) {
+        (Some(key), Some(value)) => {
+            let key = &key[namespace.len()..];
+            let decoded = decode_value_key(key).and_then(|(_, version)| {
+                Ok(decode_value::<C>(value)?.map(|value| (version, value)))
+            });
+            check(key, decoded)
+        }
+        _ => {
+            iter.status()?;
+            Ok(None)
+        }
+    }
+}
+
+/// Returns the smallest key greater than every key starting with `prefix`, or `None` if there is
+/// none because `prefix` is only made of `0xff` bytes.
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&byte| byte != 0xff)?;
+    let mut successor = prefix[..=last].to_vec();
+    successor[last] += 1;
+    Some(successor)
+}
+
+/// Returns the prefix shared by the keys of the values of `key_hash` in [`VALUES_CF`].
+fn value_key_prefix(key_hash: KeyHash) -> Vec<u8> {
+    frame(RecordKind::ValueKey, &key_hash.0)
+}
+
+/// Encodes the key of the value of `key_hash` at `version` in [`VALUES_CF`].
+fn encode_value_key(key_hash: KeyHash, version: Version) -> Vec<u8> {
+    let mut key = value_key_prefix(key_hash);
+    key.extend_from_slice(&version.to_be_bytes());
+    key
+}
+
+/// Decodes a key of [`VALUES_CF`].
+fn decode_value_key(key: &[u8]) -> Result<(KeyHash, Version)> {
+    let key = unframe(RecordKind::ValueKey, key)?;
+    if key.len() != 40 {
+        anyhow::bail!("corrupted value key: {}", hex::encode(key));
+    }
+    let (key_hash, version) = key.split_at(32);
+    Ok((KeyHash(key_hash.try_into()?), decode_version(version)?))
+}
+
+/// Encodes the key of `stale_node_index` in [`STALE_NODES_CF`].
+fn encode_stale_node_index<C: Codec>(stale_node_index: &StaleNodeIndex) -> Result<Vec<u8>> {
+    let mut key = stale_node_index.stale_since_version.to_be_bytes().to_vec();
+    key.extend(C::encode_node_key(&stale_node_index.node_key)?);
+    Ok(frame(RecordKind::StaleNodeIndex, &key))
+}
+
+/// Decodes a key of [`STALE_NODES_CF`].
+fn decode_stale_node_index<C: Codec>(key: &[u8]) -> Result<StaleNodeIndex> {
+    let key = unframe(RecordKind::StaleNodeIndex, key)?;
+    if key.len() < 8 {
+        anyhow::bail!("corrupted stale node index: {}", hex::encode(key));
+    }
+    let (version, node_key) = key.split_at(8);
+    Ok(StaleNodeIndex {
+        stale_since_version: decode_version(version)?,
+        node_key: C::decode_node_key(node_key)?,
+    })
+}
+
+/// Returns the highest version of any node or value in `node_batch`.
+fn max_version(node_batch: &NodeBatch) -> Option<Version> {
+    batch_versions(node_batch).max()
+}
+
+/// Returns the lowest version of any node or value in `node_batch`.
+fn min_version(node_batch: &NodeBatch) -> Option<Version> {
+    batch_versions(node_batch).min()
+}
+
+/// Fails if the versions of the nodes and values of `node_batch` leave a gap.
+fn check_contiguous_versions(node_batch: &NodeBatch) -> Result<()> {
+    let versions: BTreeSet<Version> = batch_versions(node_batch).collect();
+    if let (Some(first), Some(last)) = (versions.first(), versions.last()) {
+        ensure!(
+            last - first < versions.len() as u64,
+            "node batch mixes versions {:?}, which are not contiguous; nothing of the batch was \
+             written",
+            versions
+        );
+    }
+    Ok(())
+}
+
+/// Returns the version of every node and value in `node_batch`.
+fn batch_versions(node_batch: &NodeBatch) -> impl Iterator<Item = Version> + '_ {
+    let node_versions = node_batch.nodes().keys().map(NodeKey::version);
+    let value_versions = node_batch.values().keys().map(|(version, _)| *version);
+    node_versions.chain(value_versions)
+}
@@ -0,0 +1,93 @@
+//! Prunes triggered by the size of the nodes made stale, see
+//! [`RocksDbStoreConfig::auto_prune_stale_bytes`](super::RocksDbStoreConfig::auto_prune_stale_bytes).
+
+use super::{Codec, GenericTreeStore};
+use crate::{node_type::NodeKey, types::Version, SimpleHasher};
+use anyhow::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// A thread adding up the sizes of the nodes made stale by the writes of a store, and pruning the
+/// store to its least readable version once they exceed a threshold.
+///
+/// Writes only hand the keys of their stale nodes over to the thread, which reads their sizes and
+/// prunes on its own, so that neither slows the writes down. Dropping it stops the thread and
+/// waits for it to exit, so that the thread does not keep the database open past the last store
+/// using it.
+pub(super) struct AutoPruner {
+    least_readable_version: Arc<AtomicU64>,
+    /// Dropped to stop the thread.
+    stale_nodes: Option<Sender<Vec<NodeKey>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AutoPruner {
+    /// Starts a thread pruning `store` to `least_readable_version` whenever the nodes made stale
+    /// since its last prune add up to more than `threshold` bytes.
+    pub(super) fn spawn<C: Codec + Send + 'static, H: SimpleHasher + Send + 'static>(
+        store: GenericTreeStore<C, H>,
+        threshold: u64,
+        least_readable_version: Version,
+    ) -> Result<Self> {
+        let least_readable_version = Arc::new(AtomicU64::new(least_readable_version));
+        let version = Arc::clone(&least_readable_version);
+        let (stale_nodes, receiver) = mpsc::channel::<Vec<NodeKey>>();
+        let thread = thread::Builder::new()
+            .name("jmt-rocksdb-auto-prune".to_string())
+            .spawn(move || {
+                let mut stale_bytes = 0u64;
+                for node_keys in receiver {
+                    for node_key in &node_keys {
+                        // A node that cannot be read counts for nothing, it may already be pruned.
+                        if let Ok(Some((_, size))) = store.get_node_sized(node_key) {
+                            stale_bytes = stale_bytes.saturating_add(size as u64);
+                        }
+                    }
+                    if stale_bytes <= threshold {
+                        continue;
+                    }
+                    let mut min_readable_version = version.load(Ordering::Relaxed);
+                    // Sealed versions are kept, as with `retain_versions`.
+                    if let Ok(Some(sealed_version)) = store.sealed_version() {
+                        min_readable_version = min_readable_version.min(sealed_version);
+                    }
+                    // The prune holds the write lock, so it never interleaves with the writes
+                    // still landing, whose node counts it would otherwise race on. A failed
+                    // prune is tried again after the next write.
+                    if store.prune(min_readable_version).is_ok() {
+                        stale_bytes = 0;
+                    }
+                }
+            })?;
+        Ok(AutoPruner {
+            least_readable_version,
+            stale_nodes: Some(stale_nodes),
+            thread: Some(thread),
+        })
+    }
+
+    /// Makes the next prunes prune to `least_readable_version`.
+    pub(super) fn set_least_readable_version(&self, least_readable_version: Version) {
+        self.least_readable_version
+            .store(least_readable_version, Ordering::Relaxed);
+    }
+
+    /// Hands `node_keys`, just made stale, over to the thread.
+    pub(super) fn record_stale(&self, node_keys: Vec<NodeKey>) {
+        if let Some(stale_nodes) = &self.stale_nodes {
+            // The thread only stops once this is dropped.
+            let _ = stale_nodes.send(node_keys);
+        }
+    }
+}
+
+impl Drop for AutoPruner {
+    fn drop(&mut self) {
+        self.stale_nodes.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! A store buffering the writes of several versions in memory until they are flushed.
+
+use super::{merge_update_batch, BincodeCodec, Codec, GenericTreeStore, PendingOverlay};
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{TreeReader, TreeUpdateBatch},
+    types::Version,
+    KeyHash, OwnedValue, SimpleHasher,
+};
+use anyhow::{ensure, Result};
+use sha2::Sha256;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard};
+
+/// A store holding the [`TreeUpdateBatch`]es written to it in memory, and writing them to the
+/// store it wraps only when [`flush`](BufferedTreeStore::flush) is called.
+///
+/// This is meant for devices whose storage wears with every write: several versions are written
+/// to RocksDB, and its write-ahead log synced, at once rather than one by one. The store is a
+/// [`TreeReader`] that sees the buffered versions on top of the wrapped store, so each version can
+/// be computed by a [`JellyfishMerkleTree`](crate::JellyfishMerkleTree) reading from it before the
+/// previous ones are flushed. At most `max_versions` versions are buffered, past which writes fail
+/// until the next flush, so that the memory held stays bounded. The buffered versions are lost if
+/// the store is dropped without a flush.
+///
+/// As with [`BatchAccumulator`](super::BatchAccumulator), reads of values assume that the buffered
+/// versions are newer than the ones already in the wrapped store.
+pub struct BufferedTreeStore<H: SimpleHasher = Sha256, C: Codec = BincodeCodec> {
+    store: GenericTreeStore<C, H>,
+    max_versions: usize,
+    pending: RwLock<TreeUpdateBatch>,
+}
+
+impl<H: SimpleHasher, C: Codec> BufferedTreeStore<H, C> {
+    /// Creates an empty buffer of at most `max_versions` versions on top of `store`.
+    pub fn new(store: GenericTreeStore<C, H>, max_versions: usize) -> Self {
+        Self {
+            store,
+            max_versions,
+            pending: RwLock::default(),
+        }
+    }
+
+    /// Returns the store the buffered versions are flushed to.
+    pub fn inner(&self) -> &GenericTreeStore<C, H> {
+        &self.store
+    }
+
+    /// Returns the number of versions buffered, one per [`NodeStats`](crate::storage::NodeStats)
+    /// of the batches written since the last flush.
+    pub fn buffered_versions(&self) -> usize {
+        self.read_pending().node_stats.len()
+    }
+
+    /// Buffers `batch` until the next flush.
+    ///
+    /// Fails, leaving the buffer as it was, if `batch` would take it past `max_versions`
+    /// versions.
+    pub fn write_tree_update_batch(&self, batch: TreeUpdateBatch) -> Result<()> {
+        let mut pending = self.pending.write().unwrap_or_else(PoisonError::into_inner);
+        let versions = pending.node_stats.len() + batch.node_stats.len();
+        ensure!(
+            versions <= self.max_versions,
+            "buffering {versions} versions exceeds the limit of {}, flush first",
+            self.max_versions
+        );
+        merge_update_batch(&mut pending, batch);
+        Ok(())
+    }
+
+    /// Writes the buffered versions to the wrapped store in a single atomic write, as
+    /// [`RocksDbTreeStore::write_tree_update_batch`](super::RocksDbTreeStore::write_tree_update_batch)
+    /// would write their union, syncs its write-ahead log to disk, and clears the buffer.
+    ///
+    /// The buffer is kept if the write fails, so that the flush can be retried.
+    pub fn flush(&self) -> Result<()> {
+        let mut pending = self.pending.write().unwrap_or_else(PoisonError::into_inner);
+        if pending.node_batch.is_empty() && pending.stale_node_index_batch.is_empty() {
+            return Ok(());
+        }
+        self.store.write_tree_update_batch_ref(&pending)?;
+        *pending = TreeUpdateBatch::default();
+        self.store.sync_now()
+    }
+
+    /// Locks the buffered batches for reading.
+    fn read_pending(&self) -> RwLockReadGuard<'_, TreeUpdateBatch> {
+        self.pending.read().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<H: SimpleHasher, C: Codec> TreeReader for BufferedTreeStore<H, C> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        let pending = self.read_pending();
+        PendingOverlay {
+            store: &self.store,
+            pending: &pending,
+        }
+        .get_node_option(node_key)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        let pending = self.read_pending();
+        PendingOverlay {
+            store: &self.store,
+            pending: &pending,
+        }
+        .get_rightmost_leaf()
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        let pending = self.read_pending();
+        PendingOverlay {
+            store: &self.store,
+            pending: &pending,
+        }
+        .get_value_option(max_version, key_hash)
+    }
+}
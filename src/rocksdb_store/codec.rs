@@ -0,0 +1,158 @@
+//! The encoding of the nodes, node keys and values a [`GenericTreeStore`] persists.
+//!
+//! [`GenericTreeStore`]: super::GenericTreeStore
+
+use crate::node_type::{NodeKey, OrderedNodeKey};
+use anyhow::Result;
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A serialization format for the data of a [`GenericTreeStore`](super::GenericTreeStore).
+///
+/// The codec encodes the nodes and values of the tree and the node keys that are part of RocksDB
+/// keys; value keys, versions and metadata have a fixed byte layout of their own. A store must be
+/// reopened with the codec it was written with: the codec is not recorded in the database.
+pub trait Codec {
+    /// Encodes `value`.
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>>;
+
+    /// Decodes a value encoded by [`encode`](Codec::encode).
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T>;
+
+    /// Returns the length of the encoding of `value`.
+    fn encoded_size<T: Serialize + ?Sized>(value: &T) -> Result<u64> {
+        Ok(Self::encode(value)?.len() as u64)
+    }
+
+    /// Encodes `key`, a node key that is part of a RocksDB key. Defaults to
+    /// [`encode`](Codec::encode).
+    ///
+    /// RocksDB sorts keys bytewise, so the order of the encoded node keys is the order in which
+    /// iterators visit the nodes: a codec writing the integers of the keys big-endian and with a
+    /// fixed width keeps them sorted by version, see [`SortableKeyBincodeCodec`].
+    fn encode_key<T: Serialize + ?Sized>(key: &T) -> Result<Vec<u8>> {
+        Self::encode(key)
+    }
+
+    /// Decodes a key encoded by [`encode_key`](Codec::encode_key). Defaults to
+    /// [`decode`](Codec::decode).
+    fn decode_key<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        Self::decode(bytes)
+    }
+
+    /// Encodes `node_key` as part of the RocksDB keys of the nodes column family and of the
+    /// indexes keyed by node. Defaults to [`encode_key`](Codec::encode_key).
+    fn encode_node_key(node_key: &NodeKey) -> Result<Vec<u8>> {
+        Self::encode_key(node_key)
+    }
+
+    /// Decodes a node key encoded by [`encode_node_key`](Codec::encode_node_key). Defaults to
+    /// [`decode_key`](Codec::decode_key).
+    fn decode_node_key(bytes: &[u8]) -> Result<NodeKey> {
+        Self::decode_key(bytes)
+    }
+}
+
+/// The [`Codec`] of [`RocksDbTreeStore`](super::RocksDbTreeStore): bincode with its default
+/// options.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BincodeCodec;
+
+impl Codec for BincodeCodec {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    fn encoded_size<T: Serialize + ?Sized>(value: &T) -> Result<u64> {
+        Ok(bincode::serialized_size(value)?)
+    }
+}
+
+/// A [`Codec`] encoding node keys with bincode configured for big-endian, fixed-width integers,
+/// and everything else as [`BincodeCodec`] does.
+///
+/// The default bincode options write integers little-endian, so the encoded node keys do not
+/// sort by version: the key of version 256 sorts before that of version 2. With this codec they
+/// sort by version, then by nibble path, so that the nodes written by a version are adjacent in
+/// the nodes column family and scans over a range of versions read them in order. Values keep
+/// the compact encoding of [`BincodeCodec`]. A store written with [`BincodeCodec`] cannot be
+/// reopened with this codec.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SortableKeyBincodeCodec;
+
+impl SortableKeyBincodeCodec {
+    /// The bincode options node keys are encoded with.
+    fn key_options() -> impl Options {
+        bincode::DefaultOptions::new()
+            .with_big_endian()
+            .with_fixint_encoding()
+    }
+}
+
+impl Codec for SortableKeyBincodeCodec {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        BincodeCodec::encode(value)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        BincodeCodec::decode(bytes)
+    }
+
+    fn encoded_size<T: Serialize + ?Sized>(value: &T) -> Result<u64> {
+        BincodeCodec::encoded_size(value)
+    }
+
+    fn encode_key<T: Serialize + ?Sized>(key: &T) -> Result<Vec<u8>> {
+        Ok(Self::key_options().serialize(key)?)
+    }
+
+    fn decode_key<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        Ok(Self::key_options().deserialize(bytes)?)
+    }
+}
+
+/// A [`Codec`] encoding node keys as [`OrderedNodeKey`]s, and everything else as
+/// [`BincodeCodec`] does.
+///
+/// The nodes column family then holds the nodes in the breadth-first order of the tree, the
+/// root of each version first, then the nodes one nibble down, and so on, the versions of a node
+/// being adjacent and oldest first. Unlike with a serde encoding, that order is documented by
+/// [`OrderedNodeKey`] rather than left to the format. A store written with another codec cannot
+/// be reopened with this one.
+///
+/// This codec is opt-in rather than the one of
+/// [`RocksDbTreeStore`](super::RocksDbTreeStore), to protect the on-disk format: the codec is not
+/// recorded in the database, so switching the default would leave every store written with
+/// [`BincodeCodec`] unreadable after an upgrade. A store is moved to this codec by exporting a
+/// version with [`export_snapshot`](super::GenericTreeStore::export_snapshot) and importing it
+/// with [`import_snapshot`](super::GenericTreeStore::import_snapshot) into a new
+/// `GenericTreeStore<OrderedKeyBincodeCodec, H>`; snapshots do not depend on the codec, but only
+/// carry the state of the version exported.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OrderedKeyBincodeCodec;
+
+impl Codec for OrderedKeyBincodeCodec {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        BincodeCodec::encode(value)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        BincodeCodec::decode(bytes)
+    }
+
+    fn encoded_size<T: Serialize + ?Sized>(value: &T) -> Result<u64> {
+        BincodeCodec::encoded_size(value)
+    }
+
+    fn encode_node_key(node_key: &NodeKey) -> Result<Vec<u8>> {
+        Ok(OrderedNodeKey::from(node_key.clone()).encode())
+    }
+
+    fn decode_node_key(bytes: &[u8]) -> Result<NodeKey> {
+        Ok(OrderedNodeKey::decode(bytes)?.into())
+    }
+}
@@ -0,0 +1,95 @@
+//! Callbacks alerted of the corrupted records the reads of a [`GenericTreeStore`] run into, see
+//! [`GenericTreeStore::on_corruption`].
+
+use super::{CfKind, Codec, GenericTreeStore};
+use crate::{node_type::NodeKey, SimpleHasher};
+use std::sync::PoisonError;
+
+/// A corrupted record run into by a read of a store, passed to the callbacks of
+/// [`GenericTreeStore::on_corruption`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorruptionEvent {
+    /// The column family read.
+    pub column_family: CfKind,
+    /// The raw key read, without the namespace of the store.
+    pub key: Vec<u8>,
+    /// What is wrong with the record.
+    pub cause: CorruptionCause,
+}
+
+/// What is wrong with the record of a [`CorruptionEvent`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CorruptionCause {
+    /// The record is stored but could not be decoded, for `reason`.
+    Undecodable { reason: String },
+    /// There is no record of the node `node_key`, although its parent references it.
+    MissingChild { node_key: NodeKey },
+}
+
+/// A callback invoked with every corrupted record a read runs into, see
+/// [`GenericTreeStore::on_corruption`].
+pub(super) type CorruptionHook = Box<dyn Fn(&CorruptionEvent) + Send + Sync>;
+
+impl<C: Codec, H: SimpleHasher> GenericTreeStore<C, H> {
+    /// Registers `f` to be called with every corrupted record the reads of the store run into,
+    /// so that corruption can be alerted on as soon as it is found rather than only where the
+    /// error the read fails with ends up.
+    ///
+    /// The point reads of nodes, values and preimages call it when the record they read does not
+    /// decode, and the reads of nodes through [`TreeReader::get_node`], which the tree makes for
+    /// the children its internal nodes reference, including those of proofs, when the node below
+    /// the root they look for is missing. The scans, the audits such as
+    /// [`verify_consistency`](GenericTreeStore::verify_consistency), which report what they find
+    /// themselves, and the lookups of nodes that may legitimately be missing do not.
+    ///
+    /// Callbacks run in registration order on the thread of the read, before it fails, and are
+    /// shared with the clones of the store. They cannot be unregistered, and must not register
+    /// callbacks themselves.
+    ///
+    /// [`TreeReader::get_node`]: crate::storage::TreeReader::get_node
+    pub fn on_corruption(&self, f: impl Fn(&CorruptionEvent) + Send + Sync + 'static) {
+        self.corruption_hooks
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Box::new(f));
+    }
+
+    /// Calls the corruption callbacks with the event built by `event`, if there are any.
+    pub(super) fn report_corruption(&self, event: impl FnOnce() -> CorruptionEvent) {
+        let hooks = self
+            .corruption_hooks
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if hooks.is_empty() {
+            return;
+        }
+        let event = event();
+        for hook in hooks.iter() {
+            hook(&event);
+        }
+    }
+
+    /// Calls the corruption callbacks for the record of `key`, without the namespace, in
+    /// `column_family` if `decoded` failed other than by a failure of RocksDB itself, such as
+    /// reading the content of a deduplicated node, and returns it.
+    pub(super) fn check_decoded<T>(
+        &self,
+        column_family: CfKind,
+        key: &[u8],
+        decoded: anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        if let Err(err) = &decoded {
+            if err.downcast_ref::<rocksdb::Error>().is_some() {
+                return decoded;
+            }
+            self.report_corruption(|| CorruptionEvent {
+                column_family,
+                key: key.to_vec(),
+                cause: CorruptionCause::Undecodable {
+                    reason: format!("{:#}", err),
+                },
+            });
+        }
+        decoded
+    }
+}
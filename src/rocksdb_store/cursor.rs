@@ -0,0 +1,168 @@
+//! Paginated scans of the values live at one version of a [`GenericTreeStore`], and the exports
+//! of those values built on them.
+
+use super::{
+    decode_value, decode_value_key, encode_node_key, encode_value_key, prefix_read_options, Codec,
+    GenericTreeStore, NODES_CF, VALUES_CF,
+};
+use crate::{
+    node_type::NodeKey, storage::HasPreimage, types::Version, KeyHash, MissingPreimageError,
+    MissingRootError, OwnedValue, SimpleHasher,
+};
+use anyhow::{ensure, Result};
+use rocksdb::{Direction, IteratorMode};
+use serde::{Deserialize, Serialize};
+
+/// A page of the values live at a version, with the cursor of the page after it, see
+/// [`GenericTreeStore::next_page`].
+pub type ValuePage = (Vec<(KeyHash, OwnedValue)>, Option<ValueCursor>);
+
+/// A key live at a version, with its value and its preimage if the store has it, see
+/// [`GenericTreeStore::export_leaves`].
+pub type ExportedLeaf = (KeyHash, OwnedValue, Option<Vec<u8>>);
+
+/// The number of values [`GenericTreeStore::export_leaves`] reads at once.
+const EXPORT_PAGE_LEN: usize = 1024;
+
+/// The position of a scan of the values live at a version, returned by
+/// [`GenericTreeStore::next_page`] with each page for the next one to resume after.
+///
+/// A cursor is a plain position rather than a handle on the store, so that it can be handed to a
+/// client, serialized, and brought back by a later request to the same or another handle on the
+/// store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValueCursor {
+    /// The version whose live values are scanned.
+    pub version: Version,
+    /// The last key hash returned, the scan resuming with the next one, or `None` before the
+    /// first page.
+    pub last_key_hash: Option<KeyHash>,
+}
+
+impl ValueCursor {
+    /// Returns a cursor at the start of the values live at `version`.
+    pub fn new(version: Version) -> Self {
+        ValueCursor {
+            version,
+            last_key_hash: None,
+        }
+    }
+}
+
+impl<C: Codec, H: SimpleHasher> GenericTreeStore<C, H> {
+    /// Returns the next values live at the version of `cursor` after its position, at most
+    /// `limit` of them in key hash order, with the cursor of the page after them, or `None` if
+    /// this page ends the scan.
+    ///
+    /// Each page is read from a snapshot of the database, so that the writes landing meanwhile do
+    /// not show up in it. Writes of newer versions leave the values live at a version as they
+    /// were, so paging through them yields the same set as a single scan, however many writes
+    /// land between the pages, unless the version is pruned, after which pages fail with
+    /// [`MissingRootError`]. Fails if `limit` is 0.
+    pub fn next_page(&self, cursor: &ValueCursor, limit: usize) -> Result<ValuePage> {
+        ensure!(limit > 0, "a page holds at least one value");
+        let version = cursor.version;
+        let snapshot = self.db.snapshot();
+        let root_key = self.key(&encode_node_key::<C>(&NodeKey::new_empty_path(version))?);
+        if snapshot.get_cf(self.cf(NODES_CF)?, root_key)?.is_none() {
+            return Err(anyhow::anyhow!(MissingRootError { version }));
+        }
+
+        let start = cursor
+            .last_key_hash
+            .map(|key_hash| self.key(&encode_value_key(key_hash, Version::MAX)));
+        let mode = match &start {
+            Some(start) => IteratorMode::From(start, Direction::Forward),
+            None => IteratorMode::Start,
+        };
+        let mut iter = snapshot.iterator_cf_opt(
+            self.cf(VALUES_CF)?,
+            prefix_read_options(self.namespace()),
+            mode,
+        );
+        let mut page = Vec::new();
+        // Entries of a key are adjacent and sorted by version, so the last one at or below
+        // `version` is known once the next key starts.
+        let mut current: Option<(KeyHash, Option<OwnedValue>)> = None;
+        loop {
+            let entry = match iter.next().transpose()? {
+                Some((key, value)) => Some((decode_value_key(self.unprefixed(&key))?, value)),
+                None => None,
+            };
+            let next_key_hash = entry.as_ref().map(|((key_hash, _), _)| *key_hash);
+            if let Some((key_hash, value)) = current.take() {
+                if next_key_hash == Some(key_hash) {
+                    current = Some((key_hash, value));
+                } else if let Some(value) = value {
+                    if page.len() == limit {
+                        let next_cursor = ValueCursor {
+                            version,
+                            last_key_hash: page.last().map(|(key_hash, _)| *key_hash),
+                        };
+                        return Ok((page, Some(next_cursor)));
+                    }
+                    page.push((key_hash, value));
+                }
+            }
+            let Some(((key_hash, entry_version), value)) = entry else {
+                return Ok((page, None));
+            };
+            // The last key returned may have an entry at the very version the scan starts from.
+            if entry_version <= version && Some(key_hash) != cursor.last_key_hash {
+                current = Some((key_hash, decode_value::<C>(&value)?));
+            }
+        }
+    }
+
+    /// Iterates over the keys live at `version` in key hash order, yielding each with its value
+    /// and its preimage, or `None` if the store has none.
+    ///
+    /// This is the logical content of the tree at `version`, without its nodes: feeding the keys
+    /// and values to [`put_value_set`](crate::JellyfishMerkleTree::put_value_set) on an empty
+    /// tree rebuilds the same tree, under the same root hash if it is hashed the same way, and
+    /// the preimages give the keys to hash again when it is not. The values are read
+    /// [`EXPORT_PAGE_LEN`] at a time with [`next_page`](GenericTreeStore::next_page), so that
+    /// the writes landing meanwhile do not change what is exported. If `version` is not readable,
+    /// the only item is a [`MissingRootError`].
+    ///
+    /// A missing preimage is not an error, since nothing requires a writer to record them: the
+    /// keys without one are exported all the same, by key hash only. Use
+    /// [`export_leaves_strict`](GenericTreeStore::export_leaves_strict) for exports that must be
+    /// readable by key.
+    pub fn export_leaves(
+        &self,
+        version: Version,
+    ) -> impl Iterator<Item = Result<ExportedLeaf>> + '_ {
+        let mut cursor = Some(ValueCursor::new(version));
+        let mut page = Vec::new().into_iter();
+        std::iter::from_fn(move || loop {
+            if let Some((key_hash, value)) = page.next() {
+                return Some(
+                    self.preimage(key_hash)
+                        .map(|preimage| (key_hash, value, preimage)),
+                );
+            }
+            match self.next_page(&cursor.take()?, EXPORT_PAGE_LEN) {
+                Ok((values, next_cursor)) => {
+                    page = values.into_iter();
+                    cursor = next_cursor;
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        })
+    }
+
+    /// Same as [`export_leaves`](GenericTreeStore::export_leaves), yielding a
+    /// [`MissingPreimageError`] in place of every key whose preimage the store does not have, so
+    /// that the preimages of the keys yielded are never `None`.
+    pub fn export_leaves_strict(
+        &self,
+        version: Version,
+    ) -> impl Iterator<Item = Result<ExportedLeaf>> + '_ {
+        self.export_leaves(version).map(|leaf| {
+            let leaf = leaf?;
+            ensure!(leaf.2.is_some(), MissingPreimageError { key_hash: leaf.0 });
+            Ok(leaf)
+        })
+    }
+}
@@ -0,0 +1,52 @@
+//! Syncs of the write-ahead log grouped over an interval, see
+//! [`RocksDbStoreConfig::wal_sync_interval`](super::RocksDbStoreConfig::wal_sync_interval).
+
+use anyhow::Result;
+use rocksdb::DB;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Weak;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A thread syncing the write-ahead log of a database every interval, until it is dropped or the
+/// database is closed.
+///
+/// Dropping it stops the thread and waits for it to exit, so that the thread does not keep the
+/// database open past the last store using it.
+pub(super) struct WalSyncer {
+    /// Dropped to stop the thread.
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WalSyncer {
+    /// Starts a thread syncing the write-ahead log of `db` every `interval`.
+    pub(super) fn spawn(db: Weak<DB>, interval: Duration) -> Result<Self> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::Builder::new()
+            .name("jmt-rocksdb-wal-sync".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let Some(db) = db.upgrade() else {
+                        break;
+                    };
+                    // A failed sync is tried again at the next tick, callers wanting to know
+                    // call `sync_now`.
+                    let _ = db.flush_wal(true);
+                }
+            })?;
+        Ok(WalSyncer {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for WalSyncer {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
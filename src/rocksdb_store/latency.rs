@@ -0,0 +1,97 @@
+//! A histogram of the durations of the batch writes of a
+//! [`GenericTreeStore`](super::GenericTreeStore), kept with the `latency_histogram` feature.
+
+use super::WriteLatency;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The number of buckets each power of two is split into, which bounds the error of the
+/// percentiles to an eighth of their value.
+const SUB_BUCKETS: u64 = 8;
+
+/// The number of buckets covering every duration in microseconds that fits a `u64`.
+const BUCKETS: usize = 62 * SUB_BUCKETS as usize;
+
+/// Counts of durations, in microseconds, in buckets of logarithmic width: durations below
+/// [`SUB_BUCKETS`] have a bucket each, and every power of two above is split in [`SUB_BUCKETS`]
+/// buckets of equal width.
+///
+/// Recording only increments two atomics, so that concurrent writes do not wait for each other.
+pub(super) struct LatencyHistogram {
+    buckets: Box<[AtomicU64]>,
+    max_us: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            max_us: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Records a write that took `duration`.
+    pub(super) fn record(&self, duration: Duration) {
+        let us = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket(us)].fetch_add(1, Ordering::Relaxed);
+        self.max_us.fetch_max(us, Ordering::Relaxed);
+    }
+
+    /// Returns the percentiles of the durations recorded so far, each the upper bound of the
+    /// bucket holding it, capped by the longest duration.
+    pub(super) fn percentiles(&self) -> WriteLatency {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        let count = counts.iter().sum();
+        let max_us = self.max_us.load(Ordering::Relaxed);
+        let percentile = |quantile: f64| {
+            if count == 0 {
+                return 0;
+            }
+            let rank = ((quantile * count as f64).ceil() as u64).clamp(1, count);
+            let mut seen = 0;
+            for (index, bucket_count) in counts.iter().enumerate() {
+                seen += bucket_count;
+                if seen >= rank {
+                    return upper_bound(index).min(max_us);
+                }
+            }
+            max_us
+        };
+        WriteLatency {
+            count,
+            p50_us: percentile(0.5),
+            p90_us: percentile(0.9),
+            p99_us: percentile(0.99),
+            p999_us: percentile(0.999),
+            max_us,
+        }
+    }
+}
+
+/// Returns the bucket of a duration of `us` microseconds.
+fn bucket(us: u64) -> usize {
+    if us < SUB_BUCKETS {
+        return us as usize;
+    }
+    // The power of two, at least 3, below `us`, and the sub-bucket of `us` within it.
+    let exponent = u64::from(63 - us.leading_zeros());
+    let sub_bucket = (us >> (exponent - 3)) & (SUB_BUCKETS - 1);
+    ((exponent - 2) * SUB_BUCKETS + sub_bucket) as usize
+}
+
+/// Returns the longest duration, in microseconds, of the bucket `index`.
+fn upper_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let lower = (SUB_BUCKETS + index % SUB_BUCKETS) << shift;
+    lower.saturating_add((1 << shift) - 1)
+}
@@ -0,0 +1,37 @@
+//! Lazy reclamation of pruned nodes by a compaction filter on the nodes column family, see
+//! [`RocksDbStoreConfig::lazy_pruning`](super::RocksDbStoreConfig::lazy_pruning).
+
+use rocksdb::compaction_filter::Decision;
+use std::collections::HashSet;
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Name under which the filter is registered with RocksDB.
+pub(super) const FILTER_NAME: &str = "jmt_reclaimable_nodes";
+
+/// The encoded keys of the nodes no readable version needs anymore, which the compaction filter
+/// drops the next time it comes across them.
+#[derive(Default)]
+pub(super) struct ReclaimableNodes {
+    keys: RwLock<HashSet<Vec<u8>>>,
+}
+
+impl ReclaimableNodes {
+    /// Replaces the reclaimable nodes with `keys`.
+    pub(super) fn replace(&self, keys: HashSet<Vec<u8>>) {
+        *self.keys.write().unwrap_or_else(PoisonError::into_inner) = keys;
+    }
+
+    /// Returns the compaction filter dropping the reclaimable nodes.
+    pub(super) fn compaction_filter(
+        self: Arc<Self>,
+    ) -> impl FnMut(u32, &[u8], &[u8]) -> Decision + Send + 'static {
+        move |_level, key, _value| {
+            let keys = self.keys.read().unwrap_or_else(PoisonError::into_inner);
+            if keys.contains(key) {
+                Decision::Remove
+            } else {
+                Decision::Keep
+            }
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Merges of deltas into the values of a [`GenericTreeStore`](super::GenericTreeStore), see
+//! [`RocksDbStoreConfig::value_merge_operator`](super::RocksDbStoreConfig::value_merge_operator).
+
+use super::{ttl, RecordKind};
+use crate::OwnedValue;
+use anyhow::Result;
+use rocksdb::MergeOperands;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The name of the merge operator of the values column family.
+pub(super) const OPERATOR_NAME: &str = "jmt_value_merge";
+
+/// Combines a value, or `None` if the key has no value, with deltas, oldest first, into the new
+/// value, see [`RocksDbStoreConfig::value_merge_operator`](super::RocksDbStoreConfig::value_merge_operator).
+pub type ValueMergeOperator = Arc<dyn Fn(Option<&[u8]>, &[&[u8]]) -> OwnedValue + Send + Sync>;
+
+/// Returns the full merge function of the values column family, applying `operator` to the
+/// value decoded by `decode` from the record merged into, and framing its result as a value
+/// encoded by `encode`, expiring with `ttl`.
+///
+/// A record that does not decode fails the merge, and with it the read of the value.
+pub(super) fn full_merge(
+    operator: ValueMergeOperator,
+    decode: fn(&[u8]) -> Result<Option<OwnedValue>>,
+    encode: fn(&Option<OwnedValue>) -> Result<Vec<u8>>,
+    ttl: Option<Duration>,
+) -> impl Fn(&[u8], Option<&[u8]>, &MergeOperands) -> Option<Vec<u8>> + Send + Sync + 'static {
+    move |_key, existing, operands| {
+        let existing = match existing {
+            Some(record) => decode(record).ok()?,
+            None => None,
+        };
+        let deltas: Vec<&[u8]> = operands.iter().collect();
+        let merged = operator(existing.as_deref(), &deltas);
+        let payload = encode(&Some(merged)).ok()?;
+        Some(ttl::frame_expiring(RecordKind::Value, &payload, ttl))
+    }
+}
+
+/// The partial merge function of the values column family, which leaves the deltas to the full
+/// merge, the operator only knowing how to apply them to a value.
+pub(super) fn partial_merge(
+    _key: &[u8],
+    _existing: Option<&[u8]>,
+    _operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    None
+}
@@ -0,0 +1,152 @@
+//! Migration of the stores written in the legacy layout, every entry in the default column family
+//! under keys telling the kinds of entries apart only by how they decode, to the column families
+//! of the current layout, see [`GenericTreeStore::migrate_layout`].
+
+use super::{
+    frame, ttl, unframe, Codec, GenericTreeStore, RecordKind, RocksDbStoreConfig,
+    LAYOUT_VERSION_KEY, PREIMAGES_CF,
+};
+use crate::{
+    node_type::{Node, NodeKey},
+    storage::NodeBatch,
+    types::Version,
+    KeyHash, OwnedValue, SimpleHasher,
+};
+use anyhow::Result;
+use bincode::Options;
+use rocksdb::{IteratorMode, WriteBatch};
+use serde::de::DeserializeOwned;
+
+/// The version of the layout of the column families, recorded by a migration under
+/// [`LAYOUT_VERSION_KEY`].
+const LAYOUT_VERSION: u8 = 1;
+
+/// The number of legacy entries a migration moves per write.
+const MIGRATE_BATCH_SIZE: usize = 1024;
+
+/// The suffix of the keys of the legacy preimages, after the key hash.
+const LEGACY_PREIMAGE_TAG: &str = "preimage";
+
+/// A legacy entry, as its key and value decode.
+enum LegacyEntry {
+    Node(NodeKey, Node),
+    Value(KeyHash, Version, Option<OwnedValue>),
+    Preimage(KeyHash, Vec<u8>),
+}
+
+impl<C: Codec, H: SimpleHasher> GenericTreeStore<C, H> {
+    /// Migrates the store at `path` from the legacy layout, which kept every entry in the default
+    /// column family, to the current one, and records the version of the layout in the metadata.
+    ///
+    /// Each entry of the default column family is classified by decoding its key and value as
+    /// the legacy code encoded them with bincode: a node key and a node, a key hash and version
+    /// and an optional value, or a key hash tagged `preimage` and its preimage. It is then
+    /// written to its column family in the current encoding and deleted from the default one, in
+    /// the same write. An entry decoding both as a node and as a value, which the legacy keys
+    /// cannot rule out, is taken as a node and logged through `tracing` at the warning level;
+    /// entries decoding as neither are left in place and logged the same way.
+    ///
+    /// The latest version becomes the newest version of the nodes moved. The legacy layout had no
+    /// stale node index, so pruning never reclaims the nodes moved. A migration interrupted
+    /// midway resumes with the entries not moved yet when run again, and a store whose layout is
+    /// already recorded is left as it is. The store must not be open meanwhile.
+    pub fn migrate_layout<P: AsRef<std::path::Path>>(path: P) -> Result<()> {
+        let store = Self::with_hasher(path, RocksDbStoreConfig::default())?;
+        let metadata_cf = store.metadata_cf()?;
+        let layout_key = store.key(LAYOUT_VERSION_KEY);
+        if let Some(layout) = store.db.get_cf(metadata_cf, &layout_key)? {
+            unframe(RecordKind::LayoutVersion, &layout)?;
+            return Ok(());
+        }
+
+        let mut entries = store.db.iterator(IteratorMode::Start);
+        loop {
+            let mut write_batch = WriteBatch::default();
+            let mut node_batch = NodeBatch::default();
+            let mut max_version = None;
+            let mut moved = 0;
+            for item in entries.by_ref() {
+                let (key, value) = item?;
+                match classify(&key, &value) {
+                    Some(LegacyEntry::Node(node_key, node)) => {
+                        max_version = max_version.max(Some(node_key.version()));
+                        node_batch.insert_node(node_key, node);
+                    }
+                    Some(LegacyEntry::Value(key_hash, version, value)) => {
+                        node_batch.extend(None, [((version, key_hash), value)]);
+                    }
+                    Some(LegacyEntry::Preimage(key_hash, preimage)) => {
+                        write_batch.put_cf(
+                            store.cf(PREIMAGES_CF)?,
+                            store.key(&frame(RecordKind::KeyHash, &key_hash.0)),
+                            ttl::frame_expiring(RecordKind::Preimage, &preimage, None),
+                        );
+                    }
+                    None => {
+                        tracing::warn!(
+                            key = %hex::encode(&key),
+                            "left a legacy entry that decodes as no known entry in place"
+                        );
+                        continue;
+                    }
+                }
+                write_batch.delete(&key);
+                moved += 1;
+                if moved == MIGRATE_BATCH_SIZE {
+                    break;
+                }
+            }
+            store.stage_node_batch(&mut write_batch, &node_batch)?;
+            if let Some(max_version) = max_version {
+                store.stage_latest_version(&mut write_batch, max_version)?;
+            }
+            if moved < MIGRATE_BATCH_SIZE {
+                write_batch.put_cf(
+                    metadata_cf,
+                    &layout_key,
+                    frame(RecordKind::LayoutVersion, &[LAYOUT_VERSION]),
+                );
+                store.db.write(write_batch)?;
+                return Ok(());
+            }
+            store.db.write(write_batch)?;
+        }
+    }
+}
+
+/// Returns the legacy entry that `key` and `value` decode as, if any, taking an entry that
+/// decodes as both a node and a value as a node.
+fn classify(key: &[u8], value: &[u8]) -> Option<LegacyEntry> {
+    let node = decode::<NodeKey>(key).zip(decode::<Node>(value));
+    let value_entry = decode::<(KeyHash, Version)>(key).zip(decode::<Option<OwnedValue>>(value));
+    match (node, value_entry) {
+        (Some((node_key, node)), value_entry) => {
+            if value_entry.is_some() {
+                tracing::warn!(
+                    key = %hex::encode(key),
+                    "migrated a legacy entry that decodes both as a node and as a value as a node"
+                );
+            }
+            Some(LegacyEntry::Node(node_key, node))
+        }
+        (None, Some(((key_hash, version), value))) => {
+            Some(LegacyEntry::Value(key_hash, version, value))
+        }
+        (None, None) => match decode::<(KeyHash, String)>(key) {
+            Some((key_hash, tag)) if tag == LEGACY_PREIMAGE_TAG => {
+                Some(LegacyEntry::Preimage(key_hash, value.to_vec()))
+            }
+            _ => None,
+        },
+    }
+}
+
+/// Decodes `bytes` as the legacy code encoded a `T`, with bincode's default options, rejecting
+/// trailing bytes, which would mean `bytes` encode something else.
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(bytes)
+        .ok()
+}
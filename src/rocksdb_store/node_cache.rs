@@ -0,0 +1,227 @@
+//! A bounded cache of the nodes read by a [`RocksDbTreeStore`](super::RocksDbTreeStore), see
+//! [`RocksDbStoreConfig::node_cache_capacity`](super::RocksDbStoreConfig::node_cache_capacity).
+
+use crate::{node_type::NodeKey, storage::NodeWithValue};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// How a full node cache picks the node to drop for a new one, see
+/// [`RocksDbStoreConfig::node_cache_policy`](super::RocksDbStoreConfig::node_cache_policy).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NodeCachePolicy {
+    /// Drops the node read least recently.
+    #[default]
+    Lru,
+    /// Drops the node read the fewest times since it was cached, the least recently read of
+    /// those on a tie.
+    Lfu,
+}
+
+impl NodeCachePolicy {
+    /// Returns an eviction policy tracking no node yet.
+    fn build(self) -> Box<dyn EvictionPolicy> {
+        match self {
+            NodeCachePolicy::Lru => Box::<Lru>::default(),
+            NodeCachePolicy::Lfu => Box::<Lfu>::default(),
+        }
+    }
+}
+
+/// The bookkeeping a [`NodeCache`] consults to pick the node to drop once full.
+///
+/// A policy tracks exactly the keys of the cached nodes: the cache tells it of every node it
+/// caches, reads or drops.
+pub(super) trait EvictionPolicy: Send {
+    /// Starts tracking `node_key`, just cached.
+    fn insert(&mut self, node_key: &NodeKey);
+    /// Records a read of `node_key`, served from the cache.
+    fn touch(&mut self, node_key: &NodeKey);
+    /// Stops tracking `node_key`, dropped from the cache.
+    fn remove(&mut self, node_key: &NodeKey);
+    /// Stops tracking the key of the node to drop for a new one and returns it, or `None` if it
+    /// tracks none.
+    fn evict(&mut self) -> Option<NodeKey>;
+    /// Stops tracking every key.
+    fn clear(&mut self);
+}
+
+/// Tracks keys by the tick of their last read, to drop the least recently read.
+#[derive(Default)]
+struct Lru {
+    tick: u64,
+    ticks: HashMap<NodeKey, u64>,
+    by_tick: BTreeMap<u64, NodeKey>,
+}
+
+impl EvictionPolicy for Lru {
+    fn insert(&mut self, node_key: &NodeKey) {
+        self.touch(node_key);
+    }
+
+    fn touch(&mut self, node_key: &NodeKey) {
+        self.tick += 1;
+        if let Some(tick) = self.ticks.insert(node_key.clone(), self.tick) {
+            self.by_tick.remove(&tick);
+        }
+        self.by_tick.insert(self.tick, node_key.clone());
+    }
+
+    fn remove(&mut self, node_key: &NodeKey) {
+        if let Some(tick) = self.ticks.remove(node_key) {
+            self.by_tick.remove(&tick);
+        }
+    }
+
+    fn evict(&mut self) -> Option<NodeKey> {
+        let (_, node_key) = self.by_tick.pop_first()?;
+        self.ticks.remove(&node_key);
+        Some(node_key)
+    }
+
+    fn clear(&mut self) {
+        self.ticks.clear();
+        self.by_tick.clear();
+    }
+}
+
+/// Tracks keys by their read count and the tick of their last read, to drop the least
+/// frequently read.
+#[derive(Default)]
+struct Lfu {
+    tick: u64,
+    ranks: HashMap<NodeKey, (u64, u64)>,
+    by_rank: BTreeMap<(u64, u64), NodeKey>,
+}
+
+impl EvictionPolicy for Lfu {
+    fn insert(&mut self, node_key: &NodeKey) {
+        self.touch(node_key);
+    }
+
+    fn touch(&mut self, node_key: &NodeKey) {
+        self.tick += 1;
+        let reads = match self.ranks.get(node_key) {
+            Some(rank) => {
+                self.by_rank.remove(rank);
+                rank.0 + 1
+            }
+            None => 1,
+        };
+        let rank = (reads, self.tick);
+        self.ranks.insert(node_key.clone(), rank);
+        self.by_rank.insert(rank, node_key.clone());
+    }
+
+    fn remove(&mut self, node_key: &NodeKey) {
+        if let Some(rank) = self.ranks.remove(node_key) {
+            self.by_rank.remove(&rank);
+        }
+    }
+
+    fn evict(&mut self) -> Option<NodeKey> {
+        let (_, node_key) = self.by_rank.pop_first()?;
+        self.ranks.remove(&node_key);
+        Some(node_key)
+    }
+
+    fn clear(&mut self) {
+        self.ranks.clear();
+        self.by_rank.clear();
+    }
+}
+
+/// The cached nodes, with the policy tracking them.
+struct Entries {
+    nodes: HashMap<NodeKey, NodeWithValue>,
+    policy: Box<dyn EvictionPolicy>,
+}
+
+/// Nodes keyed by their node key, at most `capacity` of them, with the hit and miss counts of
+/// the lookups.
+///
+/// Unlike the proof cache, every lookup takes the lock of the entries, since a hit is recorded
+/// by the eviction policy.
+pub(super) struct NodeCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl NodeCache {
+    /// Creates an empty cache holding at most `capacity` nodes, evicting them with `policy`.
+    pub(super) fn new(capacity: usize, policy: NodeCachePolicy) -> Self {
+        NodeCache {
+            capacity,
+            entries: Mutex::new(Entries {
+                nodes: HashMap::new(),
+                policy: policy.build(),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the node cached for `node_key`, counting the lookup as a hit or a miss.
+    pub(super) fn get(&self, node_key: &NodeKey) -> Option<NodeWithValue> {
+        let mut entries = self.lock();
+        let Some(node) = entries.nodes.get(node_key).cloned() else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        entries.policy.touch(node_key);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(node)
+    }
+
+    /// Caches `node` for `node_key`, first dropping the node picked by the policy if the cache is
+    /// full.
+    pub(super) fn insert(&self, node_key: &NodeKey, node: NodeWithValue) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.lock();
+        if entries.nodes.contains_key(node_key) {
+            return;
+        }
+        if entries.nodes.len() >= self.capacity {
+            if let Some(evicted) = entries.policy.evict() {
+                entries.nodes.remove(&evicted);
+            }
+        }
+        entries.policy.insert(node_key);
+        entries.nodes.insert(node_key.clone(), node);
+    }
+
+    /// Drops the nodes cached for `node_keys`.
+    pub(super) fn remove<'a>(&self, node_keys: impl IntoIterator<Item = &'a NodeKey>) {
+        let mut entries = self.lock();
+        for node_key in node_keys {
+            if entries.nodes.remove(node_key).is_some() {
+                entries.policy.remove(node_key);
+            }
+        }
+    }
+
+    /// Drops every cached node. The hit and miss counts are kept.
+    pub(super) fn clear(&self) {
+        let mut entries = self.lock();
+        entries.nodes.clear();
+        entries.policy.clear();
+    }
+
+    /// Returns the hit count, the miss count and the number of cached nodes.
+    pub(super) fn stats(&self) -> (u64, u64, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+            self.lock().nodes.len(),
+        )
+    }
+
+    // The entries are valid whatever panicked while the lock was held.
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
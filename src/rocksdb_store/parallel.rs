@@ -0,0 +1,172 @@
+//! Walks of the tree at one version of a [`GenericTreeStore`] fanned out to the rayon thread
+//! pool, one task per child of the root.
+
+use super::{
+    check_subtree, decode_node_record, encode_node_key, seek_value,
+    snapshot::{export_subtree, write_end, write_header, write_node_record},
+    Codec, GenericTreeStore, Inconsistency, NODES_CF, NODE_CONTENTS_CF, VALUES_CF,
+};
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    storage::TreeReader,
+    types::Version,
+    KeyHash, MissingRootError, OwnedValue, SimpleHasher,
+};
+use anyhow::Result;
+use rayon::prelude::*;
+use rocksdb::Snapshot;
+use std::io::Write;
+
+impl<C: Codec + Sync, H: SimpleHasher + Sync> GenericTreeStore<C, H> {
+    /// Writes the same snapshot as [`export_snapshot`](GenericTreeStore::export_snapshot), the
+    /// subtrees under the root being read and encoded in parallel.
+    ///
+    /// Every subtree is buffered in memory before being written, so memory use grows with the
+    /// size of the tree rather than its depth.
+    pub fn par_export_snapshot<W: Write>(&self, version: Version, mut writer: W) -> Result<()> {
+        let snapshot = self.db.snapshot();
+        let reader = SnapshotReader::new(self, &snapshot);
+        let root_key = NodeKey::new_empty_path(version);
+        let root = reader
+            .get_node_option(&root_key)?
+            .ok_or_else(|| anyhow::anyhow!(MissingRootError { version }))?;
+
+        write_header(&mut writer, version)?;
+        let Node::Internal(internal_node) = &root else {
+            let counts = export_subtree(&reader, version, root_key, &mut writer)?;
+            return write_end(&mut writer, counts);
+        };
+
+        let child_keys: Vec<_> = internal_node
+            .children_sorted()
+            .map(|(nibble, child)| root_key.gen_child_node_key(child.version, nibble))
+            .collect();
+        // The serial walk visits the children in descending nibble order.
+        let subtrees = child_keys
+            .into_par_iter()
+            .rev()
+            .map(|child_key| {
+                let reader = SnapshotReader::new(self, &snapshot);
+                let mut records = Vec::new();
+                let counts = export_subtree(&reader, version, child_key, &mut records)?;
+                Ok((records, counts))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        write_node_record(&mut writer, root_key, root)?;
+        let (mut node_count, mut value_count) = (1u64, 0u64);
+        for (records, (nodes, values)) in subtrees {
+            writer.write_all(&records)?;
+            node_count += nodes;
+            value_count += values;
+        }
+        write_end(&mut writer, (node_count, value_count))
+    }
+
+    /// Returns the same inconsistencies as
+    /// [`verify_consistency`](GenericTreeStore::verify_consistency), in the same order, the
+    /// subtrees under the root being walked in parallel.
+    pub fn par_verify_consistency(&self, version: Version) -> Result<Vec<Inconsistency>> {
+        let snapshot = self.db.snapshot();
+        let reader = SnapshotReader::new(self, &snapshot);
+        let root_key = NodeKey::new_empty_path(version);
+        let root = match reader.get_node_option(&root_key)? {
+            Some(root) => root,
+            None => return Ok(vec![Inconsistency::MissingRoot { version }]),
+        };
+
+        let mut inconsistencies = Vec::new();
+        let latest_version = self.latest_version()?;
+        if latest_version.is_none_or(|latest| latest < version) {
+            inconsistencies.push(Inconsistency::LatestVersionBehind {
+                version,
+                latest_version,
+            });
+        }
+        let Node::Internal(internal_node) = &root else {
+            check_subtree(&reader, version, root_key, root, &mut inconsistencies)?;
+            return Ok(inconsistencies);
+        };
+
+        // As in the serial walk, the missing children of the root come first, then the subtrees
+        // in descending nibble order.
+        let mut children = Vec::new();
+        for (nibble, child) in internal_node.children_sorted() {
+            let child_key = root_key.gen_child_node_key(child.version, nibble);
+            match reader.get_node_option(&child_key)? {
+                Some(child_node) => children.push((child_key, child_node)),
+                None => inconsistencies.push(Inconsistency::MissingChild {
+                    parent: root_key.clone(),
+                    nibble: u8::from(nibble),
+                    child: child_key,
+                }),
+            }
+        }
+        let subtrees = children
+            .into_par_iter()
+            .rev()
+            .map(|(child_key, child_node)| {
+                let reader = SnapshotReader::new(self, &snapshot);
+                let mut found = Vec::new();
+                check_subtree(&reader, version, child_key, child_node, &mut found)?;
+                Ok(found)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        inconsistencies.extend(subtrees.into_iter().flatten());
+        Ok(inconsistencies)
+    }
+}
+
+/// A [`TreeReader`] over a RocksDB snapshot of a store.
+///
+/// Every worker of a parallel walk reads through a reader of its own, creating its own iterators,
+/// while sharing one snapshot so that all of them see the same state of the store.
+struct SnapshotReader<'a, C: Codec, H: SimpleHasher> {
+    store: &'a GenericTreeStore<C, H>,
+    snapshot: &'a Snapshot<'a>,
+}
+
+impl<'a, C: Codec, H: SimpleHasher> SnapshotReader<'a, C, H> {
+    fn new(store: &'a GenericTreeStore<C, H>, snapshot: &'a Snapshot<'a>) -> Self {
+        Self { store, snapshot }
+    }
+}
+
+impl<C: Codec, H: SimpleHasher> TreeReader for SnapshotReader<'_, C, H> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        let key = self.store.key(&encode_node_key::<C>(node_key)?);
+        let contents_cf = self.store.cf(NODE_CONTENTS_CF)?;
+        match self.snapshot.get_cf(self.store.cf(NODES_CF)?, key)? {
+            Some(value) => Ok(Some(decode_node_record::<C>(&value, |key| {
+                Ok(self.snapshot.get_cf(contents_cf, self.store.key(key))?)
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.store.get_rightmost_leaf()
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        let iter = self.snapshot.raw_iterator_cf_opt(
+            self.store.cf(VALUES_CF)?,
+            self.store.value_read_options(key_hash),
+        );
+        Ok(
+            // The walks report what they find themselves, as the serial ones do.
+            seek_value::<C>(
+                iter,
+                self.store.namespace(),
+                max_version,
+                key_hash,
+                |_, decoded| decoded,
+            )?
+            .map(|(_, value)| value),
+        )
+    }
+}
@@ -0,0 +1,148 @@
+//! Proof requests of a [`GenericTreeStore`] coalesced over a window and served by version, see
+//! [`GenericTreeStore::spawn_proof_batcher`].
+
+use super::{Codec, GenericTreeStore, ProofResult};
+use crate::{types::Version, KeyHash, SimpleHasher};
+use anyhow::{format_err, Result};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A request waiting to be served, with the sender its outcome is reported through.
+type Request<H> = (KeyHash, Version, Sender<Result<ProofResult<H>>>);
+
+/// A handle on a thread serving proof requests in batches, created by
+/// [`GenericTreeStore::spawn_proof_batcher`].
+///
+/// The thread waits for a request, then gathers the requests arriving within the window that
+/// follows it, groups them by version and serves each group with a single
+/// [`get_with_proof_batch`](GenericTreeStore::get_with_proof_batch), so that concurrent lookups
+/// at the same version share the reads of the nodes their paths have in common. Dropping the
+/// handle serves the requests still queued and waits for the thread to exit.
+pub struct ProofBatcher<H: SimpleHasher> {
+    sender: Option<Sender<Request<H>>>,
+    passes: Arc<AtomicU64>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<H: SimpleHasher + Send + 'static> ProofBatcher<H> {
+    /// Starts a thread serving proofs from `store`, coalescing the requests arriving within
+    /// `window` of the first one of a batch.
+    pub(super) fn spawn<C: Codec + Send + 'static>(
+        store: GenericTreeStore<C, H>,
+        window: Duration,
+    ) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel::<Request<H>>();
+        let passes = Arc::new(AtomicU64::new(0));
+        let thread_passes = Arc::clone(&passes);
+        let thread = thread::Builder::new()
+            .name("jmt-rocksdb-proof-batcher".to_string())
+            .spawn(move || {
+                while let Ok(first) = receiver.recv() {
+                    let deadline = Instant::now() + window;
+                    let mut by_version: BTreeMap<Version, Vec<_>> = BTreeMap::new();
+                    let mut request = Some(first);
+                    while let Some((key, version, done)) = request.take() {
+                        by_version.entry(version).or_default().push((key, done));
+                        let timeout = deadline.saturating_duration_since(Instant::now());
+                        // The requests gathered are still served once the handle is gone.
+                        request = receiver.recv_timeout(timeout).ok();
+                    }
+                    for (version, waiters) in by_version {
+                        // Counted before the waiters are answered, so that they see the pass.
+                        thread_passes.fetch_add(1, Ordering::Relaxed);
+                        serve(&store, version, waiters);
+                    }
+                }
+            })?;
+        Ok(ProofBatcher {
+            sender: Some(sender),
+            passes,
+            thread: Some(thread),
+        })
+    }
+
+    /// Queues a request for the value of `key` at `version` and its proof, and returns a
+    /// receiver getting the outcome once the batch it joins is served.
+    ///
+    /// The outcome is that of [`get_with_proof`](GenericTreeStore::get_with_proof) for the same
+    /// arguments. A batch that fails fails every request of its version with the same message.
+    /// Fails if the batcher thread is gone.
+    pub fn request(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<Receiver<Result<ProofResult<H>>>> {
+        let (done, receiver) = mpsc::channel();
+        self.sender
+            .as_ref()
+            .expect("the sender is only taken when the handle is dropped")
+            .send((key, version, done))
+            .map_err(|_| format_err!("the proof batcher thread has stopped"))?;
+        Ok(receiver)
+    }
+
+    /// Requests the value of `key` at `version` and its proof, as by
+    /// [`request`](ProofBatcher::request), and blocks until it is served.
+    pub fn get_with_proof(&self, key: KeyHash, version: Version) -> Result<ProofResult<H>> {
+        self.request(key, version)?
+            .recv()
+            .map_err(|_| format_err!("the proof batcher thread has stopped"))?
+    }
+
+    /// Returns the number of batched passes served so far, one per version of every batch.
+    pub fn passes(&self) -> u64 {
+        self.passes.load(Ordering::Relaxed)
+    }
+
+    /// Serves the requests still queued, stops the thread and waits for it to exit.
+    ///
+    /// Fails if the thread panicked.
+    pub fn close(mut self) -> Result<()> {
+        self.shut_down()
+    }
+}
+
+impl<H: SimpleHasher> ProofBatcher<H> {
+    fn shut_down(&mut self) -> Result<()> {
+        // Closing the queue ends the loop of the thread once it is drained.
+        self.sender.take();
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| format_err!("the proof batcher thread panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<H: SimpleHasher> Drop for ProofBatcher<H> {
+    fn drop(&mut self) {
+        let _ = self.shut_down();
+    }
+}
+
+/// Serves the requests `waiters` for keys at `version` with a single batched lookup.
+fn serve<C: Codec, H: SimpleHasher>(
+    store: &GenericTreeStore<C, H>,
+    version: Version,
+    waiters: Vec<(KeyHash, Sender<Result<ProofResult<H>>>)>,
+) {
+    let keys: Vec<KeyHash> = waiters.iter().map(|(key, _)| *key).collect();
+    // The requesters may have dropped their receivers, they then do not care.
+    match store.get_with_proof_batch(&keys, version) {
+        Ok(results) => {
+            for ((_, done), result) in waiters.into_iter().zip(results) {
+                let _ = done.send(Ok(result));
+            }
+        }
+        Err(err) => {
+            for (_, done) in waiters {
+                let _ = done.send(Err(format_err!("{:#}", err)));
+            }
+        }
+    }
+}
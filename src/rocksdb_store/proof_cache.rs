@@ -0,0 +1,80 @@
+//! A cache of the proofs served by a [`RocksDbTreeStore`](super::RocksDbTreeStore).
+
+use crate::{types::Version, KeyHash};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Serialized proofs keyed by the key and version they were computed for.
+type Entries = HashMap<(KeyHash, Version), Vec<u8>>;
+
+/// Serialized proofs keyed by the key and version they were computed for, with the hit and miss
+/// counts of the lookups.
+///
+/// Lookups only take the read lock of the entries and count themselves atomically, so that they
+/// do not wait for each other.
+pub(super) struct ProofCache {
+    capacity: usize,
+    entries: RwLock<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ProofCache {
+    /// Creates an empty cache holding at most `capacity` proofs.
+    pub(super) fn new(capacity: usize) -> Self {
+        ProofCache {
+            capacity,
+            entries: RwLock::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the proof cached for `key` at `version`, counting the lookup as a hit or a miss.
+    pub(super) fn get(&self, key: KeyHash, version: Version) -> Option<Vec<u8>> {
+        let entry = self.read().get(&(key, version)).cloned();
+        let counter = match entry {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        entry
+    }
+
+    /// Caches `proof` for `key` at `version`, unless the cache is full.
+    pub(super) fn insert(&self, key: KeyHash, version: Version, proof: Vec<u8>) {
+        let mut entries = self.write();
+        if entries.len() < self.capacity {
+            entries.insert((key, version), proof);
+        }
+    }
+
+    /// Drops every cached proof. The hit and miss counts are kept.
+    pub(super) fn clear(&self) {
+        self.write().clear();
+    }
+
+    /// Drops the proofs of the keys and versions for which `keep` returns `false`.
+    pub(super) fn retain(&self, mut keep: impl FnMut(KeyHash, Version) -> bool) {
+        self.write().retain(|&(key, version), _| keep(key, version));
+    }
+
+    /// Returns the hit count, the miss count and the number of cached proofs.
+    pub(super) fn stats(&self) -> (u64, u64, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+            self.read().len(),
+        )
+    }
+
+    // The entries are valid whatever panicked while the lock was held.
+    fn read(&self) -> RwLockReadGuard<'_, Entries> {
+        self.entries.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Entries> {
+        self.entries.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
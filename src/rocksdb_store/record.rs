@@ -0,0 +1,146 @@
+//! The header framing the keys and values a [`GenericTreeStore`] persists.
+//!
+//! Every key and value the store encodes starts with two bytes: the [`FORMAT_VERSION`] of the
+//! layout it was written with and the [`RecordKind`] it holds. Reads check both before decoding
+//! the rest, so that data written in another layout, or read from the wrong column family, fails
+//! with [`Corruption`] instead of being misread. The names of metadata entries and labels are
+//! chosen by the store or the caller rather than encoded, and carry no header.
+//!
+//! Fixed-width integers after the header are big-endian, and the [`Codec`] encodes the rest.
+//!
+//! [`GenericTreeStore`]: super::GenericTreeStore
+
+use super::Codec;
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+/// The version of the layout of the records written by this crate, the first byte of every
+/// record.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Length of the header of a record.
+pub(super) const HEADER_LEN: usize = 2;
+
+/// What a record holds, the second byte of its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RecordKind {
+    /// A codec-encoded [`NodeKey`](crate::storage::NodeKey), keying the nodes and refcounts.
+    NodeKey = 1,
+    /// A codec-encoded [`Node`](crate::storage::Node).
+    Node = 2,
+    /// A key hash followed by a big-endian version, keying the values.
+    ValueKey = 3,
+    /// A codec-encoded `Option<OwnedValue>`, `None` marking a deletion.
+    Value = 4,
+    /// A big-endian version followed by a codec-encoded node key, keying the stale node index.
+    StaleNodeIndex = 5,
+    /// A big-endian version, such as the latest version or the version of a label.
+    Version = 6,
+    /// A big-endian node refcount.
+    Refcount = 7,
+    /// A key hash, keying the preimages.
+    KeyHash = 8,
+    /// The preimage of a key hash, as given by the caller.
+    Preimage = 9,
+    /// The fingerprint of the hasher of the store.
+    HasherFingerprint = 10,
+    /// A [`Value`](RecordKind::Value) payload after the big-endian time it was written at, in
+    /// seconds since the Unix epoch, written with
+    /// [`RocksDbStoreConfig::value_ttl`](super::RocksDbStoreConfig::value_ttl).
+    ExpiringValue = 11,
+    /// A [`Preimage`](RecordKind::Preimage) payload after the time it was written at, as for
+    /// [`ExpiringValue`](RecordKind::ExpiringValue).
+    ExpiringPreimage = 12,
+    /// The hash of the [`Node`](RecordKind::Node) record a node key points to, written instead
+    /// of the node with
+    /// [`RocksDbStoreConfig::dedup_nodes`](super::RocksDbStoreConfig::dedup_nodes).
+    NodePointer = 13,
+    /// The hash of a [`Node`](RecordKind::Node) record, keying the deduplicated node contents.
+    ContentHash = 14,
+    /// The big-endian version of the snapshot being imported, followed by the big-endian number
+    /// of its records already written.
+    ImportCheckpoint = 15,
+    /// The hash of the borsh encoding of a [`NodeBatch`](crate::storage::NodeBatch).
+    BatchDigest = 16,
+    /// A [`Value`](RecordKind::Value) payload compressed with zstd, written for the values
+    /// longer than
+    /// [`RocksDbStoreConfig::compress_values_over`](super::RocksDbStoreConfig::compress_values_over).
+    CompressedValue = 17,
+    /// A [`CompressedValue`](RecordKind::CompressedValue) payload after the time it was written
+    /// at, as for [`ExpiringValue`](RecordKind::ExpiringValue).
+    ExpiringCompressedValue = 18,
+    /// A codec-encoded leaf [`Node`](crate::storage::Node) and the value of its key at the
+    /// version of the leaf, written instead of a [`Node`](RecordKind::Node) record for the values
+    /// no longer than
+    /// [`RocksDbStoreConfig::inline_values_up_to`](super::RocksDbStoreConfig::inline_values_up_to).
+    InlineLeaf = 19,
+    /// The metadata attached to a version, as given by the caller, see
+    /// [`GenericTreeStore::set_version_metadata`](super::GenericTreeStore::set_version_metadata).
+    VersionMetadata = 20,
+    /// The version of the layout of the column families a store was migrated to, a single byte,
+    /// see [`GenericTreeStore::migrate_layout`](super::GenericTreeStore::migrate_layout).
+    LayoutVersion = 21,
+    /// The root hash of a version followed by the big-endian version, keying the index of the
+    /// versions by root hash, see
+    /// [`GenericTreeStore::version_of_root`](super::GenericTreeStore::version_of_root).
+    RootVersion = 22,
+}
+
+/// A record read from the store does not start with the header of its kind in the current
+/// [`FORMAT_VERSION`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error(
+    "corrupted {kind:?} record: header {}, expected {}",
+    hex::encode(found),
+    hex::encode(header(*kind))
+)]
+pub struct Corruption {
+    /// The kind of record that was expected.
+    pub kind: RecordKind,
+    /// The header found instead, shorter than a header if the record is.
+    pub found: Vec<u8>,
+}
+
+/// Returns the header of a record of `kind`.
+pub(super) fn header(kind: RecordKind) -> [u8; HEADER_LEN] {
+    [FORMAT_VERSION, kind as u8]
+}
+
+/// Returns `payload` framed as a record of `kind`.
+pub(super) fn frame(kind: RecordKind, payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.extend_from_slice(&header(kind));
+    record.extend_from_slice(payload);
+    record
+}
+
+/// Returns the payload of `record`, failing with [`Corruption`] if it is not a record of `kind`.
+pub(super) fn unframe(kind: RecordKind, record: &[u8]) -> Result<&[u8], Corruption> {
+    match record.split_at_checked(HEADER_LEN) {
+        Some((found, payload)) if found == header(kind) => Ok(payload),
+        _ => Err(Corruption {
+            kind,
+            found: record[..record.len().min(HEADER_LEN)].to_vec(),
+        }),
+    }
+}
+
+/// Encodes `value` with `C` as a record of `kind`.
+pub(super) fn encode<C: Codec, T: Serialize + ?Sized>(
+    kind: RecordKind,
+    value: &T,
+) -> Result<Vec<u8>> {
+    Ok(frame(kind, &C::encode(value)?))
+}
+
+/// Decodes a record of `kind` written by [`encode`].
+pub(super) fn decode<C: Codec, T: DeserializeOwned>(kind: RecordKind, record: &[u8]) -> Result<T> {
+    C::decode(unframe(kind, record)?)
+}
+
+/// Returns the length of the record [`encode`] writes for `value`.
+pub(super) fn encoded_size<C: Codec, T: Serialize + ?Sized>(value: &T) -> Result<u64> {
+    Ok(HEADER_LEN as u64 + C::encoded_size(value)?)
+}
@@ -0,0 +1,55 @@
+//! Rebuilds of the tree at one version of a [`GenericTreeStore`] under another hasher.
+
+use super::{Codec, GenericTreeStore};
+use crate::{
+    node_type::{Node, NodeKey},
+    reader::EmptyTreeReader,
+    storage::{NodeBatch, TreeWriter},
+    types::Version,
+    JellyfishMerkleTree, RootHash, SimpleHasher,
+};
+use anyhow::Result;
+
+impl<C: Codec, H: SimpleHasher> GenericTreeStore<C, H> {
+    /// Builds the tree of the keys and values live at `version` again, hashed with `H2` instead
+    /// of the hasher of the store, writes its nodes and values at `version` to `dest`, and
+    /// returns its root hash.
+    ///
+    /// This is meant to migrate a tree to another hasher: `dest` is typically a store opened with
+    /// `H2`, filled while this store keeps serving reads, and a proof from it verifies the same
+    /// keys and values against the returned root under `H2`. Only the state at `version` is
+    /// carried over, every node and value of it being written at `version` regardless of the
+    /// version that wrote it here, so the store built holds no history before it. The key hashes
+    /// are kept as they are rather than hashed again from their preimages, so a key is looked
+    /// up in `dest` by the same key hash as here. The whole state is held in memory while
+    /// building the tree. Fails with [`MissingRootError`](crate::MissingRootError) if `version`
+    /// is not readable.
+    pub fn rehash_into<H2: SimpleHasher>(
+        &self,
+        version: Version,
+        dest: &mut impl TreeWriter,
+    ) -> Result<RootHash> {
+        let mut value_set = Vec::new();
+        self.for_each_live_value(version, |key_hash, _written_version, value| {
+            value_set.push((key_hash, Some(value)));
+            Ok(())
+        })?;
+        if value_set.is_empty() {
+            // The tree only writes nodes for non-empty value sets.
+            let root = Node::new_null();
+            let root_hash = RootHash(root.hash::<H2>());
+            let mut node_batch = NodeBatch::default();
+            node_batch.insert_node(NodeKey::new_empty_path(version), root);
+            dest.write_node_batch(&node_batch)?;
+            return Ok(root_hash);
+        }
+
+        let reader = EmptyTreeReader {
+            next_version: version,
+        };
+        let tree: JellyfishMerkleTree<EmptyTreeReader, H2> = JellyfishMerkleTree::new(&reader);
+        let (root_hash, batch) = tree.put_value_set(value_set, version)?;
+        dest.write_node_batch(&batch.node_batch)?;
+        Ok(root_hash)
+    }
+}
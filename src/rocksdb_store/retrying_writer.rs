@@ -0,0 +1,79 @@
+//! Retries of the writes of a [`TreeWriter`] failing with transient errors.
+
+use crate::storage::{NodeBatch, TreeWriter};
+use anyhow::Result;
+use rocksdb::ErrorKind;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+/// A [`TreeWriter`] retrying the writes of another one that fail with a transient error, as told
+/// by [`is_transient`], waiting longer before every retry.
+///
+/// The first retry waits `initial_backoff`, and every retry after it waits twice as long as the
+/// previous one. Once `max_retries` retries have failed, or as soon as a write fails with an
+/// error that is not transient, such as a [`Corruption`](super::Corruption), the error is
+/// returned as it is. Retrying is only sound for writers whose writes can be repeated, as
+/// [`GenericTreeStore`](super::GenericTreeStore) writes, whose failed writes leave nothing behind.
+#[derive(Debug)]
+pub struct RetryingWriter<W> {
+    inner: W,
+    max_retries: u32,
+    initial_backoff: Duration,
+}
+
+impl<W: TreeWriter> RetryingWriter<W> {
+    /// Creates a writer retrying the writes of `inner` up to `max_retries` times, waiting
+    /// `initial_backoff` before the first retry.
+    pub fn new(inner: W, max_retries: u32, initial_backoff: Duration) -> Self {
+        RetryingWriter {
+            inner,
+            max_retries,
+            initial_backoff,
+        }
+    }
+
+    /// Returns the writer whose writes are retried.
+    pub fn inner(&self) -> &W {
+        &self.inner
+    }
+}
+
+impl<W: TreeWriter> TreeWriter for RetryingWriter<W> {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        let mut backoff = self.initial_backoff;
+        let mut retries = 0;
+        loop {
+            match self.inner.write_node_batch(node_batch) {
+                Err(err) if retries < self.max_retries && is_transient(&err) => {
+                    thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Returns whether `err` may go away if the operation that failed is retried as it is.
+///
+/// These are the RocksDB errors reporting contention or a stall rather than a problem with the
+/// data or the request, being busy, timed out or asking to try again, and the I/O errors of
+/// interrupted or timed out operations, anywhere in the chain of causes of `err`.
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(err) = cause.downcast_ref::<rocksdb::Error>() {
+            return matches!(
+                err.kind(),
+                ErrorKind::Busy | ErrorKind::TimedOut | ErrorKind::TryAgain
+            );
+        }
+        cause.downcast_ref::<io::Error>().is_some_and(|err| {
+            matches!(
+                err.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            )
+        })
+    })
+}
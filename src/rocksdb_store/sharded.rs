@@ -0,0 +1,173 @@
+//! A tree store spreading its values and preimages over several [`GenericTreeStore`]s.
+
+use super::{check_contiguous_versions, Codec, GenericTreeStore};
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{HasPreimage, NodeBatch, TreeReader, TreeUpdateBatch, TreeWriter},
+    types::Version,
+    KeyHash, OwnedValue, SimpleHasher,
+};
+use anyhow::{ensure, Result};
+use rocksdb::WriteBatch;
+use std::collections::BTreeMap;
+use std::thread;
+
+/// A tree store whose values and preimages are spread over several [`GenericTreeStore`]s, the
+/// shards, by key hash, so that the writes of a batch are applied to each shard in parallel.
+///
+/// The nodes of a tree reference each other across keys, and splitting them would make every
+/// walk from the root hop between shards. They stay on the first shard, the node shard, along
+/// with the bookkeeping of the store: the latest version, the committed roots, the stale node
+/// index and the labels. The values and preimages of a key hash live on the shard picked by
+/// [`shard_index`](ShardedTreeStore::shard_index), the node shard included. The other shards only
+/// hold values and preimages, and are not meant to be read or written on their own.
+///
+/// The writes of a batch are atomic on every shard, but not across shards. The values held by the
+/// other shards are written first, in parallel, and the nodes and the values of the node shard
+/// last, so that the root of a version is only readable once every value of the version is. A
+/// write failing on some shard may leave values of versions without a root behind on the others,
+/// which no read reaches and writing the batch again overwrites.
+pub struct ShardedTreeStore<C: Codec, H: SimpleHasher> {
+    shards: Vec<GenericTreeStore<C, H>>,
+}
+
+impl<C: Codec, H: SimpleHasher> ShardedTreeStore<C, H> {
+    /// Creates a store over `shards`, the first one being the node shard. Fails if there is no
+    /// shard.
+    ///
+    /// The shard of a key depends on the number of shards, so a store must always be reopened
+    /// with the same shards in the same order.
+    pub fn new(shards: Vec<GenericTreeStore<C, H>>) -> Result<Self> {
+        ensure!(
+            !shards.is_empty(),
+            "a sharded store needs at least one shard"
+        );
+        Ok(ShardedTreeStore { shards })
+    }
+
+    /// Returns the shards of the store, the node shard first.
+    pub fn shards(&self) -> &[GenericTreeStore<C, H>] {
+        &self.shards
+    }
+
+    /// Returns the shard holding the nodes and the bookkeeping of the store.
+    pub fn node_shard(&self) -> &GenericTreeStore<C, H> {
+        &self.shards[0]
+    }
+
+    /// Returns the index of the shard holding the values and the preimage of `key_hash`, from
+    /// its first eight bytes.
+    pub fn shard_index(&self, key_hash: KeyHash) -> usize {
+        let prefix: [u8; 8] = key_hash.0[..8]
+            .try_into()
+            .expect("a key hash is 32 bytes long");
+        (u64::from_be_bytes(prefix) % self.shards.len() as u64) as usize
+    }
+
+    /// Returns the shard holding the values and the preimage of `key_hash`.
+    fn shard(&self, key_hash: KeyHash) -> &GenericTreeStore<C, H> {
+        &self.shards[self.shard_index(key_hash)]
+    }
+
+    /// Stores `preimage` as the preimage of `key_hash` on its shard, see
+    /// [`GenericTreeStore::put_key_preimage`].
+    pub fn put_key_preimage(&self, key_hash: KeyHash, preimage: &[u8]) -> Result<()> {
+        self.shard(key_hash).put_key_preimage(key_hash, preimage)
+    }
+
+    /// Splits `node_batch` into one batch per shard, the nodes going to the node shard and the
+    /// values to the shard of their key.
+    fn split(&self, node_batch: &NodeBatch) -> Vec<NodeBatch> {
+        let mut values = vec![BTreeMap::new(); self.shards.len()];
+        for (&(version, key_hash), value) in node_batch.values() {
+            values[self.shard_index(key_hash)].insert((version, key_hash), value.clone());
+        }
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(index, values)| {
+                let nodes = match index {
+                    0 => node_batch.nodes().clone(),
+                    _ => BTreeMap::new(),
+                };
+                NodeBatch::new(nodes, values)
+            })
+            .collect()
+    }
+}
+
+impl<C: Codec + Sync, H: SimpleHasher + Sync> ShardedTreeStore<C, H> {
+    /// Writes a tree update batch, as [`GenericTreeStore::write_tree_update_batch`] does on the
+    /// node shard, the values of the other shards being written to them in parallel first.
+    pub fn write_tree_update_batch(&self, batch: TreeUpdateBatch) -> Result<()> {
+        // Checked before the value shards are written, as the node shard checks too late.
+        check_contiguous_versions(&batch.node_batch)?;
+        let mut batches = self.split(&batch.node_batch);
+        self.write_value_shards(&batches[1..])?;
+        self.node_shard().write_tree_update_batch(TreeUpdateBatch {
+            node_batch: batches.swap_remove(0),
+            stale_node_index_batch: batch.stale_node_index_batch,
+            node_stats: batch.node_stats,
+        })
+    }
+
+    /// Writes `batches`, holding values only, to the shards after the node shard, each on its own
+    /// thread, and fails with the error of the first shard that failed if any.
+    fn write_value_shards(&self, batches: &[NodeBatch]) -> Result<()> {
+        thread::scope(|scope| {
+            let writes: Vec<_> = self.shards[1..]
+                .iter()
+                .zip(batches)
+                .filter(|(_, batch)| !batch.values().is_empty())
+                .map(|(shard, batch)| {
+                    scope.spawn(move || {
+                        // Written without the version checks of the node shard: a shard may hold
+                        // no value of some versions of the batch.
+                        let mut write_batch = WriteBatch::default();
+                        shard.stage_node_batch(&mut write_batch, batch)?;
+                        shard.db.write(write_batch)?;
+                        Ok(())
+                    })
+                })
+                .collect();
+            writes
+                .into_iter()
+                .try_for_each(|write| write.join().expect("a shard write panicked"))
+        })
+    }
+}
+
+impl<C: Codec, H: SimpleHasher> TreeReader for ShardedTreeStore<C, H> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.node_shard().get_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.shard(key_hash).get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.node_shard().get_rightmost_leaf()
+    }
+}
+
+impl<C: Codec, H: SimpleHasher> HasPreimage for ShardedTreeStore<C, H> {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        self.shard(key_hash).preimage(key_hash)
+    }
+}
+
+impl<C: Codec + Sync, H: SimpleHasher + Sync> TreeWriter for ShardedTreeStore<C, H> {
+    /// Writes the nodes and values of `node_batch`, as [`GenericTreeStore`] does on the node
+    /// shard, the values of the other shards being written to them in parallel first.
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        check_contiguous_versions(node_batch)?;
+        let batches = self.split(node_batch);
+        self.write_value_shards(&batches[1..])?;
+        self.node_shard().write_node_batch(&batches[0])
+    }
+}
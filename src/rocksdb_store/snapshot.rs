@@ -0,0 +1,369 @@
+//! Export and import of the tree at one version of a [`GenericTreeStore`], in a format that does
+//! not depend on the RocksDB layout.
+//!
+//! A snapshot starts with a header and is followed by length-delimited records:
+//!
+//! ```text
+//! header: MAGIC (8) | format version (u32 BE) | tree version (u64 BE)
+//! record: tag (u8)  | payload length (u32 BE) | payload
+//! ```
+//!
+//! Node records hold a bincode-encoded `(NodeKey, Node)`, value records a bincode-encoded
+//! `(KeyHash, OwnedValue)`. The snapshot ends with an end record holding the bincode-encoded
+//! `(node_count, value_count)` of the records before it, so that a truncated snapshot is detected.
+
+use super::{
+    record::{frame, unframe},
+    Codec, GenericTreeStore, RecordKind, IMPORT_CHECKPOINT_KEY,
+};
+use crate::{
+    node_type::{Node, NodeKey},
+    storage::{NodeBatch, TreeReader},
+    types::Version,
+    KeyHash, MissingRootError, OwnedValue, SimpleHasher,
+};
+use anyhow::{bail, ensure, format_err, Result};
+use rocksdb::WriteBatch;
+use std::io::{self, Read, Write};
+use std::sync::PoisonError;
+
+/// Identifies a snapshot stream.
+const MAGIC: &[u8; 8] = b"JMTSNAP\0";
+
+/// The version of the snapshot format written by [`GenericTreeStore::export_snapshot`].
+const FORMAT_VERSION: u32 = 1;
+
+/// Tag of the record ending a snapshot.
+const TAG_END: u8 = 0;
+
+/// Tag of a node record.
+const TAG_NODE: u8 = 1;
+
+/// Tag of a value record.
+const TAG_VALUE: u8 = 2;
+
+/// Number of records imported per RocksDB write.
+const IMPORT_BATCH_SIZE: usize = 1024;
+
+/// How far an import has got, reported by
+/// [`GenericTreeStore::import_snapshot_with_progress`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportProgress {
+    /// The version of the snapshot being imported.
+    pub version: Version,
+    /// The number of node and value records read so far.
+    pub records: u64,
+    /// The number of bytes read so far, header included.
+    pub bytes: u64,
+    /// The number of records an earlier, interrupted import of the same snapshot wrote, which
+    /// this import reads past without writing them again.
+    pub resumed_records: u64,
+}
+
+impl<C: Codec, H: SimpleHasher> GenericTreeStore<C, H> {
+    /// Writes the tree at `version` to `writer` as a snapshot: every node reachable from the root
+    /// of `version`, and the value of every leaf.
+    ///
+    /// The nodes are streamed while walking the tree, so memory use grows with the depth of the
+    /// tree rather than its size. Fails with [`MissingRootError`] if `version` is not readable,
+    /// and with an error if a leaf has no value at `version`.
+    pub fn export_snapshot<W: Write>(&self, version: Version, mut writer: W) -> Result<()> {
+        let root_key = NodeKey::new_empty_path(version);
+        if self.get_node_option(&root_key)?.is_none() {
+            return Err(anyhow::anyhow!(MissingRootError { version }));
+        }
+
+        write_header(&mut writer, version)?;
+        let counts = export_subtree(self, version, root_key, &mut writer)?;
+        write_end(&mut writer, counts)
+    }
+
+    /// Reads a snapshot written by [`export_snapshot`](GenericTreeStore::export_snapshot) from
+    /// `reader` into this store.
+    ///
+    /// The nodes keep their keys, so the imported version has the same root hash as the exported
+    /// one. The values are stored at the snapshot version, which makes that version, and not the
+    /// ones before it, readable. Records are written in batches as they are read; the latest
+    /// version is only updated once the end record has been read and checked, so an interrupted
+    /// import leaves the latest version of the store untouched.
+    ///
+    /// Every batch written also records how many records of the snapshot the store holds, so
+    /// that importing the same snapshot again after an interruption continues where the previous
+    /// import stopped: the records it wrote are read past, and not written again. With
+    /// [`RocksDbStoreConfig::dedup_nodes`](super::RocksDbStoreConfig::dedup_nodes), the nodes
+    /// are stored once per distinct content, as every write of the store does.
+    pub fn import_snapshot<R: Read>(&self, reader: R) -> Result<()> {
+        self.import_snapshot_with_progress(reader, 0, |_| {})
+    }
+
+    /// Same as [`import_snapshot`](GenericTreeStore::import_snapshot), storing the nodes once per
+    /// distinct content as [`RocksDbStoreConfig::dedup_nodes`](super::RocksDbStoreConfig::dedup_nodes)
+    /// does, whether or not the store is in that mode.
+    ///
+    /// Each node is hashed as it is imported, and a node whose encoding is already stored, by
+    /// this import or before it, only gets a pointer to it, so that a snapshot holding identical
+    /// subtrees under several keys is imported into less space than it takes. The writes after
+    /// the import follow the configuration of the store, and the nodes imported read the same
+    /// either way.
+    pub fn import_snapshot_deduped<R: Read>(&self, reader: R) -> Result<()> {
+        self.import_snapshot_inner(reader, 0, |_| {}, true)
+    }
+
+    /// Same as [`import_snapshot`](GenericTreeStore::import_snapshot), calling `progress` every
+    /// `interval` records read and once the import is complete.
+    ///
+    /// An `interval` of 0 only reports the completed import.
+    pub fn import_snapshot_with_progress<R: Read>(
+        &self,
+        reader: R,
+        interval: u64,
+        progress: impl FnMut(&ImportProgress),
+    ) -> Result<()> {
+        self.import_snapshot_inner(reader, interval, progress, self.config.dedup_nodes)
+    }
+
+    /// Same as [`import_snapshot_with_progress`](GenericTreeStore::import_snapshot_with_progress),
+    /// storing the nodes once per distinct content if `dedup` is set.
+    fn import_snapshot_inner<R: Read>(
+        &self,
+        reader: R,
+        interval: u64,
+        mut progress: impl FnMut(&ImportProgress),
+        dedup: bool,
+    ) -> Result<()> {
+        let mut reader = CountingReader {
+            inner: reader,
+            bytes: 0,
+        };
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        ensure!(&magic == MAGIC, "not a tree snapshot");
+        let format_version = u32::from_be_bytes(read_array(&mut reader)?);
+        ensure!(
+            format_version == FORMAT_VERSION,
+            "unsupported snapshot format version {}",
+            format_version
+        );
+        let version = Version::from_be_bytes(read_array(&mut reader)?);
+        let resumed_records = match self.import_checkpoint()? {
+            Some((checkpoint_version, records)) if checkpoint_version == version => records,
+            _ => 0,
+        };
+        let mut state = ImportProgress {
+            version,
+            resumed_records,
+            ..Default::default()
+        };
+
+        let (mut node_count, mut value_count) = (0u64, 0u64);
+        let mut node_batch = NodeBatch::default();
+        loop {
+            let (tag, payload) = read_record(&mut reader)?;
+            let resumed = state.records < resumed_records;
+            match tag {
+                TAG_NODE => {
+                    if !resumed {
+                        let (node_key, node): (NodeKey, Node) = bincode::deserialize(&payload)?;
+                        node_batch.insert_node(node_key, node);
+                    }
+                    node_count += 1;
+                }
+                TAG_VALUE => {
+                    if !resumed {
+                        let (key_hash, value): (KeyHash, OwnedValue) =
+                            bincode::deserialize(&payload)?;
+                        node_batch.insert_value(version, key_hash, value);
+                    }
+                    value_count += 1;
+                }
+                TAG_END => {
+                    let counts: (u64, u64) = bincode::deserialize(&payload)?;
+                    ensure!(
+                        counts == (node_count, value_count),
+                        "snapshot announces {} nodes and {} values but holds {} and {}",
+                        counts.0,
+                        counts.1,
+                        node_count,
+                        value_count
+                    );
+                    // The node counts bumped are read and written back under the write lock.
+                    let write_guard = self
+                        .write_lock
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner);
+                    let mut write_batch = WriteBatch::default();
+                    self.stage_node_batch_with_dedup(&mut write_batch, &node_batch, dedup)?;
+                    self.stage_latest_version(&mut write_batch, version)?;
+                    write_batch.delete_cf(self.metadata_cf()?, self.key(IMPORT_CHECKPOINT_KEY));
+                    self.db.write(write_batch)?;
+                    // The latest version stays newer than the snapshot if the store held one.
+                    if let Some(latest_version) = self.latest_version()? {
+                        self.cache_root(&[], latest_version);
+                    }
+                    drop(write_guard);
+                    self.invalidate_proofs();
+                    state.bytes = reader.bytes;
+                    progress(&state);
+                    return Ok(());
+                }
+                tag => bail!("unknown snapshot record tag {}", tag),
+            }
+
+            state.records += 1;
+            state.bytes = reader.bytes;
+            if interval > 0 && state.records.is_multiple_of(interval) {
+                progress(&state);
+            }
+            if node_batch.nodes().len() + node_batch.values().len() >= IMPORT_BATCH_SIZE {
+                let write_guard = self
+                    .write_lock
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                let mut write_batch = WriteBatch::default();
+                self.stage_node_batch_with_dedup(&mut write_batch, &node_batch, dedup)?;
+                write_batch.put_cf(
+                    self.metadata_cf()?,
+                    self.key(IMPORT_CHECKPOINT_KEY),
+                    encode_import_checkpoint(version, state.records),
+                );
+                self.db.write(write_batch)?;
+                drop(write_guard);
+                node_batch.clear();
+            }
+        }
+    }
+
+    /// Returns the version of the snapshot an interrupted import was importing and the number of
+    /// its records it wrote, if an import was interrupted.
+    fn import_checkpoint(&self) -> Result<Option<(Version, u64)>> {
+        let key = self.key(IMPORT_CHECKPOINT_KEY);
+        let Some(bytes) = self.db.get_cf(self.metadata_cf()?, key)? else {
+            return Ok(None);
+        };
+        let payload = unframe(RecordKind::ImportCheckpoint, &bytes)?;
+        let payload: [u8; 16] = payload
+            .try_into()
+            .map_err(|_| format_err!("corrupted import checkpoint: {}", hex::encode(payload)))?;
+        let (version, records) = payload.split_at(8);
+        Ok(Some((
+            Version::from_be_bytes(version.try_into().expect("8 bytes")),
+            u64::from_be_bytes(records.try_into().expect("8 bytes")),
+        )))
+    }
+}
+
+/// Encodes the checkpoint of an import of the snapshot of `version` that wrote `records` records.
+fn encode_import_checkpoint(version: Version, records: u64) -> Vec<u8> {
+    let mut payload = version.to_be_bytes().to_vec();
+    payload.extend_from_slice(&records.to_be_bytes());
+    frame(RecordKind::ImportCheckpoint, &payload)
+}
+
+/// A reader counting the bytes read through it.
+struct CountingReader<R> {
+    inner: R,
+    bytes: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes += read as u64;
+        Ok(read)
+    }
+}
+
+/// Writes the header of a snapshot of `version`.
+pub(super) fn write_header(writer: &mut impl Write, version: Version) -> Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_be_bytes())?;
+    writer.write_all(&version.to_be_bytes())?;
+    Ok(())
+}
+
+/// Writes the node and value records of the subtree of `node_key` at `version`, read from
+/// `reader`, and returns how many of each were written.
+///
+/// The subtree is walked depth first, the children of a node in descending nibble order.
+pub(super) fn export_subtree(
+    reader: &impl TreeReader,
+    version: Version,
+    node_key: NodeKey,
+    writer: &mut impl Write,
+) -> Result<(u64, u64)> {
+    let (mut node_count, mut value_count) = (0u64, 0u64);
+    let mut stack = vec![node_key];
+    while let Some(node_key) = stack.pop() {
+        let node = reader.get_node(&node_key)?;
+        match &node {
+            Node::Internal(internal_node) => {
+                for (nibble, child) in internal_node.children_sorted() {
+                    stack.push(node_key.gen_child_node_key(child.version, nibble));
+                }
+            }
+            Node::Leaf(leaf_node) => {
+                let key_hash = leaf_node.key_hash();
+                let value = reader.get_value_option(version, key_hash)?.ok_or_else(|| {
+                    format_err!("leaf {:?} has no value at version {}", node_key, version)
+                })?;
+                write_record(writer, TAG_VALUE, &(key_hash, value))?;
+                value_count += 1;
+            }
+            Node::Null => {}
+        }
+        write_node_record(writer, node_key, node)?;
+        node_count += 1;
+    }
+    Ok((node_count, value_count))
+}
+
+/// Writes the node record of `node`.
+pub(super) fn write_node_record(
+    writer: &mut impl Write,
+    node_key: NodeKey,
+    node: Node,
+) -> Result<()> {
+    write_record(writer, TAG_NODE, &(node_key, node))
+}
+
+/// Writes the end record announcing `counts` node and value records, and flushes `writer`.
+pub(super) fn write_end(writer: &mut impl Write, counts: (u64, u64)) -> Result<()> {
+    write_record(writer, TAG_END, &counts)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the record `tag` with the bincode encoding of `payload`.
+fn write_record(writer: &mut impl Write, tag: u8, payload: &impl serde::Serialize) -> Result<()> {
+    let payload = bincode::serialize(payload)?;
+    let len = u32::try_from(payload.len())
+        .map_err(|_| format_err!("snapshot record of {} bytes is too large", payload.len()))?;
+    writer.write_all(&[tag])?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(&payload)?;
+    Ok(())
+}
+
+/// Reads the next record, failing if the snapshot ends first.
+fn read_record(reader: &mut impl Read) -> Result<(u8, Vec<u8>)> {
+    let mut read = || -> io::Result<(u8, Vec<u8>)> {
+        let [tag] = read_array(reader)?;
+        let len = u32::from_be_bytes(read_array(reader)?);
+        // The payload grows as it is read, so a corrupt length cannot allocate more than the
+        // stream holds.
+        let mut payload = Vec::new();
+        reader.by_ref().take(len.into()).read_to_end(&mut payload)?;
+        if payload.len() != len as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok((tag, payload))
+    };
+    read().map_err(|err| format_err!("snapshot ended before its end record: {}", err))
+}
+
+/// Reads exactly `N` bytes.
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,107 @@
+//! Reads of a secondary [`GenericTreeStore`] catching up with its primary once they are too stale,
+//! see [`BoundedStalenessReader`].
+
+use super::{BincodeCodec, Codec, GenericTreeStore};
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{HasPreimage, NodeWithValue, TreeReader},
+    types::Version,
+    KeyHash, OwnedValue, SimpleHasher,
+};
+use anyhow::Result;
+use sha2::Sha256;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// A [`TreeReader`] over a store opened by
+/// [`open_as_secondary`](GenericTreeStore::open_as_secondary), bringing it up to date with its
+/// primary before serving a read whenever it last caught up more than `max_staleness` ago.
+///
+/// This lets read replicas serve a [`JellyfishMerkleTree`](crate::JellyfishMerkleTree) without
+/// scheduling the catch-ups themselves: a read sees every version the primary wrote up to
+/// `max_staleness` before it, at the cost of one
+/// [`try_catch_up_with_primary`](GenericTreeStore::try_catch_up_with_primary) every
+/// `max_staleness` at most, made by the first read past the bound. The reads made by a single
+/// lookup may straddle a catch-up, which only adds newer versions, and leaves those already
+/// visible as they were unless the primary pruned them.
+pub struct BoundedStalenessReader<H: SimpleHasher = Sha256, C: Codec = BincodeCodec> {
+    store: GenericTreeStore<C, H>,
+    max_staleness: Duration,
+    /// When the store last caught up, or was opened.
+    caught_up_at: Mutex<Instant>,
+}
+
+impl<H: SimpleHasher, C: Codec> BoundedStalenessReader<H, C> {
+    /// Creates a reader over `store`, a secondary just opened or caught up, that catches up with
+    /// the primary once `max_staleness` passes.
+    pub fn new(store: GenericTreeStore<C, H>, max_staleness: Duration) -> Self {
+        Self {
+            store,
+            max_staleness,
+            caught_up_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Returns the secondary store read.
+    pub fn inner(&self) -> &GenericTreeStore<C, H> {
+        &self.store
+    }
+
+    /// Catches up with the primary if the store last did more than `max_staleness` ago, and
+    /// returns whether it did.
+    ///
+    /// Every read calls this first. Concurrent reads past the bound wait for the one catching
+    /// up, rather than each catching up in turn.
+    pub fn catch_up_if_stale(&self) -> Result<bool> {
+        let mut caught_up_at = self
+            .caught_up_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if caught_up_at.elapsed() <= self.max_staleness {
+            return Ok(false);
+        }
+        self.store.try_catch_up_with_primary()?;
+        *caught_up_at = Instant::now();
+        Ok(true)
+    }
+
+    /// Returns the latest version written to the store as of its last catch-up, after catching
+    /// up if it is stale.
+    pub fn latest_version(&self) -> Result<Option<Version>> {
+        self.catch_up_if_stale()?;
+        self.store.latest_version()
+    }
+}
+
+impl<H: SimpleHasher, C: Codec> TreeReader for BoundedStalenessReader<H, C> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.catch_up_if_stale()?;
+        self.store.get_node_option(node_key)
+    }
+
+    fn get_node_with_inline_value(&self, node_key: &NodeKey) -> Result<Option<NodeWithValue>> {
+        self.catch_up_if_stale()?;
+        self.store.get_node_with_inline_value(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.catch_up_if_stale()?;
+        self.store.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.catch_up_if_stale()?;
+        self.store.get_rightmost_leaf()
+    }
+}
+
+impl<H: SimpleHasher, C: Codec> HasPreimage for BoundedStalenessReader<H, C> {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        self.catch_up_if_stale()?;
+        self.store.preimage(key_hash)
+    }
+}
@@ -0,0 +1,242 @@
+//! Structured RocksDB statistics of a [`RocksDbTreeStore`](super::RocksDbTreeStore), and sizes
+//! of the batches written to it.
+
+use anyhow::{format_err, Result};
+use rocksdb::{properties, ColumnFamily, Options, DB};
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::sync::{Mutex, PoisonError};
+
+/// Number of LSM levels reported, matching the RocksDB default of `num_levels`.
+const NUM_LEVELS: usize = 7;
+
+/// Statistics of a store, read from RocksDB properties and summed over the column families of
+/// the store.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RocksStats {
+    /// The number of SST files at each level, starting at level 0.
+    pub sst_files_per_level: Vec<u64>,
+    /// RocksDB's estimate of the size of the live data, in bytes.
+    pub estimated_live_data_bytes: u64,
+    /// The size of the active and unflushed immutable memtables, in bytes.
+    pub memtable_bytes: u64,
+    /// The memory used by the block caches, in bytes.
+    pub block_cache_usage_bytes: u64,
+}
+
+/// Coarse size estimates of a store, see
+/// [`GenericTreeStore::quick_stats`](super::GenericTreeStore::quick_stats).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QuickStats {
+    /// RocksDB's estimate of the number of keys in each column family, by the name of the column
+    /// family without the prefix of the store. Deleted and overwritten keys may still be counted
+    /// until compactions drop them.
+    pub estimated_keys: BTreeMap<&'static str, u64>,
+    /// RocksDB's estimate of the size of the live data, in bytes, summed over the column
+    /// families.
+    pub estimated_live_data_bytes: u64,
+}
+
+/// Metrics of a store, see [`GenericTreeStore::metrics`](super::GenericTreeStore::metrics).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StoreMetrics {
+    /// The number of proofs served from the proof cache.
+    pub proof_cache_hits: u64,
+    /// The number of proofs looked up in the proof cache and computed from the tree instead.
+    pub proof_cache_misses: u64,
+    /// The number of proofs currently in the proof cache.
+    pub proof_cache_entries: usize,
+    /// The number of proofs of absence served from the absence cache.
+    pub absence_cache_hits: u64,
+    /// The number of proofs looked up in the absence cache and computed from the tree instead,
+    /// those of the keys present included.
+    pub absence_cache_misses: u64,
+    /// The number of proofs of absence currently in the absence cache.
+    pub absence_cache_entries: usize,
+    /// The number of node reads served from the node cache.
+    pub node_cache_hits: u64,
+    /// The number of node reads looked up in the node cache and read from the database instead.
+    pub node_cache_misses: u64,
+    /// The number of nodes currently in the node cache.
+    pub node_cache_entries: usize,
+    /// The durations of the writes of node batches, with the `latency_histogram` feature.
+    #[cfg(feature = "latency_histogram")]
+    pub write_latency: WriteLatency,
+}
+
+/// Percentiles of the durations of the writes of node batches by a store, in microseconds, see
+/// [`StoreMetrics::write_latency`].
+///
+/// Every call to [`write_node_batch`](crate::storage::TreeWriter::write_node_batch) or to the
+/// writes of update batches counts once, however many versions it holds, from the staging of its
+/// entries to the return of RocksDB's write. Durations are counted in buckets an eighth of a power
+/// of two wide, and each percentile is the upper bound of its bucket, so it overestimates the
+/// duration by at most an eighth. All are 0 until a batch is written.
+#[cfg(feature = "latency_histogram")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteLatency {
+    /// The number of writes.
+    pub count: u64,
+    /// The median duration.
+    pub p50_us: u64,
+    /// The 90th percentile.
+    pub p90_us: u64,
+    /// The 99th percentile.
+    pub p99_us: u64,
+    /// The 99.9th percentile.
+    pub p999_us: u64,
+    /// The longest duration.
+    pub max_us: u64,
+}
+
+/// The disk space taken by each category of data of a store, see
+/// [`GenericTreeStore::disk_usage`](super::GenericTreeStore::disk_usage).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// The tree nodes.
+    pub nodes: ColumnFamilyUsage,
+    /// The values of every version, deletions included.
+    pub values: ColumnFamilyUsage,
+    /// The preimages of key hashes.
+    pub preimages: ColumnFamilyUsage,
+    /// The index of stale nodes, which pruning consumes.
+    pub stale_nodes: ColumnFamilyUsage,
+    /// The remaining bookkeeping: metadata, version labels, node refcounts and the index of the
+    /// versions with a root.
+    pub other: ColumnFamilyUsage,
+}
+
+/// The disk space taken by one category of data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ColumnFamilyUsage {
+    /// The total size of the SST files, in bytes.
+    pub sst_files_bytes: u64,
+    /// RocksDB's estimate of the size of the live data in the SST files, in bytes.
+    pub estimated_live_data_bytes: u64,
+}
+
+/// The size of a [`TreeUpdateBatch`](crate::storage::TreeUpdateBatch) once written to a store,
+/// see [`GenericTreeStore::estimate_batch`](super::GenericTreeStore::estimate_batch).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    /// The number of nodes written.
+    pub node_count: usize,
+    /// The number of value entries written, deletions included.
+    pub value_count: usize,
+    /// The number of stale node index entries written.
+    pub stale_node_count: usize,
+    /// The total size of the keys and values of the entries written, in bytes.
+    pub serialized_bytes: u64,
+}
+
+/// The internal statistics of a database, read from the options it was opened with, and the
+/// counts they held when last taken, see
+/// [`GenericTreeStore::take_statistics`](super::GenericTreeStore::take_statistics).
+pub(super) struct Statistics {
+    /// Options sharing the statistics object of the database.
+    opts: Options,
+    /// The counts last taken, by the name of the statistic followed by `COUNT` or `SUM`.
+    taken: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Statistics {
+    /// Reads the statistics of the database opened with `opts`.
+    pub(super) fn new(opts: Options) -> Self {
+        Statistics {
+            opts,
+            taken: Mutex::default(),
+        }
+    }
+
+    /// Returns the text of the statistics, with the counts taken last subtracted from the
+    /// current ones, and records the current ones as taken.
+    pub(super) fn take(&self) -> Option<String> {
+        let text = self.opts.get_statistics()?;
+        let mut taken = self.taken.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut rebased = String::with_capacity(text.len());
+        for line in text.lines() {
+            rebased.push_str(&rebase_line(line, &mut taken));
+            rebased.push('\n');
+        }
+        Some(rebased)
+    }
+}
+
+/// Rebases the counts of `line`, a statistic as RocksDB prints it, its name followed by fields
+/// such as `COUNT : 12`, on those of `taken`, which it replaces by its own. Lines of another
+/// shape are returned as they are.
+fn rebase_line(line: &str, taken: &mut BTreeMap<String, u64>) -> String {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let Some((name, fields)) = tokens.split_first() else {
+        return line.to_owned();
+    };
+    if fields.is_empty() || fields.len() % 3 != 0 || fields.chunks(3).any(|f| f[1] != ":") {
+        return line.to_owned();
+    }
+    let mut rebased = (*name).to_owned();
+    for field in fields.chunks(3) {
+        let (label, value) = (field[0], field[2]);
+        let value = match (label, value.parse::<u64>()) {
+            ("COUNT" | "SUM", Ok(current)) => {
+                let previous = taken
+                    .insert(format!("{} {}", name, label), current)
+                    .unwrap_or(0);
+                current.saturating_sub(previous).to_string()
+            }
+            _ => value.to_owned(),
+        };
+        rebased.push_str(&format!(" {} : {}", label, value));
+    }
+    rebased
+}
+
+/// Reads the statistics of `column_families` in `db`.
+pub(super) fn rocks_stats(db: &DB, column_families: &[&ColumnFamily]) -> Result<RocksStats> {
+    let mut stats = RocksStats {
+        sst_files_per_level: vec![0; NUM_LEVELS],
+        ..Default::default()
+    };
+    for cf in column_families {
+        let property = |name: &CStr| property(db, cf, name);
+        for (level, files) in stats.sst_files_per_level.iter_mut().enumerate() {
+            *files += property(&properties::num_files_at_level(level))?;
+        }
+        stats.estimated_live_data_bytes += property(properties::ESTIMATE_LIVE_DATA_SIZE)?;
+        stats.memtable_bytes += property(properties::CUR_SIZE_ALL_MEM_TABLES)?;
+        stats.block_cache_usage_bytes += property(properties::BLOCK_CACHE_USAGE)?;
+    }
+    Ok(stats)
+}
+
+/// Reads the size estimates of `column_families` in `db`, given with their names.
+pub(super) fn quick_stats(
+    db: &DB,
+    column_families: &[(&'static str, &ColumnFamily)],
+) -> Result<QuickStats> {
+    let mut stats = QuickStats::default();
+    for (name, cf) in column_families {
+        let keys = property(db, cf, properties::ESTIMATE_NUM_KEYS)?;
+        stats.estimated_keys.insert(name, keys);
+        stats.estimated_live_data_bytes += property(db, cf, properties::ESTIMATE_LIVE_DATA_SIZE)?;
+    }
+    Ok(stats)
+}
+
+/// Reads the disk usage of `column_families` in `db`, summed over them.
+pub(super) fn column_family_usage(
+    db: &DB,
+    column_families: &[&ColumnFamily],
+) -> Result<ColumnFamilyUsage> {
+    let mut usage = ColumnFamilyUsage::default();
+    for cf in column_families {
+        usage.sst_files_bytes += property(db, cf, properties::TOTAL_SST_FILES_SIZE)?;
+        usage.estimated_live_data_bytes += property(db, cf, properties::ESTIMATE_LIVE_DATA_SIZE)?;
+    }
+    Ok(usage)
+}
+
+/// Reads the integer property `name` of `cf`.
+fn property(db: &DB, cf: &ColumnFamily, name: &CStr) -> Result<u64> {
+    db.property_int_value_cf(cf, name)?
+        .ok_or_else(|| format_err!("RocksDB does not report {}", name.to_string_lossy()))
+}
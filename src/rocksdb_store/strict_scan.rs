@@ -0,0 +1,141 @@
+//! Scans of the records of a [`GenericTreeStore`] failing on the first one that does not decode.
+
+use super::{
+    decode_node_content, decode_node_key, decode_node_record, decode_stale_node_index,
+    decode_value, decode_value_key, decode_version_record, record, ttl, CfKind, Codec,
+    GenericTreeStore, RecordKind, HASHER_FINGERPRINT_KEY, IMPORT_CHECKPOINT_KEY,
+    LAST_NODE_BATCH_KEY, LATEST_VERSION_KEY, LAYOUT_VERSION_KEY, MIN_READABLE_VERSION_KEY,
+    NODE_CONTENTS_CF, SEALED_VERSION_KEY,
+};
+use crate::SimpleHasher;
+use anyhow::{bail, ensure, Result};
+use thiserror::Error;
+
+/// A raw key, without the namespace of the store, and value read by
+/// [`GenericTreeStore::scan_strict`].
+type RawEntry = (Box<[u8]>, Box<[u8]>);
+
+/// A record of a store that could not be decoded, the error of
+/// [`GenericTreeStore::scan_strict`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error(
+    "undecodable record in column family {} at key {}: {reason}",
+    column_family.name(),
+    hex::encode(key)
+)]
+pub struct UndecodableRecord {
+    /// The column family holding the record.
+    pub column_family: CfKind,
+    /// The raw key of the record, without the namespace of the store.
+    pub key: Vec<u8>,
+    /// Why the record could not be decoded.
+    pub reason: String,
+}
+
+impl<C: Codec, H: SimpleHasher> GenericTreeStore<C, H> {
+    /// Iterates over the entries of the column family `column_family` in the namespace of the
+    /// store, yielding the raw key, without the namespace, and value of each once both have been
+    /// decoded as the store would decode them.
+    ///
+    /// Unlike the reads of the store, which only decode the records they need, and the debugging
+    /// dumps, which skip what they cannot decode, this fails on the first record that does not
+    /// decode, with an [`UndecodableRecord`] holding its key, and ends there, so that an audit
+    /// reading every column family sees every corruption it could hit. Metadata entries under
+    /// names the store does not write are undecodable too. The values of labels are decoded, the
+    /// labels themselves being any bytes.
+    pub fn scan_strict(
+        &self,
+        column_family: CfKind,
+    ) -> Result<impl Iterator<Item = Result<RawEntry>> + '_> {
+        let mut iter = self.iterator(column_family.name())?;
+        let mut failed = false;
+        Ok(std::iter::from_fn(move || {
+            if failed {
+                return None;
+            }
+            let item = iter
+                .next()?
+                .map_err(anyhow::Error::from)
+                .and_then(|(key, value)| {
+                    let key: Box<[u8]> = self.unprefixed(&key).into();
+                    match self.check_record(column_family, &key, &value) {
+                        Ok(()) => Ok((key, value)),
+                        Err(err) => Err(UndecodableRecord {
+                            column_family,
+                            key: key.into_vec(),
+                            reason: format!("{:#}", err),
+                        }
+                        .into()),
+                    }
+                });
+            failed = item.is_err();
+            Some(item)
+        }))
+    }
+
+    /// Decodes the entry of `key`, without the namespace, and `value` in `column_family`.
+    fn check_record(&self, column_family: CfKind, key: &[u8], value: &[u8]) -> Result<()> {
+        match column_family {
+            CfKind::Nodes => {
+                decode_node_key::<C>(key)?;
+                let contents_cf = self.cf(NODE_CONTENTS_CF)?;
+                decode_node_record::<C>(value, |key| {
+                    Ok(self.db.get_cf(contents_cf, self.key(key))?)
+                })?;
+            }
+            CfKind::Preimages => {
+                let key_hash = record::unframe(RecordKind::KeyHash, key)?;
+                ensure!(key_hash.len() == 32, "key hash of {} bytes", key_hash.len());
+                ttl::unframe_expiring(RecordKind::Preimage, value)?;
+            }
+            CfKind::Values => {
+                decode_value_key(key)?;
+                decode_value::<C>(value)?;
+            }
+            CfKind::Metadata => {
+                let kind = match key {
+                    LATEST_VERSION_KEY | MIN_READABLE_VERSION_KEY | SEALED_VERSION_KEY => {
+                        decode_version_record(value)?;
+                        return Ok(());
+                    }
+                    HASHER_FINGERPRINT_KEY => RecordKind::HasherFingerprint,
+                    LAST_NODE_BATCH_KEY => RecordKind::BatchDigest,
+                    IMPORT_CHECKPOINT_KEY => RecordKind::ImportCheckpoint,
+                    LAYOUT_VERSION_KEY => RecordKind::LayoutVersion,
+                    _ => bail!("unknown metadata entry"),
+                };
+                record::unframe(kind, value)?;
+            }
+            CfKind::StaleNodes => {
+                decode_stale_node_index::<C>(key)?;
+                ensure!(value.is_empty(), "stale node index entry with a value");
+            }
+            CfKind::Labels => {
+                decode_version_record(value)?;
+            }
+            CfKind::Refcounts => {
+                decode_node_key::<C>(key)?;
+                let refcount = record::unframe(RecordKind::Refcount, value)?;
+                ensure!(refcount.len() == 8, "refcount of {} bytes", refcount.len());
+            }
+            CfKind::Roots => {
+                decode_version_record(key)?;
+                ensure!(value.is_empty(), "root index entry with a value");
+            }
+            CfKind::NodeContents => {
+                record::unframe(RecordKind::ContentHash, key)?;
+                decode_node_content::<C>(value)?;
+            }
+            CfKind::VersionMetadata => {
+                decode_version_record(key)?;
+                record::unframe(RecordKind::VersionMetadata, value)?;
+            }
+            CfKind::RootVersions => {
+                let key = record::unframe(RecordKind::RootVersion, key)?;
+                ensure!(key.len() == 40, "root version key of {} bytes", key.len());
+                ensure!(value.is_empty(), "root version index entry with a value");
+            }
+        }
+        Ok(())
+    }
+}
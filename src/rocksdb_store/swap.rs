@@ -0,0 +1,88 @@
+//! Switches of a [`GenericTreeStore`] to a store built in another directory.
+
+use super::{Codec, GenericTreeStore, COLUMN_FAMILIES};
+use crate::SimpleHasher;
+use anyhow::{bail, ensure, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+impl<C: Codec, H: SimpleHasher> GenericTreeStore<C, H> {
+    /// Replaces the store with the one in the directory `staged`, built beforehand, such as by
+    /// a restore, and returns the store reopened from it, with the same configuration and commit
+    /// callbacks.
+    ///
+    /// The store is flushed and closed, its directory is renamed to a sibling with a `.swap`
+    /// suffix, `staged` is renamed to the directory of the store, and the store it held opened,
+    /// after which the former store is moved to `staged`, where the caller may remove it or swap
+    /// it back in. The directories must be on the same filesystem, so that each rename is atomic.
+    /// The store is taken by value since the database can only be closed once no handle on it is
+    /// left: this fails, leaving both directories untouched, if another handle, such as a clone
+    /// or the store of a [`WriterHandle`](super::WriterHandle), is still open once this one is
+    /// dropped. Automatic prunes stop, until
+    /// [`set_least_readable_version`](GenericTreeStore::set_least_readable_version) is called on
+    /// the returned store.
+    ///
+    /// The store is closed whether or not this succeeds. On failure the renames done so far are
+    /// undone, so that the directory of the store holds the former store again, to be reopened by
+    /// the caller, and `staged` the store it held. A crash between the renames leaves one of the
+    /// stores in the `.swap` directory. Fails for a store not opened from a path, such as by
+    /// [`from_db_in_namespace`](super::RocksDbTreeStore::from_db_in_namespace).
+    pub fn swap_in<P: AsRef<Path>>(self, staged: P) -> Result<Self> {
+        let staged = staged.as_ref();
+        ensure!(
+            self.cf_prefix.is_empty() && self.config.namespace.is_none(),
+            "only a store opened from a path can be swapped"
+        );
+        ensure!(staged.is_dir(), "no staged store at {}", staged.display());
+        let path = self.db.path().to_path_buf();
+        let mut aside = path.clone().into_os_string();
+        aside.push(".swap");
+        let aside = PathBuf::from(aside);
+        ensure!(
+            !aside.exists(),
+            "{} is in the way of the swap",
+            aside.display()
+        );
+        let config = self.config.clone();
+        let commit_hooks = Arc::clone(&self.commit_hooks);
+
+        for name in COLUMN_FAMILIES {
+            self.db.flush_cf(self.cf(name)?)?;
+        }
+        self.db.flush_wal(true)?;
+        // Dropping the store also stops its threads, which hold handles of their own.
+        let db = Arc::downgrade(&self.db);
+        drop(self);
+        if db.strong_count() > 0 {
+            bail!("other handles on the store are still open");
+        }
+
+        exchange(&path, staged, &aside)?;
+        let mut store = match Self::with_hasher(&path, config) {
+            Ok(store) => store,
+            Err(err) => {
+                exchange(&path, staged, &aside)?;
+                return Err(err.context("failed to open the staged store"));
+            }
+        };
+        store.commit_hooks = commit_hooks;
+        Ok(store)
+    }
+}
+
+/// Exchanges the directories `a` and `b` through `aside`, a path that does not exist, undoing
+/// the renames done so far if one fails.
+fn exchange(a: &Path, b: &Path, aside: &Path) -> Result<()> {
+    fs::rename(a, aside).with_context(|| format!("failed to move {} aside", a.display()))?;
+    if let Err(err) = fs::rename(b, a) {
+        fs::rename(aside, a)?;
+        return Err(err).with_context(|| format!("failed to move {} in", b.display()));
+    }
+    if let Err(err) = fs::rename(aside, b) {
+        fs::rename(a, b)?;
+        fs::rename(aside, a)?;
+        return Err(err).with_context(|| format!("failed to move {} out", a.display()));
+    }
+    Ok(())
+}
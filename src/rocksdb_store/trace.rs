@@ -0,0 +1,59 @@
+//! Events recording the reads and writes of a [`GenericTreeStore`](super::GenericTreeStore),
+//! emitted through `tracing` with the `trace` feature.
+//!
+//! Without the feature, the timer is a unit struct and every function here is empty, so that the
+//! calls compile to nothing.
+
+#[cfg(feature = "trace")]
+use std::time::Instant;
+
+/// When an operation started, to report its duration.
+#[cfg(feature = "trace")]
+pub(super) struct Timer(Instant);
+
+/// When an operation started, to report its duration.
+#[cfg(not(feature = "trace"))]
+pub(super) struct Timer;
+
+/// Starts timing an operation.
+#[inline(always)]
+pub(super) fn start() -> Timer {
+    #[cfg(feature = "trace")]
+    return Timer(Instant::now());
+    #[cfg(not(feature = "trace"))]
+    return Timer;
+}
+
+/// Records a point read by `op` of a key of `key_len` bytes, which found `value_len` bytes, or
+/// nothing for `None`.
+#[inline(always)]
+pub(super) fn read(op: &'static str, key_len: usize, value_len: Option<usize>, started: Timer) {
+    #[cfg(feature = "trace")]
+    tracing::trace!(
+        target: "jmt::rocksdb_store",
+        op,
+        key_len,
+        value_len,
+        elapsed_us = started.0.elapsed().as_micros() as u64,
+        "read"
+    );
+    #[cfg(not(feature = "trace"))]
+    let _ = (op, key_len, value_len, started);
+}
+
+/// Records the write of a node batch of `node_count` nodes and `value_count` values, encoded as a
+/// RocksDB write batch of `batch_len` bytes.
+#[inline(always)]
+pub(super) fn write(node_count: usize, value_count: usize, batch_len: usize, started: Timer) {
+    #[cfg(feature = "trace")]
+    tracing::debug!(
+        target: "jmt::rocksdb_store",
+        node_count,
+        value_count,
+        batch_len,
+        elapsed_us = started.0.elapsed().as_micros() as u64,
+        "write_node_batch"
+    );
+    #[cfg(not(feature = "trace"))]
+    let _ = (node_count, value_count, batch_len, started);
+}
@@ -0,0 +1,95 @@
+//! Writes to a [`GenericTreeStore`] staged in memory, readable before they are committed.
+
+use super::{BatchAccumulator, Codec, GenericTreeStore};
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{HasPreimage, NodeBatch, TreeReader, TreeUpdateBatch},
+    types::Version,
+    KeyHash, OwnedValue, SimpleHasher,
+};
+use anyhow::Result;
+
+/// Node and value writes staged on top of a store, created by
+/// [`GenericTreeStore::transaction`] and committed together by
+/// [`commit`](Transaction::commit).
+///
+/// The transaction is a [`TreeReader`] seeing its staged writes over the contents of the store,
+/// so that a [`JellyfishMerkleTree`](crate::JellyfishMerkleTree) reading from it can compute the
+/// updates depending on them. Nothing is visible to other readers of the store until the commit,
+/// which writes everything in a single atomic write; dropping the transaction discards it.
+///
+/// rust-rocksdb does not expose RocksDB's `WriteBatchWithIndex`, so the index of the staged
+/// writes is kept as a [`NodeBatch`] and encoded once, at commit.
+pub struct Transaction<'a, C: Codec, H: SimpleHasher> {
+    staged: BatchAccumulator<'a, H, C>,
+}
+
+impl<'a, C: Codec, H: SimpleHasher> Transaction<'a, C, H> {
+    pub(super) fn new(store: &'a GenericTreeStore<C, H>) -> Self {
+        Self {
+            staged: BatchAccumulator::new(store),
+        }
+    }
+
+    /// Stages the writes of `batch`, such as one computed by a tree reading from the
+    /// transaction.
+    pub fn stage(&mut self, batch: TreeUpdateBatch) {
+        self.staged.add(batch);
+    }
+
+    /// Stages the write of `node` under `node_key`.
+    pub fn put_node(&mut self, node_key: NodeKey, node: Node) {
+        let mut node_batch = NodeBatch::default();
+        node_batch.insert_node(node_key, node);
+        self.stage_node_batch(node_batch);
+    }
+
+    /// Stages the write of `value` for `key_hash` at `version`, `None` deleting the key.
+    pub fn put_value(&mut self, version: Version, key_hash: KeyHash, value: Option<OwnedValue>) {
+        let mut node_batch = NodeBatch::default();
+        node_batch.extend([], [((version, key_hash), value)]);
+        self.stage_node_batch(node_batch);
+    }
+
+    /// Returns `true` if nothing is staged.
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty()
+    }
+
+    /// Writes everything staged to the store in a single atomic write, checked as
+    /// [`write_tree_update_batch`](GenericTreeStore::write_tree_update_batch) checks a batch.
+    pub fn commit(mut self) -> Result<()> {
+        self.staged.flush()
+    }
+
+    fn stage_node_batch(&mut self, node_batch: NodeBatch) {
+        self.staged.add(TreeUpdateBatch {
+            node_batch,
+            ..Default::default()
+        });
+    }
+}
+
+impl<C: Codec, H: SimpleHasher> TreeReader for Transaction<'_, C, H> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.staged.get_node_option(node_key)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.staged.get_rightmost_leaf()
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.staged.get_value_option(max_version, key_hash)
+    }
+}
+
+impl<C: Codec, H: SimpleHasher> HasPreimage for Transaction<'_, C, H> {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        self.staged.store.preimage(key_hash)
+    }
+}
@@ -0,0 +1,87 @@
+//! Expiry of values and preimages by a compaction filter on their column families, see
+//! [`RocksDbStoreConfig::value_ttl`](super::RocksDbStoreConfig::value_ttl).
+
+use super::record::{frame, header, unframe, Corruption, RecordKind, HEADER_LEN};
+use rocksdb::compaction_filter::Decision;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Name under which the filter is registered with RocksDB.
+pub(super) const FILTER_NAME: &str = "jmt_value_ttl";
+
+/// Length of the write time prefixed to the payload of an expiring record.
+const WRITE_TIME_LEN: usize = 8;
+
+/// The kinds of the records stamped with the time they were written at.
+const EXPIRING_KINDS: [RecordKind; 3] = [
+    RecordKind::ExpiringValue,
+    RecordKind::ExpiringCompressedValue,
+    RecordKind::ExpiringPreimage,
+];
+
+/// Returns `payload` framed as a record of `kind`, or of its expiring kind stamped with the
+/// current time when `ttl` is set.
+pub(super) fn frame_expiring(kind: RecordKind, payload: &[u8], ttl: Option<Duration>) -> Vec<u8> {
+    match (ttl, expiring_kind(kind)) {
+        (Some(_), Some(expiring)) => {
+            let mut stamped = now_secs().to_be_bytes().to_vec();
+            stamped.extend_from_slice(payload);
+            frame(expiring, &stamped)
+        }
+        _ => frame(kind, payload),
+    }
+}
+
+/// Returns the payload of `record`, a record of `kind` or of its expiring kind, failing with
+/// [`Corruption`] if it is neither.
+pub(super) fn unframe_expiring(kind: RecordKind, record: &[u8]) -> Result<&[u8], Corruption> {
+    let expiring = match expiring_kind(kind) {
+        Some(expiring) if record.starts_with(&header(expiring)) => expiring,
+        _ => return unframe(kind, record),
+    };
+    match unframe(expiring, record)?.split_at_checked(WRITE_TIME_LEN) {
+        Some((_, payload)) => Ok(payload),
+        None => Err(Corruption {
+            kind: expiring,
+            found: record[..HEADER_LEN].to_vec(),
+        }),
+    }
+}
+
+/// Returns the compaction filter dropping the expiring records written more than `ttl` ago.
+///
+/// Records written without a TTL carry no write time and are always kept.
+pub(super) fn compaction_filter(
+    ttl: Duration,
+) -> impl FnMut(u32, &[u8], &[u8]) -> Decision + Send + 'static {
+    move |_level, _key, value| {
+        let written = EXPIRING_KINDS
+            .into_iter()
+            .find_map(|kind| unframe(kind, value).ok())
+            .and_then(|payload| payload.get(..WRITE_TIME_LEN))
+            .map(|bytes| u64::from_be_bytes(bytes.try_into().expect("8 bytes")));
+        match written {
+            Some(written) if now_secs().saturating_sub(written) >= ttl.as_secs() => {
+                Decision::Remove
+            }
+            _ => Decision::Keep,
+        }
+    }
+}
+
+/// Returns the kind of the expiring records holding the same payload as records of `kind`, if
+/// records of `kind` can expire.
+fn expiring_kind(kind: RecordKind) -> Option<RecordKind> {
+    match kind {
+        RecordKind::Value => Some(RecordKind::ExpiringValue),
+        RecordKind::CompressedValue => Some(RecordKind::ExpiringCompressedValue),
+        RecordKind::Preimage => Some(RecordKind::ExpiringPreimage),
+        _ => None,
+    }
+}
+
+/// Returns the number of seconds since the Unix epoch.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
@@ -0,0 +1,128 @@
+//! Inspection of the write-ahead log files of a [`RocksDbTreeStore`](super::RocksDbTreeStore).
+
+use anyhow::Result;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Size of the blocks the records of a WAL file are laid out in.
+const BLOCK_SIZE: usize = 32 * 1024;
+
+/// Header size of the legacy record types: checksum (4), length (2) and type (1).
+const HEADER_SIZE: usize = 7;
+
+/// Header size of the recyclable record types, which add the log number (4).
+const RECYCLABLE_HEADER_SIZE: usize = HEADER_SIZE + 4;
+
+/// Size of the write batch header at the start of each logical record: the sequence number of
+/// its first operation (8) and its operation count (4), both little-endian.
+const WRITE_BATCH_HEADER_SIZE: usize = 12;
+
+/// Describes one write-ahead log file of a store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalFileInfo {
+    /// The path of the file.
+    pub path: PathBuf,
+    /// The log number RocksDB assigned to the file, taken from its name.
+    pub log_number: u64,
+    /// The size of the file in bytes.
+    pub size_bytes: u64,
+    /// The sequence number of the first operation logged in the file, or `None` if the file
+    /// holds no complete write batch.
+    pub first_sequence: Option<u64>,
+    /// The sequence number of the last operation logged in the file, or `None` if the file
+    /// holds no complete write batch.
+    pub last_sequence: Option<u64>,
+}
+
+/// Lists the WAL files in `dir`, ordered by log number.
+pub(super) fn wal_files(dir: &Path) -> Result<Vec<WalFileInfo>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new("log")) {
+            continue;
+        }
+        let log_number = match path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        {
+            Some(log_number) => log_number,
+            None => continue,
+        };
+        let contents = std::fs::read(&path)?;
+        let (first_sequence, last_sequence) = match sequence_range(&contents) {
+            Some((first, last)) => (Some(first), Some(last)),
+            None => (None, None),
+        };
+        files.push(WalFileInfo {
+            path,
+            log_number,
+            size_bytes: contents.len() as u64,
+            first_sequence,
+            last_sequence,
+        });
+    }
+    files.sort_by_key(|file| file.log_number);
+    Ok(files)
+}
+
+/// Returns the sequence numbers of the first and last operations logged in the WAL file
+/// `contents`.
+///
+/// This walks the physical records of the file, reassembles the fragmented ones and reads the
+/// write batch header of each logical record. Parsing stops at the first truncated record, which
+/// is where a crash would have cut the log. Compressed WALs are not supported and yield `None`.
+fn sequence_range(contents: &[u8]) -> Option<(u64, u64)> {
+    let mut range: Option<(u64, u64)> = None;
+    let mut record = Vec::new();
+    let mut offset = 0;
+    while offset < contents.len() {
+        let block_remaining = BLOCK_SIZE - offset % BLOCK_SIZE;
+        if block_remaining < HEADER_SIZE {
+            // The tail of a block too short for a header is zero padding.
+            offset += block_remaining;
+            continue;
+        }
+        let header = match contents.get(offset..offset + HEADER_SIZE) {
+            Some(header) => header,
+            None => break,
+        };
+        let length = u16::from_le_bytes([header[4], header[5]]) as usize;
+        let record_type = header[6];
+        let header_size = match record_type {
+            // Preallocated but unwritten space.
+            0 => break,
+            1..=4 => HEADER_SIZE,
+            5..=8 => RECYCLABLE_HEADER_SIZE,
+            // Metadata records, such as the compression type, carry no write batch.
+            _ => HEADER_SIZE,
+        };
+        let payload = match contents.get(offset + header_size..offset + header_size + length) {
+            Some(payload) => payload,
+            None => break,
+        };
+        offset += header_size + length;
+
+        match record_type {
+            // Full or first fragment of a record.
+            1 | 5 | 2 | 6 => {
+                record.clear();
+                record.extend_from_slice(payload);
+            }
+            // Middle or last fragment of a record.
+            3 | 7 | 4 | 8 => record.extend_from_slice(payload),
+            _ => continue,
+        }
+        if matches!(record_type, 1 | 5 | 4 | 8) && record.len() >= WRITE_BATCH_HEADER_SIZE {
+            let sequence = u64::from_le_bytes(record[..8].try_into().ok()?);
+            let count = u32::from_le_bytes(record[8..12].try_into().ok()?) as u64;
+            let last = sequence + count.saturating_sub(1);
+            range = Some(match range {
+                Some((first, _)) => (first, last),
+                None => (sequence, last),
+            });
+        }
+    }
+    range
+}
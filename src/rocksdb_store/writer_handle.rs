@@ -0,0 +1,84 @@
+//! Writes of a [`GenericTreeStore`] applied on a dedicated thread, fed through a bounded queue.
+
+use super::{Codec, GenericTreeStore};
+use crate::{storage::TreeUpdateBatch, SimpleHasher};
+use anyhow::{format_err, Result};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+/// A batch waiting to be written, with the sender its outcome is reported through.
+type Job = (TreeUpdateBatch, Sender<Result<()>>);
+
+/// A handle on a thread writing [`TreeUpdateBatch`]es to a store in the order they are submitted,
+/// created by [`GenericTreeStore::spawn_writer`].
+///
+/// At most `capacity` batches wait in the queue of the thread: once it is full,
+/// [`submit`](WriterHandle::submit) blocks until the thread takes the next batch, which throttles
+/// producers to the speed of the store instead of buffering without bound. Dropping the handle
+/// writes the batches still queued and waits for the thread to exit.
+pub struct WriterHandle {
+    sender: Option<SyncSender<Job>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WriterHandle {
+    /// Starts a thread writing to `store` with room for `capacity` queued batches.
+    pub(super) fn spawn<C: Codec + Send + 'static, H: SimpleHasher + Send + 'static>(
+        store: GenericTreeStore<C, H>,
+        capacity: usize,
+    ) -> Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(capacity);
+        let thread = thread::Builder::new()
+            .name("jmt-rocksdb-writer".to_string())
+            .spawn(move || {
+                for (batch, done) in receiver {
+                    // The submitter may have dropped its receiver, it then does not care.
+                    let _ = done.send(store.write_tree_update_batch_ref(&batch));
+                }
+            })?;
+        Ok(WriterHandle {
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Queues `batch` to be written, blocking while the queue is full, and returns a receiver
+    /// getting the outcome of the write once it is done.
+    ///
+    /// Batches are written one at a time in submission order, each as by
+    /// [`write_tree_update_batch`](GenericTreeStore::write_tree_update_batch). Fails if the
+    /// writer thread is gone.
+    pub fn submit(&self, batch: TreeUpdateBatch) -> Result<Receiver<Result<()>>> {
+        let (done, receiver) = mpsc::channel();
+        self.sender
+            .as_ref()
+            .expect("the sender is only taken when the handle is dropped")
+            .send((batch, done))
+            .map_err(|_| format_err!("the writer thread has stopped"))?;
+        Ok(receiver)
+    }
+
+    /// Writes the batches still queued, stops the thread and waits for it to exit.
+    ///
+    /// Fails if the thread panicked.
+    pub fn close(mut self) -> Result<()> {
+        self.shut_down()
+    }
+
+    fn shut_down(&mut self) -> Result<()> {
+        // Closing the queue ends the loop of the thread once it is drained.
+        self.sender.take();
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| format_err!("the writer thread panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for WriterHandle {
+    fn drop(&mut self) {
+        let _ = self.shut_down();
+    }
+}
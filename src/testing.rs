@@ -0,0 +1,85 @@
+//! Deterministic workloads to populate a [`JellyfishMerkleTree`](crate::JellyfishMerkleTree)
+//! with, so that benchmarks and bug reports can be reproduced exactly.
+
+use alloc::vec::Vec;
+
+use crate::{KeyHash, OwnedValue};
+
+/// The value sets of a workload, one per version, see [`generate_workload`].
+pub type Workload = Vec<Vec<(KeyHash, Option<OwnedValue>)>>;
+
+/// The longest value a workload writes, in bytes.
+const MAX_VALUE_LEN: usize = 64;
+
+/// Returns `num_versions` value sets over `num_keys` keys, the same for the same arguments on
+/// every run and platform, to be written at versions `0..num_versions` in order.
+///
+/// The first set writes every key. Each later one touches a tenth of the keys, at least one,
+/// chosen at random: a touched key that has a value is deleted one time in eight and otherwise
+/// set to a new value, and a touched key without one gets a value again. Values are 1 to 64
+/// random bytes. The keys of a set are distinct and sorted, and the key hashes are random bytes
+/// rather than the hashes of any key, so that a workload does not depend on the hasher.
+///
+/// The randomness comes from a SplitMix64 generator seeded with `seed`, implemented here rather
+/// than taken from a dependency so that a workload never changes with the version of one.
+pub fn generate_workload(seed: u64, num_keys: usize, num_versions: usize) -> Workload {
+    let mut rng = SplitMix64(seed);
+    let keys: Vec<KeyHash> = (0..num_keys).map(|_| KeyHash(rng.next_bytes())).collect();
+    let mut present = alloc::vec![false; num_keys];
+    // Shuffled in place by every set, so that the keys touched are drawn without repetition.
+    let mut order: Vec<usize> = (0..num_keys).collect();
+    let mut workload = Vec::with_capacity(num_versions);
+    for version in 0..num_versions {
+        let touched = match version {
+            0 => num_keys,
+            _ => (num_keys / 10).max(1).min(num_keys),
+        };
+        for i in 0..touched {
+            let j = i + rng.below(num_keys - i);
+            order.swap(i, j);
+        }
+        let mut value_set: Vec<_> = order[..touched]
+            .iter()
+            .map(|&index| {
+                let value = if present[index] && rng.below(8) == 0 {
+                    None
+                } else {
+                    let len = 1 + rng.below(MAX_VALUE_LEN);
+                    Some((0..len).map(|_| rng.next() as u8).collect())
+                };
+                present[index] = value.is_some();
+                (keys[index], value)
+            })
+            .collect();
+        value_set.sort_unstable_by_key(|(key_hash, _)| *key_hash);
+        workload.push(value_set);
+    }
+    workload
+}
+
+/// The SplitMix64 generator of Steele, Lea and Flood.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number below `bound`, which must not be 0.
+    fn below(&mut self, bound: usize) -> usize {
+        // The bias of the modulo is negligible for the bounds of a workload.
+        (self.next() % bound as u64) as usize
+    }
+
+    fn next_bytes(&mut self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for chunk in bytes.chunks_exact_mut(8) {
+            chunk.copy_from_slice(&self.next().to_be_bytes());
+        }
+        bytes
+    }
+}
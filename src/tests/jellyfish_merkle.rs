@@ -9,9 +9,14 @@ use rand::{rngs::StdRng, Rng, SeedableRng};
 
 use crate::SimpleHasher;
 use crate::{
+    compute_version_commitment,
     mock::MockTreeStore,
-    node_type::{Child, Children, Node, NodeKey, NodeType},
-    storage::{TreeReader, TreeUpdateBatch},
+    node_type::{Child, Children, LeafNode, Node, NodeKey, NodeType},
+    storage::{
+        HasPreimage, NodeBatch, OverlayReader, TieredTreeStore, TreeReader, TreeUpdateBatch,
+        TreeWriter,
+    },
+    stores_equal_at,
     tests::helper::{
         arb_existent_kvs_and_deletions_and_nonexistent_keys, arb_existent_kvs_and_nonexistent_keys,
         arb_interleaved_insertions_and_deletions, arb_kv_pair_with_distinct_last_nibble,
@@ -22,9 +27,12 @@ use crate::{
     },
     types::{
         nibble::{nibble_path::NibblePath, Nibble},
+        proof::{verify_batch, KeyProof, ProofItem, SparseMerkleLeafNode, SparseMerkleProof},
         Version,
     },
-    JellyfishMerkleTree, KeyHash, MissingRootError, SPARSE_MERKLE_PLACEHOLDER_HASH,
+    Bytes32Ext, ChangeKind, CorruptedNodeError, DepthStats, DuplicateKeysError,
+    JellyfishMerkleTree, KeyHash, MissingRootError, RootHash, ValueHash,
+    SPARSE_MERKLE_PLACEHOLDER_HASH,
 };
 
 fn update_nibble(original_key: &KeyHash, n: usize, nibble: u8) -> KeyHash {
@@ -50,8 +58,36 @@ macro_rules! impl_jellyfish_tests_for_hasher {
             instantiate_test_for_hasher!(test_insert_at_leaf_with_multiple_internals_created, $hasher);
             instantiate_test_for_hasher!(test_batch_insertion, $hasher);
             instantiate_test_for_hasher!(test_non_existence, $hasher);
+            instantiate_test_for_hasher!(test_proof_leaf, $hasher);
+            instantiate_test_for_hasher!(test_nonexistence_divergence_depth, $hasher);
+            instantiate_test_for_hasher!(test_get_proof_for_value_hash, $hasher);
+            instantiate_test_for_hasher!(test_swap_values, $hasher);
+            instantiate_test_for_hasher!(test_get_version_range_proof, $hasher);
+            instantiate_test_for_hasher!(test_overlay_reader, $hasher);
+            instantiate_test_for_hasher!(test_tiered_store, $hasher);
+            instantiate_test_for_hasher!(test_get_with_tagged_proof, $hasher);
+            instantiate_test_for_hasher!(test_estimate_proof_size, $hasher);
+            instantiate_test_for_hasher!(test_diff, $hasher);
+            instantiate_test_for_hasher!(test_iter_leaves_under, $hasher);
+            instantiate_test_for_hasher!(test_iter_with_proofs, $hasher);
+            instantiate_test_for_hasher!(test_get_subtree_proof, $hasher);
+            instantiate_test_for_hasher!(test_version_commitment, $hasher);
+            instantiate_test_for_hasher!(test_stores_equal_at, $hasher);
             instantiate_test_for_hasher!(test_missing_root, $hasher);
+            instantiate_test_for_hasher!(test_reject_duplicate_keys, $hasher);
+            instantiate_test_for_hasher!(test_verify_on_read, $hasher);
+            instantiate_test_for_hasher!(test_compact_proof_bytes, $hasher);
+            #[cfg(feature = "json")]
+            instantiate_test_for_hasher!(test_proof_json, $hasher);
+            instantiate_test_for_hasher!(test_verify_batch, $hasher);
+            instantiate_test_for_hasher!(test_put_prehashed_value_sets, $hasher);
+            instantiate_test_for_hasher!(test_depth_stats, $hasher);
+            instantiate_test_for_hasher!(test_fanout_histogram, $hasher);
             instantiate_test_for_hasher!(test_non_batch_empty_write_set, $hasher);
+            instantiate_test_for_hasher!(test_empty_tree_proof, $hasher);
+            instantiate_test_for_hasher!(test_delete_value_set, $hasher);
+            instantiate_test_for_hasher!(test_empty_value_is_present, $hasher);
+            instantiate_test_for_hasher!(test_generate_workload, $hasher);
             instantiate_test_for_hasher!(test_put_value_sets, $hasher);
             instantiate_test_for_hasher!(test_1000_keys, $hasher);
             instantiate_test_for_hasher!(test_1000_versions, $hasher);
@@ -509,6 +545,707 @@ fn test_batch_insertion<H: SimpleHasher>() {
     }
 }
 
+fn test_proof_leaf<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let key1 = KeyHash([0u8; 32]);
+    let value1 = vec![1u8];
+    let key2 = update_nibble(&key1, 0, 1);
+    let value2 = vec![2u8];
+    let key3 = update_nibble(&key1, 0, 15);
+    let value3 = vec![3u8];
+
+    let (_root, batch) = tree
+        .put_value_set(
+            vec![
+                (key1, Some(value1.clone())),
+                (key2, Some(value2)),
+                (key3, Some(value3)),
+            ],
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // An inclusion proof commits to the queried key and the hash of its value.
+    let (value, proof) = tree.get_with_proof(key1, 0).unwrap();
+    assert_eq!(value, Some(value1.clone()));
+    assert_eq!(
+        proof.leaf(),
+        Some((key1, crate::ValueHash::with::<H>(&value1)))
+    );
+
+    // A non-inclusion proof whose path ends at another leaf commits to that leaf.
+    let (value, proof) = tree.get_with_proof(update_nibble(&key1, 1, 1), 0).unwrap();
+    assert!(value.is_none());
+    assert_eq!(proof.leaf().map(|(key, _)| key), Some(key1));
+
+    // A non-inclusion proof against an empty slot has no leaf.
+    let (value, proof) = tree.get_with_proof(update_nibble(&key1, 0, 3), 0).unwrap();
+    assert!(value.is_none());
+    assert!(proof.leaf().is_none());
+}
+
+fn test_nonexistence_divergence_depth<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    // key1 and key2 share two nibbles, so they sit below two internal nodes under nibble 0.
+    let key1 = KeyHash([0u8; 32]);
+    let key2 = update_nibble(&key1, 2, 7);
+    let key3 = update_nibble(&key1, 0, 15);
+    let (root, batch) = tree
+        .put_value_set(
+            vec![
+                (key1, Some(vec![1u8])),
+                (key2, Some(vec![2u8])),
+                (key3, Some(vec![3u8])),
+            ],
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let (_value, proof) = tree.get_with_proof(key3, 0).unwrap();
+    assert_eq!(proof.nonexistence_divergence_depth(key3), None);
+
+    // Conflicting leaves: the depth is the prefix shared with the leaf in the proof.
+    for query in [update_nibble(&key3, 1, 4), update_nibble(&key1, 5, 1)] {
+        let (value, proof) = tree.get_with_proof(query, 0).unwrap();
+        assert!(value.is_none());
+        proof.verify_nonexistence(root, query).unwrap();
+        let (leaf_key, _) = proof.leaf().unwrap();
+        assert_eq!(
+            proof.nonexistence_divergence_depth(query),
+            Some(query.0.common_prefix_nibbles_len(&leaf_key.0))
+        );
+    }
+
+    // Empty slots: the depth is that of the internal node missing the key's nibble.
+    for (query, depth) in [
+        (update_nibble(&key1, 0, 3), 0),
+        (update_nibble(&key1, 2, 9), 2),
+    ] {
+        let (value, proof) = tree.get_with_proof(query, 0).unwrap();
+        assert!(value.is_none());
+        assert!(proof.leaf().is_none());
+        assert_eq!(proof.nonexistence_divergence_depth(query), Some(depth));
+    }
+}
+
+fn test_get_proof_for_value_hash<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let key1 = KeyHash([0u8; 32]);
+    let value1 = vec![1u8; 1024];
+    let key2 = update_nibble(&key1, 0, 1);
+    let (root, batch) = tree
+        .put_value_set(
+            vec![(key1, Some(value1.clone())), (key2, Some(vec![2u8]))],
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let (value_hash, proof) = tree.get_proof_for_value_hash(key1, 0).unwrap();
+    let value_hash = value_hash.unwrap();
+    assert_eq!(value_hash, crate::ValueHash::with::<H>(&value1));
+    assert_eq!(proof.leaf(), Some((key1, value_hash)));
+    assert_eq!(proof, tree.get_with_proof(key1, 0).unwrap().1);
+    proof
+        .verify_inclusion_with_value_hash(root, key1, value_hash)
+        .unwrap();
+    assert!(proof
+        .verify_inclusion_with_value_hash(root, key1, crate::ValueHash::with::<H>([2u8]))
+        .is_err());
+
+    // An absent key has no value hash, and its proof is a non-inclusion proof.
+    let absent = update_nibble(&key1, 0, 2);
+    let (value_hash, proof) = tree.get_proof_for_value_hash(absent, 0).unwrap();
+    assert!(value_hash.is_none());
+    proof.verify_nonexistence(root, absent).unwrap();
+}
+
+fn test_get_version_range_proof<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let unchanged = KeyHash([1u8; 32]);
+    let modified = KeyHash([2u8; 32]);
+    let created = KeyHash([3u8; 32]);
+    let updates = vec![
+        vec![(unchanged, Some(vec![1])), (modified, Some(vec![2]))],
+        vec![(modified, Some(vec![3]))],
+        vec![(created, Some(vec![4]))],
+    ];
+    for (version, update) in updates.into_iter().enumerate() {
+        let (_root, batch) = tree.put_value_set(update, version as Version).unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+
+    let proof = tree.get_version_range_proof(unchanged, 0, 2).unwrap();
+    proof.verify(unchanged).unwrap();
+    assert!(proof.is_unchanged());
+    assert_eq!(proof.from_root_hash, tree.get_root_hash(0).unwrap());
+    assert_eq!(proof.to_root_hash, tree.get_root_hash(2).unwrap());
+    assert_eq!(proof.to_value, Some(vec![1]));
+    // Another key does not verify against the same proofs.
+    assert!(proof.verify(modified).is_err());
+
+    let proof = tree.get_version_range_proof(modified, 0, 2).unwrap();
+    proof.verify(modified).unwrap();
+    assert!(!proof.is_unchanged());
+    assert_eq!(proof.from_value, Some(vec![2]));
+    assert_eq!(proof.to_value, Some(vec![3]));
+
+    // A key absent at one or both endpoints is proven absent there.
+    let proof = tree.get_version_range_proof(created, 0, 2).unwrap();
+    proof.verify(created).unwrap();
+    assert_eq!(proof.from_value, None);
+    assert_eq!(proof.to_value, Some(vec![4]));
+    let proof = tree.get_version_range_proof(created, 0, 1).unwrap();
+    proof.verify(created).unwrap();
+    assert!(proof.is_unchanged());
+
+    assert!(tree.get_version_range_proof(unchanged, 2, 0).is_err());
+    assert!(tree
+        .get_version_range_proof(unchanged, 0, 3)
+        .unwrap_err()
+        .downcast_ref::<crate::MissingRootError>()
+        .is_some());
+}
+
+fn test_overlay_reader<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let shadowed = KeyHash([1u8; 32]);
+    let unshadowed = KeyHash([2u8; 32]);
+    let inserted = KeyHash([3u8; 32]);
+    let (_root, batch) = tree
+        .put_value_set(
+            vec![(shadowed, Some(vec![1])), (unshadowed, Some(vec![2]))],
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    db.put_key_preimage(unshadowed, &b"unshadowed".to_vec());
+
+    let (pending_root, pending) = tree
+        .put_value_set(
+            vec![(shadowed, Some(vec![3])), (inserted, Some(vec![4]))],
+            1, /* version */
+        )
+        .unwrap();
+    let overlay = OverlayReader::new(&db, &pending.node_batch);
+    let speculative = JellyfishMerkleTree::<_, H>::new(&overlay);
+
+    // The pending batch shadows the committed values and adds its own.
+    assert_eq!(speculative.get_root_hash(1).unwrap(), pending_root);
+    let (value, proof) = speculative.get_with_proof(shadowed, 1).unwrap();
+    assert_eq!(value, Some(vec![3]));
+    proof
+        .verify(pending_root, shadowed, value.as_ref())
+        .unwrap();
+    assert_eq!(speculative.get(inserted, 1).unwrap(), Some(vec![4]));
+
+    // Unshadowed keys and earlier versions resolve from the base.
+    let (value, proof) = speculative.get_with_proof(unshadowed, 1).unwrap();
+    assert_eq!(value, Some(vec![2]));
+    proof
+        .verify(pending_root, unshadowed, value.as_ref())
+        .unwrap();
+    assert_eq!(speculative.get(shadowed, 0).unwrap(), Some(vec![1]));
+    assert_eq!(speculative.get(inserted, 0).unwrap(), None);
+    assert_eq!(
+        overlay.preimage(unshadowed).unwrap(),
+        Some(b"unshadowed".to_vec())
+    );
+
+    // Nothing was committed.
+    assert!(tree.get_root_hash_option(1).unwrap().is_none());
+}
+
+fn test_tiered_store<H: SimpleHasher>() {
+    let cold = MockTreeStore::default();
+    let archived = KeyHash([1u8; 32]);
+    let updated = KeyHash([2u8; 32]);
+    let (root0, batch) = JellyfishMerkleTree::<_, H>::new(&cold)
+        .put_value_set(
+            vec![(archived, Some(vec![1])), (updated, Some(vec![2]))],
+            0, /* version */
+        )
+        .unwrap();
+    cold.write_tree_update_batch(batch).unwrap();
+    cold.put_key_preimage(archived, &b"archived".to_vec());
+
+    // Version 0 only lives in the cold tier, as if pruned from the hot one after archiving.
+    let tiered = TieredTreeStore::new(MockTreeStore::default(), cold);
+    let tree = JellyfishMerkleTree::<_, H>::new(&tiered);
+    let (root1, batch) = tree
+        .put_value_set(vec![(updated, Some(vec![3]))], 1 /* version */)
+        .unwrap();
+    tiered.hot().write_tree_update_batch(batch).unwrap();
+    let cold_nodes = tiered.cold().num_nodes();
+    let (_, batch) = tree
+        .put_value_set(vec![(updated, Some(vec![4]))], 2 /* version */)
+        .unwrap();
+    tiered.write_node_batch(&batch.node_batch).unwrap();
+    assert_eq!(tiered.cold().num_nodes(), cold_nodes);
+
+    // The leaf and value of the unchanged key are only in the cold tier.
+    assert!(JellyfishMerkleTree::<_, H>::new(tiered.hot())
+        .get_with_proof(archived, 1)
+        .is_err());
+    let (value, proof) = tree.get_with_proof(archived, 1).unwrap();
+    assert_eq!(value, Some(vec![1]));
+    proof.verify(root1, archived, value.as_ref()).unwrap();
+    assert_eq!(tree.get_root_hash(0).unwrap(), root0);
+    assert_eq!(tree.get(updated, 0).unwrap(), Some(vec![2]));
+    assert_eq!(tree.get(updated, 2).unwrap(), Some(vec![4]));
+    assert_eq!(
+        tiered.preimage(archived).unwrap(),
+        Some(b"archived".to_vec())
+    );
+}
+
+fn test_get_with_tagged_proof<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let present = KeyHash([1u8; 32]);
+    let empty = KeyHash([2u8; 32]);
+    let absent = KeyHash([3u8; 32]);
+    let (root, batch) = tree
+        .put_value_set(
+            vec![(present, Some(vec![1, 2, 3])), (empty, Some(vec![]))],
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let proof = tree.get_with_tagged_proof(present, 0).unwrap();
+    assert!(matches!(&proof, KeyProof::Present(value, _) if *value == vec![1, 2, 3]));
+    proof.verify(root, present).unwrap();
+
+    // An empty value is present, not absent.
+    let proof = tree.get_with_tagged_proof(empty, 0).unwrap();
+    assert!(matches!(&proof, KeyProof::Present(value, _) if value.is_empty()));
+    proof.verify(root, empty).unwrap();
+    assert!(KeyProof::Absent(proof.proof().clone())
+        .verify(root, empty)
+        .is_err());
+
+    let proof = tree.get_with_tagged_proof(absent, 0).unwrap();
+    assert!(matches!(proof, KeyProof::Absent(_)));
+    proof.verify(root, absent).unwrap();
+    proof.proof().verify_nonexistence(root, absent).unwrap();
+    assert!(KeyProof::Present(vec![], proof.proof().clone())
+        .verify(root, absent)
+        .is_err());
+}
+
+fn test_estimate_proof_size<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let proof_size = |key: KeyHash, version: Version| {
+        let (_value, proof) = tree.get_with_proof(key, version).unwrap();
+        borsh::to_vec(&proof).unwrap().len()
+    };
+
+    // An empty tree.
+    let (_root, batch) = tree.put_value_set(vec![], 0 /* version */).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let absent = KeyHash([0xffu8; 32]);
+    assert_eq!(
+        tree.estimate_proof_size(absent, 0).unwrap(),
+        proof_size(absent, 0)
+    );
+
+    let mut rng: StdRng = SeedableRng::from_seed([0; 32]);
+    let keys: Vec<KeyHash> = (0..200).map(|_| KeyHash(rng.gen())).collect();
+    let (_root, batch) = tree
+        .put_value_set(
+            keys.iter().map(|&key| (key, Some(key.0.to_vec()))),
+            1, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let absent_keys: Vec<KeyHash> = (0..50).map(|_| KeyHash(rng.gen())).collect();
+    for &key in keys.iter().chain(&absent_keys) {
+        assert_eq!(
+            tree.estimate_proof_size(key, 1).unwrap(),
+            proof_size(key, 1)
+        );
+    }
+
+    let err = tree.estimate_proof_size(absent, 2).unwrap_err();
+    assert!(err.downcast_ref::<MissingRootError>().is_some());
+}
+
+fn test_swap_values<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let key_a = KeyHash([1u8; 32]);
+    let key_b = KeyHash([2u8; 32]);
+    let absent = KeyHash([3u8; 32]);
+    let (_root, batch) = tree
+        .put_value_set(
+            vec![(key_a, Some(vec![0xa])), (key_b, Some(vec![0xb]))],
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let (root, batch) = tree.swap_values(key_a, key_b, 1).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get_root_hash(1).unwrap(), root);
+    assert_eq!(tree.get(key_a, 1).unwrap(), Some(vec![0xb]));
+    assert_eq!(tree.get(key_b, 1).unwrap(), Some(vec![0xa]));
+    // The previous version is untouched.
+    assert_eq!(tree.get(key_a, 0).unwrap(), Some(vec![0xa]));
+
+    // Swapping with an absent key moves the value over.
+    let (_root, batch) = tree.swap_values(key_a, absent, 2).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get(key_a, 2).unwrap(), None);
+    assert_eq!(tree.get(absent, 2).unwrap(), Some(vec![0xb]));
+    assert_eq!(tree.get(key_b, 2).unwrap(), Some(vec![0xa]));
+    assert_eq!(tree.get_leaf_count(2).unwrap(), 2);
+
+    // Swapping a key with itself changes nothing.
+    let (root, batch) = tree.swap_values(key_b, key_b, 3).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(root, tree.get_root_hash(2).unwrap());
+}
+
+fn test_diff<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let mut rng: StdRng = StdRng::from_seed([0u8; 32]);
+    let keys: Vec<KeyHash> = (0..100).map(|_| KeyHash(rng.gen())).collect();
+    // Shares all but the last nibble with `keys[0]`, so that adding it splits a leaf.
+    let neighbor = update_nibble(&keys[0], 63, 15 - keys[0].0.get_nibble(63).as_usize() as u8);
+
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![0]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let mut changes = vec![
+        (keys[1], ChangeKind::Updated),
+        (keys[2], ChangeKind::Updated),
+        (keys[3], ChangeKind::Removed),
+        (keys[4], ChangeKind::Removed),
+        (neighbor, ChangeKind::Added),
+        (KeyHash([0u8; 32]), ChangeKind::Added),
+    ];
+    let (_, batch) = tree
+        .put_value_set(
+            vec![
+                (keys[1], Some(vec![1])),
+                (keys[2], Some(vec![1])),
+                (keys[3], None),
+                (keys[4], None),
+                (neighbor, Some(vec![1])),
+                (KeyHash([0u8; 32]), Some(vec![1])),
+                // Writing the same value again is not a change.
+                (keys[5], Some(vec![0])),
+            ],
+            1,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Deleting every key empties the tree.
+    let (_, batch) = tree
+        .put_value_set(
+            keys.iter()
+                .chain([&neighbor, &KeyHash([0u8; 32])])
+                .map(|key| (*key, None)),
+            2,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    changes.sort_by_key(|(key, _)| *key);
+    assert_eq!(tree.diff(0, 1).unwrap(), changes);
+    let reversed: Vec<_> = changes
+        .iter()
+        .map(|&(key, kind)| {
+            let kind = match kind {
+                ChangeKind::Added => ChangeKind::Removed,
+                ChangeKind::Removed => ChangeKind::Added,
+                ChangeKind::Updated => ChangeKind::Updated,
+            };
+            (key, kind)
+        })
+        .collect();
+    assert_eq!(tree.diff(1, 0).unwrap(), reversed);
+    assert!(tree.diff(1, 1).unwrap().is_empty());
+
+    let mut removed_all: Vec<_> = keys[..3]
+        .iter()
+        .chain(&keys[5..])
+        .chain([&neighbor, &KeyHash([0u8; 32])])
+        .map(|key| (*key, ChangeKind::Removed))
+        .collect();
+    removed_all.sort_by_key(|(key, _)| *key);
+    assert_eq!(tree.diff(1, 2).unwrap(), removed_all);
+
+    let err = tree
+        .diff(0, 3)
+        .unwrap_err()
+        .downcast::<MissingRootError>()
+        .unwrap();
+    assert_eq!(err.version, 3);
+}
+
+fn test_iter_leaves_under<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let keys: Vec<KeyHash> = [
+        [0x12, 0x34],
+        [0x12, 0x35],
+        [0x12, 0xff],
+        [0x1f, 0x00],
+        [0x80, 0x00],
+    ]
+    .iter()
+    .map(|prefix| {
+        let mut key = [0u8; 32];
+        key[..2].copy_from_slice(prefix);
+        KeyHash(key)
+    })
+    .collect();
+    let (_root, batch) = tree
+        .put_value_set(
+            keys.iter()
+                .enumerate()
+                .map(|(i, key)| (*key, Some(vec![i as u8]))),
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let leaves_under = |prefix: NibblePath| -> Vec<(KeyHash, Vec<u8>)> {
+        tree.iter_leaves_under(0, prefix)
+            .collect::<anyhow::Result<_>>()
+            .unwrap()
+    };
+    let expected = |indices: &[usize]| -> Vec<(KeyHash, Vec<u8>)> {
+        indices.iter().map(|i| (keys[*i], vec![*i as u8])).collect()
+    };
+
+    assert_eq!(
+        leaves_under(NibblePath::new(vec![])),
+        expected(&[0, 1, 2, 3, 4])
+    );
+    assert_eq!(
+        leaves_under(NibblePath::new_odd(vec![0x10])),
+        expected(&[0, 1, 2, 3])
+    );
+    assert_eq!(
+        leaves_under(NibblePath::new(vec![0x12])),
+        expected(&[0, 1, 2])
+    );
+    assert_eq!(
+        leaves_under(NibblePath::new_odd(vec![0x12, 0x30])),
+        expected(&[0, 1])
+    );
+    // `0x80..` is a leaf directly below the root, longer prefixes must still match its key.
+    assert_eq!(
+        leaves_under(NibblePath::new(vec![0x80, 0x00])),
+        expected(&[4])
+    );
+    assert!(leaves_under(NibblePath::new(vec![0x81])).is_empty());
+    assert!(leaves_under(NibblePath::new_odd(vec![0x50])).is_empty());
+    assert!(leaves_under(NibblePath::new(vec![0x12, 0x36])).is_empty());
+
+    let err = tree
+        .iter_leaves_under(1, NibblePath::new(vec![]))
+        .next()
+        .unwrap()
+        .unwrap_err();
+    assert!(err.downcast_ref::<MissingRootError>().is_some());
+}
+
+fn test_iter_with_proofs<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let mut rng: StdRng = SeedableRng::from_seed([1; 32]);
+    let mut keys: Vec<KeyHash> = (0..300).map(|_| KeyHash(rng.gen())).collect();
+    let (root, batch) = tree
+        .put_value_set(
+            keys.iter().map(|&key| (key, Some(key.0[..4].to_vec()))),
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    keys.sort();
+
+    let (start_key, end_key) = (keys[50], keys[250]);
+    let leaves: Vec<_> = tree
+        .iter_with_proofs(0, start_key, end_key)
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+    assert_eq!(
+        leaves.iter().map(|(key, _, _)| *key).collect::<Vec<_>>(),
+        keys[50..250]
+    );
+    for (key, value, proof) in &leaves {
+        assert_eq!(*value, key.0[..4].to_vec());
+        proof.verify(root, *key, Some(value)).unwrap();
+        assert_eq!(*proof, tree.get_with_proof(*key, 0).unwrap().1);
+    }
+
+    // The whole tree, and a range between two adjacent keys.
+    let all: Vec<_> = tree
+        .iter_with_proofs(0, KeyHash([0; 32]), KeyHash([0xff; 32]))
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+    assert_eq!(all.len(), keys.len());
+    assert_eq!(tree.iter_with_proofs(0, keys[7], keys[7]).count(), 0);
+
+    let err = tree
+        .iter_with_proofs(1, start_key, end_key)
+        .next()
+        .unwrap()
+        .unwrap_err();
+    assert!(err.downcast_ref::<MissingRootError>().is_some());
+}
+
+fn test_get_subtree_proof<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let mut rng: StdRng = SeedableRng::from_seed([1; 32]);
+    let keys: Vec<KeyHash> = (0..300).map(|_| KeyHash(rng.gen())).collect();
+    let (root, batch) = tree
+        .put_value_set(
+            keys.iter().map(|&key| (key, Some(key.0[..4].to_vec()))),
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // The whole tree, the subtree of a nibble, of a single key and a subtree nothing is under.
+    let key_path = NibblePath::new(keys[0].0.to_vec());
+    let single_leaf = NibblePath::new_odd(keys[0].0[..5].to_vec());
+    let empty = (0..=u8::MAX)
+        .map(|byte| NibblePath::new(vec![byte, byte]))
+        .find(|prefix| keys.iter().all(|key| key.0[..2] != prefix.bytes()[..]))
+        .unwrap();
+    for prefix in [
+        NibblePath::new(vec![]),
+        NibblePath::new_odd(vec![keys[0].0[0] & 0xf0]),
+        key_path,
+        single_leaf,
+        empty.clone(),
+    ] {
+        let (subtree_hash, proof) = tree.get_subtree_proof(0, prefix.clone()).unwrap();
+        proof.verify_subtree(root, &prefix, subtree_hash).unwrap();
+        assert!(proof.verify_subtree(root, &prefix, [0xab; 32]).is_err());
+    }
+
+    let (subtree_hash, _) = tree.get_subtree_proof(0, NibblePath::new(vec![])).unwrap();
+    assert_eq!(subtree_hash, root.0);
+    let (subtree_hash, _) = tree.get_subtree_proof(0, empty).unwrap();
+    assert_eq!(subtree_hash, SPARSE_MERKLE_PLACEHOLDER_HASH);
+
+    // A subtree of a single key hashes to its leaf.
+    let (subtree_hash, _) = tree
+        .get_subtree_proof(0, NibblePath::new(keys[0].0.to_vec()))
+        .unwrap();
+    assert_eq!(
+        subtree_hash,
+        SparseMerkleLeafNode::new(keys[0], ValueHash::with::<H>(&keys[0].0[..4])).hash::<H>()
+    );
+
+    let err = tree
+        .get_subtree_proof(1, NibblePath::new(vec![]))
+        .unwrap_err();
+    assert!(err.downcast_ref::<MissingRootError>().is_some());
+}
+
+fn test_version_commitment<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+
+    let key1 = KeyHash([1u8; 32]);
+    let key2 = KeyHash([2u8; 32]);
+    let (root0, batch) = tree
+        .put_value_set(vec![(key1, Some(vec![1u8]))], 0 /* version */)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (root1, batch) = tree
+        .put_value_set(vec![(key2, Some(vec![2u8]))], 1 /* version */)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // The commitment is deterministic and matches the one computed from its components.
+    let commitment = tree.version_commitment(1).unwrap();
+    assert_eq!(commitment, tree.version_commitment(1).unwrap());
+    assert_eq!(commitment, compute_version_commitment::<H>(1, root1, 2));
+    assert_eq!(
+        tree.version_commitment(0).unwrap(),
+        compute_version_commitment::<H>(0, root0, 1)
+    );
+
+    // Changing any single component changes the commitment.
+    assert_ne!(commitment, compute_version_commitment::<H>(2, root1, 2));
+    assert_ne!(commitment, compute_version_commitment::<H>(1, root0, 2));
+    assert_ne!(commitment, compute_version_commitment::<H>(1, root1, 3));
+
+    let err = tree.version_commitment(2).unwrap_err();
+    assert!(err.downcast_ref::<MissingRootError>().is_some());
+}
+
+fn test_stores_equal_at<H: SimpleHasher>() {
+    let a = MockTreeStore::default();
+    let b = MockTreeStore::default();
+    let tree_a = JellyfishMerkleTree::<_, H>::new(&a);
+    let tree_b = JellyfishMerkleTree::<_, H>::new(&b);
+
+    let value_set: Vec<_> = (0..50u8)
+        .map(|byte| (KeyHash([byte; 32]), Some(vec![byte])))
+        .collect();
+    let (_root, batch) = tree_a.put_value_set(value_set, 0 /* version */).unwrap();
+    a.write_tree_update_batch(batch.clone()).unwrap();
+    b.write_tree_update_batch(batch).unwrap();
+    assert!(stores_equal_at(&a, &b, 0).unwrap());
+
+    // The stores diverge at version 1, in a single leaf.
+    let key = KeyHash([7u8; 32]);
+    let (_root, batch) = tree_a
+        .put_value_set(vec![(key, Some(vec![1]))], 1 /* version */)
+        .unwrap();
+    a.write_tree_update_batch(batch).unwrap();
+    let (_root, batch) = tree_b
+        .put_value_set(vec![(key, Some(vec![2]))], 1 /* version */)
+        .unwrap();
+    b.write_tree_update_batch(batch).unwrap();
+    assert!(!stores_equal_at(&a, &b, 1).unwrap());
+    assert!(stores_equal_at(&a, &b, 0).unwrap());
+
+    // A version present in one store only differs.
+    let (_root, batch) = tree_a
+        .put_value_set(vec![(KeyHash([8u8; 32]), None)], 2 /* version */)
+        .unwrap();
+    a.write_tree_update_batch(batch).unwrap();
+    assert!(!stores_equal_at(&a, &b, 2).unwrap());
+
+    let err = stores_equal_at(&a, &b, 3).unwrap_err();
+    assert!(err.downcast_ref::<MissingRootError>().is_some());
+}
+
 fn test_non_existence<H: SimpleHasher>() {
     let db = MockTreeStore::default();
     let tree = JellyfishMerkleTree::<_, H>::new(&db);
@@ -591,6 +1328,453 @@ fn test_missing_root<H: SimpleHasher>() {
     assert_eq!(err.version, 0);
 }
 
+fn test_reject_duplicate_keys<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let key1 = KeyHash::with::<H>(b"key1");
+    let key2 = KeyHash::with::<H>(b"key2");
+    let key3 = KeyHash::with::<H>(b"key3");
+    let value_set = vec![
+        (key1, Some(b"a".to_vec())),
+        (key2, Some(b"b".to_vec())),
+        (key1, Some(b"c".to_vec())),
+        (key3, None),
+        (key2, None),
+        (key1, Some(b"d".to_vec())),
+    ];
+
+    // By default, the last value given for a key wins.
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree.put_value_set(value_set.clone(), 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get(key1, 0).unwrap(), Some(b"d".to_vec()));
+    assert_eq!(tree.get(key2, 0).unwrap(), None);
+
+    let tree = JellyfishMerkleTree::<_, H>::new(&db).reject_duplicate_keys();
+    let err = tree
+        .put_value_set(value_set.clone(), 1)
+        .unwrap_err()
+        .downcast::<DuplicateKeysError>()
+        .unwrap();
+    assert_eq!(err.version, 1);
+    let mut expected = vec![key1, key2];
+    expected.sort();
+    assert_eq!(err.key_hashes, expected);
+
+    // Every value set is checked, before any of them is applied.
+    let err = tree
+        .put_value_sets_with_proof(vec![vec![(key3, Some(b"e".to_vec()))], value_set], 1)
+        .err()
+        .unwrap()
+        .downcast::<DuplicateKeysError>()
+        .unwrap();
+    assert_eq!(err.version, 2);
+
+    let (_, batch) = tree
+        .put_value_set(vec![(key1, None), (key3, Some(b"e".to_vec()))], 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get(key3, 1).unwrap(), Some(b"e".to_vec()));
+}
+
+fn test_verify_on_read<H: SimpleHasher>() {
+    let db = MockTreeStore::new(true);
+    let key1 = KeyHash([0x00; 32]);
+    let key2 = KeyHash([0x10; 32]);
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root_hash, batch) = tree
+        .put_value_set(
+            vec![(key1, Some(b"a".to_vec())), (key2, Some(b"b".to_vec()))],
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // The leaf of `key1` is replaced by another valid leaf, which its parent does not hash to.
+    let leaf_key = NodeKey::new_empty_path(0).gen_child_node_key(0, Nibble::from(0));
+    let tampered = LeafNode::new(key1, ValueHash::with::<H>(b"tampered"));
+    let mut node_batch = NodeBatch::default();
+    node_batch.insert_node(leaf_key.clone(), tampered.clone().into());
+    db.write_node_batch(&node_batch).unwrap();
+
+    // By default, the tampered leaf is served, with a proof that does not verify.
+    let (value, proof) = tree.get_with_proof(key1, 0).unwrap();
+    assert_eq!(value, Some(b"a".to_vec()));
+    assert!(proof.verify(root_hash, key1, value.as_ref()).is_err());
+
+    let tree = JellyfishMerkleTree::<_, H>::new(&db).verify_on_read();
+    let err = tree
+        .get_with_proof(key1, 0)
+        .unwrap_err()
+        .downcast::<CorruptedNodeError>()
+        .unwrap();
+    assert_eq!(err.node_key, leaf_key);
+    assert_eq!(err.found, tampered.hash::<H>());
+    let err = tree
+        .get_subtree_proof(0, NibblePath::new(vec![0x00]))
+        .unwrap_err();
+    assert!(err.downcast_ref::<CorruptedNodeError>().is_some());
+
+    // The tampered leaf is also caught as the sibling of the proof of `key2`.
+    let err = tree
+        .get_with_proof(key2, 0)
+        .unwrap_err()
+        .downcast::<CorruptedNodeError>()
+        .unwrap();
+    assert_eq!(err.node_key, leaf_key);
+
+    // Once the leaf is restored, every read succeeds again.
+    let mut node_batch = NodeBatch::default();
+    let original = LeafNode::new(key1, ValueHash::with::<H>(b"a"));
+    node_batch.insert_node(leaf_key, original.into());
+    db.write_node_batch(&node_batch).unwrap();
+    for (key, value) in [(key1, b"a"), (key2, b"b")] {
+        let (found, proof) = tree.get_with_proof(key, 0).unwrap();
+        assert_eq!(found, Some(value.to_vec()));
+        proof.verify(root_hash, key, found.as_ref()).unwrap();
+    }
+}
+
+fn test_verify_batch<H: SimpleHasher + Sync>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let keys: Vec<KeyHash> = (0..6u8).map(|i| KeyHash([i; 32])).collect();
+    let (root, batch) = tree
+        .put_value_set(
+            keys[..4]
+                .iter()
+                .map(|key| (*key, Some(key.0[..1].to_vec()))),
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let mut items: Vec<ProofItem<H>> = keys
+        .iter()
+        .map(|key| {
+            let (value, proof) = tree.get_with_proof(*key, 0).unwrap();
+            (*key, value, proof, root)
+        })
+        .collect();
+    // A wrong value, a claimed value for an absent key, and a wrong root.
+    items[1].1 = Some(vec![9]);
+    items[4].1 = Some(vec![4]);
+    items[2].3 = RootHash([7; 32]);
+
+    let results = verify_batch(&items);
+    assert_eq!(results.len(), items.len());
+    let valid: Vec<bool> = results.iter().map(Result::is_ok).collect();
+    assert_eq!(valid, [true, false, false, true, false, true]);
+    for ((key, value, proof, root_hash), result) in items.iter().zip(&results) {
+        assert_eq!(
+            proof.verify(*root_hash, *key, value.as_ref()).is_ok(),
+            result.is_ok()
+        );
+    }
+    assert!(verify_batch::<H>(&[]).is_empty());
+}
+
+fn test_compact_proof_bytes<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    // Two keys differing in their last bit only, whose proofs have 255 placeholder siblings,
+    // among random keys giving the proofs some non-placeholder siblings at the top.
+    let deep_key = KeyHash([0u8; 32]);
+    let mut sibling_key = deep_key;
+    sibling_key.0[31] = 1;
+    let mut rng: StdRng = SeedableRng::from_seed([0; 32]);
+    let keys: Vec<KeyHash> = [deep_key, sibling_key]
+        .into_iter()
+        .chain((0..100).map(|_| KeyHash(rng.gen())))
+        .collect();
+    let (root, batch) = tree
+        .put_value_set(keys.iter().map(|&key| (key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let absent = KeyHash([0xffu8; 32]);
+    for &key in keys.iter().chain([&absent]) {
+        let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+        let bytes = proof.to_compact_bytes();
+        let decoded = SparseMerkleProof::<H>::from_compact_bytes(&bytes).unwrap();
+        assert_eq!(decoded, proof);
+        decoded.verify(root, key, value.as_ref()).unwrap();
+        assert!(bytes.len() <= bincode::serialize(&proof).unwrap().len());
+    }
+
+    let (value, proof) = tree.get_with_proof(deep_key, 0).unwrap();
+    assert_eq!(proof.siblings().len(), 256);
+    let bytes = proof.to_compact_bytes();
+    assert!(bytes.len() * 2 < bincode::serialize(&proof).unwrap().len());
+    SparseMerkleProof::<H>::from_compact_bytes(&bytes)
+        .unwrap()
+        .verify(root, deep_key, value.as_ref())
+        .unwrap();
+
+    // Truncated, extended or corrupted encodings are rejected.
+    assert!(SparseMerkleProof::<H>::from_compact_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(
+        SparseMerkleProof::<H>::from_compact_bytes(&[bytes.as_slice(), &[0]].concat()).is_err()
+    );
+    let mut corrupted = bytes.clone();
+    corrupted[0] = 2;
+    assert!(SparseMerkleProof::<H>::from_compact_bytes(&corrupted).is_err());
+    assert!(SparseMerkleProof::<H>::from_compact_bytes(&[]).is_err());
+}
+
+#[cfg(feature = "json")]
+fn test_proof_json<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let mut rng: StdRng = SeedableRng::from_seed([0; 32]);
+    let keys: Vec<KeyHash> = (0..100).map(|_| KeyHash(rng.gen())).collect();
+    let (root, batch) = tree
+        .put_value_set(keys.iter().map(|&key| (key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let absent = KeyHash([0xffu8; 32]);
+    for &key in keys.iter().chain([&absent]) {
+        let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+        let json = proof.to_json();
+        let decoded = SparseMerkleProof::<H>::from_json(&json).unwrap();
+        assert_eq!(decoded, proof);
+        assert_eq!(decoded.to_json(), json);
+        decoded.verify(root, key, value.as_ref()).unwrap();
+    }
+
+    // Siblings are tagged with their kind, placeholders carrying nothing else.
+    let (_, proof) = tree.get_with_proof(keys[0], 0).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&proof.to_json()).unwrap();
+    let siblings = json["siblings"].as_array().unwrap();
+    assert_eq!(siblings.len(), proof.siblings().len());
+    for (sibling, node) in siblings.iter().zip(proof.siblings()) {
+        match sibling["kind"].as_str().unwrap() {
+            "placeholder" => assert_eq!(sibling.as_object().unwrap().len(), 1),
+            "internal" | "leaf" => assert_eq!(sibling["hash"], hex::encode(node.hash::<H>())),
+            kind => panic!("unexpected sibling kind {}", kind),
+        }
+    }
+    assert_eq!(json["leaf"]["key_hash"], hex::encode(keys[0].0));
+
+    // A sibling whose hash does not match its contents is rejected, as is malformed JSON.
+    let mut tampered = json.clone();
+    let internal = tampered["siblings"]
+        .as_array_mut()
+        .unwrap()
+        .iter_mut()
+        .find(|sibling| sibling["kind"] != "placeholder")
+        .unwrap();
+    internal["hash"] = hex::encode([0u8; 32]).into();
+    assert!(SparseMerkleProof::<H>::from_json(&tampered.to_string()).is_err());
+    assert!(SparseMerkleProof::<H>::from_json("{}").is_err());
+}
+
+fn test_empty_value_is_present<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let empty = KeyHash([1u8; 32]);
+    let deleted = KeyHash([2u8; 32]);
+    let absent = KeyHash([3u8; 32]);
+    let (root0, batch) = tree
+        .put_value_set(vec![(empty, Some(vec![])), (deleted, Some(vec![2]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (root1, batch) = tree.put_value_set(vec![(deleted, None)], 1).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // The empty value is present, with an inclusion proof that does not prove it absent.
+    for (version, root) in [(0, root0), (1, root1)] {
+        assert_eq!(tree.get(empty, version).unwrap(), Some(vec![]));
+        assert!(tree.is_present(empty, version).unwrap());
+        let (value, proof) = tree.get_with_proof(empty, version).unwrap();
+        assert_eq!(value, Some(vec![]));
+        proof.verify(root, empty, Some(&[] as &[u8])).unwrap();
+        assert!(proof.verify(root, empty, None::<&[u8]>).is_err());
+    }
+
+    // The deleted key is absent once deleted, as is a key never written.
+    assert!(tree.is_present(deleted, 0).unwrap());
+    assert!(!tree.is_present(deleted, 1).unwrap());
+    let (value, proof) = tree.get_with_proof(deleted, 1).unwrap();
+    assert_eq!(value, None);
+    proof.verify(root1, deleted, None::<&[u8]>).unwrap();
+    assert!(proof.verify(root1, deleted, Some(&[] as &[u8])).is_err());
+    assert!(!tree.is_present(absent, 1).unwrap());
+    assert!(tree.is_present(empty, 2).is_err());
+}
+
+fn test_generate_workload<H: SimpleHasher>() {
+    use crate::testing::generate_workload;
+
+    let workload = generate_workload(7, 100, 20);
+    assert_eq!(workload, generate_workload(7, 100, 20));
+    assert_ne!(workload, generate_workload(8, 100, 20));
+    // The output is pinned, so that a workload reproduces across releases too.
+    assert_eq!(
+        hex::encode(workload[0][0].0 .0),
+        "01a4f3c6a77f45cc3fdee61ce2f65ddc59fc4e4862e7b91bb37af8c7f1b98ffe"
+    );
+    assert_eq!(workload.len(), 20);
+    assert_eq!(workload[0].len(), 100);
+    assert!(workload[0].iter().all(|(_, value)| value.is_some()));
+    for value_set in &workload[1..] {
+        assert_eq!(value_set.len(), 10);
+        assert!(value_set.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+    assert!(workload.iter().flatten().any(|(_, value)| value.is_none()));
+
+    // The same workload builds the same tree.
+    let roots: Vec<_> = (0..2)
+        .map(|_| {
+            let db = MockTreeStore::default();
+            let tree = JellyfishMerkleTree::<_, H>::new(&db);
+            let (roots, batch) = tree.put_value_sets(workload.clone(), 0).unwrap();
+            db.write_tree_update_batch(batch).unwrap();
+            roots
+        })
+        .collect();
+    assert_eq!(roots[0], roots[1]);
+    assert!(generate_workload(7, 0, 3).iter().all(Vec::is_empty));
+}
+
+fn test_put_prehashed_value_sets<H: SimpleHasher>() {
+    let mut rng: StdRng = SeedableRng::from_seed([0; 32]);
+    let keys: Vec<KeyHash> = (0..50).map(|_| KeyHash(rng.gen())).collect();
+    let value_sets: Vec<Vec<(KeyHash, Option<Vec<u8>>)>> = vec![
+        keys.iter()
+            .map(|&key| (key, Some(key.0.to_vec())))
+            .collect(),
+        keys.iter()
+            .step_by(3)
+            .map(|&key| (key, None))
+            .chain(keys.iter().step_by(5).map(|&key| (key, Some(vec![1]))))
+            .collect(),
+    ];
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (roots, batch) = tree.put_value_sets(value_sets.clone(), 0).unwrap();
+
+    let prehashed_db = MockTreeStore::default();
+    let prehashed_tree = JellyfishMerkleTree::<_, H>::new(&prehashed_db);
+    let prehashed_value_sets = value_sets.iter().map(|value_set| {
+        value_set.iter().map(|(key, value)| {
+            let value = value
+                .clone()
+                .map(|value| (ValueHash::with::<H>(&value), value));
+            (*key, value)
+        })
+    });
+    let (prehashed_roots, prehashed_batch) = prehashed_tree
+        .put_prehashed_value_sets(prehashed_value_sets, 0)
+        .unwrap();
+    assert_eq!(prehashed_roots, roots);
+    assert_eq!(prehashed_batch, batch);
+
+    // A wrong hash is caught in debug builds.
+    #[cfg(debug_assertions)]
+    {
+        let wrong_hash = ValueHash::with::<H>(b"another value");
+        let value_set = vec![(keys[0], Some((wrong_hash, vec![2])))];
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            prehashed_tree.put_prehashed_value_set(value_set, 0)
+        }));
+        assert!(result.is_err());
+    }
+}
+
+fn test_depth_stats<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (_, batch) = tree.put_value_set(vec![], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.depth_stats(0).unwrap(), DepthStats::default());
+
+    // Two keys sharing their first 63 nibbles, whose leaves sit below 64 internal nodes, and a
+    // key diverging from them at the first nibble.
+    let deep_key = KeyHash([0u8; 32]);
+    let mut sibling_key = deep_key;
+    sibling_key.0[31] = 1;
+    let shallow_key = KeyHash([0xffu8; 32]);
+    let (_, batch) = tree
+        .put_value_set(
+            [deep_key, sibling_key, shallow_key].map(|key| (key, Some(key.0.to_vec()))),
+            1,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let stats = tree.depth_stats(1).unwrap();
+    assert_eq!(stats.leaves, 3);
+    assert_eq!(stats.max_depth, 64);
+    assert_eq!(stats.min_depth, 1);
+    assert!((stats.mean_depth - 43.0).abs() < 1e-9);
+    assert_eq!(tree.sample_depth_stats(1, 3).unwrap(), stats);
+
+    // The first two of the three leaves in key order are sampled.
+    let sampled = tree.sample_depth_stats(1, 2).unwrap();
+    assert_eq!(sampled.leaves, 2);
+    assert_eq!(sampled.min_depth, 64);
+    assert_eq!(sampled.max_depth, 64);
+    assert!(tree.sample_depth_stats(1, 0).is_err());
+
+    let mut rng: StdRng = SeedableRng::from_seed([0; 32]);
+    let keys: Vec<KeyHash> = (0..1000).map(|_| KeyHash(rng.gen())).collect();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|&key| (key, Some(key.0.to_vec()))), 2)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let stats = tree.depth_stats(2).unwrap();
+    assert_eq!(stats.leaves, 1003);
+    assert_eq!(stats.max_depth, 64);
+    let sampled = tree.sample_depth_stats(2, 100).unwrap();
+    assert_eq!(sampled.leaves, 100);
+    assert!(sampled.min_depth >= stats.min_depth);
+    assert!(sampled.max_depth <= stats.max_depth);
+
+    assert!(tree
+        .depth_stats(3)
+        .unwrap_err()
+        .downcast_ref::<MissingRootError>()
+        .is_some());
+}
+
+fn test_fanout_histogram<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    // The root forks on four nibbles, and its first child on three.
+    let keys = [0x00, 0x01, 0x02, 0x10, 0x20, 0x30].map(|byte| {
+        let mut key = [0u8; 32];
+        key[0] = byte;
+        KeyHash(key)
+    });
+    let (_, batch) = tree
+        .put_value_set(keys.map(|key| (key, Some(vec![1]))), 0 /* version */)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let histogram = tree.fanout_histogram(0).unwrap();
+    let mut expected = [0u64; 17];
+    expected[3] = 1;
+    expected[4] = 1;
+    assert_eq!(histogram, expected);
+    // Every node of the only version is live, so the others are the leaves.
+    let internal_nodes = (db.num_nodes() - tree.get_leaf_count(0).unwrap()) as u64;
+    assert_eq!(histogram.iter().sum::<u64>(), internal_nodes);
+
+    let (_, batch) = tree
+        .put_value_set(vec![(keys[0], None)], 1 /* version */)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    expected[3] = 0;
+    expected[2] = 1;
+    assert_eq!(tree.fanout_histogram(1).unwrap(), expected);
+    assert!(tree
+        .fanout_histogram(2)
+        .unwrap_err()
+        .downcast_ref::<MissingRootError>()
+        .is_some());
+}
+
 fn test_non_batch_empty_write_set<H: SimpleHasher>() {
     let db = MockTreeStore::default();
     let tree = JellyfishMerkleTree::<_, H>::new(&db);
@@ -600,6 +1784,97 @@ fn test_non_batch_empty_write_set<H: SimpleHasher>() {
     assert_eq!(root.0, SPARSE_MERKLE_PLACEHOLDER_HASH);
 }
 
+fn test_empty_tree_proof<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let (root, batch) = tree.put_value_set(vec![], 0 /* version */).unwrap();
+    assert_eq!(root, JellyfishMerkleTree::<MockTreeStore, H>::EMPTY_ROOT);
+    assert_eq!(root.0, SPARSE_MERKLE_PLACEHOLDER_HASH);
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(
+        db.get_node(&NodeKey::new_empty_path(0)).unwrap(),
+        Node::Null
+    );
+
+    // Every key is absent, with a proof of it against the placeholder root.
+    for key in [
+        KeyHash([0u8; 32]),
+        KeyHash([7u8; 32]),
+        KeyHash([u8::MAX; 32]),
+    ] {
+        let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+        assert_eq!(value, None);
+        assert!(proof.leaf().is_none() && proof.siblings().is_empty());
+        proof.verify(root, key, value.as_ref()).unwrap();
+        assert!(proof.verify(root, key, Some(&vec![1])).is_err());
+    }
+
+    // The first key replaces the empty root, which goes stale as any replaced root does.
+    let key = KeyHash([1u8; 32]);
+    let (root1, batch) = tree
+        .put_value_set(vec![(key, Some(vec![1]))], 1 /* version */)
+        .unwrap();
+    assert_ne!(root1, root);
+    assert_eq!(
+        batch
+            .stale_node_index_batch
+            .iter()
+            .map(|index| (index.stale_since_version, index.node_key.clone()))
+            .collect::<Vec<_>>(),
+        vec![(1, NodeKey::new_empty_path(0))]
+    );
+    db.write_tree_update_batch(batch).unwrap();
+    let (value, proof) = tree.get_with_proof(key, 1).unwrap();
+    assert_eq!(value, Some(vec![1]));
+    proof.verify(root1, key, value.as_ref()).unwrap();
+    let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+    assert_eq!(value, None);
+    proof.verify(root, key, value.as_ref()).unwrap();
+
+    db.purge_stale_nodes(1).unwrap();
+    assert!(tree.get_root_hash_option(0).unwrap().is_none());
+    assert_eq!(tree.get(key, 1).unwrap(), Some(vec![1]));
+}
+
+fn test_delete_value_set<H: SimpleHasher>() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let keys: Vec<KeyHash> = (0..10u8).map(|i| KeyHash([i; 32])).collect();
+    let (_, batch) = tree
+        .put_value_set(
+            keys.iter().map(|key| (*key, Some(key.0[..1].to_vec()))),
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let absent = KeyHash([u8::MAX; 32]);
+    let deleted = vec![keys[1], keys[4], keys[7], absent];
+    let (root, batch) = tree
+        .delete_value_set(deleted.clone(), 1 /* version */)
+        .unwrap();
+    let (expected_root, _) = tree
+        .put_value_set(deleted.iter().map(|key| (*key, None)), 1 /* version */)
+        .unwrap();
+    assert_eq!(root, expected_root);
+    for key in &deleted {
+        assert_eq!(batch.node_batch.values().get(&(1, *key)), Some(&None));
+    }
+    db.write_tree_update_batch(batch).unwrap();
+
+    for key in &keys {
+        let (value, proof) = tree.get_with_proof(*key, 1).unwrap();
+        if deleted.contains(key) {
+            assert_eq!(value, None);
+        } else {
+            assert_eq!(value, Some(key.0[..1].to_vec()));
+        }
+        proof.verify(root, *key, value.as_ref()).unwrap();
+        assert_eq!(tree.get(*key, 0).unwrap(), Some(key.0[..1].to_vec()));
+    }
+    assert_eq!(tree.get_leaf_count(1).unwrap(), 7);
+}
+
 fn test_put_value_sets<H: SimpleHasher>() {
     let mut keys = vec![];
     let mut values = vec![];
@@ -6,9 +6,10 @@ use alloc::{format, vec};
 use proptest::prelude::*;
 
 use crate::types::nibble::{
-    nibble_path::{arb_internal_nibble_path, skip_common_prefix, NibblePath},
+    nibble_path::{arb_internal_nibble_path, nibble_path_of, skip_common_prefix, NibblePath},
     Nibble,
 };
+use crate::KeyHash;
 
 #[test]
 fn test_nibble_path_fmt() {
@@ -37,6 +38,18 @@ fn test_create_nibble_path_success() {
     assert_eq!(nibble_path.num_nibbles(), 0);
 }
 
+#[test]
+fn test_nibble_path_of() {
+    let mut bytes = [0u8; 32];
+    bytes[0] = 0xa7;
+    bytes[31] = 0x3c;
+    let nibble_path = nibble_path_of(KeyHash(bytes));
+    assert_eq!(nibble_path.num_nibbles(), 64);
+    assert_eq!(nibble_path.get_nibble(0), Nibble::from(0x0a));
+    assert_eq!(nibble_path.get_nibble(1), Nibble::from(0x07));
+    assert_eq!(nibble_path.last(), Some(Nibble::from(0x0c)));
+}
+
 #[test]
 #[should_panic(expected = "Last nibble must be 0.")]
 fn test_create_nibble_path_failure() {
@@ -11,7 +11,7 @@ use rand::{rngs::OsRng, Rng, RngCore};
 use sha2::Sha256;
 
 use crate::{
-    node_type::{Child, Children, InternalNode, Node, NodeKey, NodeType},
+    node_type::{Child, Children, InternalNode, Node, NodeKey, NodeType, OrderedNodeKey},
     storage::TreeReader,
     types::{
         nibble::{nibble_path::NibblePath, Nibble},
@@ -1101,3 +1101,32 @@ proptest! {
         }
     }
 }
+
+proptest! {
+    #[test]
+    fn test_ordered_node_key_sorts_as_encoded(
+        node_keys in prop::collection::vec(any::<NodeKey>(), 0..64),
+    ) {
+        let mut by_key: Vec<OrderedNodeKey> =
+            node_keys.iter().cloned().map(OrderedNodeKey::from).collect();
+        by_key.sort();
+        let mut by_bytes: Vec<Vec<u8>> = by_key.iter().map(OrderedNodeKey::encode).collect();
+        by_bytes.sort();
+        let decoded = by_bytes
+            .iter()
+            .map(|bytes| OrderedNodeKey::decode(bytes))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        prop_assert_eq!(&decoded, &by_key);
+        for pair in by_key.windows(2) {
+            let depths = (
+                pair[0].node_key().nibble_path().num_nibbles(),
+                pair[1].node_key().nibble_path().num_nibbles(),
+            );
+            prop_assert!(depths.0 <= depths.1);
+        }
+        for node_key in node_keys {
+            prop_assert_eq!(NodeKey::from(OrderedNodeKey::from(node_key.clone())), node_key);
+        }
+    }
+}
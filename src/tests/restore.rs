@@ -194,3 +194,89 @@ fn restore_without_interruption<H: SimpleHasher>(
 
     assert_success::<H>(target_db, expected_root_hash, btree, target_version);
 }
+
+/// Builds a tree holding four keys under each of the first nibbles `0` to `3`, with `corrupted`
+/// replacing the value of the last key under the first nibble `2`.
+fn init_grouped_db<H: SimpleHasher>(
+    corrupted: bool,
+) -> (MockTreeStore, Version, Vec<(KeyHash, OwnedValue)>) {
+    let mut entries = Vec::new();
+    for first in 0u8..4 {
+        for second in 0u8..4 {
+            let mut key = [0u8; 32];
+            key[0] = first << 4 | second;
+            let value = if corrupted && first == 2 && second == 3 {
+                alloc::vec![0xff]
+            } else {
+                alloc::vec![first, second]
+            };
+            entries.push((KeyHash(key), value));
+        }
+    }
+    let (db, version) = init_mock_db::<H>(&entries.clone().into_iter().collect());
+    (db, version, entries)
+}
+
+fn test_verify_partial<H: SimpleHasher>() {
+    let (db, version, entries) = init_grouped_db::<H>(false);
+    let tree = JellyfishMerkleTree::<_, H>::new(&db);
+    let root = match db
+        .get_node(&crate::storage::NodeKey::new_empty_path(version))
+        .unwrap()
+    {
+        crate::storage::Node::Internal(root) => root,
+        _ => panic!("The root must be an internal node."),
+    };
+    // The trusted subtree roots of every first nibble, as known from another source.
+    let expected_subtree_roots: Vec<_> = (0u8..16)
+        .map(|nibble| {
+            let hash = root
+                .child(nibble.into())
+                .map_or(crate::SPARSE_MERKLE_PLACEHOLDER_HASH, |child| child.hash);
+            (alloc::vec![nibble], RootHash(hash))
+        })
+        .collect();
+
+    // A restore from an honest peer matches the trusted subtree roots after every chunk.
+    let mut restore = JellyfishMerkleRestore::<H>::new(
+        Arc::new(MockTreeStore::default()),
+        version,
+        tree.get_root_hash(version).unwrap(),
+    )
+    .unwrap();
+    assert!(restore.verify_partial(&expected_subtree_roots).unwrap());
+    for chunk in entries.chunks(4) {
+        let proof = tree
+            .get_range_proof(chunk.last().unwrap().0, version)
+            .unwrap();
+        restore.add_chunk(chunk.to_vec(), proof).unwrap();
+        assert!(restore.verify_partial(&expected_subtree_roots).unwrap());
+    }
+    restore.finish().unwrap();
+
+    // A bad peer serves chunks consistent with its own root, so each chunk passes its range
+    // proof. The partial check catches the corrupted subtree as soon as it is complete.
+    let (bad_db, _, bad_entries) = init_grouped_db::<H>(true);
+    let bad_tree = JellyfishMerkleTree::<_, H>::new(&bad_db);
+    let mut restore = JellyfishMerkleRestore::<H>::new(
+        Arc::new(MockTreeStore::default()),
+        version,
+        bad_tree.get_root_hash(version).unwrap(),
+    )
+    .unwrap();
+    let mut partial_checks = Vec::new();
+    for chunk in bad_entries.chunks(4) {
+        let proof = bad_tree
+            .get_range_proof(chunk.last().unwrap().0, version)
+            .unwrap();
+        restore.add_chunk(chunk.to_vec(), proof).unwrap();
+        partial_checks.push(restore.verify_partial(&expected_subtree_roots).unwrap());
+    }
+    // The subtree under `2` is only complete once the chunk under `3` has been added.
+    assert_eq!(partial_checks, alloc::vec![true, true, true, false]);
+}
+
+#[test]
+fn test_verify_partial_sha256() {
+    test_verify_partial::<Sha256>()
+}
@@ -4,7 +4,8 @@
 //! Tests for the Jellyfish Merkle Tree using RocksDB as backing storage.
 
 use crate::{
-    rocksdb_store::RocksDbTreeStore,
+    rocksdb_store::{Inconsistency, RecordKind, RocksDbTreeStore, FORMAT_VERSION},
+    storage::NodeKey,
     JellyfishMerkleTree, KeyHash, SPARSE_MERKLE_PLACEHOLDER_HASH,
 };
 use sha2::Sha256;
@@ -14,6 +15,13 @@ fn hash_leaf(key: KeyHash, value_hash: crate::ValueHash) -> [u8; 32] {
     SparseMerkleLeafNode::new(key, value_hash).hash::<Sha256>()
 }
 
+/// Encodes `payload` with bincode as a stored record of `kind`.
+fn raw_record(kind: RecordKind, payload: &impl serde::Serialize) -> anyhow::Result<Vec<u8>> {
+    let mut record = vec![FORMAT_VERSION, kind as u8];
+    record.extend(bincode::serialize(payload)?);
+    Ok(record)
+}
+
 #[test]
 fn test_rocksdb_basic_operations() -> anyhow::Result<()> {
     // Create a temporary RocksDB store
@@ -182,3 +190,5406 @@ fn test_rocksdb_visualization_example() -> anyhow::Result<()> {
     
     Ok(())
 }
+
+#[test]
+fn test_rocksdb_export_dot() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+
+    let key1 = KeyHash([0x10u8; 32]);
+    let value1 = vec![0x01];
+    let key2 = KeyHash([0x20u8; 32]);
+    let value2 = vec![0x02];
+
+    let (_root, batch) =
+        tree.put_value_set(vec![(key1, Some(value1.clone())), (key2, Some(value2))], 0)?;
+    db.write_tree_update_batch(batch)?;
+
+    let dot = db.export_dot(0)?;
+    assert!(dot.starts_with("digraph jmt {"));
+    assert!(dot.trim_end().ends_with('}'));
+    // The two leaves diverge at the first nibble, so the root has children at nibbles 1 and 2.
+    assert!(dot.contains("[label=\"1\"]"));
+    assert!(dot.contains("[label=\"2\"]"));
+    assert!(dot.contains(&hex::encode(key1.0)));
+    assert!(dot.contains(&hex::encode(crate::ValueHash::with::<Sha256>(&value1).0)));
+
+    // Exporting a version that was never written fails with `MissingRootError`.
+    let err = db.export_dot(1).unwrap_err();
+    assert!(err.downcast_ref::<crate::MissingRootError>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_get_with_proof_batch() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+
+    // Keys sharing long prefixes, so that their paths share ancestors.
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    for i in 0u8..16 {
+        let mut key = [0u8; 32];
+        key[0] = i >> 2;
+        key[1] = i;
+        keys.push(KeyHash(key));
+        values.push((KeyHash(key), Some(vec![i])));
+    }
+    let (root, batch) = tree.put_value_set(values, 0)?;
+    db.write_tree_update_batch(batch)?;
+
+    // Query in an arbitrary order, including a duplicate and two absent keys.
+    let mut queried = vec![keys[7], keys[0], KeyHash([0xffu8; 32]), keys[15], keys[7]];
+    queried.push(KeyHash([0x01u8; 32]));
+
+    let batched = db.get_with_proof_batch(&queried, 0)?;
+    assert_eq!(batched.len(), queried.len());
+    for (key, (value, proof)) in queried.iter().zip(batched) {
+        let (expected_value, expected_proof) = tree.get_with_proof(*key, 0)?;
+        assert_eq!(value, expected_value);
+        assert_eq!(proof, expected_proof);
+        proof.verify(root, *key, value.as_ref())?;
+    }
+
+    // An unknown version fails just like `get_with_proof`.
+    let err = db.get_with_proof_batch(&queried, 1).unwrap_err();
+    assert!(err.downcast_ref::<crate::MissingRootError>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_mmap_reads() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+    use tempfile::TempDir;
+
+    let temp_dir = TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        allow_mmap_reads: true,
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(temp_dir.path(), config.clone())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+
+    let key1 = KeyHash([1u8; 32]);
+    let value1 = vec![0x01, 0x02];
+    let key2 = KeyHash([2u8; 32]);
+    let value2 = vec![0x03, 0x04];
+    let (root, batch) = tree.put_value_set(
+        vec![(key1, Some(value1.clone())), (key2, Some(value2.clone()))],
+        0,
+    )?;
+    db.write_tree_update_batch(batch)?;
+    // Flush so that the reads below are served from SST files rather than the memtable.
+    db.db().flush()?;
+
+    let (retrieved, proof) = tree.get_with_proof(key1, 0)?;
+    assert_eq!(retrieved, Some(value1.clone()));
+    proof.verify(root, key1, Some(&value1))?;
+
+    // Reopening with mmap reads still serves everything that was persisted.
+    drop(db);
+    let db = RocksDbTreeStore::with_config(temp_dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(0)?, root);
+    assert_eq!(tree.get_with_proof(key2, 0)?.0, Some(value2));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_latest_version() -> anyhow::Result<()> {
+    use crate::{mock::MockTreeStore, rocksdb_store::RocksDbStoreConfig};
+    use tempfile::TempDir;
+
+    let temp_dir = TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        allow_backfill: true,
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(temp_dir.path(), config)?;
+    assert_eq!(db.latest_version()?, None);
+
+    // Compute the batches of versions 0 to 2 against a scratch store, so that they can be
+    // written to the RocksDB store in any order.
+    let scratch = MockTreeStore::default();
+    let tree: JellyfishMerkleTree<MockTreeStore, Sha256> = JellyfishMerkleTree::new(&scratch);
+    let key = KeyHash([1u8; 32]);
+    let mut batches = Vec::new();
+    for version in 0..3u8 {
+        let (_root, batch) =
+            tree.put_value_set(vec![(key, Some(vec![version]))], version.into())?;
+        scratch.write_tree_update_batch(batch.clone())?;
+        batches.push(batch);
+    }
+    let batch_v2 = batches.pop().unwrap();
+    let batch_v1 = batches.pop().unwrap();
+    let batch_v0 = batches.pop().unwrap();
+
+    db.write_tree_update_batch(batch_v0)?;
+    assert_eq!(db.latest_version()?, Some(0));
+    db.write_tree_update_batch(batch_v2)?;
+    assert_eq!(db.latest_version()?, Some(2));
+    db.write_tree_update_batch(batch_v1)?;
+    assert_eq!(db.latest_version()?, Some(2));
+
+    // The latest version survives reopening the store.
+    drop(db);
+    let db = RocksDbTreeStore::new(temp_dir.path())?;
+    assert_eq!(db.latest_version()?, Some(2));
+
+    Ok(())
+}
+
+/// Writes versions `0..versions` to `db`, each updating `KeyHash([1; 32])` while
+/// `KeyHash([2; 32])` keeps the value it was given at version 0. Returns the root of each version.
+fn write_versions(db: &RocksDbTreeStore, versions: u8) -> anyhow::Result<Vec<crate::RootHash>> {
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(db);
+    let mut roots = Vec::new();
+    for version in 0..versions {
+        let mut value_set = vec![(KeyHash([1u8; 32]), Some(vec![version]))];
+        if version == 0 {
+            value_set.push((KeyHash([2u8; 32]), Some(vec![0xff])));
+        }
+        let (root, batch) = tree.put_value_set(value_set, version.into())?;
+        db.write_tree_update_batch(batch)?;
+        roots.push(root);
+    }
+    Ok(roots)
+}
+
+#[test]
+fn test_rocksdb_prune_preserves_labeled_versions() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let roots = write_versions(&db, 4)?;
+    db.set_label("checkpoint", 1)?;
+    assert_eq!(db.label_version("checkpoint")?, Some(1));
+
+    db.prune(3)?;
+
+    // The labeled version stays fully queryable, including the leaf it shares with version 0.
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(1)?, roots[1]);
+    for (key, value) in [
+        (KeyHash([1u8; 32]), vec![1]),
+        (KeyHash([2u8; 32]), vec![0xff]),
+    ] {
+        let (retrieved, proof) = tree.get_with_proof(key, 1)?;
+        assert_eq!(retrieved, Some(value.clone()));
+        proof.verify(roots[1], key, Some(&value))?;
+    }
+
+    // The unlabeled versions below the watermark are gone, the latest version is intact.
+    for version in [0, 2] {
+        let err = tree
+            .get_with_proof(KeyHash([1u8; 32]), version)
+            .unwrap_err();
+        assert!(err.downcast_ref::<crate::MissingRootError>().is_some());
+    }
+    assert_eq!(tree.get_with_proof(KeyHash([1u8; 32]), 3)?.0, Some(vec![3]));
+
+    // Once the label is removed, the next prune reclaims the checkpoint too.
+    db.remove_label("checkpoint")?;
+    db.prune(3)?;
+    assert!(tree.get_root_hash(1).is_err());
+    assert_eq!(
+        tree.get_with_proof(KeyHash([2u8; 32]), 3)?.0,
+        Some(vec![0xff])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_auto_prune_preserves_labeled_versions() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+    use tempfile::TempDir;
+
+    let temp_dir = TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        retain_versions: Some(2),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(temp_dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+
+    let roots = write_versions(&db, 2)?;
+    db.set_label("checkpoint", 0)?;
+    let (_root, batch) = tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![2]))], 2)?;
+    db.write_tree_update_batch(batch)?;
+    let (_root, batch) = tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![3]))], 3)?;
+    db.write_tree_update_batch(batch)?;
+
+    // Versions 2 and 3 are retained, version 0 is labeled and version 1 was pruned.
+    assert_eq!(tree.get_root_hash(0)?, roots[0]);
+    assert!(tree.get_root_hash(1).is_err());
+    assert_eq!(tree.get_with_proof(KeyHash([1u8; 32]), 2)?.0, Some(vec![2]));
+    assert_eq!(tree.get_with_proof(KeyHash([1u8; 32]), 3)?.0, Some(vec![3]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_batch_accumulator() -> anyhow::Result<()> {
+    use crate::rocksdb_store::BatchAccumulator;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let key1 = KeyHash([1u8; 32]);
+    let key2 = KeyHash([2u8; 32]);
+
+    let mut accumulator = BatchAccumulator::new(&db);
+    let mut roots = Vec::new();
+    for (version, value_set) in [
+        vec![(key1, Some(vec![0])), (key2, Some(vec![0xff]))],
+        vec![(key1, Some(vec![1]))],
+        vec![(key1, None)],
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        // Each version is computed on top of the pending, unflushed ones.
+        let tree: JellyfishMerkleTree<BatchAccumulator, Sha256> =
+            JellyfishMerkleTree::new(&accumulator);
+        let (root, batch) = tree.put_value_set(value_set, version as u64)?;
+        accumulator.add(batch);
+        roots.push(root);
+    }
+
+    // Nothing reaches the store before the flush.
+    assert_eq!(db.latest_version()?, None);
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert!(tree.get_root_hash_option(0)?.is_none());
+
+    accumulator.flush()?;
+    assert!(accumulator.is_empty());
+    assert_eq!(db.latest_version()?, Some(2));
+
+    for (version, root) in roots.iter().enumerate() {
+        assert_eq!(tree.get_root_hash(version as u64)?, *root);
+    }
+    assert_eq!(tree.get_with_proof(key1, 0)?.0, Some(vec![0]));
+    assert_eq!(tree.get_with_proof(key1, 1)?.0, Some(vec![1]));
+    assert_eq!(tree.get_with_proof(key1, 2)?.0, None);
+    assert_eq!(tree.get_with_proof(key2, 2)?.0, Some(vec![0xff]));
+
+    // The merged stale node indexes let pruning reclaim the flushed versions.
+    db.prune(2)?;
+    assert!(tree.get_root_hash_option(1)?.is_none());
+    assert_eq!(tree.get_root_hash(2)?, roots[2]);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_wal_file_info() -> anyhow::Result<()> {
+    use tempfile::TempDir;
+
+    let temp_dir = TempDir::new()?;
+    let db = RocksDbTreeStore::new(temp_dir.path())?;
+    write_versions(&db, 3)?;
+
+    // Nothing was flushed, so every write is still only in the WAL.
+    let wal_files = db.wal_file_info()?;
+    assert!(!wal_files.is_empty());
+    let latest_sequence = db.db().latest_sequence_number();
+    let logged = wal_files
+        .iter()
+        .find(|file| file.size_bytes > 0)
+        .expect("a non-empty WAL file");
+    assert!(logged.first_sequence.is_some());
+    assert_eq!(logged.last_sequence, Some(latest_sequence));
+    assert_eq!(logged.size_bytes, std::fs::metadata(&logged.path)?.len());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_verify_at() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    write_versions(&db, 2)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+
+    let key = KeyHash([1u8; 32]);
+    let (value, proof) = tree.get_with_proof(key, 1)?;
+    assert_eq!(value, Some(vec![1]));
+    db.verify_at(1, key, value.as_ref(), &proof)?;
+
+    // The proof of an absent key verifies its absence.
+    let absent = KeyHash([3u8; 32]);
+    let (value, absent_proof) = tree.get_with_proof(absent, 1)?;
+    assert!(value.is_none());
+    db.verify_at(1, absent, None::<Vec<u8>>, &absent_proof)?;
+
+    // A corrupted value, or the right value at the wrong version, does not verify.
+    let err = db.verify_at(1, key, Some(vec![0xaa]), &proof).unwrap_err();
+    assert!(err.to_string().contains("version 1"));
+    assert!(db.verify_at(0, key, Some(vec![1]), &proof).is_err());
+
+    let err = db.verify_at(2, key, Some(vec![1]), &proof).unwrap_err();
+    assert!(err.downcast_ref::<crate::MissingRootError>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_compact_tombstones() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+
+    let deleted = KeyHash([1u8; 32]);
+    let reinserted = KeyHash([2u8; 32]);
+    let deleted_late = KeyHash([3u8; 32]);
+    let pinned = KeyHash([4u8; 32]);
+    let value_sets = [
+        vec![
+            (deleted, Some(vec![0])),
+            (reinserted, Some(vec![0])),
+            (deleted_late, Some(vec![0])),
+        ],
+        vec![(deleted, None), (reinserted, None), (pinned, Some(vec![1]))],
+        vec![(reinserted, Some(vec![2])), (pinned, None)],
+        vec![(deleted_late, None)],
+    ];
+    for (version, value_set) in value_sets.into_iter().enumerate() {
+        let (_root, batch) = tree.put_value_set(value_set, version as u64)?;
+        db.write_tree_update_batch(batch)?;
+    }
+    // Version 1 is the only one that reads `pinned` as set.
+    db.set_label("checkpoint", 1)?;
+
+    assert_eq!(db.compact_tombstones(3)?, 1);
+
+    // The key deleted at version 1 has no entries left and still reads as absent.
+    assert!(db.value_versions(deleted)?.is_empty());
+    assert_eq!(tree.get_with_proof(deleted, 3)?.0, None);
+
+    // The key set again after its deletion keeps all of its entries.
+    assert_eq!(db.value_versions(reinserted)?, vec![0, 1, 2]);
+    assert_eq!(tree.get_with_proof(reinserted, 3)?.0, Some(vec![2]));
+    assert_eq!(tree.get_with_proof(reinserted, 0)?.0, Some(vec![0]));
+
+    // A deletion that is not older than the watermark is kept, and so are the entries the
+    // labeled version still reads.
+    assert_eq!(db.value_versions(deleted_late)?, vec![0, 3]);
+    assert_eq!(db.value_versions(pinned)?, vec![1, 2]);
+    assert_eq!(tree.get_with_proof(pinned, 1)?.0, Some(vec![1]));
+
+    // Without the label, the pinned key is compacted too.
+    db.remove_label("checkpoint")?;
+    assert_eq!(db.compact_tombstones(3)?, 1);
+    assert!(db.value_versions(pinned)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_verify_consistency() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (_root, batch) = tree.put_value_set(
+        vec![
+            (KeyHash([0x10; 32]), Some(vec![1])),
+            (KeyHash([0x20; 32]), Some(vec![2])),
+        ],
+        0,
+    )?;
+    db.write_tree_update_batch(batch)?;
+    assert!(db.verify_consistency(0)?.is_empty());
+
+    // Drop the leaf under nibble 2 of the root; only that edge should be reported.
+    let root_key = NodeKey::new_empty_path(0);
+    let child_key = root_key.gen_child_node_key(0, 2u8.into());
+    let nodes_cf = db.db().cf_handle("nodes").unwrap();
+    db.db()
+        .delete_cf(nodes_cf, raw_record(RecordKind::NodeKey, &child_key)?)?;
+    assert_eq!(
+        db.verify_consistency(0)?,
+        vec![Inconsistency::MissingChild {
+            parent: root_key,
+            nibble: 2,
+            child: child_key,
+        }]
+    );
+
+    assert_eq!(
+        db.verify_consistency(1)?,
+        vec![Inconsistency::MissingRoot { version: 1 }]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_value_full_bloom() -> anyhow::Result<()> {
+    use crate::{rocksdb_store::RocksDbStoreConfig, storage::TreeReader};
+    use rocksdb::perf::{set_perf_stats, PerfContext, PerfMetric, PerfStatsLevel};
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        value_full_bloom_bits: Some(10.0),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    write_versions(&db, 3)?;
+    // The bloom filter is built with the SST file, so move the values out of the memtable.
+    db.db().flush_cf(db.db().cf_handle("values").unwrap())?;
+
+    set_perf_stats(PerfStatsLevel::EnableCount);
+    let mut perf = PerfContext::default();
+
+    // Written at the latest version: served by the exact-version point read.
+    assert_eq!(db.get_latest_value(KeyHash([1u8; 32]))?, Some(vec![2]));
+    // Only written at version 0: the filter rejects the exact key and the seek finds it.
+    perf.reset();
+    assert_eq!(db.get_latest_value(KeyHash([2u8; 32]))?, Some(vec![0xff]));
+    assert_eq!(perf.metric(PerfMetric::BloomSstMissCount), 1);
+    // Never written.
+    perf.reset();
+    assert_eq!(db.get_latest_value(KeyHash([3u8; 32]))?, None);
+    assert_eq!(perf.metric(PerfMetric::BloomSstMissCount), 1);
+
+    // Exact-version reads through the tree agree.
+    for version in 0..3 {
+        assert_eq!(
+            db.get_value_option(version, KeyHash([1u8; 32]))?,
+            Some(vec![version as u8])
+        );
+    }
+    set_perf_stats(PerfStatsLevel::Disable);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_bloom_filter() -> anyhow::Result<()> {
+    use crate::{rocksdb_store::RocksDbStoreConfig, storage::TreeReader};
+    use rocksdb::perf::{set_perf_stats, PerfContext, PerfMetric, PerfStatsLevel};
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        bloom_filter_bits: Some(10.0),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config.clone())?;
+    write_versions(&db, 3)?;
+    let root = JellyfishMerkleTree::<_, Sha256>::new(&db).get_root_hash(2)?;
+    drop(db);
+
+    // Reads round-trip through the filtered SST files once reopened.
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    for name in ["nodes", "values"] {
+        db.db().flush_cf(db.db().cf_handle(name).unwrap())?;
+    }
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(2)?, root);
+    for version in 0..3 {
+        let (value, proof) = tree.get_with_proof(KeyHash([1u8; 32]), version)?;
+        assert_eq!(value, Some(vec![version as u8]));
+        proof.verify(tree.get_root_hash(version)?, KeyHash([1u8; 32]), value)?;
+    }
+    assert_eq!(tree.get(KeyHash([2u8; 32]), 2)?, Some(vec![0xff]));
+    assert_eq!(tree.get(KeyHash([3u8; 32]), 2)?, None);
+
+    // The root of a version never written is rejected by the filter of the nodes.
+    set_perf_stats(PerfStatsLevel::EnableCount);
+    let mut perf = PerfContext::default();
+    perf.reset();
+    assert!(db.get_node_option(&NodeKey::new_empty_path(7))?.is_none());
+    assert_eq!(perf.metric(PerfMetric::BloomSstMissCount), 1);
+    set_perf_stats(PerfStatsLevel::Disable);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_from_db() -> anyhow::Result<()> {
+    use rocksdb::{Options, DB};
+    use std::sync::Arc;
+
+    let dir = tempfile::TempDir::new()?;
+    let mut opts = Options::default();
+    opts.create_if_missing(true);
+    opts.create_missing_column_families(true);
+    let mut cf_names = RocksDbTreeStore::column_family_names("jmt_");
+    cf_names.push("other".to_string());
+    let shared = Arc::new(DB::open_cf(&opts, dir.path(), &cf_names)?);
+
+    let db = RocksDbTreeStore::from_db(shared.clone(), "jmt_")?;
+    let roots = write_versions(&db, 2)?;
+    shared.put_cf(shared.cf_handle("other").unwrap(), b"unrelated", b"data")?;
+
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(1)?, roots[1]);
+    assert_eq!(tree.get(KeyHash([1u8; 32]), 1)?, Some(vec![1]));
+    assert_eq!(db.latest_version()?, Some(1));
+    // The tree leaves the default column family, and the unrelated one, alone.
+    assert_eq!(shared.iterator(rocksdb::IteratorMode::Start).count(), 0);
+    assert_eq!(
+        shared.get_cf(shared.cf_handle("other").unwrap(), b"unrelated")?,
+        Some(b"data".to_vec())
+    );
+
+    // Another prefix names column families the database was not opened with.
+    let err = RocksDbTreeStore::from_db(shared, "other_").err().unwrap();
+    assert!(err.to_string().contains("other_nodes"));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_export_csv() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    write_versions(&db, 2)?;
+
+    let mut csv = Vec::new();
+    db.export_csv(1, &mut csv)?;
+    let csv = String::from_utf8(csv)?;
+    let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+    assert_eq!(
+        rows,
+        vec![
+            vec!["key_hash_hex", "value_hex", "written_version"],
+            vec![hex::encode([1u8; 32]).as_str(), "01", "1"],
+            vec![hex::encode([2u8; 32]).as_str(), "ff", "0"],
+        ]
+    );
+
+    // Older versions export the values they could read.
+    let mut csv = Vec::new();
+    db.export_csv(0, &mut csv)?;
+    assert!(String::from_utf8(csv)?.contains(&format!("{},00,0", hex::encode([1u8; 32]))));
+
+    let err = db.export_csv(2, Vec::new()).unwrap_err();
+    assert!(err.downcast_ref::<crate::MissingRootError>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_snapshot_round_trip() -> anyhow::Result<()> {
+    let source = RocksDbTreeStore::new_temporary()?;
+    let roots = write_versions(&source, 3)?;
+
+    let mut snapshot = Vec::new();
+    source.export_snapshot(2, &mut snapshot)?;
+
+    let target = RocksDbTreeStore::new_temporary()?;
+    target.import_snapshot(snapshot.as_slice())?;
+    assert_eq!(target.latest_version()?, Some(2));
+
+    let source_tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> =
+        JellyfishMerkleTree::new(&source);
+    let target_tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> =
+        JellyfishMerkleTree::new(&target);
+    assert_eq!(target_tree.get_root_hash(2)?, roots[2]);
+    for key in [KeyHash([1u8; 32]), KeyHash([2u8; 32]), KeyHash([3u8; 32])] {
+        let (value, proof) = target_tree.get_with_proof(key, 2)?;
+        assert_eq!(value, source_tree.get(key, 2)?);
+        proof.verify(roots[2], key, value)?;
+    }
+
+    // A truncated snapshot is rejected without moving the latest version.
+    let truncated = RocksDbTreeStore::new_temporary()?;
+    let err = truncated
+        .import_snapshot(&snapshot[..snapshot.len() - 1])
+        .unwrap_err();
+    assert!(err.to_string().contains("snapshot"), "{err}");
+    assert_eq!(truncated.latest_version()?, None);
+
+    // So is a record announcing more bytes than the stream holds, without allocating them.
+    let mut oversized = snapshot[..20].to_vec();
+    oversized.push(1);
+    oversized.extend_from_slice(&u32::MAX.to_be_bytes());
+    oversized.extend_from_slice(&[0; 16]);
+    let err = truncated.import_snapshot(oversized.as_slice()).unwrap_err();
+    assert!(err.to_string().contains("snapshot ended"), "{err}");
+    assert_eq!(truncated.latest_version()?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_snapshot_resumed_import() -> anyhow::Result<()> {
+    let source = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&source);
+    let keys: Vec<_> = (0..2000u32)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+    let values = keys.iter().map(|key| (*key, Some(key.0[..4].to_vec())));
+    let (root, batch) = tree.put_value_set(values, 0)?;
+    source.write_tree_update_batch(batch)?;
+    let mut snapshot = Vec::new();
+    source.export_snapshot(0, &mut snapshot)?;
+
+    // The first import is cut off halfway through the stream.
+    let target = RocksDbTreeStore::new_temporary()?;
+    assert!(target
+        .import_snapshot(&snapshot[..snapshot.len() / 2])
+        .is_err());
+    assert_eq!(target.latest_version()?, None);
+
+    let mut reports = Vec::new();
+    target.import_snapshot_with_progress(snapshot.as_slice(), 500, |progress| {
+        reports.push(*progress)
+    })?;
+    let last = *reports.last().unwrap();
+    assert_eq!(reports.len() as u64, last.records / 500 + 1);
+    assert_eq!(last.bytes, snapshot.len() as u64);
+    assert!(last.resumed_records > 0 && last.resumed_records < last.records);
+    assert!(reports
+        .windows(2)
+        .all(|pair| pair[0].records < pair[1].records && pair[0].bytes < pair[1].bytes));
+
+    assert_eq!(target.latest_version()?, Some(0));
+    assert!(target.verify_consistency(0)?.is_empty());
+    let target_tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> =
+        JellyfishMerkleTree::new(&target);
+    assert_eq!(target_tree.get_root_hash(0)?, root);
+    for key in keys.iter().step_by(97) {
+        let (value, proof) = target_tree.get_with_proof(*key, 0)?;
+        assert_eq!(value, Some(key.0[..4].to_vec()));
+        proof.verify(root, *key, value)?;
+    }
+
+    // The checkpoint is cleared, so importing again writes everything.
+    let mut resumed_records = None;
+    target.import_snapshot_with_progress(snapshot.as_slice(), 0, |progress| {
+        resumed_records = Some(progress.resumed_records)
+    })?;
+    assert_eq!(resumed_records, Some(0));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_check_no_single_child_internals() -> anyhow::Result<()> {
+    use crate::{
+        node_type::{Child, NodeType},
+        storage::TreeReader,
+    };
+
+    // `InternalNode::new` refuses to build a node with a single leaf child, so the bad node is
+    // written through a mirror of the serialized shape of `Node::Internal`.
+    #[derive(serde::Serialize)]
+    enum RawNode {
+        _Null,
+        Internal(RawInternalNode),
+    }
+    #[derive(serde::Serialize)]
+    struct RawInternalNode {
+        children: RawChildren,
+        leaf_count: usize,
+    }
+    #[derive(serde::Serialize)]
+    struct RawChildren {
+        children: Box<[Option<Child>; 16]>,
+        num_children: usize,
+    }
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    // Both keys start with nibble 0, so the root legitimately has a single internal child.
+    write_versions(&db, 2)?;
+    assert!(db.check_no_single_child_internals(0)?.is_empty());
+    assert!(db.check_no_single_child_internals(1)?.is_empty());
+
+    // Write a version 2 whose root has the leaf of version 0 as its only child.
+    let leaf_key = NodeKey::new_empty_path(0).gen_child_node_key(0, 0u8.into());
+    let leaf_key = leaf_key.gen_child_node_key(0, 2u8.into());
+    let leaf = db.get_node(&leaf_key)?;
+    let mut children: Box<[Option<Child>; 16]> = Box::default();
+    children[0] = Some(Child::new(leaf.hash::<Sha256>(), 0, NodeType::Leaf));
+    let bad_root = RawNode::Internal(RawInternalNode {
+        children: RawChildren {
+            children,
+            num_children: 1,
+        },
+        leaf_count: 1,
+    });
+    let bad_root_key = NodeKey::new_empty_path(2);
+    let nodes_cf = db.db().cf_handle("nodes").unwrap();
+    db.db().put_cf(
+        nodes_cf,
+        raw_record(RecordKind::NodeKey, &bad_root_key)?,
+        raw_record(RecordKind::Node, &bad_root)?,
+    )?;
+    assert_eq!(db.check_no_single_child_internals(2)?, vec![bad_root_key]);
+
+    let err = db.check_no_single_child_internals(3).unwrap_err();
+    assert!(err.downcast_ref::<crate::MissingRootError>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_refcount_nodes() -> anyhow::Result<()> {
+    use crate::{rocksdb_store::RocksDbStoreConfig, storage::TreeWriter};
+
+    // Writing version 0 again after version 1 made some of its nodes stale: with an imperfect
+    // stale index, as here, plain pruning removes nodes the rewrite still needs.
+    let rewrite_and_prune = |refcount_nodes: bool| -> anyhow::Result<RocksDbTreeStore> {
+        let dir = tempfile::TempDir::new()?;
+        let config = RocksDbStoreConfig {
+            refcount_nodes,
+            ..Default::default()
+        };
+        let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+        let scratch = crate::mock::MockTreeStore::default();
+        let tree: JellyfishMerkleTree<_, Sha256> = JellyfishMerkleTree::new(&scratch);
+        let (_root, batch0) = tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![0]))], 0)?;
+        scratch.write_tree_update_batch(batch0.clone())?;
+        let (_root, batch1) = tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![1]))], 1)?;
+
+        db.write_tree_update_batch(batch0.clone())?;
+        db.write_tree_update_batch(batch1)?;
+        db.write_node_batch(&batch0.node_batch)?;
+        db.prune(1)?;
+        Ok(db)
+    };
+
+    let db = rewrite_and_prune(false)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert!(tree.get_root_hash_option(0)?.is_none());
+
+    let db = rewrite_and_prune(true)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert!(tree.get_root_hash_option(0)?.is_some());
+    assert_eq!(tree.get(KeyHash([1u8; 32]), 0)?, Some(vec![0]));
+    assert_eq!(tree.get(KeyHash([1u8; 32]), 1)?, Some(vec![1]));
+
+    // The stale index entries are consumed, so only a second mark would remove the node.
+    db.prune(1)?;
+    assert!(tree.get_root_hash_option(0)?.is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_map_values_at_version() -> anyhow::Result<()> {
+    #[derive(Debug, PartialEq)]
+    struct Entry {
+        first_key_byte: u8,
+        value: u8,
+    }
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    write_versions(&db, 3)?;
+
+    let decode = |key_hash: KeyHash, value: &[u8]| -> anyhow::Result<Entry> {
+        match value {
+            [value] => Ok(Entry {
+                first_key_byte: key_hash.0[0],
+                value: *value,
+            }),
+            _ => anyhow::bail!("unexpected value length {}", value.len()),
+        }
+    };
+    assert_eq!(
+        db.map_values_at_version(1, decode)?,
+        vec![
+            Entry {
+                first_key_byte: 1,
+                value: 1
+            },
+            Entry {
+                first_key_byte: 2,
+                value: 0xff
+            },
+        ]
+    );
+
+    // Errors from the callback stop the iteration.
+    let mut calls = 0;
+    let err = db
+        .map_values_at_version(2, |_, _| -> anyhow::Result<()> {
+            calls += 1;
+            anyhow::bail!("stop")
+        })
+        .unwrap_err();
+    assert_eq!(err.to_string(), "stop");
+    assert_eq!(calls, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_rocks_stats() -> anyhow::Result<()> {
+    let dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::new(dir.path())?;
+    write_versions(&db, 2)?;
+
+    let stats = db.rocks_stats()?;
+    assert_eq!(stats.sst_files_per_level.len(), 7);
+    assert!(stats.sst_files_per_level.iter().all(|files| *files == 0));
+    assert!(stats.memtable_bytes > 0);
+
+    for name in ["nodes", "values"] {
+        db.db().flush_cf(db.db().cf_handle(name).unwrap())?;
+    }
+    let stats = db.rocks_stats()?;
+    assert_eq!(stats.sst_files_per_level[0], 2);
+    assert!(stats.estimated_live_data_bytes > 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_quick_stats() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let stats = db.quick_stats()?;
+    let mut names = RocksDbTreeStore::column_family_names("");
+    names.sort();
+    assert_eq!(
+        stats.estimated_keys.keys().copied().collect::<Vec<_>>(),
+        names
+    );
+    // A new store only holds its hasher fingerprint, in the metadata.
+    assert_eq!(stats.estimated_keys["nodes"], 0);
+    assert_eq!(stats.estimated_keys["values"], 0);
+    assert_eq!(stats.estimated_live_data_bytes, 0);
+
+    write_versions(&db, 2)?;
+    let stats = db.quick_stats()?;
+    assert!(stats.estimated_keys["nodes"] > 0);
+    assert!(stats.estimated_keys["values"] > 0);
+
+    Ok(())
+}
+
+#[cfg(feature = "blake3_tests")]
+#[test]
+fn test_rocksdb_blake3_store() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+
+    type Blake3Store = RocksDbTreeStore<blake3::Hasher>;
+
+    let dir = tempfile::TempDir::new()?;
+    let db = Blake3Store::with_hasher(dir.path(), RocksDbStoreConfig::default())?;
+    let tree: JellyfishMerkleTree<Blake3Store, blake3::Hasher> = JellyfishMerkleTree::new(&db);
+    let key = KeyHash([1u8; 32]);
+    let (root, batch) = tree.put_value_set(vec![(key, Some(vec![1]))], 0)?;
+    db.write_tree_update_batch(batch)?;
+
+    let (value, proof) = tree.get_with_proof(key, 0)?;
+    assert_eq!(value, Some(vec![1]));
+    proof.verify(root, key, value.as_ref())?;
+    db.verify_at(0, key, value.as_ref(), &proof)?;
+    let batched = db.get_with_proof_batch(&[key], 0)?;
+    assert_eq!(batched, vec![(value, proof)]);
+    drop(db);
+
+    // The store remembers its hasher, and reopens only with it.
+    let err = RocksDbTreeStore::new(dir.path()).err().unwrap();
+    assert!(err.to_string().contains("another hasher"), "{err}");
+    let db = Blake3Store::with_hasher(dir.path(), RocksDbStoreConfig::default())?;
+    let tree: JellyfishMerkleTree<Blake3Store, blake3::Hasher> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(0)?, root);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_max_value_size() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        max_value_size: Some(16),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let small = KeyHash([1u8; 32]);
+    let large = KeyHash([2u8; 32]);
+
+    let (_root, batch) = tree.put_value_set(
+        vec![(small, Some(vec![0u8; 16])), (large, Some(vec![0u8; 17]))],
+        0,
+    )?;
+    let err = db.write_tree_update_batch(batch).unwrap_err();
+    assert!(err.to_string().contains("17 bytes"), "{err}");
+
+    // Nothing of the rejected batch was written, not even the value that fit.
+    assert_eq!(db.latest_version()?, None);
+    assert!(tree.get_root_hash_option(0)?.is_none());
+    assert!(db.value_versions(small)?.is_empty());
+
+    let (root, batch) = tree.put_value_set(vec![(small, Some(vec![0u8; 16]))], 0)?;
+    db.write_tree_update_batch(batch)?;
+    assert_eq!(tree.get_root_hash(0)?, root);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_amplification_ratio() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let keys: Vec<KeyHash> = (0..8u8).map(|i| KeyHash([i; 32])).collect();
+    let deleted = KeyHash([0xff; 32]);
+
+    let (_root, batch) = tree.put_value_set(
+        keys.iter()
+            .map(|key| (*key, Some(vec![0])))
+            .chain([(deleted, Some(vec![0]))])
+            .collect::<Vec<_>>(),
+        0,
+    )?;
+    db.write_tree_update_batch(batch)?;
+    let initial_ratio = db.amplification_ratio(0)?;
+    assert!(initial_ratio >= 1.0, "{initial_ratio}");
+
+    let (_root, batch) = tree.put_value_set(vec![(deleted, None)], 1)?;
+    db.write_tree_update_batch(batch)?;
+    for version in 2..10u64 {
+        let (_root, batch) = tree.put_value_set(
+            keys.iter()
+                .map(|key| (*key, Some(vec![version as u8; 32])))
+                .collect::<Vec<_>>(),
+            version,
+        )?;
+        db.write_tree_update_batch(batch)?;
+    }
+    let ratio = db.amplification_ratio(9)?;
+    assert!(ratio > 1.0, "{ratio}");
+
+    db.prune(9)?;
+    db.compact_tombstones(9)?;
+    let compacted_ratio = db.amplification_ratio(9)?;
+    assert!(compacted_ratio < ratio, "{compacted_ratio} >= {ratio}");
+    assert!(compacted_ratio >= 1.0, "{compacted_ratio}");
+
+    assert!(db
+        .amplification_ratio(10)
+        .unwrap_err()
+        .downcast_ref::<crate::MissingRootError>()
+        .is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_leaf_count() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let key = |i: u8| KeyHash([i; 32]);
+
+    let updates: Vec<Vec<(KeyHash, Option<Vec<u8>>)>> = vec![
+        (0..10).map(|i| (key(i), Some(vec![i]))).collect(),
+        (0..4).map(|i| (key(i), None)).collect(),
+        // Deleting an absent key and overwriting a live one leave the count unchanged.
+        vec![(key(0), None), (key(5), Some(vec![0xff]))],
+        (0..6).map(|i| (key(i), Some(vec![i]))).collect(),
+        (0..10).map(|i| (key(i), None)).collect(),
+    ];
+    for (version, update) in updates.into_iter().enumerate() {
+        let version = version as u64;
+        let (_root, batch) = tree.put_value_set(update, version)?;
+        db.write_tree_update_batch(batch)?;
+    }
+
+    // The counts match a full walk of each version.
+    for (version, expected) in [(0, 10), (1, 6), (2, 6), (3, 10), (4, 0)] {
+        let walked = db.map_values_at_version(version, |_, _| Ok(()))?.len() as u64;
+        assert_eq!(walked, expected);
+        assert_eq!(db.leaf_count(version)?, expected);
+    }
+
+    assert!(db
+        .leaf_count(5)
+        .unwrap_err()
+        .downcast_ref::<crate::MissingRootError>()
+        .is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_estimate_batch() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let stored_bytes = |db: &RocksDbTreeStore| -> anyhow::Result<u64> {
+        let mut bytes = 0;
+        for name in ["nodes", "values", "stale_nodes"] {
+            let cf = db.db().cf_handle(name).unwrap();
+            for item in db.db().iterator_cf(cf, rocksdb::IteratorMode::Start) {
+                let (key, value) = item?;
+                bytes += (key.len() + value.len()) as u64;
+            }
+        }
+        Ok(bytes)
+    };
+
+    let (_root, batch) =
+        tree.put_value_set((0..4u8).map(|i| (KeyHash([i; 32]), Some(vec![i; 8]))), 0)?;
+    db.write_tree_update_batch(batch)?;
+
+    let (root, batch) = tree.put_value_set(
+        vec![
+            (KeyHash([0; 32]), Some(vec![0xff; 64])),
+            (KeyHash([1; 32]), None),
+        ],
+        1,
+    )?;
+    let stats = db.estimate_batch(&batch)?;
+    assert_eq!(stats.node_count, batch.node_batch.nodes().len());
+    assert_eq!(stats.value_count, 2);
+    assert_eq!(stats.stale_node_count, batch.stale_node_index_batch.len());
+    assert!(stats.stale_node_count > 0);
+
+    // Estimating writes nothing, and the estimate is what writing the batch adds.
+    assert_eq!(db.latest_version()?, Some(0));
+    let bytes_before = stored_bytes(&db)?;
+    db.write_tree_update_batch(batch)?;
+    assert_eq!(stored_bytes(&db)?, bytes_before + stats.serialized_bytes);
+    assert_eq!(tree.get_root_hash(1)?, root);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_history_of() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let key = KeyHash([1u8; 32]);
+    // Neighbours on both sides, which the history must not pick up.
+    let before = KeyHash([0u8; 32]);
+    let after = KeyHash([2u8; 32]);
+
+    let updates = [
+        vec![
+            (before, Some(vec![0])),
+            (key, Some(vec![1])),
+            (after, Some(vec![2])),
+        ],
+        vec![(key, Some(vec![3]))],
+        vec![(after, Some(vec![4]))],
+        vec![(key, None)],
+        vec![(key, Some(vec![5])), (before, Some(vec![6]))],
+    ];
+    for (version, update) in updates.into_iter().enumerate() {
+        let (_root, batch) = tree.put_value_set(update, version as u64)?;
+        db.write_tree_update_batch(batch)?;
+    }
+
+    let history = db.history_of(key)?.collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(
+        history,
+        vec![
+            (0, Some(vec![1])),
+            (1, Some(vec![3])),
+            (3, None),
+            (4, Some(vec![5])),
+        ]
+    );
+    assert_eq!(db.history_of(KeyHash([3u8; 32]))?.count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_mirror_tree_writer() -> anyhow::Result<()> {
+    use crate::{
+        mock::MockTreeStore,
+        storage::{MirrorTreeWriter, TreeReader, TreeUpdateWriter},
+    };
+
+    let mirror =
+        MirrorTreeWriter::new(RocksDbTreeStore::new_temporary()?, MockTreeStore::default());
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> =
+        JellyfishMerkleTree::new(mirror.primary());
+    let keys: Vec<KeyHash> = (0..6u8).map(|i| KeyHash([i; 32])).collect();
+
+    let mut node_keys = Vec::new();
+    for version in 0..4u64 {
+        let update = keys
+            .iter()
+            .skip(version as usize)
+            .map(|key| (*key, Some(vec![version as u8; 4])))
+            .chain([(keys[0], (version % 2 == 0).then(|| vec![0xff]))])
+            .collect::<Vec<_>>();
+        let (_root, batch) = tree.put_value_set(update, version)?;
+        node_keys.extend(batch.node_batch.nodes().keys().cloned());
+        mirror.write_tree_update_batch(&batch)?;
+    }
+
+    // Both backends hold the same nodes and values.
+    let secondary: JellyfishMerkleTree<MockTreeStore, Sha256> =
+        JellyfishMerkleTree::new(mirror.secondary());
+    for node_key in &node_keys {
+        assert_eq!(
+            mirror.primary().get_node_option(node_key)?,
+            mirror.secondary().get_node_option(node_key)?
+        );
+    }
+    for version in 0..4u64 {
+        assert_eq!(
+            tree.get_root_hash(version)?,
+            secondary.get_root_hash(version)?
+        );
+        for key in &keys {
+            assert_eq!(
+                mirror.primary().get_value_option(version, *key)?,
+                mirror.secondary().get_value_option(version, *key)?
+            );
+        }
+    }
+
+    // Both backends hold the same stale node index: purging the secondary up to the latest
+    // version removes exactly the nodes the primary lists as stale.
+    let stale = mirror
+        .primary()
+        .iter_stale_nodes()?
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert!(!stale.is_empty());
+    let num_nodes = mirror.secondary().num_nodes();
+    mirror.secondary().purge_stale_nodes(3)?;
+    assert_eq!(mirror.secondary().num_nodes(), num_nodes - stale.len());
+    for (_, node_key) in &stale {
+        assert_eq!(mirror.secondary().get_node_option(node_key)?, None);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_get_value_with_version() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let key = KeyHash([1u8; 32]);
+    let other = KeyHash([2u8; 32]);
+
+    let updates = [
+        vec![(key, Some(vec![0]))],
+        vec![(other, Some(vec![1]))],
+        vec![(key, Some(vec![2]))],
+        vec![(other, Some(vec![3]))],
+        vec![(other, None)],
+    ];
+    for (version, update) in updates.into_iter().enumerate() {
+        let (_root, batch) = tree.put_value_set(update, version as u64)?;
+        db.write_tree_update_batch(batch)?;
+    }
+
+    assert_eq!(db.get_value_with_version(5, key)?, Some((2, vec![2])));
+    assert_eq!(db.get_value_with_version(1, key)?, Some((0, vec![0])));
+    assert_eq!(db.get_value_with_version(0, other)?, None);
+    assert_eq!(db.get_value_with_version(3, other)?, Some((3, vec![3])));
+    // A deletion reads as absent.
+    assert_eq!(db.get_value_with_version(5, other)?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_writer_handle() -> anyhow::Result<()> {
+    use crate::mock::MockTreeStore;
+
+    // The batches are computed ahead against an in-memory store, then all queued at once.
+    let scratch = MockTreeStore::default();
+    let scratch_tree: JellyfishMerkleTree<MockTreeStore, Sha256> =
+        JellyfishMerkleTree::new(&scratch);
+    let mut batches = Vec::new();
+    for version in 0..8u64 {
+        let (root, batch) = scratch_tree.put_value_set(
+            vec![(KeyHash([version as u8; 32]), Some(vec![version as u8]))],
+            version,
+        )?;
+        scratch.write_tree_update_batch(batch.clone())?;
+        batches.push((root, batch));
+    }
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let writer = db.spawn_writer(2)?;
+    let confirmations = batches
+        .iter()
+        .map(|(_, batch)| writer.submit(batch.clone()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    for confirmation in confirmations {
+        confirmation.recv()??;
+    }
+    writer.close()?;
+
+    assert_eq!(db.latest_version()?, Some(7));
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    for (version, (root, _)) in batches.iter().enumerate() {
+        assert_eq!(tree.get_root_hash(version as u64)?, *root);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_disk_usage() -> anyhow::Result<()> {
+    use crate::storage::HasPreimage;
+
+    let dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::new(dir.path())?;
+    write_versions(&db, 3)?;
+    db.put_key_preimage(KeyHash([1u8; 32]), b"preimage of key 1")?;
+    assert_eq!(
+        db.preimage(KeyHash([1u8; 32]))?,
+        Some(b"preimage of key 1".to_vec())
+    );
+    assert_eq!(db.preimage(KeyHash([2u8; 32]))?, None);
+
+    // Nothing is on disk before the memtables are flushed.
+    assert_eq!(db.disk_usage()?.nodes.sst_files_bytes, 0);
+
+    for name in RocksDbTreeStore::column_family_names("") {
+        db.db().flush_cf(db.db().cf_handle(&name).unwrap())?;
+    }
+    let usage = db.disk_usage()?;
+    for (category, usage) in [
+        ("nodes", usage.nodes),
+        ("values", usage.values),
+        ("preimages", usage.preimages),
+        ("stale_nodes", usage.stale_nodes),
+        ("other", usage.other),
+    ] {
+        assert!(usage.sst_files_bytes > 0, "{category}");
+        assert!(usage.estimated_live_data_bytes > 0, "{category}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_repair() -> anyhow::Result<()> {
+    let dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::new(dir.path())?;
+    write_versions(&db, 3)?;
+    let root = JellyfishMerkleTree::<_, Sha256>::new(&db).get_root_hash(2)?;
+    drop(db);
+
+    // Repairing a healthy database keeps its data, including the writes not flushed yet.
+    RocksDbTreeStore::repair(dir.path())?;
+    let db = RocksDbTreeStore::new(dir.path())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(2)?, root);
+    assert_eq!(db.latest_version()?, Some(2));
+    assert_eq!(tree.get(KeyHash([1u8; 32]), 2)?, Some(vec![2]));
+    assert!(db.verify_consistency(2)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_rejects_version_regression() -> anyhow::Result<()> {
+    use crate::{mock::MockTreeStore, storage::TreeReader};
+
+    let scratch = MockTreeStore::default();
+    let tree: JellyfishMerkleTree<MockTreeStore, Sha256> = JellyfishMerkleTree::new(&scratch);
+    let key = KeyHash([1u8; 32]);
+    let mut batches = Vec::new();
+    for version in 0..3u8 {
+        let (_root, batch) =
+            tree.put_value_set(vec![(key, Some(vec![version]))], version.into())?;
+        scratch.write_tree_update_batch(batch.clone())?;
+        batches.push(batch);
+    }
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    db.write_tree_update_batch(batches[0].clone())?;
+    db.write_tree_update_batch(batches[2].clone())?;
+
+    // Both an older version and the latest one again are rejected, and nothing is written.
+    for stale in [&batches[1], &batches[2]] {
+        let err = db.write_tree_update_batch(stale.clone()).unwrap_err();
+        assert!(err.to_string().contains("not newer"), "{err}");
+    }
+    assert_eq!(db.latest_version()?, Some(2));
+    assert!(db.get_node_option(&NodeKey::new_empty_path(1))?.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_shared_block_cache() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+    use rocksdb::Cache;
+
+    let cache = Cache::new_lru_cache(8 << 20);
+    let config = RocksDbStoreConfig {
+        block_cache: Some(cache.clone()),
+        ..Default::default()
+    };
+    let dirs = [tempfile::TempDir::new()?, tempfile::TempDir::new()?];
+    let stores = dirs
+        .iter()
+        .map(|dir| RocksDbTreeStore::with_config(dir.path(), config.clone()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut roots = Vec::new();
+    for (i, db) in stores.iter().enumerate() {
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(db);
+        let (root, batch) =
+            tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![i as u8]))], 0)?;
+        db.write_tree_update_batch(batch)?;
+        for name in ["nodes", "values"] {
+            db.db().flush_cf(db.db().cf_handle(name).unwrap())?;
+        }
+        roots.push(root);
+    }
+    let usage_before_reads = cache.get_usage();
+
+    // Both stores read their own data, through the blocks of the one cache.
+    for (i, db) in stores.iter().enumerate() {
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(db);
+        let (value, proof) = tree.get_with_proof(KeyHash([1u8; 32]), 0)?;
+        assert_eq!(value, Some(vec![i as u8]));
+        proof.verify(roots[i], KeyHash([1u8; 32]), value)?;
+    }
+    assert!(cache.get_usage() > usage_before_reads);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_proof_cache() -> anyhow::Result<()> {
+    use crate::rocksdb_store::{RocksDbStoreConfig, StoreMetrics};
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        proof_cache_capacity: Some(16),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let key = KeyHash([1u8; 32]);
+    let (root, batch) = tree.put_value_set(vec![(key, Some(vec![1]))], 0)?;
+    db.write_tree_update_batch(batch)?;
+
+    // The second request for the same proof is served from the cache.
+    let first = db.get_with_proof(key, 0)?;
+    let second = db.get_with_proof(key, 0)?;
+    assert_eq!(first, second);
+    second.1.verify(root, key, second.0.as_ref())?;
+    let StoreMetrics {
+        proof_cache_hits,
+        proof_cache_misses,
+        proof_cache_entries,
+        ..
+    } = db.metrics();
+    assert_eq!(
+        (proof_cache_hits, proof_cache_misses, proof_cache_entries),
+        (1, 1, 1)
+    );
+
+    // Writing a newer version empties the cache.
+    let (_, batch) = tree.put_value_set(vec![(key, Some(vec![2]))], 1)?;
+    db.write_tree_update_batch(batch)?;
+    assert_eq!(db.metrics().proof_cache_entries, 0);
+    assert_eq!(db.get_with_proof(key, 0)?, first);
+    assert_eq!(db.metrics().proof_cache_misses, 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_wal_settings() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        recycle_log_file_num: 4,
+        max_total_wal_size: 1 << 20,
+        ..Default::default()
+    };
+    let key = KeyHash([1u8; 32]);
+    let root = {
+        let db = RocksDbTreeStore::with_config(dir.path(), config.clone())?;
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+        let (root, batch) = tree.put_value_set(vec![(key, Some(vec![1]))], 0)?;
+        db.write_tree_update_batch(batch)?;
+        root
+    };
+
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(0)?, root);
+    assert_eq!(tree.get(key, 0)?, Some(vec![1]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_generic_tree_store() -> anyhow::Result<()> {
+    use crate::rocksdb_store::{Codec, GenericTreeStore, RocksDbStoreConfig};
+    use serde::{de::DeserializeOwned, Serialize};
+    use sha2::Sha512_256;
+
+    struct JsonCodec;
+
+    impl Codec for JsonCodec {
+        fn encode<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<Vec<u8>> {
+            Ok(serde_json::to_vec(value)?)
+        }
+
+        fn decode<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<T> {
+            Ok(serde_json::from_slice(bytes)?)
+        }
+    }
+
+    type JsonStore = GenericTreeStore<JsonCodec, Sha512_256>;
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        retain_versions: Some(1),
+        ..Default::default()
+    };
+    let key = KeyHash([1u8; 32]);
+    let root = {
+        let db = JsonStore::with_hasher(dir.path(), config.clone())?;
+        let tree: JellyfishMerkleTree<JsonStore, Sha512_256> = JellyfishMerkleTree::new(&db);
+        for version in 0..3u8 {
+            let (_, batch) = tree.put_value_set(
+                vec![
+                    (key, Some(vec![version])),
+                    (KeyHash([version; 32]), Some(vec![])),
+                ],
+                version.into(),
+            )?;
+            db.write_tree_update_batch(batch)?;
+        }
+        let root = tree.get_root_hash(2)?;
+        let (value, proof) = tree.get_with_proof(key, 2)?;
+        assert_eq!(value, Some(vec![2]));
+        db.verify_at(2, key, value.as_ref(), &proof)?;
+        root
+    };
+
+    // The nodes are written as JSON.
+    let db = JsonStore::with_hasher(dir.path(), config)?;
+    let raw_nodes = db.db().iterator_cf(
+        db.db().cf_handle("nodes").unwrap(),
+        rocksdb::IteratorMode::Start,
+    );
+    for item in raw_nodes {
+        let (_, node) = item?;
+        assert_eq!(node[..2], [FORMAT_VERSION, RecordKind::Node as u8]);
+        serde_json::from_slice::<serde_json::Value>(&node[2..])?;
+    }
+    let tree: JellyfishMerkleTree<JsonStore, Sha512_256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(2)?, root);
+    assert_eq!(tree.get(key, 2)?, Some(vec![2]));
+    assert!(tree.get_root_hash_option(0)?.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_prefix_bounded_reads() -> anyhow::Result<()> {
+    use rocksdb::perf::{set_perf_stats, PerfContext, PerfMetric, PerfStatsLevel};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let key = KeyHash([1u8; 32]);
+    let neighbors = [KeyHash([0u8; 32]), KeyHash([2u8; 32])];
+    for version in 0..200u64 {
+        let value = Some(version.to_be_bytes().to_vec());
+        let mut value_set: Vec<_> = neighbors.iter().map(|n| (*n, value.clone())).collect();
+        if version % 50 == 10 {
+            value_set.push((key, value));
+        }
+        let (_, batch) = tree.put_value_set(value_set, version)?;
+        db.write_tree_update_batch(batch)?;
+    }
+    // Compacting the deleted neighbors leaves a RocksDB deletion for each of their entries, on
+    // both sides of the entries of `key`.
+    let (_, batch) = tree.put_value_set(neighbors.iter().map(|n| (*n, None)), 200)?;
+    db.write_tree_update_batch(batch)?;
+    assert_eq!(db.compact_tombstones(201)?, 2);
+
+    set_perf_stats(PerfStatsLevel::EnableCount);
+    let mut perf = PerfContext::default();
+    let visited = |perf: &PerfContext| {
+        perf.metric(PerfMetric::NextOnMemtableCount)
+            + perf.metric(PerfMetric::PrevOnMemtableCount)
+            + perf.metric(PerfMetric::InternalDeleteSkippedCount)
+    };
+
+    // Neither read steps onto the deletions of the neighbors.
+    perf.reset();
+    let history = db.history_of(key)?.collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(history.len(), 4);
+    assert!(visited(&perf) <= 6, "{}", perf.report(true));
+
+    perf.reset();
+    assert_eq!(db.get_value_with_version(5, key)?, None);
+    assert_eq!(
+        db.get_value_with_version(40, key)?,
+        Some((10, 10u64.to_be_bytes().to_vec()))
+    );
+    assert!(visited(&perf) <= 4, "{}", perf.report(true));
+
+    set_perf_stats(PerfStatsLevel::Disable);
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_on_commit() -> anyhow::Result<()> {
+    use crate::RootHash;
+    use std::sync::{Arc, Mutex};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let calls = Arc::new(Mutex::new(Vec::<(&str, u64, RootHash)>::new()));
+    for name in ["first", "second"] {
+        let calls = Arc::clone(&calls);
+        db.on_commit(move |version, root_hash| {
+            calls.lock().unwrap().push((name, version, root_hash));
+        });
+    }
+
+    let mut roots = Vec::new();
+    for version in 0..2 {
+        let (root, batch) = tree.put_value_set(
+            vec![(KeyHash([1u8; 32]), Some(vec![version as u8]))],
+            version,
+        )?;
+        db.write_tree_update_batch(batch)?;
+        roots.push(root);
+    }
+
+    // A rejected batch is not committed, and calls nothing.
+    let (_, batch) = tree.put_value_set(vec![(KeyHash([2u8; 32]), Some(vec![]))], 1)?;
+    assert!(db.write_tree_update_batch(batch).is_err());
+
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![
+            ("first", 0, roots[0]),
+            ("second", 0, roots[0]),
+            ("first", 1, roots[1]),
+            ("second", 1, roots[1]),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_commit_versions() -> anyhow::Result<()> {
+    use crate::{mock::MockTreeStore, rocksdb_store::RocksDbStoreConfig};
+
+    let scratch = MockTreeStore::default();
+    let tree: JellyfishMerkleTree<MockTreeStore, Sha256> = JellyfishMerkleTree::new(&scratch);
+    let key = KeyHash([1u8; 32]);
+    let mut batches = Vec::new();
+    let mut roots = Vec::new();
+    for version in 0..4u8 {
+        // The value of version 2 is too large for the store below.
+        let value = vec![version; if version == 2 { 32 } else { 1 }];
+        let (root, batch) = tree.put_value_set(vec![(key, Some(value))], version.into())?;
+        scratch.write_tree_update_batch(batch.clone())?;
+        batches.push(batch);
+        roots.push(root);
+    }
+
+    let config = RocksDbStoreConfig {
+        max_value_size: Some(16),
+        ..Default::default()
+    };
+    let dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+
+    // The failing batch in the middle of the list leaves the store untouched.
+    let err = db.commit_versions(batches.clone()).unwrap_err();
+    assert!(err.to_string().contains("32 bytes"), "{err}");
+    assert_eq!(db.latest_version()?, None);
+    assert!(tree.get_root_hash_option(0)?.is_none());
+    assert!(db.value_versions(key)?.is_empty());
+
+    // So does a list whose versions go back.
+    let err = db
+        .commit_versions(vec![batches[1].clone(), batches[0].clone()])
+        .unwrap_err();
+    assert!(err.to_string().contains("not newer"), "{err}");
+    assert_eq!(db.latest_version()?, None);
+
+    db.commit_versions(batches[..2].to_vec())?;
+    assert_eq!(db.latest_version()?, Some(1));
+    for version in 0..2 {
+        assert_eq!(tree.get_root_hash(version)?, roots[version as usize]);
+        assert_eq!(tree.get(key, version)?, Some(vec![version as u8]));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_lazy_pruning() -> anyhow::Result<()> {
+    use crate::{rocksdb_store::RocksDbStoreConfig, storage::TreeReader};
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        lazy_pruning: true,
+        ..Default::default()
+    };
+    let key = KeyHash([1u8; 32]);
+    let compact_nodes = |db: &RocksDbTreeStore| -> anyhow::Result<()> {
+        let nodes_cf = db.db().cf_handle("nodes").unwrap();
+        db.db().flush_cf(nodes_cf)?;
+        db.db()
+            .compact_range_cf(nodes_cf, None::<&[u8]>, None::<&[u8]>);
+        Ok(())
+    };
+    {
+        let db = RocksDbTreeStore::with_config(dir.path(), config.clone())?;
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+        for version in 0..3u8 {
+            let (_, batch) = tree.put_value_set(
+                vec![
+                    (key, Some(vec![version])),
+                    (KeyHash([version + 2; 32]), Some(vec![])),
+                ],
+                version.into(),
+            )?;
+            db.write_tree_update_batch(batch)?;
+        }
+
+        // Nothing is deleted until a compaction drops the stale nodes.
+        db.set_min_readable_version(1)?;
+        assert!(db.get_node_option(&NodeKey::new_empty_path(0))?.is_some());
+        compact_nodes(&db)?;
+        assert!(!db.has_node(&NodeKey::new_empty_path(0))?);
+        assert_eq!(tree.get(key, 1)?, Some(vec![1]));
+        assert_eq!(tree.get(key, 2)?, Some(vec![2]));
+    }
+
+    // The threshold survives reopening the store.
+    {
+        let db = RocksDbTreeStore::with_config(dir.path(), config.clone())?;
+        db.set_min_readable_version(2)?;
+    }
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    compact_nodes(&db)?;
+    assert!(db.get_node_option(&NodeKey::new_empty_path(1))?.is_none());
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get(key, 2)?, Some(vec![2]));
+    assert!(db.verify_consistency(2)?.is_empty());
+
+    // Stores without the filter refuse the call.
+    let other = RocksDbTreeStore::new_temporary()?;
+    assert!(other.set_min_readable_version(0).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_close() -> anyhow::Result<()> {
+    let dir = tempfile::TempDir::new()?;
+    let key = KeyHash([1u8; 32]);
+    let db = RocksDbTreeStore::new(dir.path())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (root, batch) = tree.put_value_set(vec![(key, Some(vec![1]))], 0)?;
+    db.write_tree_update_batch(batch)?;
+    db.close()?;
+
+    // The lock is released and the data flushed, without going through the WAL.
+    let db = RocksDbTreeStore::new(dir.path())?;
+    assert!(db.disk_usage()?.nodes.sst_files_bytes > 0);
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(0)?, root);
+    assert_eq!(tree.get(key, 0)?, Some(vec![1]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_transaction() -> anyhow::Result<()> {
+    use crate::storage::TreeReader;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let key = KeyHash([1u8; 32]);
+    let mut txn = db.transaction();
+
+    // Version 1 is computed from version 0 before either is committed.
+    let staged: JellyfishMerkleTree<_, Sha256> = JellyfishMerkleTree::new(&txn);
+    let (_, batch) = staged.put_value_set(vec![(key, Some(vec![0]))], 0)?;
+    txn.stage(batch);
+    let staged: JellyfishMerkleTree<_, Sha256> = JellyfishMerkleTree::new(&txn);
+    let (root, batch) = staged.put_value_set(vec![(key, Some(vec![1]))], 1)?;
+    txn.stage(batch);
+
+    // The transaction reads its writes, the store does not see them.
+    assert!(txn.get_node_option(&NodeKey::new_empty_path(1))?.is_some());
+    assert_eq!(txn.get_value_option(1, key)?, Some(vec![1]));
+    assert_eq!(txn.get_value_option(0, key)?, Some(vec![0]));
+    assert!(!db.has_node(&NodeKey::new_empty_path(0))?);
+    assert_eq!(db.get_value_option(1, key)?, None);
+    assert_eq!(db.latest_version()?, None);
+
+    txn.commit()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(1)?, root);
+    assert_eq!(tree.get(key, 0)?, Some(vec![0]));
+    assert_eq!(tree.get(key, 1)?, Some(vec![1]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_parallel_walks() -> anyhow::Result<()> {
+    use crate::{node_type::Node, storage::TreeReader};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    for version in 0..3u8 {
+        let values = (0..200u8).map(|i| (KeyHash::with::<Sha256>([i, version]), Some(vec![i])));
+        let (_root, batch) = tree.put_value_set(values, version.into())?;
+        db.write_tree_update_batch(batch)?;
+    }
+
+    for version in 0..3 {
+        let (mut serial, mut parallel) = (Vec::new(), Vec::new());
+        db.export_snapshot(version, &mut serial)?;
+        db.par_export_snapshot(version, &mut parallel)?;
+        assert_eq!(parallel, serial);
+    }
+
+    // Drop a child of the root and a deeper node; both walks report them in the same order.
+    let root_key = NodeKey::new_empty_path(2);
+    let child_key = root_key.gen_child_node_key(2, 2u8.into());
+    let nodes_cf = db.db().cf_handle("nodes").unwrap();
+    db.db()
+        .delete_cf(nodes_cf, raw_record(RecordKind::NodeKey, &child_key)?)?;
+    let parent_key = match db.get_node(&root_key)? {
+        Node::Internal(root) => root
+            .children_sorted()
+            .map(|(nibble, child)| root_key.gen_child_node_key(child.version, nibble))
+            .nth(7)
+            .unwrap(),
+        _ => unreachable!(),
+    };
+    let grandchild_key = match db.get_node(&parent_key)? {
+        Node::Internal(parent) => parent
+            .children_sorted()
+            .map(|(nibble, child)| parent_key.gen_child_node_key(child.version, nibble))
+            .next()
+            .unwrap(),
+        _ => unreachable!(),
+    };
+    db.db()
+        .delete_cf(nodes_cf, raw_record(RecordKind::NodeKey, &grandchild_key)?)?;
+    let inconsistencies = db.verify_consistency(2)?;
+    assert_eq!(inconsistencies.len(), 2);
+    assert_eq!(db.par_verify_consistency(2)?, inconsistencies);
+    assert_eq!(db.par_verify_consistency(0)?, db.verify_consistency(0)?);
+    assert_eq!(
+        db.par_verify_consistency(3)?,
+        vec![Inconsistency::MissingRoot { version: 3 }]
+    );
+    assert!(db.par_export_snapshot(3, Vec::new()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_record_format() -> anyhow::Result<()> {
+    use crate::{rocksdb_store::Corruption, storage::TreeReader};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let key = KeyHash([1u8; 32]);
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (_root, batch) = tree.put_value_set(vec![(key, Some(vec![1]))], 0)?;
+    db.write_tree_update_batch(batch)?;
+
+    // Every key and value written starts with the format version and the kind of its record.
+    let root_key = raw_record(RecordKind::NodeKey, &NodeKey::new_empty_path(0))?;
+    let nodes_cf = db.db().cf_handle("nodes").unwrap();
+    let root = db.db().get_cf(nodes_cf, &root_key)?.unwrap();
+    assert_eq!(root[..2], [FORMAT_VERSION, RecordKind::Node as u8]);
+    let values_cf = db.db().cf_handle("values").unwrap();
+    for item in db.db().iterator_cf(values_cf, rocksdb::IteratorMode::Start) {
+        let (value_key, value) = item?;
+        assert_eq!(value_key[..2], [FORMAT_VERSION, RecordKind::ValueKey as u8]);
+        assert_eq!(value[..2], [FORMAT_VERSION, RecordKind::Value as u8]);
+    }
+    let metadata_cf = db.db().cf_handle("metadata").unwrap();
+    let latest_version = db.db().get_cf(metadata_cf, b"latest_version")?.unwrap();
+    assert_eq!(
+        latest_version[..2],
+        [FORMAT_VERSION, RecordKind::Version as u8]
+    );
+
+    // A record with another format version is rejected rather than misread.
+    let mut bogus = root.clone();
+    bogus[0] = FORMAT_VERSION + 1;
+    db.db().put_cf(nodes_cf, &root_key, &bogus)?;
+    let err = db
+        .get_node_option(&NodeKey::new_empty_path(0))
+        .unwrap_err()
+        .downcast::<Corruption>()
+        .unwrap();
+    assert_eq!(err.kind, RecordKind::Node);
+    assert_eq!(err.found, vec![FORMAT_VERSION + 1, RecordKind::Node as u8]);
+
+    // So is a record of another kind.
+    db.db().put_cf(metadata_cf, b"latest_version", &root)?;
+    let err = db
+        .latest_version()
+        .unwrap_err()
+        .downcast::<Corruption>()
+        .unwrap();
+    assert_eq!(err.kind, RecordKind::Version);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_get_latest_with_proof() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let key = KeyHash([1u8; 32]);
+    assert!(db.get_latest_with_proof(key).is_err());
+
+    let roots = write_versions(&db, 3)?;
+    let (value, proof) = db.get_latest_with_proof(key)?;
+    assert_eq!(value, Some(vec![2]));
+    proof.verify(roots[2], key, value)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_value_ttl() -> anyhow::Result<()> {
+    use crate::{
+        rocksdb_store::RocksDbStoreConfig,
+        storage::{HasPreimage, TreeReader},
+    };
+    use std::time::Duration;
+
+    let key = KeyHash([1u8; 32]);
+    let write = |db: &RocksDbTreeStore| -> anyhow::Result<()> {
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(db);
+        let (_root, batch) = tree.put_value_set(vec![(key, Some(vec![1]))], 0)?;
+        db.write_tree_update_batch(batch)?;
+        db.put_key_preimage(key, b"key")?;
+        for name in ["nodes", "values", "preimages"] {
+            let cf = db.db().cf_handle(name).unwrap();
+            db.db().flush_cf(cf)?;
+            db.db().compact_range_cf(cf, None::<&[u8]>, None::<&[u8]>);
+        }
+        Ok(())
+    };
+
+    // Values and preimages are readable right after they are written, and until they expire.
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        value_ttl: Some(Duration::from_secs(3600)),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    write(&db)?;
+    assert_eq!(db.get_value_option(0, key)?, Some(vec![1]));
+    assert_eq!(db.get_latest_value(key)?, Some(vec![1]));
+    assert_eq!(db.preimage(key)?, Some(b"key".to_vec()));
+
+    // Expired entries are dropped by compactions, while the nodes are kept.
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        value_ttl: Some(Duration::ZERO),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    write(&db)?;
+    assert_eq!(db.get_value_option(0, key)?, None);
+    assert_eq!(db.preimage(key)?, None);
+    assert!(db.get_node_option(&NodeKey::new_empty_path(0))?.is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_preimages() -> anyhow::Result<()> {
+    use crate::storage::HasPreimage;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let present = [KeyHash([1u8; 32]), KeyHash([3u8; 32])];
+    db.put_key_preimage(present[0], b"first")?;
+    db.put_key_preimage(present[1], b"third")?;
+
+    let keys = [
+        present[1],
+        KeyHash([2u8; 32]),
+        present[0],
+        KeyHash([4u8; 32]),
+    ];
+    let preimages = db.preimages(&keys)?;
+    assert_eq!(
+        preimages,
+        vec![Some(b"third".to_vec()), None, Some(b"first".to_vec()), None]
+    );
+    for (key, preimage) in keys.iter().zip(&preimages) {
+        assert_eq!(&db.preimage(*key)?, preimage);
+    }
+    assert!(db.preimages(&[])?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_write_node_batch_rejects_mixed_versions() -> anyhow::Result<()> {
+    use crate::storage::{NodeBatch, TreeReader, TreeWriter};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let key = KeyHash([1u8; 32]);
+    let (_root, batch) = tree.put_value_set(vec![(key, Some(vec![0]))], 0)?;
+
+    // A value of version 2 slipped into the batch of version 0.
+    let mut mixed = batch.node_batch.clone();
+    mixed.insert_value(2, KeyHash([2u8; 32]), vec![2]);
+    let err = db.write_node_batch(&mixed).unwrap_err();
+    assert!(err.to_string().contains("not contiguous"), "{err}");
+    assert!(!db.has_node(&NodeKey::new_empty_path(0))?);
+    assert_eq!(db.get_value_option(0, key)?, None);
+
+    // Consecutive versions, as written by put_value_sets, are accepted.
+    let mut consecutive = batch.node_batch.clone();
+    consecutive.insert_value(1, KeyHash([2u8; 32]), vec![1]);
+    db.write_node_batch(&consecutive)?;
+    db.write_node_batch(&NodeBatch::default())?;
+    assert_eq!(db.get_value_option(1, KeyHash([2u8; 32]))?, Some(vec![1]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_write_node_batch_retry() -> anyhow::Result<()> {
+    use crate::{rocksdb_store::RocksDbStoreConfig, storage::TreeWriter};
+    use rocksdb::IteratorMode;
+    use tempfile::TempDir;
+
+    /// A column family name, key and value.
+    type Entry = (String, Box<[u8]>, Box<[u8]>);
+
+    // Every entry of every column family of the store.
+    fn contents(db: &RocksDbTreeStore) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        for name in RocksDbTreeStore::column_family_names("") {
+            let cf = db.db().cf_handle(&name).unwrap();
+            for item in db.db().iterator_cf(cf, IteratorMode::Start) {
+                let (key, value) = item.unwrap();
+                entries.push((name.clone(), key, value));
+            }
+        }
+        entries
+    }
+
+    for refcount_nodes in [false, true] {
+        let open = |dir: &TempDir| {
+            let config = RocksDbStoreConfig {
+                refcount_nodes,
+                ..Default::default()
+            };
+            RocksDbTreeStore::with_config(dir.path(), config)
+        };
+        let (once_dir, twice_dir) = (TempDir::new()?, TempDir::new()?);
+        let (once, twice) = (open(&once_dir)?, open(&twice_dir)?);
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&once);
+
+        let mut batches = Vec::new();
+        for version in 0..2u8 {
+            let values = vec![(KeyHash([version; 32]), Some(vec![version]))];
+            let (_root, batch) = tree.put_value_set(values, version.into())?;
+            once.write_node_batch(&batch.node_batch)?;
+            twice.write_node_batch(&batch.node_batch)?;
+            twice.write_node_batch(&batch.node_batch)?;
+            assert_eq!(contents(&once), contents(&twice));
+            batches.push(batch.node_batch);
+        }
+
+        // Written again after another batch, the first batch is a rewrite, which only counts
+        // with refcounts.
+        twice.write_node_batch(&batches[0])?;
+        assert_eq!(contents(&once) == contents(&twice), !refcount_nodes);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_bulk_verifier() -> anyhow::Result<()> {
+    use crate::rocksdb_store::{BulkVerifier, RocksDbStoreConfig};
+
+    let dir = tempfile::TempDir::new()?;
+    let roots = {
+        let db = RocksDbTreeStore::new(dir.path())?;
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+        let mut roots = Vec::new();
+        // Version 1 updates every key of version 0 and adds as many.
+        for version in 0..2u8 {
+            let values = (0..=255u8).flat_map(|i| {
+                (0..=version)
+                    .map(move |j| (KeyHash::with::<Sha256>([i, j]), Some(vec![i, version])))
+            });
+            let (root, batch) = tree.put_value_set(values, version.into())?;
+            db.write_tree_update_batch(batch)?;
+            roots.push(root);
+        }
+        roots
+    };
+
+    let db = RocksDbTreeStore::open_read_only(dir.path(), RocksDbStoreConfig::default())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let verifiers = (0..4)
+        .map(|levels| BulkVerifier::new(&db, 1, levels))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    for i in 0..=255u8 {
+        // Keys present at version 1, and keys absent from it.
+        let present = [
+            KeyHash::with::<Sha256>([i, 0]),
+            KeyHash::with::<Sha256>([i, 1]),
+        ];
+        for key in present.into_iter().chain([KeyHash([i; 32])]) {
+            let (value, proof) = tree.get_with_proof(key, 1)?;
+            proof.verify(roots[1], key, value.as_ref())?;
+            for verifier in &verifiers {
+                assert_eq!(verifier.root_hash(), roots[1]);
+                verifier.verify(key, value.as_ref(), &proof)?;
+                assert!(verifier.verify(key, Some(b"forged"), &proof).is_err());
+            }
+        }
+        // Proofs of the values of version 0 do not verify.
+        let key = KeyHash::with::<Sha256>([i, 0]);
+        let (value, proof) = tree.get_with_proof(key, 0)?;
+        for verifier in &verifiers {
+            assert!(verifier.verify(key, value.as_ref(), &proof).is_err());
+        }
+    }
+
+    assert!(BulkVerifier::new(&db, 2, 2).is_err());
+    assert!(BulkVerifier::new(&db, 1, 17).is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn test_rocksdb_trace_events() -> anyhow::Result<()> {
+    use crate::storage::TreeWriter;
+    use std::sync::{Arc, Mutex};
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    /// Collects the messages of the events of the store.
+    #[derive(Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    struct Message(String);
+
+    impl Visit for Message {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.0 = format!("{:?}", value);
+            }
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "op" {
+                self.0 = value.to_string();
+            }
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.target() == "jmt::rocksdb_store"
+        }
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut message = Message(String::new());
+            event.record(&mut message);
+            self.0.lock().unwrap().push(message.0);
+        }
+        fn enter(&self, _: &span::Id) {}
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    let recorder = Recorder::default();
+    let events = Arc::clone(&recorder.0);
+    tracing::subscriber::with_default(recorder, || -> anyhow::Result<()> {
+        let db = RocksDbTreeStore::new_temporary()?;
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+        let key = KeyHash::with::<Sha256>(b"key");
+        let (_root, batch) = tree.put_value_set([(key, Some(b"value".to_vec()))], 0)?;
+        db.write_node_batch(&batch.node_batch)?;
+        assert_eq!(tree.get(key, 0)?, Some(b"value".to_vec()));
+        Ok(())
+    })?;
+
+    let events = events.lock().unwrap();
+    assert!(events.iter().any(|event| event == "write_node_batch"));
+    assert!(events.iter().any(|event| event == "get_node_option"));
+    assert!(events.iter().any(|event| event == "get_value_option"));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_committed_versions() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    assert_eq!(db.committed_versions()?.count(), 0);
+
+    write_versions(&db, 3)?;
+    let versions = db
+        .committed_versions()?
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(versions, vec![0, 1, 2]);
+
+    // Pruning removes the root of version 0, stale since version 1.
+    db.prune(1)?;
+    let versions = db
+        .committed_versions()?
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(versions, vec![1, 2]);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_dedup_nodes() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+    use rocksdb::IteratorMode;
+    use tempfile::TempDir;
+
+    let dir = TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        dedup_nodes: true,
+        ..Default::default()
+    };
+    let deduped = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let plain = RocksDbTreeStore::new_temporary()?;
+
+    // Version 1 changes every value and version 2 sets them back, rewriting the leaves of
+    // version 0.
+    let keys: Vec<_> = (0..64u8).map(|i| KeyHash::with::<Sha256>([i])).collect();
+    let mut roots = Vec::new();
+    for version in 0..3u8 {
+        let values = keys
+            .iter()
+            .map(|key| (*key, Some(vec![version % 2])))
+            .collect::<Vec<_>>();
+        let mut version_roots = Vec::new();
+        for db in [&deduped, &plain] {
+            let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(db);
+            let (root, batch) = tree.put_value_set(values.clone(), version.into())?;
+            db.write_tree_update_batch(batch)?;
+            version_roots.push(root);
+        }
+        assert_eq!(version_roots[0], version_roots[1]);
+        roots.push(version_roots[0]);
+    }
+
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&deduped);
+    for (version, root) in roots.iter().enumerate() {
+        assert_eq!(tree.get_root_hash(version as u64)?, *root);
+        assert!(deduped.verify_consistency(version as u64)?.is_empty());
+        for key in &keys {
+            let (value, proof) = tree.get_with_proof(*key, version as u64)?;
+            assert_eq!(value, Some(vec![version as u8 % 2]));
+            proof.verify(*root, *key, value.as_ref())?;
+        }
+    }
+
+    // The leaves of version 2 point to the contents written for version 0.
+    let count = |db: &RocksDbTreeStore, name: &str| {
+        let cf = db.db().cf_handle(name).unwrap();
+        db.db().iterator_cf(cf, IteratorMode::Start).count()
+    };
+    assert_eq!(count(&deduped, "nodes"), count(&plain, "nodes"));
+    assert_eq!(count(&plain, "node_contents"), 0);
+    assert_eq!(
+        count(&deduped, "node_contents"),
+        count(&deduped, "nodes") - keys.len()
+    );
+
+    // Reopened without the mode, the store still reads the deduplicated nodes.
+    drop(deduped);
+    let reopened = RocksDbTreeStore::new(dir.path())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&reopened);
+    assert_eq!(tree.get(keys[0], 2)?, Some(vec![0]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_prune_keep_last() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let roots = write_versions(&db, 5)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let versions = || -> anyhow::Result<Vec<_>> { db.committed_versions()?.collect() };
+
+    assert_eq!(db.prune_keep_last(10)?, 0);
+    assert_eq!(db.prune_keep_last(5)?, 0);
+    assert_eq!(versions()?, vec![0, 1, 2, 3, 4]);
+
+    assert_eq!(db.prune_keep_last(2)?, 3);
+    assert_eq!(versions()?, vec![3, 4]);
+    for version in 3..5 {
+        assert_eq!(tree.get_root_hash(version)?, roots[version as usize]);
+    }
+    assert!(tree.get_root_hash(2).is_err());
+    assert_eq!(tree.get(KeyHash([1u8; 32]), 3)?, Some(vec![3]));
+
+    // Zero keeps the latest version.
+    assert_eq!(db.prune_keep_last(0)?, 1);
+    assert_eq!(versions()?, vec![4]);
+    assert_eq!(tree.get_root_hash(4)?, roots[4]);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_preview_root() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    write_versions(&db, 2)?;
+    let values = vec![
+        (KeyHash([1u8; 32]), None),
+        (KeyHash([3u8; 32]), Some(vec![3])),
+    ];
+
+    let preview = db.preview_root(1, values.clone())?;
+    // Nothing was written.
+    assert_eq!(db.latest_version()?, Some(1));
+    assert_eq!(db.committed_versions()?.count(), 2);
+
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (root, batch) = tree.put_value_set(values.clone(), 2)?;
+    db.write_tree_update_batch(batch)?;
+    assert_eq!(preview, root);
+    assert_eq!(tree.get_root_hash(2)?, preview);
+
+    let err = db.preview_root(5, values).unwrap_err();
+    assert!(err.downcast_ref::<crate::MissingRootError>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_namespaces() -> anyhow::Result<()> {
+    use crate::storage::HasPreimage;
+    use rocksdb::{Options, DB};
+    use std::sync::Arc;
+
+    let dir = tempfile::TempDir::new()?;
+    let mut opts = Options::default();
+    opts.create_if_missing(true);
+    opts.create_missing_column_families(true);
+    let cf_names = RocksDbTreeStore::column_family_names("");
+    let shared = Arc::new(DB::open_cf(&opts, dir.path(), &cf_names)?);
+    let first = RocksDbTreeStore::from_db_in_namespace(shared.clone(), "", [1; 8])?;
+    let second = RocksDbTreeStore::from_db_in_namespace(shared.clone(), "", [2; 8])?;
+
+    let first_roots = write_versions(&first, 3)?;
+    let second_tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> =
+        JellyfishMerkleTree::new(&second);
+    let (second_root, batch) =
+        second_tree.put_value_set(vec![(KeyHash([3u8; 32]), Some(vec![3]))], 0)?;
+    second.write_tree_update_batch(batch)?;
+    first.set_label("checkpoint", 1)?;
+    first.put_key_preimage(KeyHash([1u8; 32]), b"first")?;
+
+    let first_tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> =
+        JellyfishMerkleTree::new(&first);
+    assert_eq!(first.latest_version()?, Some(2));
+    assert_eq!(second.latest_version()?, Some(0));
+    assert_eq!(first_tree.get_root_hash(0)?, first_roots[0]);
+    assert_eq!(second_tree.get_root_hash(0)?, second_root);
+    assert_ne!(first_roots[0], second_root);
+    assert_eq!(first_tree.get(KeyHash([1u8; 32]), 2)?, Some(vec![2]));
+    assert_eq!(first_tree.get(KeyHash([3u8; 32]), 2)?, None);
+    assert_eq!(second_tree.get(KeyHash([1u8; 32]), 0)?, None);
+    assert_eq!(second.get_latest_value(KeyHash([3u8; 32]))?, Some(vec![3]));
+    let versions = |store: &RocksDbTreeStore| -> anyhow::Result<Vec<_>> {
+        store.committed_versions()?.collect()
+    };
+    assert_eq!(versions(&first)?, vec![0, 1, 2]);
+    assert_eq!(versions(&second)?, vec![0]);
+    assert_eq!(first.label_version("checkpoint")?, Some(1));
+    assert_eq!(second.label_version("checkpoint")?, None);
+    assert_eq!(first.preimage(KeyHash([1u8; 32]))?, Some(b"first".to_vec()));
+    assert_eq!(second.preimage(KeyHash([1u8; 32]))?, None);
+
+    // Pruning one store leaves the other alone.
+    first.remove_label("checkpoint")?;
+    first.prune(2)?;
+    assert!(first_tree.get_root_hash(0).is_err());
+    assert_eq!(second_tree.get_root_hash(0)?, second_root);
+    assert!(second.verify_consistency(0)?.is_empty());
+
+    // The stores are found again under their namespaces.
+    drop((first, second));
+    let reopened: RocksDbTreeStore = RocksDbTreeStore::from_db_in_namespace(shared, "", [2; 8])?;
+    assert_eq!(reopened.latest_version()?, Some(0));
+    assert_eq!(
+        reopened.get_latest_value(KeyHash([3u8; 32]))?,
+        Some(vec![3])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_stale_ratio() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    assert_eq!(db.stale_ratio()?, 0.0);
+
+    write_versions(&db, 2)?;
+    let early = db.stale_ratio()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    for version in 2..10u8 {
+        let value_set = vec![(KeyHash([1u8; 32]), Some(vec![version]))];
+        let (_, batch) = tree.put_value_set(value_set, version.into())?;
+        db.write_tree_update_batch(batch)?;
+    }
+    let late = db.stale_ratio()?;
+    assert!(early > 0.0);
+    assert!(late > early, "{} <= {}", late, early);
+
+    db.prune(9)?;
+    assert_eq!(db.stale_ratio()?, 0.0);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_warm_cache() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    // The keys spread over the 16 children of the root, each with 16 leaves below it.
+    let value_set: Vec<_> = (0..=255u8)
+        .map(|byte| (KeyHash([byte; 32]), Some(vec![byte])))
+        .collect();
+    let (_, batch) = tree.put_value_set(value_set, 0)?;
+    db.write_tree_update_batch(batch)?;
+
+    assert_eq!(db.warm_cache(0, 0)?, 1);
+    assert_eq!(db.warm_cache(0, 1)?, 17);
+    assert_eq!(db.warm_cache(0, 2)?, 273);
+    assert_eq!(db.warm_cache(0, 64)?, 273);
+
+    let err = db.warm_cache(1, 2).unwrap_err();
+    assert!(err.downcast_ref::<crate::MissingRootError>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_stores_equal_at_mock() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let mock = crate::mock::MockTreeStore::default();
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (_, batch) = tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![1]))], 0)?;
+    mock.write_tree_update_batch(batch.clone())?;
+    db.write_tree_update_batch(batch)?;
+    assert!(crate::stores_equal_at(&db, &mock, 0)?);
+
+    let (_, batch) = tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![2]))], 1)?;
+    db.write_tree_update_batch(batch)?;
+    assert!(!crate::stores_equal_at(&db, &mock, 1)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_retrying_writer() -> anyhow::Result<()> {
+    use crate::rocksdb_store::{is_transient, Corruption, RetryingWriter};
+    use crate::storage::{NodeBatch, TreeWriter};
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    /// Fails with `error` before every write while `failures` is positive, then writes to
+    /// `store`, counting its calls.
+    struct FlakyWriter<'a> {
+        store: &'a RocksDbTreeStore,
+        failures: Cell<u32>,
+        error: fn() -> anyhow::Error,
+        calls: Cell<u32>,
+    }
+
+    impl TreeWriter for FlakyWriter<'_> {
+        fn write_node_batch(&self, node_batch: &NodeBatch) -> anyhow::Result<()> {
+            self.calls.set(self.calls.get() + 1);
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+                return Err((self.error)());
+            }
+            self.store.write_node_batch(node_batch)
+        }
+    }
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (root, batch) = tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![1]))], 0)?;
+    let timed_out = || std::io::Error::from(std::io::ErrorKind::TimedOut).into();
+    let corrupted = || {
+        Corruption {
+            kind: RecordKind::Node,
+            found: vec![],
+        }
+        .into()
+    };
+    assert!(is_transient(&timed_out()));
+    assert!(!is_transient(&corrupted()));
+
+    let writer = RetryingWriter::new(
+        FlakyWriter {
+            store: &db,
+            failures: Cell::new(2),
+            error: timed_out,
+            calls: Cell::new(0),
+        },
+        3,
+        Duration::from_millis(1),
+    );
+    writer.write_node_batch(&batch.node_batch)?;
+    assert_eq!(writer.inner().calls.get(), 3);
+    assert_eq!(tree.get_root_hash(0)?, root);
+
+    // Running out of retries returns the last error.
+    let writer = RetryingWriter::new(
+        FlakyWriter {
+            store: &db,
+            failures: Cell::new(5),
+            error: timed_out,
+            calls: Cell::new(0),
+        },
+        2,
+        Duration::from_millis(1),
+    );
+    assert!(writer.write_node_batch(&batch.node_batch).is_err());
+    assert_eq!(writer.inner().calls.get(), 3);
+
+    // Corruption is not transient and fails at once.
+    let writer = RetryingWriter::new(
+        FlakyWriter {
+            store: &db,
+            failures: Cell::new(1),
+            error: corrupted,
+            calls: Cell::new(0),
+        },
+        3,
+        Duration::from_millis(1),
+    );
+    let err = writer.write_node_batch(&batch.node_batch).unwrap_err();
+    assert!(err.downcast_ref::<Corruption>().is_some());
+    assert_eq!(writer.inner().calls.get(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_compressed_values() -> anyhow::Result<()> {
+    use crate::{rocksdb_store::RocksDbStoreConfig, storage::TreeWriter};
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        compress_values_over: Some(64),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let small = KeyHash([1u8; 32]);
+    let large = KeyHash([2u8; 32]);
+    let large_value: Vec<u8> = (0..4096u32).map(|i| (i % 7) as u8).collect();
+    let value_set = vec![
+        (small, Some(vec![1u8; 16])),
+        (large, Some(large_value.clone())),
+    ];
+    let (_root, batch) = tree.put_value_set(value_set, 0)?;
+    db.write_node_batch(&batch.node_batch)?;
+
+    assert_eq!(tree.get(small, 0)?, Some(vec![1u8; 16]));
+    assert_eq!(tree.get(large, 0)?, Some(large_value.clone()));
+
+    // Only the large value is stored compressed.
+    let stored = |key_hash: KeyHash| -> anyhow::Result<Vec<u8>> {
+        let mut key = vec![FORMAT_VERSION, RecordKind::ValueKey as u8];
+        key.extend_from_slice(&key_hash.0);
+        key.extend_from_slice(&0u64.to_be_bytes());
+        let cf = db.db().cf_handle("values").unwrap();
+        Ok(db.db().get_cf(cf, key)?.unwrap())
+    };
+    assert_eq!(
+        stored(small)?,
+        raw_record(RecordKind::Value, &Some(vec![1u8; 16]))?
+    );
+    let compressed = stored(large)?;
+    assert_eq!(
+        compressed[..2],
+        [FORMAT_VERSION, RecordKind::CompressedValue as u8]
+    );
+    assert!(compressed.len() < large_value.len() / 4);
+
+    // The values stay readable without the option.
+    drop(db);
+    let reopened = RocksDbTreeStore::new(dir.path())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&reopened);
+    assert_eq!(tree.get(small, 0)?, Some(vec![1u8; 16]));
+    assert_eq!(tree.get(large, 0)?, Some(large_value));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_get_node_sized() -> anyhow::Result<()> {
+    use crate::{rocksdb_store::RocksDbStoreConfig, storage::TreeReader};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    write_versions(&db, 2)?;
+    let root_key = NodeKey::new_empty_path(1);
+    let (node, size) = db.get_node_sized(&root_key)?.unwrap();
+    assert_eq!(node, db.get_node(&root_key)?);
+    assert_eq!(size, raw_record(RecordKind::Node, &node)?.len());
+    assert!(db.get_node_sized(&NodeKey::new_empty_path(5))?.is_none());
+
+    // A deduplicated node reports the size of its content.
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        dedup_nodes: true,
+        ..Default::default()
+    };
+    let deduped = RocksDbTreeStore::with_config(dir.path(), config)?;
+    write_versions(&deduped, 2)?;
+    let (deduped_node, deduped_size) = deduped.get_node_sized(&root_key)?.unwrap();
+    assert_eq!(deduped_node, node);
+    assert_eq!(deduped_size, size);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_ttl_caching_reader() -> anyhow::Result<()> {
+    use crate::node_type::{LeafNode, Node};
+    use crate::storage::{TreeReader, TtlCachingReader};
+    use crate::{OwnedValue, Version};
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    /// Reads from `store`, counting the node and value lookups.
+    struct CountingReader<'a> {
+        store: &'a RocksDbTreeStore,
+        reads: Cell<u32>,
+    }
+
+    impl TreeReader for CountingReader<'_> {
+        fn get_node_option(&self, node_key: &NodeKey) -> anyhow::Result<Option<Node>> {
+            self.reads.set(self.reads.get() + 1);
+            self.store.get_node_option(node_key)
+        }
+
+        fn get_value_option(
+            &self,
+            max_version: Version,
+            key_hash: KeyHash,
+        ) -> anyhow::Result<Option<OwnedValue>> {
+            self.reads.set(self.reads.get() + 1);
+            self.store.get_value_option(max_version, key_hash)
+        }
+
+        fn get_rightmost_leaf(&self) -> anyhow::Result<Option<(NodeKey, LeafNode)>> {
+            self.store.get_rightmost_leaf()
+        }
+    }
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    write_versions(&db, 1)?;
+    let reader = TtlCachingReader::new(
+        CountingReader {
+            store: &db,
+            reads: Cell::new(0),
+        },
+        Duration::from_millis(500),
+        16,
+    );
+    let root_key = NodeKey::new_empty_path(0);
+    let key = KeyHash([1u8; 32]);
+    let value = Some(vec![0]);
+    let root = Some(db.get_node(&root_key)?);
+
+    assert_eq!(reader.get_node_option(&root_key)?, root);
+    assert_eq!(reader.get_value_option(0, key)?, value);
+    assert_eq!(reader.inner().reads.get(), 2);
+
+    // Within the TTL, both are served from the cache.
+    assert_eq!(reader.get_node_option(&root_key)?, root);
+    assert_eq!(reader.get_value_option(0, key)?, value);
+    assert_eq!(reader.inner().reads.get(), 2);
+
+    // Once expired, both are read again.
+    std::thread::sleep(Duration::from_millis(600));
+    assert_eq!(reader.get_node_option(&root_key)?, root);
+    assert_eq!(reader.get_value_option(0, key)?, value);
+    assert_eq!(reader.inner().reads.get(), 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_compact_cf() -> anyhow::Result<()> {
+    use crate::rocksdb_store::CfKind;
+
+    let dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::new(dir.path())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let keys = [KeyHash([1u8; 32]), KeyHash([2u8; 32])];
+    // Flushing after every overwrite leaves an SST file of values per version, each spanning the
+    // keys of the others.
+    let values_cf = db.db().cf_handle("values").unwrap();
+    for version in 0..3u8 {
+        let value_set = keys.map(|key| (key, Some(vec![version])));
+        let (_root, batch) = tree.put_value_set(value_set.to_vec(), version.into())?;
+        db.write_tree_update_batch(batch)?;
+        db.db().flush_cf(values_cf)?;
+    }
+    let sst_files = |db: &RocksDbTreeStore| -> anyhow::Result<u64> {
+        Ok(db.rocks_stats()?.sst_files_per_level.iter().sum())
+    };
+    assert_eq!(sst_files(&db)?, 3);
+
+    db.compact_cf(CfKind::Values, None, None)?;
+    assert_eq!(sst_files(&db)?, 1);
+    for version in 0..3u8 {
+        for key in keys {
+            assert_eq!(tree.get(key, version.into())?, Some(vec![version]));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_write_tree_update_batch_ref() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let mirror = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (root, batch) = tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![1]))], 0)?;
+
+    db.write_tree_update_batch_ref(&batch)?;
+    assert_eq!(tree.get_root_hash(0)?, root);
+
+    // The batch is still there to be mirrored once written.
+    assert!(!batch.node_batch.nodes().is_empty());
+    mirror.write_tree_update_batch(batch)?;
+    let mirrored: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&mirror);
+    assert_eq!(mirrored.get_root_hash(0)?, root);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_sharded_store() -> anyhow::Result<()> {
+    use crate::rocksdb_store::ShardedTreeStore;
+    use crate::storage::{HasPreimage, TreeReader};
+
+    let store = ShardedTreeStore::new(vec![
+        RocksDbTreeStore::new_temporary()?,
+        RocksDbTreeStore::new_temporary()?,
+    ])?;
+    let tree: JellyfishMerkleTree<ShardedTreeStore<_, Sha256>, Sha256> =
+        JellyfishMerkleTree::new(&store);
+    let keys: Vec<KeyHash> = (0..16u8).map(|i| KeyHash([i; 32])).collect();
+    let (root, batch) =
+        tree.put_value_set(keys.iter().map(|&key| (key, Some(key.0[..1].to_vec()))), 0)?;
+    store.write_tree_update_batch(batch)?;
+    for &key in &keys {
+        store.put_key_preimage(key, &key.0)?;
+    }
+
+    // Every shard got some of the keys, and only the node shard has nodes.
+    let shard_indices: Vec<usize> = keys.iter().map(|&key| store.shard_index(key)).collect();
+    assert!(shard_indices.contains(&0) && shard_indices.contains(&1));
+    let root_key = NodeKey::new_empty_path(0);
+    assert!(store.node_shard().get_node_option(&root_key)?.is_some());
+    assert!(store.shards()[1].get_node_option(&root_key)?.is_none());
+    for (&key, &index) in keys.iter().zip(&shard_indices) {
+        for (shard_index, shard) in store.shards().iter().enumerate() {
+            let held = shard_index == index;
+            assert_eq!(shard.get_value_option(0, key)?.is_some(), held);
+            assert_eq!(shard.preimage(key)?.is_some(), held);
+        }
+    }
+
+    // Reads fan out to the shard of each key.
+    assert_eq!(tree.get_root_hash(0)?, root);
+    for &key in &keys {
+        let (value, proof) = tree.get_with_proof(key, 0)?;
+        assert_eq!(value, Some(key.0[..1].to_vec()));
+        proof.verify(root, key, value.as_ref())?;
+        assert_eq!(store.preimage(key)?, Some(key.0.to_vec()));
+    }
+
+    // Overwrites through the tree writer land on the shards too.
+    let (root, batch) = tree.put_value_set(vec![(keys[1], Some(vec![0xff]))], 1)?;
+    crate::storage::TreeWriter::write_node_batch(&store, &batch.node_batch)?;
+    assert_eq!(tree.get_root_hash(1)?, root);
+    assert_eq!(tree.get(keys[1], 1)?, Some(vec![0xff]));
+    assert_eq!(tree.get(keys[1], 0)?, Some(vec![1]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_wal_sync_interval() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+    use std::time::{Duration, Instant};
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        wal_sync_interval: Some(Duration::from_secs(3600)),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config.clone())?;
+    let roots = write_versions(&db, 2)?;
+    db.sync_now()?;
+
+    // Dropping the store stops the sync thread without waiting for its next sync.
+    let started = Instant::now();
+    drop(db);
+    assert!(started.elapsed() < Duration::from_secs(60));
+
+    let reopened = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&reopened);
+    assert_eq!(tree.get_root_hash(1)?, roots[1]);
+    assert_eq!(tree.get(KeyHash([1u8; 32]), 1)?, Some(vec![1]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_seal_version() -> anyhow::Result<()> {
+    let dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::new(dir.path())?;
+    write_versions(&db, 4)?;
+    assert_eq!(db.sealed_version()?, None);
+    db.seal_version(2)?;
+    // Sealing a later version keeps the lowest one.
+    db.seal_version(3)?;
+    assert_eq!(db.sealed_version()?, Some(2));
+
+    // Pruning below the sealed version is allowed, and leaves it readable.
+    db.prune(2)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert!(tree.get_root_hash(1).is_err());
+    assert_eq!(tree.get(KeyHash([1u8; 32]), 2)?, Some(vec![2]));
+
+    // Pruning the sealed version is rejected, pruning nothing.
+    let err = db.prune(3).unwrap_err();
+    assert!(err.to_string().contains("sealed"), "{err}");
+    assert_eq!(tree.get(KeyHash([1u8; 32]), 2)?, Some(vec![2]));
+
+    // The seal persists.
+    drop(db);
+    let reopened = RocksDbTreeStore::new(dir.path())?;
+    assert_eq!(reopened.sealed_version()?, Some(2));
+    assert!(reopened.prune(3).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_raw_keys() -> anyhow::Result<()> {
+    use crate::storage::{HasPreimage, TreeReader};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let root = db.put_raw(
+        vec![
+            (b"alice".as_slice(), Some(vec![1])),
+            (b"bob".as_slice(), Some(vec![2])),
+        ],
+        0,
+    )?;
+    db.put_raw(vec![(b"alice", None)], 1)?;
+
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(0)?, root);
+    assert_eq!(db.get_value_by_raw_key(0, b"alice")?, Some(vec![1]));
+    assert_eq!(db.get_value_by_raw_key(1, b"alice")?, None);
+    assert_eq!(db.get_value_by_raw_key(1, b"bob")?, Some(vec![2]));
+    assert_eq!(db.get_value_by_raw_key(1, b"carol")?, None);
+
+    // The raw keys are the preimages of the key hashes the tree holds.
+    let key_hash = KeyHash::with::<Sha256>(b"bob");
+    assert_eq!(db.get_value_option(1, key_hash)?, Some(vec![2]));
+    assert_eq!(db.preimage(key_hash)?, Some(b"bob".to_vec()));
+    assert_eq!(
+        db.preimage(KeyHash::with::<Sha256>(b"alice"))?,
+        Some(b"alice".to_vec())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_clone() -> anyhow::Result<()> {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RocksDbTreeStore>();
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let clone = db.clone();
+    let roots = write_versions(&db, 3)?;
+
+    let reader = std::thread::spawn(move || -> anyhow::Result<_> {
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&clone);
+        Ok((tree.get_root_hash(2)?, tree.get(KeyHash([1u8; 32]), 2)?))
+    });
+    let (root, value) = reader.join().unwrap()?;
+    assert_eq!(root, roots[2]);
+    assert_eq!(value, Some(vec![2]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_has_node() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+    use crate::storage::NibblePath;
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        bloom_filter_bits: Some(10.0),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    write_versions(&db, 2)?;
+
+    // Answered from the memtable, then from the SST files and their bloom filters.
+    for flushed in [false, true] {
+        if flushed {
+            db.db().flush_cf(db.db().cf_handle("nodes").unwrap())?;
+        }
+        assert!(db.has_node(&NodeKey::new_empty_path(0))?);
+        assert!(db.has_node(&NodeKey::new_empty_path(1))?);
+        assert!(!db.has_node(&NodeKey::new_empty_path(2))?);
+        assert!(!db.has_node(&NodeKey::new(1, NibblePath::new_odd(vec![0x10])))?);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_deterministic_batch_layout() -> anyhow::Result<()> {
+    use crate::storage::{NodeBatch, TreeUpdateBatch};
+
+    let mut rng: rand::rngs::StdRng = rand::SeedableRng::from_seed([2; 32]);
+    let value_set: Vec<_> = (0..100)
+        .map(|_| (KeyHash(rand::Rng::gen(&mut rng)), Some(vec![1])))
+        .collect();
+    let source = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&source);
+    let (_root, batch) = tree.put_value_set(value_set, 0)?;
+
+    // The same batch, its entries inserted in another order.
+    let mut reversed = NodeBatch::default();
+    reversed.extend(
+        batch.node_batch.nodes().clone().into_iter().rev(),
+        batch.node_batch.values().clone().into_iter().rev(),
+    );
+    let layouts = [batch.node_batch.clone(), reversed]
+        .into_iter()
+        .map(|node_batch| -> anyhow::Result<_> {
+            let dir = tempfile::TempDir::new()?;
+            let db = RocksDbTreeStore::new(dir.path())?;
+            db.write_tree_update_batch(TreeUpdateBatch {
+                node_batch,
+                ..Default::default()
+            })?;
+            let mut entries = Vec::new();
+            for name in ["nodes", "values"] {
+                let cf = db.db().cf_handle(name).unwrap();
+                db.db().flush_cf(cf)?;
+                for item in db.db().iterator_cf(cf, rocksdb::IteratorMode::Start) {
+                    entries.push(item?);
+                }
+            }
+            let files: Vec<_> = db
+                .db()
+                .live_files()?
+                .into_iter()
+                .map(|file| {
+                    (
+                        file.column_family_name,
+                        file.level,
+                        file.start_key,
+                        file.end_key,
+                        file.num_entries,
+                    )
+                })
+                .collect();
+            Ok((entries, files))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert!(!layouts[0].0.is_empty());
+    assert_eq!(layouts[0], layouts[1]);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_auto_prune_stale_bytes() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+    use std::time::{Duration, Instant};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    assert!(db.set_least_readable_version(0).is_err());
+
+    let config = RocksDbStoreConfig {
+        auto_prune_stale_bytes: Some(1),
+        ..Default::default()
+    };
+    let temp_dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::with_config(temp_dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let roots = write_versions(&db, 4)?;
+
+    // Nothing is pruned before a least readable version is set.
+    assert_eq!(tree.get_root_hash(0)?, roots[0]);
+    db.set_least_readable_version(3)?;
+    let (root, batch) = tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![4]))], 4)?;
+    db.write_tree_update_batch(batch)?;
+
+    // The stale nodes of the write exceed the threshold, and the prune follows in the background.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while tree.get_root_hash(0).is_ok() {
+        assert!(Instant::now() < deadline, "version 0 was never pruned");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    for version in 0..3 {
+        assert!(tree.get_root_hash(version).is_err());
+    }
+    assert_eq!(tree.get_root_hash(3)?, roots[3]);
+    assert_eq!(tree.get_root_hash(4)?, root);
+    for (key, value) in [
+        (KeyHash([1u8; 32]), vec![4]),
+        (KeyHash([2u8; 32]), vec![0xff]),
+    ] {
+        let (retrieved, proof) = tree.get_with_proof(key, 4)?;
+        assert_eq!(retrieved, Some(value.clone()));
+        proof.verify(root, key, Some(&value))?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_max_open_files() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+
+    let config = RocksDbStoreConfig {
+        max_open_files: Some(20),
+        table_cache_num_shard_bits: Some(1),
+        ..Default::default()
+    };
+    let temp_dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::with_config(temp_dir.path(), config.clone())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+
+    // Every version lands in files of its own, more than the table cache holds.
+    let mut roots = Vec::new();
+    for version in 0..30u8 {
+        let key = KeyHash([version; 32]);
+        let (root, batch) = tree.put_value_set(vec![(key, Some(vec![version]))], version.into())?;
+        db.write_tree_update_batch(batch)?;
+        for name in ["nodes", "values"] {
+            db.db().flush_cf(db.db().cf_handle(name).unwrap())?;
+        }
+        roots.push(root);
+    }
+    assert!(db.db().live_files()?.len() > 20);
+
+    let check = |db: &RocksDbTreeStore| -> anyhow::Result<()> {
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(db);
+        for version in 0..30u8 {
+            let key = KeyHash([version; 32]);
+            let (value, proof) = tree.get_with_proof(key, version.into())?;
+            assert_eq!(value, Some(vec![version]));
+            proof.verify(roots[usize::from(version)], key, Some(vec![version]))?;
+        }
+        Ok(())
+    };
+    check(&db)?;
+    drop(db);
+    check(&RocksDbTreeStore::with_config(temp_dir.path(), config)?)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_value_cursor() -> anyhow::Result<()> {
+    use crate::rocksdb_store::ValueCursor;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let mut rng: rand::rngs::StdRng = rand::SeedableRng::from_seed([3; 32]);
+    let keys: Vec<KeyHash> = (0..100)
+        .map(|_| KeyHash(rand::Rng::gen(&mut rng)))
+        .collect();
+    let (_root, batch) = tree.put_value_set(keys.iter().map(|&key| (key, Some(vec![0]))), 0)?;
+    db.write_tree_update_batch(batch)?;
+    // Version 1 updates some keys and deletes others.
+    let value_set = keys[..40]
+        .iter()
+        .enumerate()
+        .map(|(i, &key)| (key, (i % 2 == 0).then(|| vec![1])));
+    let (_root, batch) = tree.put_value_set(value_set, 1)?;
+    db.write_tree_update_batch(batch)?;
+
+    let full_scan =
+        db.map_values_at_version(1, |key_hash, value| Ok((key_hash, value.to_vec())))?;
+    assert_eq!(full_scan.len(), 80);
+
+    // Versions written between the pages do not move the scan.
+    let mut paged = Vec::new();
+    let mut cursor = Some(ValueCursor::new(1));
+    let mut version = 2;
+    while let Some(page_cursor) = cursor {
+        let (page, next_cursor) = db.next_page(&page_cursor, 7)?;
+        assert!(page.len() <= 7);
+        paged.extend(page);
+        cursor = next_cursor;
+        let (_root, batch) = tree.put_value_set(vec![(keys[version as usize], None)], version)?;
+        db.write_tree_update_batch(batch)?;
+        version += 1;
+    }
+    assert_eq!(paged, full_scan);
+
+    assert!(db.next_page(&ValueCursor::new(1), 0).is_err());
+    let err = db.next_page(&ValueCursor::new(1000), 7).unwrap_err();
+    assert!(err.downcast_ref::<crate::MissingRootError>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_state_digest() -> anyhow::Result<()> {
+    let build = |preimage: &[u8]| -> anyhow::Result<RocksDbTreeStore> {
+        let db = RocksDbTreeStore::new_temporary()?;
+        write_versions(&db, 2)?;
+        db.put_key_preimage(KeyHash([1u8; 32]), b"one")?;
+        db.put_key_preimage(KeyHash([2u8; 32]), preimage)?;
+        Ok(db)
+    };
+    let (a, b) = (build(b"two")?, build(b"two")?);
+    assert_eq!(a.state_digest(1)?, b.state_digest(1)?);
+    assert_ne!(a.state_digest(0)?, a.state_digest(1)?);
+
+    // A differing preimage changes the digest, though the trees are the same.
+    let c = build(b"deux")?;
+    assert_ne!(a.state_digest(1)?, c.state_digest(1)?);
+
+    let err = a.state_digest(2).unwrap_err();
+    assert!(err.downcast_ref::<crate::MissingRootError>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_sortable_key_codec() -> anyhow::Result<()> {
+    use crate::{
+        node_type::NodeKey,
+        rocksdb_store::{BincodeCodec, Codec, GenericTreeStore, SortableKeyBincodeCodec},
+        storage::TreeReader,
+    };
+
+    // Sorted by version, as the encoded keys of the new codec are and those of the default one
+    // are not.
+    let node_keys: Vec<NodeKey> = [1, 2, 255, 256]
+        .into_iter()
+        .map(NodeKey::new_empty_path)
+        .collect();
+    let encoded = |encode: fn(&NodeKey) -> anyhow::Result<Vec<u8>>| {
+        node_keys
+            .iter()
+            .map(encode)
+            .collect::<anyhow::Result<Vec<_>>>()
+    };
+    let sortable = encoded(SortableKeyBincodeCodec::encode_key)?;
+    assert!(sortable.windows(2).all(|pair| pair[0] < pair[1]));
+    let default = encoded(BincodeCodec::encode_key)?;
+    assert!(!default.windows(2).all(|pair| pair[0] < pair[1]));
+    for (node_key, key) in node_keys.iter().zip(&sortable) {
+        assert_eq!(
+            SortableKeyBincodeCodec::decode_key::<NodeKey>(key)?,
+            *node_key
+        );
+    }
+
+    type SortableStore = GenericTreeStore<SortableKeyBincodeCodec, Sha256>;
+    let dir = tempfile::TempDir::new()?;
+    let db = SortableStore::with_hasher(dir.path(), Default::default())?;
+    let tree: JellyfishMerkleTree<SortableStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let mut roots = Vec::new();
+    for version in 0..300u64 {
+        let key = KeyHash([(version % 7) as u8; 32]);
+        let (root, batch) =
+            tree.put_value_set(vec![(key, Some(version.to_be_bytes().to_vec()))], version)?;
+        db.write_tree_update_batch(batch)?;
+        roots.push(root);
+    }
+
+    // The nodes column family holds the nodes of each version together, in version order.
+    let nodes_cf = db.db().cf_handle("nodes").unwrap();
+    let versions: Vec<u64> = db
+        .db()
+        .iterator_cf(nodes_cf, rocksdb::IteratorMode::Start)
+        .map(|item| -> anyhow::Result<u64> {
+            let (key, _) = item?;
+            Ok(SortableKeyBincodeCodec::decode_key::<NodeKey>(&key[2..])?.version())
+        })
+        .collect::<anyhow::Result<_>>()?;
+    assert!(versions.windows(2).all(|pair| pair[0] <= pair[1]));
+
+    for version in [0, 7, 255, 256, 299] {
+        let key = KeyHash([(version % 7) as u8; 32]);
+        assert_eq!(
+            db.get_value_option(version, key)?,
+            Some(version.to_be_bytes().to_vec())
+        );
+        assert_eq!(tree.get_root_hash(version)?, roots[version as usize]);
+        let (value, proof) = tree.get_with_proof(key, version)?;
+        proof.verify(roots[version as usize], key, value)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_ordered_key_codec() -> anyhow::Result<()> {
+    use crate::{
+        node_type::{NodeKey, OrderedNodeKey},
+        rocksdb_store::{GenericTreeStore, OrderedKeyBincodeCodec},
+    };
+
+    type OrderedStore = GenericTreeStore<OrderedKeyBincodeCodec, Sha256>;
+    let dir = tempfile::TempDir::new()?;
+    let db = OrderedStore::with_hasher(dir.path(), Default::default())?;
+    let tree: JellyfishMerkleTree<OrderedStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let mut roots = Vec::new();
+    for version in 0..50u64 {
+        let key = KeyHash([(version % 11) as u8; 32]);
+        let (root, batch) =
+            tree.put_value_set(vec![(key, Some(version.to_be_bytes().to_vec()))], version)?;
+        db.write_tree_update_batch(batch)?;
+        roots.push(root);
+    }
+
+    // The nodes column family holds the shallower nodes first, sorted as their keys are.
+    let nodes_cf = db.db().cf_handle("nodes").unwrap();
+    let node_keys: Vec<OrderedNodeKey> = db
+        .db()
+        .iterator_cf(nodes_cf, rocksdb::IteratorMode::Start)
+        .map(|item| -> anyhow::Result<OrderedNodeKey> {
+            let (key, _) = item?;
+            OrderedNodeKey::decode(&key[2..])
+        })
+        .collect::<anyhow::Result<_>>()?;
+    assert!(node_keys.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(
+        node_keys[..50].to_vec(),
+        (0..50)
+            .map(|version| OrderedNodeKey::from(NodeKey::new_empty_path(version)))
+            .collect::<Vec<_>>()
+    );
+
+    for version in [0, 10, 11, 49] {
+        let key = KeyHash([(version % 11) as u8; 32]);
+        assert_eq!(tree.get_root_hash(version)?, roots[version as usize]);
+        let (value, proof) = tree.get_with_proof(key, version)?;
+        assert_eq!(value, Some(version.to_be_bytes().to_vec()));
+        proof.verify(roots[version as usize], key, value)?;
+    }
+    db.prune(40)?;
+    assert!(tree.get_root_hash(39).is_err());
+    assert_eq!(tree.get_root_hash(40)?, roots[40]);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_batch_at() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let mut rng: rand::rngs::StdRng = rand::SeedableRng::from_seed([4; 32]);
+    let keys: Vec<KeyHash> = (0..50).map(|_| KeyHash(rand::Rng::gen(&mut rng))).collect();
+
+    // Inserts, then updates and deletions of some of the keys.
+    let mut batches = Vec::new();
+    for version in 0..3u64 {
+        let value_set: Vec<_> = match version {
+            0 => keys.iter().map(|&key| (key, Some(vec![0]))).collect(),
+            _ => keys[..20]
+                .iter()
+                .enumerate()
+                .map(|(i, &key)| (key, (i % 3 != 0).then(|| vec![version as u8])))
+                .collect(),
+        };
+        let (_root, batch) = tree.put_value_set(value_set, version)?;
+        db.write_tree_update_batch_ref(&batch)?;
+        batches.push(batch);
+    }
+
+    for (version, batch) in batches.iter().enumerate() {
+        assert_eq!(db.batch_at(version as u64)?, *batch);
+    }
+    let err = db.batch_at(3).unwrap_err();
+    assert!(err.downcast_ref::<crate::MissingRootError>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_scan_strict() -> anyhow::Result<()> {
+    use crate::rocksdb_store::{CfKind, UndecodableRecord};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    write_versions(&db, 3)?;
+    db.put_key_preimage(KeyHash([1u8; 32]), b"one")?;
+    db.set_label("checkpoint", 1)?;
+    db.set_version_metadata(1, b"block".to_vec())?;
+    let column_families = [
+        CfKind::Nodes,
+        CfKind::Preimages,
+        CfKind::Values,
+        CfKind::Metadata,
+        CfKind::StaleNodes,
+        CfKind::Labels,
+        CfKind::Refcounts,
+        CfKind::Roots,
+        CfKind::NodeContents,
+        CfKind::VersionMetadata,
+        CfKind::RootVersions,
+    ];
+    for cf in column_families {
+        for item in db.scan_strict(cf)? {
+            item?;
+        }
+    }
+    let values = db.scan_strict(CfKind::Values)?.count();
+    assert_eq!(values, 4);
+
+    // A value whose record is garbage, between two good ones.
+    let bad_key = raw_record(RecordKind::ValueKey, &())?
+        .into_iter()
+        .chain([1u8; 32])
+        .chain(5u64.to_be_bytes())
+        .collect::<Vec<_>>();
+    let values_cf = db.db().cf_handle("values").unwrap();
+    db.db().put_cf(values_cf, &bad_key, b"garbage")?;
+
+    let items: Vec<_> = db.scan_strict(CfKind::Values)?.collect();
+    // The scan stops at the bad record, after the three entries of key 1 before it.
+    assert_eq!(items.len(), 4);
+    assert!(items[..3].iter().all(Result::is_ok));
+    let err = items[3].as_ref().unwrap_err();
+    let undecodable = err.downcast_ref::<UndecodableRecord>().unwrap();
+    assert_eq!(undecodable.column_family, CfKind::Values);
+    assert_eq!(undecodable.key, bad_key);
+    assert!(err.to_string().contains(&hex::encode(&bad_key)));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_rate_limit() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+
+    let config = RocksDbStoreConfig {
+        rate_limit_bytes_per_sec: Some(1 << 20),
+        ..Default::default()
+    };
+    let temp_dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::with_config(temp_dir.path(), config.clone())?;
+    let roots = write_versions(&db, 5)?;
+    // Flushes go through the limiter.
+    for name in ["nodes", "values"] {
+        db.db().flush_cf(db.db().cf_handle(name).unwrap())?;
+    }
+    db.db().compact_range_cf(
+        db.db().cf_handle("nodes").unwrap(),
+        None::<&[u8]>,
+        None::<&[u8]>,
+    );
+    drop(db);
+
+    let db = RocksDbTreeStore::with_config(temp_dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    for (version, root) in roots.into_iter().enumerate() {
+        let version = version as u64;
+        let (value, proof) = tree.get_with_proof(KeyHash([1u8; 32]), version)?;
+        assert_eq!(value, Some(vec![version as u8]));
+        proof.verify(root, KeyHash([1u8; 32]), value.as_ref())?;
+        assert_eq!(tree.get(KeyHash([2u8; 32]), version)?, Some(vec![0xff]));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_rehash_into() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+    use sha2::Sha512_256;
+
+    type Sha512_256Store = RocksDbTreeStore<Sha512_256>;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    write_versions(&db, 3)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (_, batch) = tree.put_value_set(
+        vec![
+            (KeyHash([2u8; 32]), None),
+            (KeyHash([3u8; 32]), Some(vec![3])),
+        ],
+        3,
+    )?;
+    db.write_tree_update_batch(batch)?;
+
+    let dir = tempfile::TempDir::new()?;
+    let mut dest = Sha512_256Store::with_hasher(dir.path(), RocksDbStoreConfig::default())?;
+    let root = db.rehash_into::<Sha512_256>(3, &mut dest)?;
+    assert_ne!(root, tree.get_root_hash(3)?);
+
+    // The destination proves the keys live at version 3, and only them, under the new hasher.
+    let dest_tree: JellyfishMerkleTree<Sha512_256Store, Sha512_256> =
+        JellyfishMerkleTree::new(&dest);
+    assert_eq!(dest_tree.get_root_hash(3)?, root);
+    assert_eq!(dest.leaf_count(3)?, db.leaf_count(3)?);
+    for (key, expected) in [
+        (KeyHash([1u8; 32]), Some(vec![2])),
+        (KeyHash([2u8; 32]), None),
+        (KeyHash([3u8; 32]), Some(vec![3])),
+    ] {
+        let (value, proof) = dest_tree.get_with_proof(key, 3)?;
+        assert_eq!(value, expected);
+        proof.verify(root, key, value.as_ref())?;
+    }
+
+    // An empty version gives the placeholder root.
+    let empty = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&empty);
+    let (_, batch) = tree.put_value_set(vec![(KeyHash([1u8; 32]), None)], 0)?;
+    empty.write_tree_update_batch(batch)?;
+    let dir = tempfile::TempDir::new()?;
+    let mut dest = Sha512_256Store::with_hasher(dir.path(), RocksDbStoreConfig::default())?;
+    let root = empty.rehash_into::<Sha512_256>(0, &mut dest)?;
+    assert_eq!(root, tree.get_root_hash(0)?);
+    let dest_tree: JellyfishMerkleTree<Sha512_256Store, Sha512_256> =
+        JellyfishMerkleTree::new(&dest);
+    assert_eq!(dest_tree.get_root_hash(0)?, root);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_buffered_tree_store() -> anyhow::Result<()> {
+    use crate::rocksdb_store::BufferedTreeStore;
+
+    let dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::new(dir.path())?;
+    let buffered = BufferedTreeStore::new(db.clone(), 3);
+    let mut roots = Vec::new();
+    for version in 0..3u8 {
+        let tree: JellyfishMerkleTree<BufferedTreeStore, Sha256> =
+            JellyfishMerkleTree::new(&buffered);
+        let key = KeyHash([version; 32]);
+        let (root, batch) = tree.put_value_set(vec![(key, Some(vec![version]))], version.into())?;
+        buffered.write_tree_update_batch(batch)?;
+        roots.push(root);
+    }
+    assert_eq!(buffered.buffered_versions(), 3);
+
+    // The buffered versions are readable from the buffer, but not written through yet.
+    let tree: JellyfishMerkleTree<BufferedTreeStore, Sha256> = JellyfishMerkleTree::new(&buffered);
+    for (version, root) in roots.iter().enumerate() {
+        for key in 0..=version as u8 {
+            let (value, proof) = tree.get_with_proof(KeyHash([key; 32]), version as u64)?;
+            assert_eq!(value, Some(vec![key]));
+            proof.verify(*root, KeyHash([key; 32]), value.as_ref())?;
+        }
+    }
+    assert_eq!(db.latest_version()?, None);
+    assert!(!db.has_node(&NodeKey::new_empty_path(0))?);
+
+    // The buffer is full.
+    let (_, batch) = tree.put_value_set(vec![(KeyHash([3u8; 32]), Some(vec![3]))], 3)?;
+    let err = buffered.write_tree_update_batch(batch).unwrap_err();
+    assert!(err.to_string().contains("flush first"), "{err}");
+    assert_eq!(buffered.buffered_versions(), 3);
+
+    buffered.flush()?;
+    assert_eq!(buffered.buffered_versions(), 0);
+    drop(buffered);
+    drop(db);
+
+    // The flushed versions survive a reopen.
+    let db = RocksDbTreeStore::new(dir.path())?;
+    assert_eq!(db.latest_version()?, Some(2));
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    for (version, root) in roots.into_iter().enumerate() {
+        assert_eq!(tree.get_root_hash(version as u64)?, root);
+        let key = KeyHash([version as u8; 32]);
+        assert_eq!(tree.get(key, version as u64)?, Some(vec![version as u8]));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_version_exists() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    assert!(!db.version_exists(0)?);
+    write_versions(&db, 3)?;
+    // A gap: versions 3 and 4 are never written.
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (_, batch) = tree.put_value_set(vec![(KeyHash([5u8; 32]), Some(vec![5]))], 5)?;
+    db.write_tree_update_batch(batch)?;
+
+    for version in [0, 1, 2, 5] {
+        assert!(db.version_exists(version)?, "{version}");
+    }
+    for version in [3, 4, 6, u64::MAX] {
+        assert!(!db.version_exists(version)?, "{version}");
+    }
+
+    db.prune(2)?;
+    assert!(!db.version_exists(0)?);
+    assert!(!db.version_exists(1)?);
+    assert!(db.version_exists(2)?);
+    assert_eq!(
+        db.committed_versions()?
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        vec![2, 5]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_value_prefix_extractor() -> anyhow::Result<()> {
+    use crate::rocksdb_store::{RocksDbStoreConfig, ValueCursor};
+    use crate::storage::TreeReader;
+
+    let config = RocksDbStoreConfig {
+        value_prefix_extractor: true,
+        value_full_bloom_bits: Some(10.0),
+        ..Default::default()
+    };
+    let temp_dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::with_config(temp_dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+
+    // Key hashes differing in their last byte only, so that the entries of one sit right after
+    // those of the other, each version flushed to files of its own.
+    let key = |last: u8| {
+        let mut key = [7u8; 32];
+        key[31] = last;
+        KeyHash(key)
+    };
+    for version in 0..4u8 {
+        let value_set = vec![
+            (key(1), Some(vec![1, version])),
+            (key(2), (version % 2 == 0).then(|| vec![2, version])),
+            (key(3), Some(vec![3, version])),
+        ];
+        let (_, batch) = tree.put_value_set(value_set, version.into())?;
+        db.write_tree_update_batch(batch)?;
+        db.db().flush_cf(db.db().cf_handle("values").unwrap())?;
+    }
+
+    // The history of a key stops where the next key hash starts.
+    let history = db.history_of(key(2))?.collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(
+        history,
+        vec![
+            (0, Some(vec![2, 0])),
+            (1, None),
+            (2, Some(vec![2, 2])),
+            (3, None),
+        ]
+    );
+    assert_eq!(db.history_of(key(4))?.count(), 0);
+    for version in 0..4u8 {
+        let version_u64 = u64::from(version);
+        assert_eq!(
+            db.get_value_option(version_u64, key(1))?,
+            Some(vec![1, version])
+        );
+        assert_eq!(
+            db.get_value_option(version_u64, key(2))?,
+            (version % 2 == 0).then(|| vec![2, version])
+        );
+        assert_eq!(db.get_value_option(version_u64, key(4))?, None);
+    }
+    assert_eq!(db.get_value_with_version(3, key(2))?, None);
+    assert_eq!(db.get_value_with_version(3, key(3))?, Some((3, vec![3, 3])));
+
+    // Scans across key hashes still see every key.
+    let live = db.map_values_at_version(2, |key_hash, value| Ok((key_hash, value.to_vec())))?;
+    assert_eq!(
+        live,
+        vec![
+            (key(1), vec![1, 2]),
+            (key(2), vec![2, 2]),
+            (key(3), vec![3, 2]),
+        ]
+    );
+    let (page, cursor) = db.next_page(&ValueCursor::new(2), 1)?;
+    assert_eq!(page, vec![(key(1), vec![1, 2])]);
+    let (page, _) = db.next_page(&cursor.unwrap(), 2)?;
+    assert_eq!(page, vec![(key(2), vec![2, 2]), (key(3), vec![3, 2])]);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_swap_in() -> anyhow::Result<()> {
+    let dir = tempfile::TempDir::new()?;
+    let live_path = dir.path().join("live");
+    let staged_path = dir.path().join("staged");
+
+    let staged = RocksDbTreeStore::new(&staged_path)?;
+    let staged_roots = write_versions(&staged, 3)?;
+    drop(staged);
+
+    let db = RocksDbTreeStore::new(&live_path)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (live_root, batch) = tree.put_value_set(vec![(KeyHash([9u8; 32]), Some(vec![9]))], 0)?;
+    db.write_tree_update_batch(batch)?;
+
+    // Another handle keeps the database open.
+    let clone = db.clone();
+    let err = db.swap_in(&staged_path).err().unwrap();
+    assert!(err.to_string().contains("other handles"), "{err}");
+    drop(clone);
+    assert!(staged_path.is_dir());
+
+    let db = RocksDbTreeStore::new(&live_path)?;
+    let db = db.swap_in(&staged_path)?;
+    assert_eq!(db.latest_version()?, Some(2));
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    for (version, root) in staged_roots.iter().enumerate() {
+        assert_eq!(tree.get_root_hash(version as u64)?, *root);
+        assert_eq!(
+            tree.get(KeyHash([1u8; 32]), version as u64)?,
+            Some(vec![version as u8])
+        );
+    }
+    assert_eq!(tree.get(KeyHash([9u8; 32]), 0)?, None);
+
+    // The former store is left at the staged path.
+    let former = RocksDbTreeStore::new(&staged_path)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&former);
+    assert_eq!(tree.get_root_hash(0)?, live_root);
+    drop(former);
+
+    // A failed open swaps the stores back.
+    std::fs::remove_dir_all(&staged_path)?;
+    std::fs::create_dir(&staged_path)?;
+    std::fs::write(staged_path.join("CURRENT"), b"garbage")?;
+    assert!(db.swap_in(&staged_path).is_err());
+    assert!(staged_path.join("CURRENT").is_file());
+    let db = RocksDbTreeStore::new(&live_path)?;
+    assert_eq!(db.latest_version()?, Some(2));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_concurrent_readers_and_writer() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    const VERSIONS: u64 = 50;
+    const KEYS: u8 = 8;
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        proof_cache_capacity: Some(64),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let done = Arc::new(AtomicBool::new(false));
+    let reads = Arc::new(AtomicU64::new(0));
+
+    // Every version rewrites every key with the version, so a read mixing two versions shows.
+    let writer = {
+        let db = db.clone();
+        std::thread::spawn(move || -> anyhow::Result<()> {
+            let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+            for version in 0..VERSIONS {
+                let value_set =
+                    (0..KEYS).map(|key| (KeyHash([key; 32]), Some(version.to_be_bytes().to_vec())));
+                let (_, batch) = tree.put_value_set(value_set, version)?;
+                db.write_tree_update_batch(batch)?;
+            }
+            Ok(())
+        })
+    };
+    let readers: Vec<_> = (0..8)
+        .map(|reader| {
+            let db = db.clone();
+            let done = Arc::clone(&done);
+            let reads = Arc::clone(&reads);
+            std::thread::spawn(move || -> anyhow::Result<()> {
+                let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> =
+                    JellyfishMerkleTree::new(&db);
+                let mut last_seen = 0;
+                // Each reader reads at least a few times after the writer is done.
+                let mut reads_after_done = 0;
+                while reads_after_done < 3 {
+                    if done.load(Ordering::Relaxed) {
+                        reads_after_done += 1;
+                    }
+                    let Some(version) = db.latest_version()? else {
+                        continue;
+                    };
+                    assert!(version >= last_seen);
+                    last_seen = version;
+                    let root = tree.get_root_hash(version)?;
+                    let key = KeyHash([reader; 32]);
+                    let (value, proof) = db.get_with_proof(key, version)?;
+                    assert_eq!(value, Some(version.to_be_bytes().to_vec()));
+                    proof.verify(root, key, value.as_ref())?;
+                    for key in 0..KEYS {
+                        assert_eq!(
+                            tree.get(KeyHash([key; 32]), version)?,
+                            Some(version.to_be_bytes().to_vec())
+                        );
+                    }
+                    reads.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(())
+            })
+        })
+        .collect();
+
+    writer.join().unwrap()?;
+    done.store(true, Ordering::Relaxed);
+    for reader in readers {
+        reader.join().unwrap()?;
+    }
+    assert!(reads.load(Ordering::Relaxed) >= 8 * 3);
+    assert_eq!(db.latest_version()?, Some(VERSIONS - 1));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_get_with_proof_against() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RootMismatch;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let roots = write_versions(&db, 2)?;
+    let key = KeyHash([1u8; 32]);
+
+    let (value, proof) = db.get_with_proof_against(key, 1, roots[1])?;
+    assert_eq!(value, Some(vec![1]));
+    proof.verify(roots[1], key, value.as_ref())?;
+    let (value, _) = db.get_with_proof_against(KeyHash([3u8; 32]), 1, roots[1])?;
+    assert_eq!(value, None);
+
+    // A fork: the trusted root of version 1 is another one than the root of the store.
+    let fork = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&fork);
+    let (_, batch) = tree.put_value_set(vec![(key, Some(vec![0]))], 0)?;
+    fork.write_tree_update_batch(batch)?;
+    let (trusted_root, batch) = tree.put_value_set(vec![(key, Some(vec![0xaa]))], 1)?;
+    fork.write_tree_update_batch(batch)?;
+
+    let err = db
+        .get_with_proof_against(key, 1, trusted_root)
+        .err()
+        .unwrap();
+    let mismatch = err.downcast_ref::<RootMismatch>().unwrap();
+    assert_eq!(
+        *mismatch,
+        RootMismatch {
+            version: 1,
+            expected: trusted_root,
+            actual: roots[1],
+        }
+    );
+    // The root of another version does not match either.
+    let err = db.get_with_proof_against(key, 1, roots[0]).err().unwrap();
+    assert!(err.downcast_ref::<RootMismatch>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_iter_stale_nodes() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(db.iter_stale_nodes()?.count(), 0);
+
+    let mut expected = Vec::new();
+    for version in 0..3u8 {
+        let mut value_set = vec![(KeyHash([1u8; 32]), Some(vec![version]))];
+        if version == 0 {
+            value_set.push((KeyHash([2u8; 32]), Some(vec![0xff])));
+        }
+        let (_, batch) = tree.put_value_set(value_set, version.into())?;
+        expected.extend(
+            batch
+                .stale_node_index_batch
+                .iter()
+                .map(|index| (index.stale_since_version, index.node_key.clone())),
+        );
+        db.write_tree_update_batch(batch)?;
+    }
+    assert!(!expected.is_empty());
+
+    let stale_nodes = db.iter_stale_nodes()?.collect::<anyhow::Result<Vec<_>>>()?;
+    assert!(stale_nodes.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    let mut sorted = stale_nodes.clone();
+    sorted.sort();
+    expected.sort();
+    assert_eq!(sorted, expected);
+    // The root of version 0 is stale since version 1.
+    assert!(stale_nodes.contains(&(1, NodeKey::new_empty_path(0))));
+
+    // A prune reclaims the nodes stale since the versions it reaches, and only those.
+    db.prune(1)?;
+    let stale_nodes = db.iter_stale_nodes()?.collect::<anyhow::Result<Vec<_>>>()?;
+    let remaining: Vec<_> = expected
+        .iter()
+        .filter(|(version, _)| *version > 1)
+        .cloned()
+        .collect();
+    assert!(!remaining.is_empty());
+    let mut sorted = stale_nodes;
+    sorted.sort();
+    assert_eq!(sorted, remaining);
+
+    db.prune(2)?;
+    assert_eq!(db.iter_stale_nodes()?.count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_open_with_retry() -> anyhow::Result<()> {
+    use std::time::{Duration, Instant};
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("db");
+    let db = RocksDbTreeStore::open_with_retry(&path, 3, Duration::from_millis(10))?;
+    write_versions(&db, 1)?;
+
+    // The lock of the database is held until the first store is dropped, meanwhile the next
+    // attempts fail and are tried again.
+    let holder = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        drop(db);
+    });
+    let db = RocksDbTreeStore::open_with_retry(&path, 100, Duration::from_millis(20))?;
+    holder.join().unwrap();
+    assert_eq!(db.latest_version()?, Some(0));
+
+    // The attempts run out while the lock is held.
+    let start = Instant::now();
+    let err = RocksDbTreeStore::open_with_retry(&path, 3, Duration::from_millis(10))
+        .err()
+        .unwrap();
+    assert!(format!("{:#}", err).contains("attempt 3 of 3"));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    drop(db);
+
+    // A corrupted database fails on the first attempt.
+    std::fs::write(path.join("CURRENT"), b"garbage")?;
+    let start = Instant::now();
+    let err = RocksDbTreeStore::open_with_retry(&path, 5, Duration::from_secs(10))
+        .err()
+        .unwrap();
+    assert!(format!("{:#}", err).contains("attempt 1 of 5"));
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    assert!(RocksDbTreeStore::open_with_retry(&path, 0, Duration::ZERO).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_inline_values() -> anyhow::Result<()> {
+    use crate::rocksdb_store::{CfKind, RocksDbStoreConfig};
+    use crate::storage::{NibblePath, TreeReader};
+
+    let dir = tempfile::tempdir()?;
+    let config = RocksDbStoreConfig {
+        inline_values_up_to: Some(8),
+        ..RocksDbStoreConfig::default()
+    };
+    let inline_db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let db = RocksDbTreeStore::new_temporary()?;
+    let small_key = KeyHash([1u8; 32]);
+    let large_key = KeyHash([2u8; 32]);
+    let mut near_key = small_key;
+    near_key.0[31] = 0;
+    let value_sets = vec![
+        vec![
+            (small_key, Some(vec![1; 8])),
+            (large_key, Some(vec![2; 100])),
+        ],
+        // Moves the leaf of the small key down without changing its value.
+        vec![(near_key, Some(vec![3]))],
+        vec![(small_key, Some(vec![4; 4])), (large_key, None)],
+    ];
+    let mut roots = Vec::new();
+    for store in [&inline_db, &db] {
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(store);
+        let (store_roots, batch) = tree.put_value_sets(value_sets.clone(), 0)?;
+        store.write_tree_update_batch(batch)?;
+        roots.push(store_roots);
+    }
+    assert_eq!(roots[0], roots[1]);
+
+    // The leaves written with their small values, but neither the large value nor the moved leaf.
+    let inline_leaves = inline_db
+        .scan_strict(CfKind::Nodes)?
+        .filter(|item| {
+            item.as_ref().is_ok_and(|(_, record)| {
+                record[..2] == [FORMAT_VERSION, RecordKind::InlineLeaf as u8]
+            })
+        })
+        .count();
+    assert_eq!(inline_leaves, 3);
+
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&inline_db);
+    let expected: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    for version in 0..3 {
+        for key in [small_key, large_key] {
+            let (value, proof) = tree.get_with_proof(key, version)?;
+            let (expected_value, expected_proof) = expected.get_with_proof(key, version)?;
+            assert_eq!(value, expected_value);
+            assert!(proof == expected_proof);
+            proof.verify(roots[0][version as usize], key, value.as_ref())?;
+        }
+    }
+    let small_leaf = NodeKey::new(0, NibblePath::new(vec![0x01]));
+    let (_, value) = inline_db.get_node_with_inline_value(&small_leaf)?.unwrap();
+    assert_eq!(value, Some(vec![1; 8]));
+    let large_leaf = NodeKey::new(0, NibblePath::new(vec![0x02]));
+    let (_, value) = inline_db.get_node_with_inline_value(&large_leaf)?.unwrap();
+    assert_eq!(value, None);
+
+    // Leaves written with values inline stay readable without the option.
+    drop(inline_db);
+    let inline_db = RocksDbTreeStore::new(dir.path())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&inline_db);
+    assert_eq!(tree.get_with_proof(small_key, 2)?.0, Some(vec![4; 4]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_commit_optimistic() -> anyhow::Result<()> {
+    use crate::rocksdb_store::Conflict;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (_, batch) = tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![0]))], 0)?;
+    db.commit_optimistic(None, batch)?;
+
+    // Two writers compute version 1 from version 0.
+    let (first_root, first) = tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![1]))], 1)?;
+    let (_, second) = tree.put_value_set(vec![(KeyHash([2u8; 32]), Some(vec![2]))], 1)?;
+    db.commit_optimistic(Some(0), first)?;
+    let err = db.commit_optimistic(Some(0), second).err().unwrap();
+    assert_eq!(
+        *err.downcast_ref::<Conflict>().unwrap(),
+        Conflict {
+            base_version: Some(0),
+            latest_version: Some(1),
+        }
+    );
+    // The conflicting batch wrote nothing.
+    assert_eq!(tree.get_root_hash(1)?, first_root);
+    assert_eq!(tree.get(KeyHash([2u8; 32]), 1)?, None);
+
+    // Recomputed against the new latest version, the batch commits.
+    let (root, second) = tree.put_value_set(vec![(KeyHash([2u8; 32]), Some(vec![2]))], 2)?;
+    db.commit_optimistic(Some(1), second)?;
+    assert_eq!(tree.get_root_hash(2)?, root);
+    assert_eq!(tree.get(KeyHash([1u8; 32]), 2)?, Some(vec![1]));
+
+    // A batch computed against the empty tree conflicts with any written version.
+    let (_, batch) = tree.put_value_set(vec![(KeyHash([3u8; 32]), Some(vec![3]))], 3)?;
+    let err = db.commit_optimistic(None, batch).err().unwrap();
+    assert!(err.downcast_ref::<Conflict>().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_changes_since() -> anyhow::Result<()> {
+    use crate::storage::{NodeBatch, TreeUpdateBatch};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let backup = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let mut roots = Vec::new();
+    for version in 0..5u8 {
+        // The last key is deleted at every odd version.
+        let last_value = (version % 2 == 0).then(|| vec![version]);
+        let (root, batch) = tree.put_value_set(
+            vec![
+                (KeyHash([version; 32]), Some(vec![version])),
+                (KeyHash([0xff; 32]), last_value),
+            ],
+            version.into(),
+        )?;
+        if version <= 2 {
+            backup.write_tree_update_batch(batch.clone())?;
+        }
+        db.write_tree_update_batch(batch)?;
+        roots.push(root);
+    }
+
+    let (nodes, values) = db.changes_since(2)?;
+    assert!(nodes.iter().all(|(node_key, _)| node_key.version() > 2));
+    assert!(nodes
+        .iter()
+        .any(|(node_key, _)| *node_key == NodeKey::new_empty_path(4)));
+    assert_eq!(values.len(), 4);
+    assert!(values.contains(&((3, KeyHash([0xff; 32])), None)));
+
+    backup.write_tree_update_batch(TreeUpdateBatch {
+        node_batch: NodeBatch::new(nodes.into_iter().collect(), values.into_iter().collect()),
+        ..TreeUpdateBatch::default()
+    })?;
+    assert_eq!(backup.latest_version()?, Some(4));
+    let backup_tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> =
+        JellyfishMerkleTree::new(&backup);
+    for (version, root) in roots.into_iter().enumerate() {
+        let version = version as u64;
+        assert_eq!(backup_tree.get_root_hash(version)?, root);
+        for key in [KeyHash([version as u8; 32]), KeyHash([0xff; 32])] {
+            assert_eq!(backup_tree.get(key, version)?, tree.get(key, version)?);
+        }
+    }
+
+    let (nodes, values) = db.changes_since(4)?;
+    assert!(nodes.is_empty() && values.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_cached_root() -> anyhow::Result<()> {
+    use crate::{node_type::NodeKey, storage::TreeReader};
+
+    let dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::new(dir.path())?;
+    assert!(db.cached_root().is_none());
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let mut batches = Vec::new();
+    for version in 0..3u64 {
+        let key = KeyHash([version as u8; 32]);
+        let (_, batch) = tree.put_value_set(vec![(key, Some(vec![version as u8]))], version)?;
+        batches.push(batch);
+    }
+
+    // Each commit replaces the root with that of its version, as read back from the database.
+    for (version, batch) in batches[..2].iter().enumerate() {
+        db.write_tree_update_batch(batch.clone())?;
+        let root_key = NodeKey::new_empty_path(version as u64);
+        let (cached_key, cached_root) = db.cached_root().unwrap();
+        assert_eq!(cached_key, root_key);
+        assert_eq!(
+            cached_root,
+            batch.node_batch.get_node(&root_key).unwrap().clone()
+        );
+        assert_eq!(
+            db.clone().cached_root(),
+            Some((cached_key, cached_root.clone()))
+        );
+        let fresh: RocksDbTreeStore =
+            RocksDbTreeStore::open_read_only(dir.path(), Default::default())?;
+        assert_eq!(fresh.get_node(&root_key)?, cached_root);
+    }
+    let cached = db.cached_root();
+    let key = KeyHash([1; 32]);
+    let (value, proof) = tree.get_with_proof(key, 1)?;
+    proof.verify(tree.get_root_hash(1)?, key, value)?;
+
+    // A rejected write leaves the root of the version before it.
+    assert!(db.write_tree_update_batch(batches[0].clone()).is_err());
+    assert_eq!(db.cached_root(), cached);
+    assert!(db.commit_optimistic(Some(0), batches[2].clone()).is_err());
+    assert_eq!(db.cached_root(), cached);
+
+    // Reopening the store reads the root again.
+    drop(db);
+    let db = RocksDbTreeStore::new(dir.path())?;
+    assert_eq!(db.cached_root(), cached);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_flush_on_drop() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+
+    let dir = tempfile::TempDir::new()?;
+    let key = KeyHash([1u8; 32]);
+    let config = RocksDbStoreConfig {
+        flush_on_drop: true,
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config.clone())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (root, batch) = tree.put_value_set(vec![(key, Some(vec![1]))], 0)?;
+    db.write_tree_update_batch(batch)?;
+    // Dropping a clone leaves the flush to the last handle.
+    drop(db.clone());
+    drop(db);
+
+    let db = RocksDbTreeStore::with_config(dir.path(), config.clone())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(0)?, root);
+    assert_eq!(tree.get(key, 0)?, Some(vec![1]));
+    drop(db);
+
+    // A read-only store cannot flush: dropping it only logs the error, closing it returns it.
+    let read_only: RocksDbTreeStore = RocksDbTreeStore::open_read_only(dir.path(), config.clone())?;
+    drop(read_only);
+    let read_only: RocksDbTreeStore = RocksDbTreeStore::open_read_only(dir.path(), config)?;
+    assert!(read_only.close().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_compact_key_history() -> anyhow::Result<()> {
+    use crate::storage::TreeReader;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let hot = KeyHash([1u8; 32]);
+    let other = KeyHash([2u8; 32]);
+    let mut roots = Vec::new();
+    for version in 0..10u64 {
+        let mut value_set = vec![(hot, Some(vec![version as u8]))];
+        if version % 3 == 0 {
+            value_set.push((other, Some(vec![version as u8])));
+        }
+        let (root, batch) = tree.put_value_set(value_set, version)?;
+        db.write_tree_update_batch(batch)?;
+        roots.push(root);
+    }
+    db.set_label("checkpoint", 2)?;
+
+    // The entries read below version 6 go, but for that of the labeled version.
+    assert_eq!(db.compact_key_history(hot, 6)?, 5);
+    assert_eq!(db.value_versions(hot)?, vec![2, 6, 7, 8, 9]);
+    for version in 6..10u64 {
+        let (value, proof) = tree.get_with_proof(hot, version)?;
+        assert_eq!(value, Some(vec![version as u8]));
+        proof.verify(roots[version as usize], hot, value.as_ref())?;
+    }
+    assert_eq!(db.get_value_option(2, hot)?, Some(vec![2]));
+    assert_eq!(db.get_value_option(4, hot)?, Some(vec![2]));
+    assert_eq!(db.value_versions(other)?, vec![0, 3, 6, 9]);
+
+    // The entry read at the threshold itself stays, and compacting again removes nothing more.
+    assert_eq!(db.compact_key_history(hot, 7)?, 1);
+    assert_eq!(db.value_versions(hot)?, vec![2, 7, 8, 9]);
+    assert_eq!(db.get_value_option(7, hot)?, Some(vec![7]));
+    assert_eq!(db.compact_key_history(hot, 7)?, 0);
+
+    Ok(())
+}
+
+#[cfg(feature = "latency_histogram")]
+#[test]
+fn test_rocksdb_write_latency() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(db.metrics().write_latency, Default::default());
+
+    for version in 0..20u64 {
+        let value_set: Vec<_> = (0..50u8)
+            .map(|i| (KeyHash([i; 32]), Some(vec![i, version as u8])))
+            .collect();
+        let (_root, batch) = tree.put_value_set(value_set, version)?;
+        db.write_tree_update_batch(batch)?;
+    }
+
+    let latency = db.metrics().write_latency;
+    assert_eq!(latency.count, 20);
+    assert!(latency.p50_us > 0);
+    assert!(latency.p50_us <= latency.p90_us);
+    assert!(latency.p90_us <= latency.p99_us);
+    assert!(latency.p99_us <= latency.p999_us);
+    assert!(latency.p999_us <= latency.max_us);
+
+    // Clones share the histogram.
+    let clone = db.clone();
+    assert_eq!(clone.metrics().write_latency, latency);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_atomic_flush() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        atomic_flush: true,
+        ..Default::default()
+    };
+    let keys: Vec<_> = (0..20u8).map(|i| KeyHash([i; 32])).collect();
+    let mut roots = Vec::new();
+    let db = RocksDbTreeStore::with_config(dir.path(), config.clone())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    for version in 0..5u64 {
+        let value_set: Vec<_> = keys
+            .iter()
+            .map(|key| (*key, Some(vec![key.0[0], version as u8])))
+            .collect();
+        let (root, batch) = tree.put_value_set(value_set, version)?;
+        db.write_tree_update_batch(batch)?;
+        roots.push(root);
+        // The first versions reach the SST files, the last ones only the write-ahead log.
+        if version == 2 {
+            db.clone().close()?;
+        }
+    }
+    drop(db);
+
+    // RocksDB persists the options each database was opened with.
+    let options = std::fs::read_dir(dir.path())?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|path| path.to_string_lossy().contains("OPTIONS-"))
+        .map(std::fs::read_to_string)
+        .collect::<Result<String, _>>()?;
+    assert!(options.contains("atomic_flush=true"));
+
+    // The metadata, roots, nodes and values all stand at the latest version.
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(db.latest_version()?, Some(4));
+    let committed_versions: Vec<_> = db.committed_versions()?.collect::<anyhow::Result<_>>()?;
+    assert_eq!(committed_versions, vec![0, 1, 2, 3, 4]);
+    assert_eq!(tree.get_root_hash(4)?, roots[4]);
+    for key in &keys {
+        let (value, proof) = tree.get_with_proof(*key, 4)?;
+        assert_eq!(value, Some(vec![key.0[0], 4]));
+        proof.verify(roots[4], *key, value.as_ref())?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_absence_cache() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        absence_cache_capacity: Some(16),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let present = KeyHash([1u8; 32]);
+    let absent = KeyHash([2u8; 32]);
+    let (root, batch) = tree.put_value_set(vec![(present, Some(vec![1]))], 0)?;
+    db.write_tree_update_batch(batch)?;
+
+    // The second query for the absent key is served from the cache, the present key is not cached.
+    let first = db.get_with_proof(absent, 0)?;
+    let second = db.get_with_proof(absent, 0)?;
+    assert_eq!(second.0, None);
+    assert_eq!(first, second);
+    second.1.verify(root, absent, second.0.as_ref())?;
+    assert_eq!(db.get_with_proof(present, 0)?.0, Some(vec![1]));
+    let metrics = db.metrics();
+    assert_eq!(
+        (
+            metrics.absence_cache_hits,
+            metrics.absence_cache_misses,
+            metrics.absence_cache_entries
+        ),
+        (1, 2, 1)
+    );
+
+    // Committing a newer version drops it, even though the key stays absent.
+    let other = KeyHash([3u8; 32]);
+    let (_, batch) = tree.put_value_set(vec![(other, Some(vec![3]))], 1)?;
+    db.write_tree_update_batch(batch)?;
+    assert_eq!(db.metrics().absence_cache_entries, 0);
+    assert_eq!(db.get_with_proof(absent, 1)?.0, None);
+    assert_eq!(db.get_with_proof(absent, 1)?.0, None);
+    assert_eq!(db.metrics().absence_cache_hits, 2);
+    assert_eq!(db.metrics().absence_cache_entries, 1);
+
+    // Inserting the key drops its proofs of absence.
+    let (root, batch) = tree.put_value_set(vec![(absent, Some(vec![2]))], 2)?;
+    db.write_tree_update_batch(batch)?;
+    assert_eq!(db.metrics().absence_cache_entries, 0);
+    let (value, proof) = db.get_with_proof(absent, 2)?;
+    assert_eq!(value, Some(vec![2]));
+    proof.verify(root, absent, value.as_ref())?;
+    assert_eq!(db.get_with_proof(absent, 0)?, first);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_as_of() -> anyhow::Result<()> {
+    use crate::storage::TreeReader;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let updated = KeyHash([1u8; 32]);
+    let added = KeyHash([2u8; 32]);
+    let mut roots = Vec::new();
+    for version in 0..2u64 {
+        let (root, batch) =
+            tree.put_value_set(vec![(updated, Some(vec![version as u8]))], version)?;
+        db.write_tree_update_batch(batch)?;
+        roots.push(root);
+    }
+    let (_, batch) =
+        tree.put_value_set(vec![(updated, Some(vec![2])), (added, Some(vec![2]))], 2)?;
+    db.write_tree_update_batch(batch)?;
+
+    // Nothing written at version 2 is read through the view of version 1.
+    let view = db.as_of(1);
+    assert_eq!(view.get_value_option(2, updated)?, Some(vec![1]));
+    assert_eq!(view.get_value_option(u64::MAX, added)?, None);
+    assert_eq!(view.get_node_option(&NodeKey::new_empty_path(2))?, None);
+    let view_tree: JellyfishMerkleTree<_, Sha256> = JellyfishMerkleTree::new(&view);
+    assert!(view_tree.get_root_hash(2).is_err());
+    for version in 0..2u64 {
+        assert_eq!(view_tree.get_root_hash(version)?, roots[version as usize]);
+        let (value, proof) = view_tree.get_with_proof(updated, version)?;
+        assert_eq!(value, Some(vec![version as u8]));
+        proof.verify(roots[version as usize], updated, value.as_ref())?;
+    }
+
+    // The store itself still reads version 2.
+    assert_eq!(db.get_value_option(2, added)?, Some(vec![2]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_db_write_buffer_size() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        db_write_buffer_size: Some(64 * 1024),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config.clone())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    // The batches add up to many times the budget, which the memtables flush to stay within.
+    let mut roots = Vec::new();
+    for version in 0..20u64 {
+        let value_set: Vec<_> = (0..50u8)
+            .map(|i| (KeyHash([i; 32]), Some(vec![version as u8; 256])))
+            .collect();
+        let (root, batch) = tree.put_value_set(value_set, version)?;
+        db.write_tree_update_batch(batch)?;
+        roots.push(root);
+    }
+    for version in [0, 10, 19u64] {
+        for i in [0, 25, 49u8] {
+            let key = KeyHash([i; 32]);
+            let (value, proof) = tree.get_with_proof(key, version)?;
+            assert_eq!(value, Some(vec![version as u8; 256]));
+            proof.verify(roots[version as usize], key, value.as_ref())?;
+        }
+    }
+    drop(db);
+
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    assert_eq!(tree.get_root_hash(19)?, roots[19]);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_find_orphan_values() -> anyhow::Result<()> {
+    use crate::storage::{NodeBatch, TreeWriter};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let live = KeyHash([1u8; 32]);
+    let deleted = KeyHash([2u8; 32]);
+    let (_, batch) =
+        tree.put_value_set(vec![(live, Some(vec![1])), (deleted, Some(vec![2]))], 0)?;
+    db.write_tree_update_batch(batch)?;
+    let (_, batch) = tree.put_value_set(vec![(deleted, None)], 1)?;
+    db.write_tree_update_batch(batch)?;
+    assert!(db.find_orphan_values(0)?.is_empty());
+    assert!(db.find_orphan_values(1)?.is_empty());
+
+    // Values written without their leaves, for a key with no leaf and over the leaf of another.
+    let orphan = KeyHash([3u8; 32]);
+    let mut values_only = NodeBatch::default();
+    values_only.insert_value(1, orphan, vec![3]);
+    values_only.insert_value(1, live, vec![4]);
+    db.write_node_batch(&values_only)?;
+    assert_eq!(db.find_orphan_values(1)?, vec![(1, live), (1, orphan)]);
+    assert!(db.find_orphan_values(0)?.is_empty());
+    assert!(db.find_orphan_values(2).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_merge_value() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+    use crate::storage::TreeReader;
+    use std::sync::Arc;
+
+    fn counter(value: Option<&[u8]>) -> u64 {
+        value.map_or(0, |value| u64::from_le_bytes(value.try_into().unwrap()))
+    }
+    let dir = tempfile::TempDir::new()?;
+    let config = RocksDbStoreConfig {
+        value_merge_operator: Some(Arc::new(|value: Option<&[u8]>, deltas: &[&[u8]]| {
+            let sum = deltas
+                .iter()
+                .fold(counter(value), |sum, delta| sum + counter(Some(delta)));
+            sum.to_le_bytes().to_vec()
+        })),
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config.clone())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let key = KeyHash([1u8; 32]);
+    let fresh = KeyHash([2u8; 32]);
+    let (root, batch) = tree.put_value_set(vec![(key, Some(10u64.to_le_bytes().to_vec()))], 0)?;
+    db.write_tree_update_batch(batch)?;
+
+    // The deltas add up on top of the value written through the tree, or of no value.
+    for delta in [1u64, 2, 3] {
+        db.merge_value(key, 0, &delta.to_le_bytes())?;
+    }
+    db.merge_value(fresh, 0, &5u64.to_le_bytes())?;
+    assert_eq!(counter(db.get_value_option(0, key)?.as_deref()), 16);
+    assert_eq!(counter(db.get_value_option(3, key)?.as_deref()), 16);
+    assert_eq!(counter(db.get_value_option(0, fresh)?.as_deref()), 5);
+
+    // The leaf still commits to the value written through the tree.
+    let (value, proof) = tree.get_with_proof(key, 0)?;
+    assert!(proof.verify(root, key, value.as_ref()).is_err());
+    proof.verify(root, key, Some(10u64.to_le_bytes()))?;
+
+    // The merged values survive a reopen, and stores without an operator cannot merge.
+    drop(db);
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    assert_eq!(counter(db.get_value_option(0, key)?.as_deref()), 16);
+    let plain = RocksDbTreeStore::new_temporary()?;
+    assert!(plain.merge_value(key, 0, &1u64.to_le_bytes()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_export_leaves() -> anyhow::Result<()> {
+    use crate::mock::MockTreeStore;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    // More keys than an export page, some deleted or updated by later versions.
+    let keys: Vec<KeyHash> = (0..1500u32)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+    let (_, batch) =
+        tree.put_value_set(keys.iter().map(|key| (*key, Some(key.0[..4].to_vec()))), 0)?;
+    db.write_tree_update_batch(batch)?;
+    let (root, batch) = tree.put_value_set(
+        vec![(keys[0], None), (keys[1], Some(vec![1])), (keys[2], None)],
+        1,
+    )?;
+    db.write_tree_update_batch(batch)?;
+    db.put_key_preimage(keys[1], &1u32.to_be_bytes())?;
+    let (_, batch) = tree.put_value_set(vec![(keys[3], None)], 2)?;
+    db.write_tree_update_batch(batch)?;
+
+    let exported = db.export_leaves(1).collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(exported.len(), keys.len() - 2);
+    assert!(exported.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    for (key_hash, value, preimage) in &exported {
+        assert_eq!(tree.get(*key_hash, 1)?.as_ref(), Some(value));
+        let expected = (*key_hash == keys[1]).then(|| 1u32.to_be_bytes().to_vec());
+        assert_eq!(preimage, &expected);
+    }
+
+    // Rebuilding a fresh tree from the export reproduces the root of the version.
+    let rebuilt = MockTreeStore::default();
+    let rebuilt_tree: JellyfishMerkleTree<MockTreeStore, Sha256> =
+        JellyfishMerkleTree::new(&rebuilt);
+    let (rebuilt_root, _) = rebuilt_tree.put_value_set(
+        exported
+            .into_iter()
+            .map(|(key_hash, value, _)| (key_hash, Some(value))),
+        0,
+    )?;
+    assert_eq!(rebuilt_root, root);
+
+    let mut missing = db.export_leaves(3);
+    assert!(missing.next().unwrap().is_err());
+    assert!(missing.next().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_node_cache_policy() -> anyhow::Result<()> {
+    use crate::rocksdb_store::{NodeCachePolicy, RocksDbStoreConfig};
+    use crate::storage::TreeReader;
+
+    let dir = tempfile::TempDir::new()?;
+    let hot = 4u64;
+    let rounds = 10u64;
+    let capacity = 8;
+    {
+        let db = RocksDbTreeStore::with_config(dir.path(), RocksDbStoreConfig::default())?;
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+        for version in 0..hot + rounds * capacity as u64 {
+            let key = KeyHash([version as u8; 32]);
+            let (_, batch) = tree.put_value_set(vec![(key, Some(vec![1]))], version)?;
+            db.write_tree_update_batch(batch)?;
+        }
+    }
+
+    // Each round reads the hot roots twice, then as many cold roots as the cache holds, each once.
+    let mut hits = Vec::new();
+    for policy in [NodeCachePolicy::Lru, NodeCachePolicy::Lfu] {
+        let config = RocksDbStoreConfig {
+            node_cache_capacity: Some(capacity),
+            node_cache_policy: policy,
+            ..Default::default()
+        };
+        let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+        for round in 0..rounds {
+            for _ in 0..2 {
+                for version in 0..hot {
+                    let root_key = NodeKey::new_empty_path(version);
+                    assert!(db.get_node_option(&root_key)?.is_some());
+                }
+            }
+            for cold in 0..capacity as u64 {
+                let root_key = NodeKey::new_empty_path(hot + round * capacity as u64 + cold);
+                assert!(db.get_node_option(&root_key)?.is_some());
+            }
+        }
+        let metrics = db.metrics();
+        assert_eq!(metrics.node_cache_entries, capacity);
+        hits.push(metrics.node_cache_hits);
+    }
+
+    // LRU drops the hot roots during every scan, so only their second reads hit, while LFU keeps
+    // them cached from their second reads on.
+    assert_eq!(hits, vec![rounds * hot, hot + (rounds - 1) * 2 * hot]);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_on_corruption() -> anyhow::Result<()> {
+    use crate::rocksdb_store::{CfKind, CorruptionCause, CorruptionEvent};
+    use std::sync::{Arc, Mutex};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (_, batch) = tree.put_value_set(
+        vec![
+            (KeyHash([0x10; 32]), Some(vec![1])),
+            (KeyHash([0x20; 32]), Some(vec![2])),
+        ],
+        0,
+    )?;
+    db.write_tree_update_batch(batch)?;
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&events);
+    db.on_corruption(move |event| recorded.lock().unwrap().push(event.clone()));
+
+    // Reads of sound records and of versions that do not exist are not corruptions.
+    assert_eq!(tree.get(KeyHash([0x20; 32]), 0)?, Some(vec![2]));
+    assert!(tree.get_with_proof(KeyHash([0x20; 32]), 1).is_err());
+    assert!(events.lock().unwrap().is_empty());
+
+    // Overwrite the leaf under nibble 2 of the root with a record that does not decode.
+    let child_key = NodeKey::new_empty_path(0).gen_child_node_key(0, 2u8.into());
+    let raw_child_key = raw_record(RecordKind::NodeKey, &child_key)?;
+    let nodes_cf = db.db().cf_handle("nodes").unwrap();
+    db.db().put_cf(
+        nodes_cf,
+        &raw_child_key,
+        [FORMAT_VERSION, RecordKind::Node as u8, 0xff],
+    )?;
+    assert!(tree.get_with_proof(KeyHash([0x20; 32]), 0).is_err());
+    {
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].column_family, CfKind::Nodes);
+        assert_eq!(events[0].key, raw_child_key);
+        assert!(matches!(
+            events[0].cause,
+            CorruptionCause::Undecodable { .. }
+        ));
+    }
+
+    // A child its parent references but that is gone is reported too.
+    db.db().delete_cf(nodes_cf, &raw_child_key)?;
+    assert!(tree.get_with_proof(KeyHash([0x20; 32]), 0).is_err());
+    assert_eq!(
+        events.lock().unwrap().last(),
+        Some(&CorruptionEvent {
+            column_family: CfKind::Nodes,
+            key: raw_child_key,
+            cause: CorruptionCause::MissingChild {
+                node_key: child_key,
+            },
+        })
+    );
+    assert_eq!(events.lock().unwrap().len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_import_snapshot_deduped() -> anyhow::Result<()> {
+    use crate::node_type::{Child, Children, InternalNode, LeafNode, Node, NodeType};
+    use crate::storage::TreeReader;
+    use rocksdb::IteratorMode;
+
+    // A snapshot of a root with the same subtree of two leaves under each of its 16 nibbles,
+    // written record by record in the snapshot format.
+    let leaves: Vec<_> = (1u8..=2)
+        .map(|nibble| {
+            let key_hash = KeyHash([0x10 + nibble; 32]);
+            let value = vec![nibble];
+            let leaf = LeafNode::new(key_hash, crate::ValueHash::with::<Sha256>(&value));
+            (nibble, key_hash, value, Node::Leaf(leaf))
+        })
+        .collect();
+    let mut subtree_children = Children::new();
+    for (nibble, _, _, leaf) in &leaves {
+        let child = Child::new(leaf.hash::<Sha256>(), 0, NodeType::Leaf);
+        subtree_children.insert((*nibble).into(), child);
+    }
+    let subtree = Node::Internal(InternalNode::new(subtree_children));
+    let subtree_hash = subtree.hash::<Sha256>();
+    let mut root_children = Children::new();
+    for nibble in 0u8..16 {
+        let child = Child::new(subtree_hash, 0, NodeType::Internal { leaf_count: 2 });
+        root_children.insert(nibble.into(), child);
+    }
+    let root = Node::Internal(InternalNode::new(root_children));
+
+    let mut snapshot = b"JMTSNAP\0".to_vec();
+    snapshot.extend(1u32.to_be_bytes());
+    snapshot.extend(0u64.to_be_bytes());
+    let mut push = |tag: u8, payload: Vec<u8>| {
+        snapshot.push(tag);
+        snapshot.extend((payload.len() as u32).to_be_bytes());
+        snapshot.extend(payload);
+    };
+    let root_key = NodeKey::new_empty_path(0);
+    push(1, bincode::serialize(&(&root_key, &root))?);
+    for nibble in 0u8..16 {
+        let subtree_key = root_key.gen_child_node_key(0, nibble.into());
+        push(1, bincode::serialize(&(&subtree_key, &subtree))?);
+        for (nibble, _, _, leaf) in &leaves {
+            let leaf_key = subtree_key.gen_child_node_key(0, (*nibble).into());
+            push(1, bincode::serialize(&(leaf_key, leaf))?);
+        }
+    }
+    for (_, key_hash, value, _) in &leaves {
+        push(2, bincode::serialize(&(key_hash, value))?);
+    }
+    push(0, bincode::serialize(&(1 + 16 * 3u64, 2u64))?);
+
+    let plain = RocksDbTreeStore::new_temporary()?;
+    plain.import_snapshot(snapshot.as_slice())?;
+    let deduped = RocksDbTreeStore::new_temporary()?;
+    deduped.import_snapshot_deduped(snapshot.as_slice())?;
+
+    // The 16 subtrees share the contents of their three nodes.
+    let node_bytes = |db: &RocksDbTreeStore| {
+        ["nodes", "node_contents"]
+            .into_iter()
+            .flat_map(|name| {
+                let cf = db.db().cf_handle(name).unwrap();
+                db.db().iterator_cf(cf, IteratorMode::Start)
+            })
+            .map(|entry| entry.map(|(key, value)| key.len() + value.len()))
+            .sum::<Result<usize, _>>()
+    };
+    assert!(node_bytes(&deduped)? < node_bytes(&plain)?);
+    let contents_cf = deduped.db().cf_handle("node_contents").unwrap();
+    assert_eq!(
+        deduped
+            .db()
+            .iterator_cf(contents_cf, IteratorMode::Start)
+            .count(),
+        4
+    );
+
+    // Both stores hold the same tree under the same root hash.
+    let root_hash = crate::RootHash(root.hash::<Sha256>());
+    for db in [&plain, &deduped] {
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(db);
+        assert_eq!(tree.get_root_hash(0)?, root_hash);
+        assert_eq!(db.latest_version()?, Some(0));
+        for (_, key_hash, value, _) in &leaves {
+            let (found, proof) = tree.get_with_proof(*key_hash, 0)?;
+            assert_eq!(found.as_ref(), Some(value));
+            proof.verify(root_hash, *key_hash, found.as_ref())?;
+        }
+    }
+    let leaf_key = root_key
+        .gen_child_node_key(0, 7u8.into())
+        .gen_child_node_key(0, 2u8.into());
+    assert_eq!(deduped.get_node(&leaf_key)?, plain.get_node(&leaf_key)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_version_metadata() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let key = KeyHash([1u8; 32]);
+
+    // Metadata set ahead of its version is only stored once the version is committed.
+    for version in 0..3u64 {
+        db.set_version_metadata(version, format!("block {}", version))?;
+        assert_eq!(db.get_version_metadata(version)?, None);
+        let (_, batch) = tree.put_value_set(vec![(key, Some(vec![version as u8]))], version)?;
+        db.write_tree_update_batch(batch)?;
+        let metadata = format!("block {}", version).into_bytes();
+        assert_eq!(db.get_version_metadata(version)?, Some(metadata));
+    }
+    assert_eq!(db.get_version_metadata(3)?, None);
+
+    // Metadata of a committed version is replaced right away.
+    db.set_version_metadata(2, b"block 2, amended".to_vec())?;
+    assert_eq!(
+        db.get_version_metadata(2)?,
+        Some(b"block 2, amended".to_vec())
+    );
+
+    // Pruning removes the metadata of the versions it removes, but not of labeled ones.
+    db.set_label("checkpoint", 0)?;
+    db.prune(2)?;
+    assert_eq!(db.get_version_metadata(0)?, Some(b"block 0".to_vec()));
+    assert_eq!(db.get_version_metadata(1)?, None);
+    assert!(db.get_version_metadata(2)?.is_some());
+    db.remove_label("checkpoint")?;
+    db.prune(2)?;
+    assert_eq!(db.get_version_metadata(0)?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_put_one() -> anyhow::Result<()> {
+    let fast = RocksDbTreeStore::new_temporary()?;
+    let general = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&general);
+    let updates = [
+        (KeyHash([1u8; 32]), Some(b"one".to_vec())),
+        (KeyHash([2u8; 32]), Some(b"two".to_vec())),
+        (KeyHash([1u8; 32]), Some(b"uno".to_vec())),
+        (KeyHash([2u8; 32]), None),
+        (KeyHash([3u8; 32]), Some(Vec::new())),
+    ];
+    for (version, (key, value)) in updates.into_iter().enumerate() {
+        let version = version as u64;
+        let (root_hash, batch) = tree.put_value_set(vec![(key, value.clone())], version)?;
+        general.write_tree_update_batch(batch)?;
+        assert_eq!(fast.put_one(key, value, version)?, root_hash);
+        assert_eq!(fast.latest_version()?, Some(version));
+    }
+
+    let fast_tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&fast);
+    assert_eq!(fast_tree.get(KeyHash([1u8; 32]), 4)?, Some(b"uno".to_vec()));
+    assert_eq!(fast_tree.get(KeyHash([2u8; 32]), 4)?, None);
+    assert_eq!(fast_tree.get(KeyHash([2u8; 32]), 1)?, Some(b"two".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_write_raw_batch() -> anyhow::Result<()> {
+    let source = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&source);
+    let keys: Vec<KeyHash> = (0..20u8)
+        .map(|i| KeyHash([i.wrapping_mul(37); 32]))
+        .collect();
+    for version in 0..2u64 {
+        let value_set = keys
+            .iter()
+            .map(|key| (*key, Some(vec![key.0[0], version as u8])));
+        let (_, batch) = tree.put_value_set(value_set, version)?;
+        source.write_tree_update_batch(batch)?;
+    }
+    let raw_entries = |cf: &str| -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let cf = source.db().cf_handle(cf).unwrap();
+        source
+            .db()
+            .iterator_cf(cf, rocksdb::IteratorMode::Start)
+            .map(|item| Ok(item.map(|(key, value)| (key.to_vec(), value.to_vec()))?))
+            .collect()
+    };
+
+    // The raw entries of the source read back as its tree.
+    let restored = RocksDbTreeStore::new_temporary()?;
+    restored.write_raw_batch(raw_entries("nodes")?, raw_entries("values")?)?;
+    assert_eq!(restored.latest_version()?, Some(1));
+    let restored_tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> =
+        JellyfishMerkleTree::new(&restored);
+    for version in 0..2u64 {
+        let root_hash = tree.get_root_hash(version)?;
+        assert_eq!(restored_tree.get_root_hash(version)?, root_hash);
+        for key in &keys {
+            let (value, proof) = restored_tree.get_with_proof(*key, version)?;
+            assert_eq!(value, Some(vec![key.0[0], version as u8]));
+            proof.verify(root_hash, *key, value.as_ref())?;
+        }
+    }
+    assert!(restored.verify_consistency(1)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_all_key_hashes() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let keys: Vec<KeyHash> = [9u8, 3, 7, 1, 5].map(|i| KeyHash([i; 32])).to_vec();
+    let (_, batch) = tree.put_value_set(keys.iter().map(|key| (*key, Some(vec![1]))), 0)?;
+    db.write_tree_update_batch(batch)?;
+    let (_, batch) = tree.put_value_set(
+        vec![(keys[0], Some(vec![2])), (keys[1], None), (keys[2], None)],
+        1,
+    )?;
+    db.write_tree_update_batch(batch)?;
+    let (_, batch) = tree.put_value_set(vec![(keys[1], Some(vec![3]))], 2)?;
+    db.write_tree_update_batch(batch)?;
+
+    // Every key is listed once in order, including those deleted, whatever their entries.
+    let mut expected = keys.clone();
+    expected.sort();
+    let listed = db.all_key_hashes()?.collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(listed, expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_statistics() -> anyhow::Result<()> {
+    let dir = tempfile::TempDir::new()?;
+    let config = crate::rocksdb_store::RocksDbStoreConfig {
+        enable_statistics: true,
+        ..Default::default()
+    };
+    let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let keys_written = |statistics: &str| -> u64 {
+        let line = statistics
+            .lines()
+            .find(|line| line.starts_with("rocksdb.number.keys.written "))
+            .unwrap();
+        line.rsplit(' ').next().unwrap().parse().unwrap()
+    };
+
+    // Opening wrote the fingerprint of the hasher, which the reset drops.
+    db.reset_statistics();
+    let (_, batch) = tree.put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![1]))], 0)?;
+    db.write_tree_update_batch(batch)?;
+    let statistics = db.take_statistics().unwrap();
+    assert!(statistics.contains("rocksdb.db.write.micros"));
+    assert!(keys_written(&statistics) > 0);
+
+    // Each take only counts what happened since the previous one.
+    assert_eq!(keys_written(&db.take_statistics().unwrap()), 0);
+    let (_, batch) = tree.put_value_set(vec![(KeyHash([2u8; 32]), Some(vec![2]))], 1)?;
+    db.write_tree_update_batch(batch)?;
+    assert!(keys_written(&db.clone().take_statistics().unwrap()) > 0);
+
+    // A store opened without statistics has none.
+    let plain = RocksDbTreeStore::new_temporary()?;
+    let (_, batch) = JellyfishMerkleTree::<_, Sha256>::new(&plain)
+        .put_value_set(vec![(KeyHash([1u8; 32]), Some(vec![1]))], 0)?;
+    plain.write_tree_update_batch(batch)?;
+    assert_eq!(plain.take_statistics(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_migrate_layout() -> anyhow::Result<()> {
+    use crate::storage::HasPreimage;
+
+    let dir = tempfile::TempDir::new()?;
+    // The trees are computed against a store in the current layout, and written to the default
+    // column family of the migrated one as the legacy code wrote them.
+    let reference = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&reference);
+    let keys: Vec<KeyHash> = (0..10u8).map(|i| KeyHash::with::<Sha256>([i])).collect();
+    {
+        let legacy = rocksdb::DB::open_default(dir.path())?;
+        for version in 0..3u64 {
+            let value_set = keys.iter().enumerate().map(|(i, key)| {
+                let value = (i as u64 != version).then(|| vec![i as u8, version as u8]);
+                (*key, value)
+            });
+            let (_, batch) = tree.put_value_set(value_set, version)?;
+            for (node_key, node) in batch.node_batch.nodes() {
+                legacy.put(bincode::serialize(node_key)?, bincode::serialize(node)?)?;
+            }
+            for ((version, key_hash), value) in batch.node_batch.values() {
+                let key = bincode::serialize(&(*key_hash, *version))?;
+                legacy.put(key, bincode::serialize(value)?)?;
+            }
+            reference.write_tree_update_batch(batch)?;
+        }
+        let preimage_key = bincode::serialize(&(keys[0], "preimage"))?;
+        legacy.put(preimage_key, [0u8])?;
+        legacy.put(b"unrelated", b"data")?;
+    }
+
+    <RocksDbTreeStore>::migrate_layout(dir.path())?;
+    let db = RocksDbTreeStore::new(dir.path())?;
+    assert_eq!(db.latest_version()?, Some(2));
+    assert_eq!(db.committed_versions()?.count(), 3);
+    let migrated: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    for version in 0..3u64 {
+        let root_hash = tree.get_root_hash(version)?;
+        assert_eq!(migrated.get_root_hash(version)?, root_hash);
+        for (i, key) in keys.iter().enumerate() {
+            let (value, proof) = migrated.get_with_proof(*key, version)?;
+            assert_eq!(value, tree.get(*key, version)?);
+            assert_eq!(value.is_none(), i as u64 == version);
+            proof.verify(root_hash, *key, value.as_ref())?;
+        }
+    }
+    assert_eq!(db.preimage(keys[0])?, Some(vec![0]));
+    assert!(db.verify_consistency(2)?.is_empty());
+
+    // Only the entry that is none of the legacy ones is left in the default column family.
+    let left: Vec<_> = db
+        .db()
+        .iterator(rocksdb::IteratorMode::Start)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(left.len(), 1);
+    assert_eq!(&*left[0].0, b"unrelated");
+    drop(db);
+    <RocksDbTreeStore>::migrate_layout(dir.path())?;
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_proof_batcher() -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let keys: Vec<KeyHash> = (0..8u8).map(|i| KeyHash([i; 32])).collect();
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (root_hash, batch) =
+        tree.put_value_set(keys.iter().map(|key| (*key, Some(vec![key.0[0]]))), 0)?;
+    db.write_tree_update_batch(batch)?;
+
+    // Within a window that never ends, the requests queued are only served once the batcher is
+    // closed, all of them in the same batch.
+    let batcher = db.spawn_proof_batcher(Duration::from_secs(3600))?;
+    let receivers = keys
+        .iter()
+        .map(|key| batcher.request(*key, 0))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    for receiver in &receivers {
+        assert!(receiver.try_recv().is_err());
+    }
+    batcher.close()?;
+    for (key, receiver) in keys.iter().zip(receivers) {
+        let (value, proof) = receiver.recv()??;
+        assert_eq!(value, Some(vec![key.0[0]]));
+        proof.verify(root_hash, *key, value.as_ref())?;
+    }
+
+    // Each pass is counted by the time its requests are answered.
+    let batcher = db.spawn_proof_batcher(Duration::ZERO)?;
+    std::thread::scope(|scope| {
+        let lookups: Vec<_> = keys
+            .iter()
+            .map(|key| scope.spawn(|| batcher.get_with_proof(*key, 0)))
+            .collect();
+        for (key, lookup) in keys.iter().zip(lookups) {
+            let (value, proof) = lookup.join().unwrap()?;
+            assert_eq!(value, Some(vec![key.0[0]]));
+            proof.verify(root_hash, *key, value.as_ref())?;
+        }
+        anyhow::Ok(())
+    })?;
+    let passes = batcher.passes();
+    assert!((1..=keys.len() as u64).contains(&passes));
+
+    // A version that is not readable fails every request of its batch.
+    assert!(batcher.get_with_proof(keys[0], 1).is_err());
+    assert_eq!(batcher.passes(), passes + 1);
+    batcher.close()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_periodic_compaction() -> anyhow::Result<()> {
+    use crate::rocksdb_store::{CfKind, RocksDbStoreConfig};
+    use std::time::Duration;
+
+    let dir = tempfile::TempDir::new()?;
+    let config = || RocksDbStoreConfig {
+        periodic_compaction: Some(Duration::from_secs(24 * 3600)),
+        ..Default::default()
+    };
+    let key = KeyHash([1; 32]);
+    let root_hash = {
+        let db = RocksDbTreeStore::with_config(dir.path(), config())?;
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+        let (root_hash, batch) = tree.put_value_set(vec![(key, Some(vec![1]))], 0)?;
+        db.write_tree_update_batch(batch)?;
+        db.compact_cf(CfKind::Nodes, None, None)?;
+        root_hash
+    };
+    // RocksDB records the options set on the column families in its latest options file.
+    let options_file = std::fs::read_dir(dir.path())?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|name| name.starts_with("OPTIONS-"))
+        .max()
+        .unwrap();
+    let options = std::fs::read_to_string(dir.path().join(options_file))?;
+    assert!(options.contains("periodic_compaction_seconds=86400"));
+
+    let db = RocksDbTreeStore::with_config(dir.path(), config())?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let (value, proof) = tree.get_with_proof(key, 0)?;
+    assert_eq!(value, Some(vec![1]));
+    proof.verify(root_hash, key, value.as_ref())?;
+    let (_, batch) = tree.put_value_set(vec![(key, None)], 1)?;
+    db.write_tree_update_batch(batch)?;
+    assert_eq!(tree.get(key, 1)?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_compute_root() -> anyhow::Result<()> {
+    use crate::{compute_root, OwnedValue};
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let entries: Vec<(KeyHash, OwnedValue)> = (0..16u8)
+        .map(|i| (KeyHash([i.wrapping_mul(37); 32]), vec![i]))
+        .collect();
+    let value_set = entries
+        .iter()
+        .map(|(key, value)| (*key, Some(value.clone())));
+    let (root_hash, batch) = tree.put_value_set(value_set, 0)?;
+    db.write_tree_update_batch(batch)?;
+    assert_eq!(compute_root::<Sha256>(&entries), root_hash);
+    assert_eq!(compute_root::<Sha256>(&entries), tree.get_root_hash(0)?);
+
+    // The last entry of a key wins.
+    let mut repeated = entries.clone();
+    repeated.insert(0, (entries[3].0, vec![0xff]));
+    assert_eq!(compute_root::<Sha256>(&repeated), root_hash);
+
+    let (empty_root, _) = tree.put_value_set(vec![], 0)?;
+    assert_eq!(compute_root::<Sha256>(&[]), empty_root);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_export_partial_preimages() -> anyhow::Result<()> {
+    use crate::MissingPreimageError;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let preimages: Vec<[u8; 4]> = (0..8u32).map(u32::to_be_bytes).collect();
+    let keys: Vec<KeyHash> = preimages.iter().map(KeyHash::with::<Sha256>).collect();
+    let (root_hash, batch) =
+        tree.put_value_set(keys.iter().map(|key| (*key, Some(key.0[..4].to_vec()))), 0)?;
+    db.write_tree_update_batch(batch)?;
+    // Only the even keys have their preimage recorded.
+    for (key, preimage) in keys.iter().zip(&preimages).step_by(2) {
+        db.put_key_preimage(*key, preimage)?;
+    }
+    let expected_preimage = |key_hash: KeyHash| {
+        let i = keys.iter().position(|key| *key == key_hash).unwrap();
+        (i % 2 == 0).then(|| preimages[i].to_vec())
+    };
+    let is_missing_preimage = |err: &anyhow::Error, key_hash: KeyHash| {
+        err.downcast_ref::<MissingPreimageError>()
+            .is_some_and(|err| err.key_hash == key_hash)
+    };
+
+    // By default, the keys without a preimage are exported without one.
+    let exported = db.export_leaves(0).collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(exported.len(), keys.len());
+    for (key_hash, _, preimage) in &exported {
+        assert_eq!(*preimage, expected_preimage(*key_hash));
+    }
+    let proven = tree
+        .iter_with_proofs_and_preimages(0, KeyHash([0; 32]), KeyHash([0xff; 32]))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(proven.len(), keys.len());
+    for (key_hash, value, preimage, proof) in &proven {
+        assert_eq!(*preimage, expected_preimage(*key_hash));
+        proof.verify(root_hash, *key_hash, Some(value))?;
+    }
+
+    // The strict variants fail for each of them, and export the others.
+    let strict: Vec<_> = db.export_leaves_strict(0).collect();
+    assert_eq!(strict.len(), keys.len());
+    for ((key_hash, _, preimage), leaf) in exported.iter().zip(&strict) {
+        match preimage {
+            Some(_) => assert_eq!(leaf.as_ref().unwrap().2, *preimage),
+            None => assert!(is_missing_preimage(leaf.as_ref().unwrap_err(), *key_hash)),
+        }
+    }
+    let strict: Vec<_> = tree
+        .iter_with_proofs_and_preimages_strict(0, KeyHash([0; 32]), KeyHash([0xff; 32]))
+        .collect();
+    assert_eq!(strict.len(), keys.len());
+    for ((key_hash, _, preimage, _), leaf) in proven.iter().zip(&strict) {
+        match preimage {
+            Some(_) => assert_eq!(leaf.as_ref().unwrap().2, *preimage),
+            None => assert!(is_missing_preimage(leaf.as_ref().unwrap_err(), *key_hash)),
+        }
+    }
+
+    // Once every preimage is recorded, the strict variants export everything.
+    for (key, preimage) in keys.iter().zip(&preimages).skip(1).step_by(2) {
+        db.put_key_preimage(*key, preimage)?;
+    }
+    let strict = db
+        .export_leaves_strict(0)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(strict.len(), keys.len());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_version_of_root() -> anyhow::Result<()> {
+    use crate::RootHash;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let mut roots = Vec::new();
+    for version in 0..3u64 {
+        let value_set = vec![(KeyHash([version as u8; 32]), Some(vec![version as u8]))];
+        let (root_hash, batch) = tree.put_value_set(value_set, version)?;
+        db.write_tree_update_batch(batch)?;
+        roots.push(root_hash);
+    }
+    // Writing a key again with the value it has leaves the root as it was.
+    let (root_hash, batch) = tree.put_value_set(vec![(KeyHash([2; 32]), Some(vec![2]))], 3)?;
+    db.write_tree_update_batch(batch)?;
+    assert_eq!(root_hash, roots[2]);
+
+    for (version, root_hash) in roots.iter().enumerate() {
+        assert_eq!(db.version_of_root(*root_hash)?, Some(version as u64));
+    }
+    assert_eq!(db.version_of_root(RootHash([7; 32]))?, None);
+
+    // Once the earliest version with a root is pruned, the next one with it is found.
+    db.prune(3)?;
+    assert_eq!(db.version_of_root(roots[2])?, Some(3));
+    assert_eq!(db.version_of_root(roots[0])?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_bounded_staleness_reader() -> anyhow::Result<()> {
+    use crate::rocksdb_store::{BoundedStalenessReader, RocksDbStoreConfig};
+    use std::time::Duration;
+
+    let primary_dir = tempfile::TempDir::new()?;
+    let secondary_dir = tempfile::TempDir::new()?;
+    let primary = RocksDbTreeStore::new(primary_dir.path())?;
+    let primary_tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> =
+        JellyfishMerkleTree::new(&primary);
+    let (_, batch) = primary_tree.put_value_set(vec![(KeyHash([1; 32]), Some(vec![1]))], 0)?;
+    primary.write_tree_update_batch(batch)?;
+
+    let secondary = <RocksDbTreeStore>::open_as_secondary(
+        primary_dir.path(),
+        secondary_dir.path(),
+        RocksDbStoreConfig::default(),
+    )?;
+    let max_staleness = Duration::from_millis(300);
+    let reader = BoundedStalenessReader::new(secondary, max_staleness);
+    let tree: JellyfishMerkleTree<BoundedStalenessReader, Sha256> =
+        JellyfishMerkleTree::new(&reader);
+    assert_eq!(tree.get(KeyHash([1; 32]), 0)?, Some(vec![1]));
+
+    let (root_hash, batch) =
+        primary_tree.put_value_set(vec![(KeyHash([2; 32]), Some(vec![2]))], 1)?;
+    primary.write_tree_update_batch(batch)?;
+    // Within the bound, the reads are served without catching up.
+    assert_eq!(reader.latest_version()?, Some(0));
+    assert!(!reader.inner().version_exists(1)?);
+
+    // Past it, the next read catches up and sees the new version.
+    std::thread::sleep(max_staleness + Duration::from_millis(100));
+    let (value, proof) = tree.get_with_proof(KeyHash([2; 32]), 1)?;
+    assert_eq!(value, Some(vec![2]));
+    proof.verify(root_hash, KeyHash([2; 32]), value.as_ref())?;
+    assert_eq!(reader.latest_version()?, Some(1));
+    assert!(!reader.catch_up_if_stale()?);
+
+    // Secondaries reject writes.
+    let (_, batch) = tree.put_value_set(vec![(KeyHash([3; 32]), Some(vec![3]))], 2)?;
+    assert!(reader.inner().write_tree_update_batch(batch).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_compact_tombstones_sealed() -> anyhow::Result<()> {
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let key = KeyHash([1u8; 32]);
+    let value_sets = [
+        vec![(key, Some(vec![0]))],
+        vec![],
+        vec![(key, None)],
+        vec![],
+    ];
+    for (version, value_set) in value_sets.into_iter().enumerate() {
+        let (_root, batch) = tree.put_value_set(value_set, version as u64)?;
+        db.write_tree_update_batch(batch)?;
+    }
+    db.seal_version(1)?;
+
+    // Compacting the deletion at version 2 would change what the sealed version 1 reads.
+    assert!(db.compact_tombstones(3).is_err());
+    assert_eq!(db.value_versions(key)?, vec![0, 2]);
+    assert_eq!(tree.get(key, 1)?, Some(vec![0]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_write_tree_update_batch_rejects_mixed_versions() -> anyhow::Result<()> {
+    use crate::storage::TreeReader;
+
+    let db = RocksDbTreeStore::new_temporary()?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let key = KeyHash([1u8; 32]);
+    let (_root, batch) = tree.put_value_set(vec![(key, Some(vec![0]))], 0)?;
+
+    // A value of version 2 slipped into the batch of version 0.
+    let mut mixed = batch.clone();
+    mixed
+        .node_batch
+        .insert_value(2, KeyHash([2u8; 32]), vec![2]);
+    let err = db.write_tree_update_batch(mixed.clone()).unwrap_err();
+    assert!(err.to_string().contains("not contiguous"), "{err}");
+    let err = db.commit_versions(vec![mixed]).unwrap_err();
+    assert!(err.to_string().contains("not contiguous"), "{err}");
+    assert_eq!(db.latest_version()?, None);
+    assert!(!db.has_node(&NodeKey::new_empty_path(0))?);
+    assert_eq!(db.get_value_option(0, key)?, None);
+
+    db.write_tree_update_batch(batch)?;
+    assert_eq!(db.get_value_option(0, key)?, Some(vec![0]));
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_prune_concurrent_with_write() -> anyhow::Result<()> {
+    use crate::{
+        rocksdb_store::RocksDbStoreConfig,
+        storage::{NodeBatch, TreeReader, TreeWriter},
+    };
+    use std::sync::Barrier;
+
+    let scratch = crate::mock::MockTreeStore::default();
+    let tree: JellyfishMerkleTree<_, Sha256> = JellyfishMerkleTree::new(&scratch);
+    // Many keys, so that the prune reads the counts of many nodes before writing any.
+    let keys: Vec<KeyHash> = (0..512u64)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+    let value_set = |value: u8| keys.iter().map(move |key| (*key, Some(vec![value])));
+    let (_root, batch0) = tree.put_value_set(value_set(0), 0)?;
+    scratch.write_tree_update_batch(batch0.clone())?;
+    let (_root, batch1) = tree.put_value_set(value_set(1), 1)?;
+
+    // A rewrite of the root of version 0 racing a prune of the nodes version 1 made stale:
+    // whichever goes first, the root is left, as the rewrite either bumps its count before the
+    // prune drops it or writes it again after the prune deleted it. The rewrite is short, so that
+    // it lands while the prune is still reading the counts of the many nodes it drops.
+    let root_key = NodeKey::new_empty_path(0);
+    let root = batch0.node_batch.get_node(&root_key).unwrap().clone();
+    let rewrite = NodeBatch::new([(root_key.clone(), root)].into(), Default::default());
+    for _ in 0..8 {
+        let config = RocksDbStoreConfig {
+            refcount_nodes: true,
+            ..Default::default()
+        };
+        let dir = tempfile::TempDir::new()?;
+        let db = RocksDbTreeStore::with_config(dir.path(), config)?;
+        db.write_tree_update_batch(batch0.clone())?;
+        db.write_tree_update_batch(batch1.clone())?;
+
+        let barrier = Barrier::new(2);
+        std::thread::scope(|scope| -> anyhow::Result<()> {
+            let writer = scope.spawn(|| {
+                barrier.wait();
+                db.write_node_batch(&rewrite)
+            });
+            let pruner = scope.spawn(|| {
+                barrier.wait();
+                db.prune(1)
+            });
+            writer.join().unwrap()?;
+            pruner.join().unwrap()?;
+            Ok(())
+        })?;
+
+        assert!(db.get_node_option(&root_key)?.is_some());
+        assert!(db.verify_consistency(1)?.is_empty());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_seal_version_concurrent_with_prune() -> anyhow::Result<()> {
+    use crate::storage::TreeReader;
+    use std::sync::Barrier;
+
+    // Many keys, so that the prune reads many stale index entries before writing anything.
+    let keys: Vec<KeyHash> = (0..512u64)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+    let root_key = NodeKey::new_empty_path(1);
+    for _ in 0..8 {
+        let db = RocksDbTreeStore::new_temporary()?;
+        let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+        for version in 0..4u64 {
+            let value_set = keys.iter().map(|key| (*key, Some(vec![version as u8])));
+            let (_root, batch) = tree.put_value_set(value_set, version)?;
+            db.write_tree_update_batch(batch)?;
+        }
+
+        // A seal racing a prune that would remove the version sealed: either the prune completes
+        // first, and the version is gone when the seal returns, or it sees the seal and fails.
+        let barrier = Barrier::new(2);
+        let (readable_when_sealed, pruned) = std::thread::scope(|scope| {
+            let sealer = scope.spawn(|| -> anyhow::Result<bool> {
+                barrier.wait();
+                db.seal_version(1)?;
+                Ok(db.get_node_option(&root_key)?.is_some())
+            });
+            let pruner = scope.spawn(|| {
+                barrier.wait();
+                db.prune(3).is_ok()
+            });
+            (sealer.join().unwrap(), pruner.join().unwrap())
+        });
+        if readable_when_sealed? {
+            assert!(!pruned);
+            assert!(db.get_node_option(&root_key)?.is_some());
+            assert_eq!(tree.get(keys[0], 1)?, Some(vec![1]));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_auto_prune_with_refcount_nodes() -> anyhow::Result<()> {
+    use crate::rocksdb_store::RocksDbStoreConfig;
+    use std::time::{Duration, Instant};
+
+    let config = RocksDbStoreConfig {
+        auto_prune_stale_bytes: Some(1),
+        refcount_nodes: true,
+        ..Default::default()
+    };
+    let temp_dir = tempfile::TempDir::new()?;
+    let db = RocksDbTreeStore::with_config(temp_dir.path(), config)?;
+    let tree: JellyfishMerkleTree<RocksDbTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let keys: Vec<KeyHash> = (0..64u64)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+
+    // The background prunes run while the next versions are written, each rewriting every key.
+    db.set_least_readable_version(0)?;
+    for version in 0..32u64 {
+        let value_set = keys.iter().map(|key| (*key, Some(vec![version as u8])));
+        let (_root, batch) = tree.put_value_set(value_set, version)?;
+        db.write_tree_update_batch(batch)?;
+        db.set_least_readable_version(version)?;
+    }
+    let (root, batch) = tree.put_value_set(vec![(keys[0], Some(vec![32]))], 32)?;
+    db.write_tree_update_batch(batch)?;
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while tree.get_root_hash(30).is_ok() {
+        assert!(Instant::now() < deadline, "version 30 was never pruned");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    // Only the versions below the least readable version lost nodes.
+    assert!(db.verify_consistency(31)?.is_empty());
+    assert!(db.verify_consistency(32)?.is_empty());
+    assert_eq!(tree.get_root_hash(32)?, root);
+    for key in &keys[1..] {
+        assert_eq!(tree.get(*key, 32)?, Some(vec![31]));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_import_older_snapshot_keeps_cached_root() -> anyhow::Result<()> {
+    let source = RocksDbTreeStore::new_temporary()?;
+    write_versions(&source, 2)?;
+    let mut snapshot = Vec::new();
+    source.export_snapshot(1, &mut snapshot)?;
+
+    // Importing version 1 into a store at version 3 leaves version 3 the latest, and cached.
+    let db = RocksDbTreeStore::new_temporary()?;
+    let roots = write_versions(&db, 4)?;
+    db.import_snapshot(snapshot.as_slice())?;
+    assert_eq!(db.latest_version()?, Some(3));
+    let (root_key, root) = db.cached_root().unwrap();
+    assert_eq!(root_key, NodeKey::new_empty_path(3));
+    assert_eq!(crate::RootHash(root.hash::<Sha256>()), roots[3]);
+
+    Ok(())
+}
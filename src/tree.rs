@@ -1,6 +1,9 @@
 use crate::storage::Node::Leaf;
 use crate::SPARSE_MERKLE_PLACEHOLDER_HASH;
-use alloc::{collections::BTreeMap, vec::Vec};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
 use alloc::{format, vec};
 use anyhow::{bail, ensure, format_err, Context, Result};
 use core::marker::PhantomData;
@@ -13,29 +16,116 @@ use std::collections::HashMap;
 use crate::proof::definition::UpdateMerkleProof;
 use crate::proof::{SparseMerkleLeafNode, SparseMerkleNode};
 use crate::{
-    node_type::{Child, Children, InternalNode, LeafNode, Node, NodeKey, NodeType},
-    storage::{TreeReader, TreeUpdateBatch},
+    node_type::{
+        get_child_and_sibling_half_start, Child, Children, InternalNode, LeafNode, Node, NodeKey,
+        NodeType,
+    },
+    reader::EmptyTreeReader,
+    storage::{HasPreimage, NodeWithValue, TreeReader, TreeUpdateBatch},
     tree_cache::TreeCache,
     types::{
         nibble::{
             nibble_path::{skip_common_prefix, NibbleIterator, NibblePath},
             Nibble, NibbleRangeIterator, ROOT_NIBBLE_HEIGHT,
         },
-        proof::{SparseMerkleProof, SparseMerkleRangeProof},
+        proof::{KeyProof, SparseMerkleProof, SparseMerkleRangeProof, VersionRangeProof},
         Version,
     },
-    Bytes32Ext, KeyHash, MissingRootError, OwnedValue, RootHash, SimpleHasher, ValueHash,
+    Bytes32Ext, CorruptedNodeError, DuplicateKeysError, KeyHash, MissingPreimageError,
+    MissingRootError, OwnedValue, RootHash, SimpleHasher, ValueHash,
 };
 
+/// Domain separator of the commitments computed by [`compute_version_commitment`].
+const VERSION_COMMITMENT_DOMAIN_SEPARATOR: &[u8] = b"JMT::VersionCommitment";
+
+/// Computes a single commitment to `version`, the `root_hash` of the tree at that version and its
+/// `leaf_count`, so that a client can check all three at once.
+///
+/// The commitment is the `H` hash of `JMT::VersionCommitment`, followed by the big-endian
+/// `version`, the root hash and the big-endian `leaf_count` as a `u64`.
+pub fn compute_version_commitment<H: SimpleHasher>(
+    version: Version,
+    root_hash: RootHash,
+    leaf_count: u64,
+) -> [u8; 32] {
+    let mut hasher = H::new();
+    hasher.update(VERSION_COMMITMENT_DOMAIN_SEPARATOR);
+    hasher.update(&version.to_be_bytes());
+    hasher.update(&root_hash.0);
+    hasher.update(&leaf_count.to_be_bytes());
+    hasher.finalize()
+}
+
+/// Returns the root hash of the tree holding exactly `entries`, hashed with `H`, without a store.
+///
+/// The tree is built in memory over an empty reader, as a first version written to a store
+/// would be, so that the root is the one any store gets by committing `entries` to an empty
+/// tree. Of several entries for the same key, the last one wins.
+pub fn compute_root<H: SimpleHasher>(entries: &[(KeyHash, OwnedValue)]) -> RootHash {
+    let value_set: BTreeMap<KeyHash, Option<OwnedValue>> = entries
+        .iter()
+        .map(|(key, value)| (*key, Some(value.clone())))
+        .collect();
+    let reader = EmptyTreeReader { next_version: 0 };
+    let (root_hash, _) = JellyfishMerkleTree::<_, H>::new(&reader)
+        .put_value_set(value_set, 0)
+        .expect("an empty tree has no node to be missing and the keys are distinct");
+    root_hash
+}
+
+/// Returns whether `a` and `b` hold the same tree at `version`.
+///
+/// Both trees are walked together from the root, comparing the nodes read for each node key,
+/// which carry the hashes of their children, and stopping at the first difference. Unlike a
+/// comparison of the stored bytes, this holds across stores encoding their nodes differently. A
+/// node present in only one of the stores is a difference. Fails with [`MissingRootError`] if
+/// neither store has the root of `version`, and if a node is missing from both.
+pub fn stores_equal_at<A: TreeReader, B: TreeReader>(
+    a: &A,
+    b: &B,
+    version: Version,
+) -> Result<bool> {
+    let mut stack = vec![NodeKey::new_empty_path(version)];
+    while let Some(node_key) = stack.pop() {
+        let node = a.get_node_option(&node_key)?;
+        if node != b.get_node_option(&node_key)? {
+            return Ok(false);
+        }
+        match node {
+            Some(Node::Internal(internal_node)) => {
+                for (nibble, child) in internal_node.children_sorted() {
+                    stack.push(node_key.gen_child_node_key(child.version, nibble));
+                }
+            }
+            Some(_) => {}
+            None if node_key.nibble_path().is_empty() => {
+                return Err(anyhow::anyhow!(MissingRootError { version }))
+            }
+            None => bail!("missing node {:?} in both stores", node_key),
+        }
+    }
+    Ok(true)
+}
+
 /// A [`JellyfishMerkleTree`] instantiated using the `sha2::Sha256` hasher.
 /// This is a sensible default choice for most applications.
 #[cfg(any(test, feature = "sha2"))]
 pub type Sha256Jmt<'a, R> = JellyfishMerkleTree<'a, R, sha2::Sha256>;
 
+/// A leaf yielded by
+/// [`iter_with_proofs_and_preimages`](JellyfishMerkleTree::iter_with_proofs_and_preimages): its key
+/// hash, its value, the preimage of its key hash if the reader has it, and its proof.
+pub type ProvenLeaf<H> = (KeyHash, OwnedValue, Option<Vec<u8>>, SparseMerkleProof<H>);
+
+/// The key hashes and values, or `None` for deletions, written at one version.
+type ValueSet<V = Option<OwnedValue>> = Vec<(KeyHash, V)>;
+
 /// A Jellyfish Merkle tree data structure, parameterized by a [`TreeReader`] `R`
 /// and a [`SimpleHasher`] `H`. See [`crate`] for description.
 pub struct JellyfishMerkleTree<'a, R, H: SimpleHasher> {
     reader: &'a R,
+    reject_duplicate_keys: bool,
+    verify_on_read: bool,
     _phantom_hasher: PhantomData<H>,
 }
 
@@ -54,10 +144,132 @@ where
     pub fn new(reader: &'a R) -> Self {
         Self {
             reader,
+            reject_duplicate_keys: false,
+            verify_on_read: false,
             _phantom_hasher: Default::default(),
         }
     }
 
+    /// Makes [`put_value_sets`](JellyfishMerkleTree::put_value_sets),
+    /// [`put_value_sets_with_proof`](JellyfishMerkleTree::put_value_sets_with_proof),
+    /// [`put_prehashed_value_sets`](JellyfishMerkleTree::put_prehashed_value_sets) and the
+    /// methods built on them fail with a [`DuplicateKeysError`] listing the key hashes given more
+    /// than once in a value set, before computing anything.
+    ///
+    /// By default, a key hash given more than once in a value set takes the last value given for
+    /// it, which hides the bugs of callers that did not mean to give it twice.
+    pub fn reject_duplicate_keys(mut self) -> Self {
+        self.reject_duplicate_keys = true;
+        self
+    }
+
+    /// Makes [`get_with_proof`](JellyfishMerkleTree::get_with_proof), the methods built on it
+    /// and [`get_subtree_proof`](JellyfishMerkleTree::get_subtree_proof) hash every node they
+    /// read below the root, those on the path to the key and those read as siblings for the
+    /// proof, and fail with a [`CorruptedNodeError`] if one does not hash to the hash its parent
+    /// records for it.
+    ///
+    /// By default, nodes are trusted as read, so that a node corrupted in storage into another
+    /// valid node is served as it is, with a proof that fails to verify. The check catches such
+    /// corruption at read time instead, at the cost of hashing every node read once more.
+    pub fn verify_on_read(mut self) -> Self {
+        self.verify_on_read = true;
+        self
+    }
+
+    /// Returns the hash `parent` records for its child at `child_key` if the tree verifies the
+    /// nodes it reads, see [`verify_on_read`](JellyfishMerkleTree::verify_on_read).
+    fn expected_child_hash(&self, parent: &InternalNode, child_key: &NodeKey) -> Option<[u8; 32]> {
+        if !self.verify_on_read {
+            return None;
+        }
+        let child_index = child_key.nibble_path().last()?;
+        parent.child(child_index).map(|child| child.hash)
+    }
+
+    /// Fails with a [`CorruptedNodeError`] if one of `siblings`, those of the path to the child
+    /// `n` of `parent`, at `node_key`, from the top, was read from a child of `parent` that does
+    /// not hash to the hash `parent` records for it, if the tree verifies the nodes it reads.
+    fn check_sibling_hashes(
+        &self,
+        node_key: &NodeKey,
+        parent: &InternalNode,
+        n: Nibble,
+        siblings: &[SparseMerkleNode],
+    ) -> Result<()> {
+        if !self.verify_on_read {
+            return Ok(());
+        }
+        for (sibling, height) in siblings.iter().zip((0..4u8).rev()) {
+            let (_, start) = get_child_and_sibling_half_start(n, height);
+            let width = 1u8 << height;
+            let children: Vec<_> = (start..start + width)
+                .filter_map(|index| Some((index, parent.child(Nibble::from(index))?)))
+                .collect();
+            // A sibling is read from the child under it when it is the only one and a leaf, or at
+            // the lowest level, and computed from the hashes of the children otherwise.
+            if let [(index, child)] = children[..] {
+                if width == 1 || child.is_leaf() {
+                    let child_key = node_key.gen_child_node_key(child.version, Nibble::from(index));
+                    Self::check_hash(&child_key, sibling.hash::<H>(), child.hash)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with a [`CorruptedNodeError`] if `node`, read at `node_key`, does not hash to
+    /// `expected`, the hash its parent records for it, if known.
+    fn check_node_hash(node_key: &NodeKey, node: &Node, expected: Option<[u8; 32]>) -> Result<()> {
+        match expected {
+            Some(expected) => Self::check_hash(node_key, node.hash::<H>(), expected),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails with a [`CorruptedNodeError`] if `found`, the hash of the node read at `node_key`,
+    /// is not `expected`.
+    fn check_hash(node_key: &NodeKey, found: [u8; 32], expected: [u8; 32]) -> Result<()> {
+        ensure!(
+            found == expected,
+            CorruptedNodeError {
+                node_key: node_key.clone(),
+                expected,
+                found,
+            }
+        );
+        Ok(())
+    }
+
+    /// Collects `value_sets`, checking them for duplicate key hashes if the tree rejects them.
+    fn collect_value_sets<V>(
+        &self,
+        value_sets: impl IntoIterator<Item = impl IntoIterator<Item = (KeyHash, V)>>,
+        first_version: Version,
+    ) -> Result<Vec<ValueSet<V>>> {
+        let value_sets: Vec<ValueSet<V>> = value_sets
+            .into_iter()
+            .map(|value_set| value_set.into_iter().collect())
+            .collect();
+        if self.reject_duplicate_keys {
+            for (idx, value_set) in value_sets.iter().enumerate() {
+                let mut seen = BTreeSet::new();
+                let duplicates: BTreeSet<_> = value_set
+                    .iter()
+                    .map(|(key, _)| *key)
+                    .filter(|key| !seen.insert(*key))
+                    .collect();
+                if !duplicates.is_empty() {
+                    return Err(anyhow::anyhow!(DuplicateKeysError {
+                        version: first_version + idx as u64,
+                        key_hashes: duplicates.into_iter().collect(),
+                    }));
+                }
+            }
+        }
+        Ok(value_sets)
+    }
+
     /// Get the node hash from the cache if exists, otherwise compute it.
     fn get_hash(
         node_key: &NodeKey,
@@ -345,6 +557,9 @@ where
     /// This is a convenient function that calls
     /// [`put_value_sets`](struct.JellyfishMerkleTree.html#method.put_value_sets) with a single
     /// `keyed_value_set`.
+    ///
+    /// `Some(vec![])` sets a key to the empty value, which is a value like any other: the key
+    /// keeps a leaf, and reads back as `Some(vec![])`. Only `None` deletes a key.
     pub fn put_value_set(
         &self,
         value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
@@ -359,6 +574,33 @@ where
         Ok((root_hashes[0], tree_update_batch))
     }
 
+    /// Same as [`put_value_set`](JellyfishMerkleTree::put_value_set) with a single entry, setting
+    /// `key` to `value` at `version`, or deleting it if `value` is `None`.
+    ///
+    /// The update is computed directly, without collecting a value set first, which is most of
+    /// the overhead of the general path for updates touching a single key.
+    pub fn put_value(
+        &self,
+        key: KeyHash,
+        value: Option<OwnedValue>,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        let mut tree_cache = TreeCache::new(self.reader, version)?;
+        let action = if value.is_some() { "insert" } else { "delete" };
+        let value_hash = value.as_ref().map(|v| ValueHash::with::<H>(v));
+        tree_cache.put_value(version, key, value);
+        self.put(key, value_hash, version, &mut tree_cache, false)
+            .with_context(|| {
+                format!(
+                    "failed to {} key for version {}, key = {:?}",
+                    action, version, key
+                )
+            })?;
+        tree_cache.freeze::<H>()?;
+        let (root_hashes, tree_update_batch) = tree_cache.into();
+        Ok((root_hashes[0], tree_update_batch))
+    }
+
     /// This is a convenient function that calls
     /// [`put_value_sets_with_proof`](struct.JellyfishMerkleTree.html#method.put_value_sets) with a single
     /// `keyed_value_set`.
@@ -380,6 +622,46 @@ where
         Ok((hash, proof, batch_update))
     }
 
+    /// Deletes every key of `keys` at `version`, as
+    /// [`put_value_set`](JellyfishMerkleTree::put_value_set) does with a value set mapping each of
+    /// them to `None`.
+    ///
+    /// The batch holds a tombstone for each key, including those absent from the tree, after
+    /// which the keys read as `None` at `version` and above.
+    pub fn delete_value_set(
+        &self,
+        keys: Vec<KeyHash>,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        self.put_value_set(keys.into_iter().map(|key| (key, None)), version)
+    }
+
+    /// Swaps the values of `key_a` and `key_b` at `version`: each key gets the value the other
+    /// one had at the previous version, in a single batch.
+    ///
+    /// An absent key counts as holding `None`, so swapping with it moves the value of the other
+    /// key over and deletes it from its former slot. Swapping a key with itself leaves its value
+    /// unchanged. Like [`put_value_set`](JellyfishMerkleTree::put_value_set), the batch is
+    /// computed on top of the tree at `version - 1`, or the empty tree for version 0.
+    pub fn swap_values(
+        &self,
+        key_a: KeyHash,
+        key_b: KeyHash,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        let (value_a, value_b) = match version.checked_sub(1) {
+            Some(base_version) => (
+                self.get(key_a, base_version)?,
+                self.get(key_b, base_version)?,
+            ),
+            None => (None, None),
+        };
+        if key_a == key_b {
+            return self.put_value_set(vec![(key_a, value_a)], version);
+        }
+        self.put_value_set(vec![(key_a, value_b), (key_b, value_a)], version)
+    }
+
     /// Returns the new nodes and values in a batch after applying `value_set`. For
     /// example, if after transaction `T_i` the committed state of tree in the persistent storage
     /// looks like the following structure:
@@ -426,6 +708,7 @@ where
         value_sets: impl IntoIterator<Item = impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>>,
         first_version: Version,
     ) -> Result<(Vec<RootHash>, TreeUpdateBatch)> {
+        let value_sets = self.collect_value_sets(value_sets, first_version)?;
         let mut tree_cache = TreeCache::new(self.reader, first_version)?;
         for (idx, value_set) in value_sets.into_iter().enumerate() {
             let version = first_version + idx as u64;
@@ -449,6 +732,70 @@ where
         Ok(tree_cache.into())
     }
 
+    /// Same as [`put_value_sets`](JellyfishMerkleTree::put_value_sets), for values given along
+    /// with their hashes, which the tree uses instead of hashing the values again.
+    ///
+    /// This saves hashing every value a second time when the caller already hashed them, as
+    /// ingestion pipelines checking the values they receive do. The hashes are trusted: in debug
+    /// builds, this panics if one of them is not the hash of its value, while in release builds
+    /// a wrong hash makes the tree commit to it, and proofs of the value fail to verify.
+    pub fn put_prehashed_value_sets(
+        &self,
+        value_sets: impl IntoIterator<
+            Item = impl IntoIterator<Item = (KeyHash, Option<(ValueHash, OwnedValue)>)>,
+        >,
+        first_version: Version,
+    ) -> Result<(Vec<RootHash>, TreeUpdateBatch)> {
+        let value_sets = self.collect_value_sets(value_sets, first_version)?;
+        let mut tree_cache = TreeCache::new(self.reader, first_version)?;
+        for (idx, value_set) in value_sets.into_iter().enumerate() {
+            let version = first_version + idx as u64;
+            for (i, (key, value)) in value_set.into_iter().enumerate() {
+                let action = if value.is_some() { "insert" } else { "delete" };
+                let (value_hash, value) = value.unzip();
+                if let (Some(value_hash), Some(value)) = (value_hash, &value) {
+                    debug_assert_eq!(
+                        value_hash,
+                        ValueHash::with::<H>(value),
+                        "wrong value hash given for key {:?}",
+                        key
+                    );
+                }
+                tree_cache.put_value(version, key, value);
+                self.put(key, value_hash, version, &mut tree_cache, false)
+                    .with_context(|| {
+                        format!(
+                            "failed to {} key {} for version {}, key = {:?}",
+                            action, i, version, key
+                        )
+                    })?;
+            }
+
+            // Freezes the current cache to make all contents in the current cache immutable.
+            tree_cache.freeze::<H>()?;
+        }
+
+        Ok(tree_cache.into())
+    }
+
+    /// This is a convenient function that calls
+    /// [`put_prehashed_value_sets`](JellyfishMerkleTree::put_prehashed_value_sets) with a single
+    /// `value_set`.
+    pub fn put_prehashed_value_set(
+        &self,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<(ValueHash, OwnedValue)>)>,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        let (root_hashes, tree_update_batch) =
+            self.put_prehashed_value_sets(vec![value_set], version)?;
+        assert_eq!(
+            root_hashes.len(),
+            1,
+            "root_hashes must consist of a single value.",
+        );
+        Ok((root_hashes[0], tree_update_batch))
+    }
+
     #[cfg(feature = "migration")]
     /// Append value sets to the latest version of the tree, without incrementing its version.
     pub fn append_value_set(
@@ -490,6 +837,7 @@ where
         value_sets: impl IntoIterator<Item = impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>>,
         first_version: Version,
     ) -> Result<(Vec<(RootHash, UpdateMerkleProof<H>)>, TreeUpdateBatch)> {
+        let value_sets = self.collect_value_sets(value_sets, first_version)?;
         let mut tree_cache = TreeCache::new(self.reader, first_version)?;
         let mut batch_proofs = Vec::new();
         for (idx, value_set) in value_sets.into_iter().enumerate() {
@@ -627,8 +975,9 @@ where
                         root_node_key
                     );
                 }
-                // Delete the old null node if the at the same version
-                if root_node_key.version() == version && node_already_exists {
+                // Delete the old null node if the at the same version, and make that of an empty
+                // older version stale once a leaf replaces it, as any other replaced root is
+                if node_already_exists && (root_node_key.version() == version || value.is_some()) {
                     tree_cache.delete_node(&root_node_key, false /* is_leaf */);
                 }
                 if let Some(value) = value {
@@ -696,7 +1045,7 @@ where
 
                 let new_proof_opt = proof_opt.map(|proof| {
                     // The move siblings function allows zero copy moves for proof
-                    let proof_leaf = proof.leaf();
+                    let proof_leaf = proof.leaf_node();
                     let mut new_siblings = proof.take_siblings();
                     // We need to reverse the siblings
                     siblings.reverse();
@@ -1007,19 +1356,72 @@ where
     }
 
     /// Returns the value (if applicable) and the corresponding merkle proof.
+    ///
+    /// A version written without any key, such as a first version of no keys, has a null root
+    /// persisted, whose hash is [`EMPTY_ROOT`](JellyfishMerkleTree::EMPTY_ROOT): every key then
+    /// has no value, with a proof of no sibling and no leaf verifying its absence against it.
     pub fn get_with_proof(
         &self,
         key: KeyHash,
         version: Version,
     ) -> Result<(Option<OwnedValue>, SparseMerkleProof<H>)> {
-        // Empty tree just returns proof with no sibling hash.
+        let (leaf, proof) = self.get_leaf_with_proof(key, version)?;
+        let value = match leaf {
+            Some((_, Some(value))) => Some(value),
+            Some((leaf_node, None)) => Some(self.reader.get_value(version, leaf_node.key_hash())?),
+            None => None,
+        };
+        Ok((value, proof))
+    }
+
+    /// Same as [`get_with_proof`](JellyfishMerkleTree::get_with_proof), with the value and proof
+    /// tagged as [`KeyProof::Present`] or [`KeyProof::Absent`].
+    ///
+    /// The tag is taken from the proof itself, so a key holding an empty value is present, and
+    /// the proof of an absent key is a non-inclusion proof.
+    pub fn get_with_tagged_proof(&self, key: KeyHash, version: Version) -> Result<KeyProof<H>> {
+        Ok(match self.get_with_proof(key, version)? {
+            (Some(value), proof) => KeyProof::Present(value, proof),
+            (None, proof) => KeyProof::Absent(proof),
+        })
+    }
+
+    /// Returns the hash of the value of `key` at `version` and the proof of it, without reading
+    /// the value itself.
+    ///
+    /// The hash is the one committed to by the leaf of `key`, so the proof verifies against it
+    /// with [`SparseMerkleProof::verify_inclusion_with_value_hash`]. This saves reading large
+    /// values when only their commitment is needed. For an absent key, the hash is `None` and the
+    /// proof is a non-inclusion proof, as with [`get_with_proof`](JellyfishMerkleTree::get_with_proof).
+    pub fn get_proof_for_value_hash(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<(Option<ValueHash>, SparseMerkleProof<H>)> {
+        let (leaf, proof) = self.get_leaf_with_proof(key, version)?;
+        Ok((leaf.map(|(leaf_node, _)| leaf_node.value_hash()), proof))
+    }
+
+    /// Returns the length of the borsh encoding of the proof
+    /// [`get_with_proof`](JellyfishMerkleTree::get_with_proof) returns for `key` at `version`,
+    /// without building the proof.
+    ///
+    /// This walks the same path as the proof, reading only the nodes on it: whether each sibling
+    /// is null, which is all its encoded length depends on, is known from the children of the
+    /// internal nodes, so no sibling is read or hashed. Callers serving proofs over a
+    /// bandwidth-limited link can reject or batch requests from it.
+    pub fn estimate_proof_size(&self, key: KeyHash, version: Version) -> Result<usize> {
+        // The tag of the optional leaf and the length of the siblings, then each sibling: a null
+        // sibling is only its tag, the others their tag and two hashes, as is a leaf.
+        const FRAMING_SIZE: usize = 1 + 4;
+        const NULL_SIBLING_SIZE: usize = 1;
+        const SIBLING_SIZE: usize = 1 + 64;
+        const LEAF_SIZE: usize = 64;
+
+        let mut size = FRAMING_SIZE;
         let mut next_node_key = NodeKey::new_empty_path(version);
-        let mut siblings: Vec<SparseMerkleNode> = vec![];
         let nibble_path = NibblePath::new(key.0.to_vec());
         let mut nibble_iter = nibble_path.nibbles();
-
-        // We limit the number of loops here deliberately to avoid potential cyclic graph bugs
-        // in the tree structure.
         for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
             let next_node = self.reader.get_node(&next_node_key).map_err(|err| {
                 if nibble_depth == 0 {
@@ -1028,6 +1430,76 @@ where
                     err
                 }
             })?;
+            match next_node {
+                Node::Internal(internal_node) => {
+                    let queried_child_index = nibble_iter
+                        .next()
+                        .ok_or_else(|| format_err!("ran out of nibbles"))?;
+                    for exists in internal_node.only_child_sibling_existence(queried_child_index) {
+                        size += if exists {
+                            SIBLING_SIZE
+                        } else {
+                            NULL_SIBLING_SIZE
+                        };
+                    }
+                    next_node_key = match internal_node
+                        .get_only_child_without_siblings(&next_node_key, queried_child_index)
+                    {
+                        Some(node_key) => node_key,
+                        None => return Ok(size),
+                    };
+                }
+                Node::Leaf(_) => return Ok(size + LEAF_SIZE),
+                Node::Null => {
+                    if nibble_depth == 0 {
+                        return Ok(size);
+                    } else {
+                        bail!(
+                            "Non-root null node exists with node key {:?}",
+                            next_node_key
+                        );
+                    }
+                }
+            }
+        }
+        bail!("Jellyfish Merkle tree has cyclic graph inside.");
+    }
+
+    /// Descends to `key` at `version` and returns its leaf, if the key exists, with the proof of
+    /// it. Only nodes are read, the leaf with its value if the reader keeps it inline, see
+    /// [`TreeReader::get_node_with_inline_value`].
+    fn get_leaf_with_proof(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<(Option<NodeWithValue<LeafNode>>, SparseMerkleProof<H>)> {
+        // Empty tree just returns proof with no sibling hash.
+        let mut next_node_key = NodeKey::new_empty_path(version);
+        let mut expected_hash = None;
+        let mut siblings: Vec<SparseMerkleNode> = vec![];
+        let nibble_path = NibblePath::new(key.0.to_vec());
+        let mut nibble_iter = nibble_path.nibbles();
+
+        // We limit the number of loops here deliberately to avoid potential cyclic graph bugs
+        // in the tree structure.
+        for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
+            let (next_node, inline_value) = self
+                .reader
+                .get_node_with_inline_value(&next_node_key)
+                .and_then(|node| match node {
+                    Some(node) => Ok(node),
+                    // Looked for again through `get_node`, so that the reader fails the way it
+                    // fails on missing nodes.
+                    None => Ok((self.reader.get_node(&next_node_key)?, None)),
+                })
+                .map_err(|err| {
+                    if nibble_depth == 0 {
+                        anyhow::anyhow!(MissingRootError { version })
+                    } else {
+                        err
+                    }
+                })?;
+            Self::check_node_hash(&next_node_key, &next_node, expected_hash)?;
             match next_node {
                 Node::Internal(internal_node) => {
                     let queried_child_index = nibble_iter
@@ -1040,10 +1512,19 @@ where
                             &next_node_key,
                             queried_child_index,
                         );
+                    self.check_sibling_hashes(
+                        &next_node_key,
+                        &internal_node,
+                        queried_child_index,
+                        &siblings_in_internal,
+                    )?;
 
                     siblings.append(&mut siblings_in_internal);
                     next_node_key = match child_node_key {
-                        Some(node_key) => node_key,
+                        Some(node_key) => {
+                            expected_hash = self.expected_child_hash(&internal_node, &node_key);
+                            node_key
+                        }
                         None => {
                             return Ok((
                                 None,
@@ -1057,11 +1538,7 @@ where
                 }
                 Node::Leaf(leaf_node) => {
                     return Ok((
-                        if leaf_node.key_hash() == key {
-                            Some(self.reader.get_value(version, leaf_node.key_hash())?)
-                        } else {
-                            None
-                        },
+                        (leaf_node.key_hash() == key).then(|| (leaf_node.clone(), inline_value)),
                         SparseMerkleProof::new(Some(leaf_node.into()), {
                             siblings.reverse();
                             siblings
@@ -1083,6 +1560,86 @@ where
         bail!("Jellyfish Merkle tree has cyclic graph inside.");
     }
 
+    /// Returns the hash of the subtree of the keys starting with `prefix` at `version`, with the
+    /// proof connecting it to the root of the version, to be checked with
+    /// [`SparseMerkleProof::verify_subtree`].
+    ///
+    /// This lets a verifier holding the keys under `prefix` trust them without the rest of the
+    /// tree. The subtree hashes as in the tree: to its only leaf if it has one key, and to
+    /// [`SPARSE_MERKLE_PLACEHOLDER_HASH`] if it has none. The proof then ends where that leaf or
+    /// empty subtree sits, which may be above `prefix`, and holds the leaf found there, if any.
+    pub fn get_subtree_proof(
+        &self,
+        version: Version,
+        prefix: NibblePath,
+    ) -> Result<([u8; 32], SparseMerkleProof<H>)> {
+        let mut next_node_key = NodeKey::new_empty_path(version);
+        let mut expected_hash = None;
+        let mut siblings: Vec<SparseMerkleNode> = vec![];
+        let mut nibble_iter = prefix.nibbles();
+
+        for nibble_depth in 0..=prefix.num_nibbles() {
+            let next_node = self.reader.get_node(&next_node_key).map_err(|err| {
+                if nibble_depth == 0 {
+                    anyhow::anyhow!(MissingRootError { version })
+                } else {
+                    err
+                }
+            })?;
+            Self::check_node_hash(&next_node_key, &next_node, expected_hash)?;
+            let (subtree_hash, leaf) = match next_node {
+                Node::Internal(internal_node) => match nibble_iter.next() {
+                    Some(nibble) => {
+                        let (child_node_key, mut siblings_in_internal) = internal_node
+                            .get_only_child_with_siblings::<H>(self.reader, &next_node_key, nibble);
+                        self.check_sibling_hashes(
+                            &next_node_key,
+                            &internal_node,
+                            nibble,
+                            &siblings_in_internal,
+                        )?;
+                        siblings.append(&mut siblings_in_internal);
+                        match child_node_key {
+                            Some(node_key) => {
+                                expected_hash = self.expected_child_hash(&internal_node, &node_key);
+                                next_node_key = node_key;
+                                continue;
+                            }
+                            None => (SPARSE_MERKLE_PLACEHOLDER_HASH, None),
+                        }
+                    }
+                    None => (internal_node.hash::<H>(), None),
+                },
+                Node::Leaf(leaf_node) => {
+                    let key_path = NibblePath::new(leaf_node.key_hash().0.to_vec());
+                    let subtree_hash = if key_path
+                        .nibbles()
+                        .take(prefix.num_nibbles())
+                        .eq(prefix.nibbles())
+                    {
+                        leaf_node.hash::<H>()
+                    } else {
+                        SPARSE_MERKLE_PLACEHOLDER_HASH
+                    };
+                    (subtree_hash, Some(leaf_node.into()))
+                }
+                Node::Null => {
+                    if nibble_depth == 0 {
+                        (SPARSE_MERKLE_PLACEHOLDER_HASH, None)
+                    } else {
+                        bail!(
+                            "Non-root null node exists with node key {:?}",
+                            next_node_key
+                        );
+                    }
+                }
+            };
+            siblings.reverse();
+            return Ok((subtree_hash, SparseMerkleProof::new(leaf, siblings)));
+        }
+        bail!("Jellyfish Merkle tree has cyclic graph inside.");
+    }
+
     fn search_closest_extreme_node(
         &self,
         version: Version,
@@ -1388,6 +1945,40 @@ where
         Ok(SparseMerkleRangeProof::new(siblings))
     }
 
+    /// Returns the values of `key` at `from_version` and `to_version` with their proofs and the
+    /// root hashes of both versions, see [`VersionRangeProof`].
+    ///
+    /// The key may be absent at either endpoint, the proof of that endpoint then being a
+    /// non-inclusion proof. Fails if `from_version` is greater than `to_version`, and with
+    /// [`MissingRootError`] if either version is not readable.
+    pub fn get_version_range_proof(
+        &self,
+        key: KeyHash,
+        from_version: Version,
+        to_version: Version,
+    ) -> Result<VersionRangeProof<H>> {
+        ensure!(
+            from_version <= to_version,
+            "from_version {} is greater than to_version {}",
+            from_version,
+            to_version
+        );
+        let (from_value, from_proof) = self.get_with_proof(key, from_version)?;
+        let from_root_hash = self.get_root_hash(from_version)?;
+        let (to_value, to_proof) = self.get_with_proof(key, to_version)?;
+        let to_root_hash = self.get_root_hash(to_version)?;
+        Ok(VersionRangeProof {
+            from_version,
+            from_root_hash,
+            from_value,
+            from_proof,
+            to_version,
+            to_root_hash,
+            to_value,
+            to_proof,
+        })
+    }
+
     /// Returns the value (if applicable), without any proof.
     ///
     /// Equivalent to [`get_with_proof`](JellyfishMerkleTree::get_with_proof) and dropping the
@@ -1396,6 +1987,16 @@ where
         self.get_without_proof(key, version)
     }
 
+    /// Returns whether `key` has a value at `version`, the empty value included.
+    ///
+    /// This is told by the kind of the proof of `key`, inclusion or non-inclusion, rather than by
+    /// the value read: a key set to the empty value has a leaf committing to the hash of no
+    /// bytes, while a deleted key has no leaf at all.
+    pub fn is_present(&self, key: KeyHash, version: Version) -> Result<bool> {
+        let (_, proof) = self.get_with_proof(key, version)?;
+        Ok(proof.leaf().is_some_and(|(key_hash, _)| key_hash == key))
+    }
+
     fn get_root_node(&self, version: Version) -> Result<Node> {
         self.get_root_node_option(version)?
             .ok_or_else(|| format_err!("Root node not found for version {}.", version))
@@ -1416,10 +2017,474 @@ where
             .map(|n| RootHash(n.hash::<H>())))
     }
 
+    /// Iterates over the leaves at `version` whose key hashes start with the nibbles of `prefix`,
+    /// yielding each key hash with its value in tree order, i.e. by ascending key hash.
+    ///
+    /// The walk descends straight to the node at `prefix` and only visits the subtree below it,
+    /// so the iterator is empty if no key at `version` has that prefix. If `version` has no
+    /// root, the only item is a [`MissingRootError`].
+    pub fn iter_leaves_under(
+        &self,
+        version: Version,
+        prefix: NibblePath,
+    ) -> impl Iterator<Item = Result<(KeyHash, OwnedValue)>> + '_ {
+        let mut stack = vec![NodeKey::new_empty_path(version)];
+        core::iter::from_fn(move || {
+            while let Some(node_key) = stack.pop() {
+                let node = match self.reader.get_node_option(&node_key) {
+                    Ok(Some(node)) => node,
+                    Ok(None) => {
+                        stack.clear();
+                        return Some(Err(if node_key.nibble_path().is_empty() {
+                            anyhow::anyhow!(MissingRootError { version })
+                        } else {
+                            format_err!("Missing node with node key {:?}", node_key)
+                        }));
+                    }
+                    Err(err) => {
+                        stack.clear();
+                        return Some(Err(err));
+                    }
+                };
+                match node {
+                    Node::Internal(internal_node) => {
+                        let depth = node_key.nibble_path().num_nibbles();
+                        if depth < prefix.num_nibbles() {
+                            // Still above the prefix: only follow the child on its path.
+                            let nibble = prefix.get_nibble(depth);
+                            if let Some(child) = internal_node.child(nibble) {
+                                stack.push(node_key.gen_child_node_key(child.version, nibble));
+                            }
+                        } else {
+                            let children: Vec<_> = internal_node
+                                .children_sorted()
+                                .map(|(nibble, child)| {
+                                    node_key.gen_child_node_key(child.version, nibble)
+                                })
+                                .collect();
+                            // Push in reverse so that children are visited in ascending order.
+                            stack.extend(children.into_iter().rev());
+                        }
+                    }
+                    Node::Leaf(leaf_node) => {
+                        // A leaf can sit above the end of the prefix, so check its full key.
+                        let key_hash = leaf_node.key_hash();
+                        let key_path = NibblePath::new(key_hash.0.to_vec());
+                        if key_path
+                            .nibbles()
+                            .take(prefix.num_nibbles())
+                            .eq(prefix.nibbles())
+                        {
+                            let value = self.reader.get_value(version, key_hash);
+                            return Some(value.map(|value| (key_hash, value)));
+                        }
+                    }
+                    Node::Null => {}
+                }
+            }
+            None
+        })
+    }
+
+    /// Iterates over the leaves at `version` whose key hashes are at least `start_key` and below
+    /// `end_key`, yielding each key hash with its value and the proof
+    /// [`get_with_proof`](JellyfishMerkleTree::get_with_proof) returns for it, in tree order.
+    ///
+    /// The walk reads every node of the range once: the siblings of the path to a node are
+    /// computed when its parent is visited and shared by the proofs of every leaf below it, instead
+    /// of being rebuilt from the root for each leaf. Subtrees entirely outside of the range are not
+    /// visited. Memory stays bounded by the depth of the tree whatever the size of the range: the
+    /// walk holds at most the unvisited children of the nodes on the current path, each with the
+    /// siblings of its own path, up to 4 per level. If `version` has no root, the only item is a
+    /// [`MissingRootError`].
+    pub fn iter_with_proofs(
+        &self,
+        version: Version,
+        start_key: KeyHash,
+        end_key: KeyHash,
+    ) -> impl Iterator<Item = Result<(KeyHash, OwnedValue, SparseMerkleProof<H>)>> + '_ {
+        let start_path = NibblePath::new(start_key.0.to_vec());
+        let end_path = NibblePath::new(end_key.0.to_vec());
+        // The nodes left to visit, each with the siblings of its path, from the root down.
+        let mut stack: Vec<(NodeKey, Vec<SparseMerkleNode>)> =
+            vec![(NodeKey::new_empty_path(version), vec![])];
+        core::iter::from_fn(move || {
+            while let Some((node_key, siblings)) = stack.pop() {
+                let node = match self.reader.get_node_option(&node_key) {
+                    Ok(Some(node)) => node,
+                    Ok(None) => {
+                        stack.clear();
+                        return Some(Err(if node_key.nibble_path().is_empty() {
+                            anyhow::anyhow!(MissingRootError { version })
+                        } else {
+                            format_err!("Missing node with node key {:?}", node_key)
+                        }));
+                    }
+                    Err(err) => {
+                        stack.clear();
+                        return Some(Err(err));
+                    }
+                };
+                match node {
+                    Node::Internal(internal_node) => {
+                        let depth = node_key.nibble_path().num_nibbles();
+                        // The prefix of the keys of the subtree at `nibble`, compared to the
+                        // prefix of the same length of `bound`.
+                        let compare_prefix = |nibble: Nibble, bound: &NibblePath| {
+                            node_key
+                                .nibble_path()
+                                .nibbles()
+                                .chain(core::iter::once(nibble))
+                                .cmp(bound.nibbles().take(depth + 1))
+                        };
+                        let mut children = vec![];
+                        for (nibble, _) in internal_node.children_sorted() {
+                            if compare_prefix(nibble, &start_path) == Ordering::Less
+                                || compare_prefix(nibble, &end_path) == Ordering::Greater
+                            {
+                                continue;
+                            }
+                            let (child_key, child_siblings) = internal_node
+                                .get_only_child_with_siblings::<H>(self.reader, &node_key, nibble);
+                            let child_key = child_key.expect("the child at nibble exists");
+                            let mut path_siblings = siblings.clone();
+                            path_siblings.extend(child_siblings);
+                            children.push((child_key, path_siblings));
+                        }
+                        // Push in reverse so that children are visited in ascending order.
+                        stack.extend(children.into_iter().rev());
+                    }
+                    Node::Leaf(leaf_node) => {
+                        let key_hash = leaf_node.key_hash();
+                        if key_hash < start_key || key_hash >= end_key {
+                            continue;
+                        }
+                        let mut siblings = siblings;
+                        // Proofs list their siblings from the leaf up.
+                        siblings.reverse();
+                        let proof = SparseMerkleProof::new(Some(leaf_node.into()), siblings);
+                        let value = self.reader.get_value(version, key_hash);
+                        return Some(value.map(|value| (key_hash, value, proof)));
+                    }
+                    Node::Null => {}
+                }
+            }
+            None
+        })
+    }
+
+    /// Returns the keys whose values differ between the trees at `from` and `to`, sorted by key
+    /// hash, each with how it changed going from `from` to `to`.
+    ///
+    /// The two trees are walked side by side and subtrees with the same hash at both versions are
+    /// skipped without being read, so the cost grows with the number of changes rather than the
+    /// size of the trees. Fails with [`MissingRootError`] if either version has no root.
+    pub fn diff(&self, from: Version, to: Version) -> Result<Vec<(KeyHash, ChangeKind)>> {
+        let subtree_of_root = |version| -> Result<Option<(NodeKey, Node)>> {
+            let root = self
+                .get_root_node_option(version)?
+                .ok_or_else(|| anyhow::anyhow!(MissingRootError { version }))?;
+            Ok(match root {
+                Node::Null => None,
+                root => Some((NodeKey::new_empty_path(version), root)),
+            })
+        };
+        let mut changes = Vec::new();
+        self.diff_subtrees(
+            subtree_of_root(from)?,
+            subtree_of_root(to)?,
+            0,
+            &mut changes,
+        )?;
+        changes.sort_by_key(|(key_hash, _)| *key_hash);
+        Ok(changes)
+    }
+
+    /// Appends to `changes` the keys that differ between the `old` and `new` subtrees, both at
+    /// `depth` nibbles below the root.
+    fn diff_subtrees(
+        &self,
+        old: Option<(NodeKey, Node)>,
+        new: Option<(NodeKey, Node)>,
+        depth: usize,
+        changes: &mut Vec<(KeyHash, ChangeKind)>,
+    ) -> Result<()> {
+        match (&old, &new) {
+            (None, None) => {}
+            (Some((_, Node::Leaf(old_leaf))), Some((_, Node::Leaf(new_leaf)))) => {
+                if old_leaf.key_hash() != new_leaf.key_hash() {
+                    changes.push((old_leaf.key_hash(), ChangeKind::Removed));
+                    changes.push((new_leaf.key_hash(), ChangeKind::Added));
+                } else if old_leaf.value_hash() != new_leaf.value_hash() {
+                    changes.push((new_leaf.key_hash(), ChangeKind::Updated));
+                }
+            }
+            (Some((_, Node::Leaf(old_leaf))), None) => {
+                changes.push((old_leaf.key_hash(), ChangeKind::Removed));
+            }
+            (None, Some((_, Node::Leaf(new_leaf)))) => {
+                changes.push((new_leaf.key_hash(), ChangeKind::Added));
+            }
+            _ => {
+                // At least one side is an internal node: compare the subtrees under each nibble,
+                // a leaf on the other side belonging to the nibble its key continues with.
+                let both_internal = match (&old, &new) {
+                    (Some((_, Node::Internal(old_node))), Some((_, Node::Internal(new_node)))) => {
+                        Some((old_node, new_node))
+                    }
+                    _ => None,
+                };
+                for nibble in 0..16u8 {
+                    let nibble = Nibble::from(nibble);
+                    if let Some((old_node, new_node)) = both_internal {
+                        let same_hash = match (old_node.child(nibble), new_node.child(nibble)) {
+                            (Some(old_child), Some(new_child)) => old_child.hash == new_child.hash,
+                            (None, None) => true,
+                            _ => false,
+                        };
+                        if same_hash {
+                            continue;
+                        }
+                    }
+                    self.diff_subtrees(
+                        self.subtree_at(old.as_ref(), nibble, depth)?,
+                        self.subtree_at(new.as_ref(), nibble, depth)?,
+                        depth + 1,
+                        changes,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the subtree under `nibble` of `subtree`, which is `depth` nibbles below the root: a
+    /// child of an internal node, read from the store, or a leaf itself if its key continues with
+    /// `nibble`.
+    fn subtree_at(
+        &self,
+        subtree: Option<&(NodeKey, Node)>,
+        nibble: Nibble,
+        depth: usize,
+    ) -> Result<Option<(NodeKey, Node)>> {
+        Ok(match subtree {
+            Some((node_key, Node::Internal(internal_node))) => match internal_node.child(nibble) {
+                Some(child) => {
+                    let child_key = node_key.gen_child_node_key(child.version, nibble);
+                    let child_node = self.reader.get_node(&child_key)?;
+                    Some((child_key, child_node))
+                }
+                None => None,
+            },
+            Some((node_key, Node::Leaf(leaf_node)))
+                if leaf_node.key_hash().0.get_nibble(depth) == nibble =>
+            {
+                Some((node_key.clone(), Node::Leaf(leaf_node.clone())))
+            }
+            _ => None,
+        })
+    }
+
+    /// Returns the commitment to `version`, its root hash and its leaf count computed by
+    /// [`compute_version_commitment`].
+    pub fn version_commitment(&self, version: Version) -> Result<[u8; 32]> {
+        let root = self
+            .get_root_node_option(version)?
+            .ok_or_else(|| anyhow::anyhow!(MissingRootError { version }))?;
+        Ok(compute_version_commitment::<H>(
+            version,
+            RootHash(root.hash::<H>()),
+            root.leaf_count() as u64,
+        ))
+    }
+
     // TODO: should this be public? seems coupled to tests?
     pub fn get_leaf_count(&self, version: Version) -> Result<usize> {
         self.get_root_node(version).map(|n| n.leaf_count())
     }
+
+    /// Returns the minimum, maximum and mean depths of the leaves of the tree at `version`, the
+    /// depth of a leaf being the number of internal nodes above it.
+    ///
+    /// This reads every node of the tree, see
+    /// [`sample_depth_stats`](JellyfishMerkleTree::sample_depth_stats) for large trees. Keys
+    /// sharing long prefixes push their leaves deep, so a maximum depth far above the logarithm
+    /// in base 16 of the number of leaves points at a pathological key distribution. Fails with
+    /// a [`MissingRootError`] if `version` has no root.
+    pub fn depth_stats(&self, version: Version) -> Result<DepthStats> {
+        let mut stats = DepthStats::default();
+        let mut stack = vec![NodeKey::new_empty_path(version)];
+        while let Some(node_key) = stack.pop() {
+            match self.get_walked_node(&node_key)? {
+                Node::Internal(internal_node) => stack.extend(
+                    internal_node
+                        .children_sorted()
+                        .map(|(nibble, child)| node_key.gen_child_node_key(child.version, nibble)),
+                ),
+                Node::Leaf(_) => stats.add(node_key.nibble_path().num_nibbles()),
+                Node::Null => {}
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Same as [`depth_stats`](JellyfishMerkleTree::depth_stats), measuring the depths of at
+    /// most `max_samples` leaves, spread evenly over the leaves of the tree in key order.
+    ///
+    /// Each sampled leaf is reached by descending from the root along the leaf counts of the
+    /// internal nodes, so that the cost is bounded by `max_samples` times the depth of the tree
+    /// whatever its size, and every leaf is as likely to be sampled as any other whatever its
+    /// depth. The maximum and minimum are those of the sampled leaves, so the deepest leaf may be
+    /// missed. All the leaves are measured if there are at most `max_samples` of them. Fails if
+    /// `max_samples` is 0.
+    pub fn sample_depth_stats(&self, version: Version, max_samples: usize) -> Result<DepthStats> {
+        ensure!(max_samples > 0, "sampling depths takes at least one sample");
+        let root_key = NodeKey::new_empty_path(version);
+        let leaf_count = self.get_walked_node(&root_key)?.leaf_count();
+        if leaf_count <= max_samples {
+            return self.depth_stats(version);
+        }
+        let mut stats = DepthStats::default();
+        for sample in 0..max_samples {
+            // The index, in key order, of the leaf to sample.
+            let mut index = (sample as u128 * leaf_count as u128 / max_samples as u128) as usize;
+            let mut node_key = root_key.clone();
+            while let Node::Internal(internal_node) = self.get_walked_node(&node_key)? {
+                let (nibble, child) = internal_node
+                    .children_sorted()
+                    .find(|(_, child)| {
+                        let found = index < child.leaf_count();
+                        if !found {
+                            index -= child.leaf_count();
+                        }
+                        found
+                    })
+                    .ok_or_else(|| format_err!("Leaf counts of {:?} are invalid.", node_key))?;
+                node_key = node_key.gen_child_node_key(child.version, nibble);
+            }
+            stats.add(node_key.nibble_path().num_nibbles());
+        }
+        Ok(stats)
+    }
+
+    /// Returns the number of internal nodes of the tree at `version` with each number of
+    /// children, the entry at index `n` counting those with `n` children.
+    ///
+    /// The entries add up to the number of internal nodes of the tree. Internal nodes always have
+    /// at least two children, a single one being collapsed into its parent, so the first two
+    /// entries are zero, and a tree whose counts pile up at the low end spends most of its
+    /// internal nodes on paths with few branches. This reads every node of the tree. Fails with
+    /// a [`MissingRootError`] if `version` has no root.
+    pub fn fanout_histogram(&self, version: Version) -> Result<[u64; 17]> {
+        let mut histogram = [0u64; 17];
+        let mut stack = vec![NodeKey::new_empty_path(version)];
+        while let Some(node_key) = stack.pop() {
+            if let Node::Internal(internal_node) = self.get_walked_node(&node_key)? {
+                histogram[internal_node.children_unsorted().count()] += 1;
+                stack.extend(
+                    internal_node
+                        .children_sorted()
+                        .filter(|(_, child)| !child.is_leaf())
+                        .map(|(nibble, child)| node_key.gen_child_node_key(child.version, nibble)),
+                );
+            }
+        }
+        Ok(histogram)
+    }
+
+    /// Reads the node at `node_key`, failing with a [`MissingRootError`] for a missing root.
+    fn get_walked_node(&self, node_key: &NodeKey) -> Result<Node> {
+        match self.reader.get_node_option(node_key)? {
+            Some(node) => Ok(node),
+            None if node_key.nibble_path().is_empty() => Err(anyhow::anyhow!(MissingRootError {
+                version: node_key.version(),
+            })),
+            None => bail!("Missing node with node key {:?}", node_key),
+        }
+    }
+}
+
+impl<'a, R, H> JellyfishMerkleTree<'a, R, H>
+where
+    R: 'a + TreeReader + HasPreimage,
+    H: SimpleHasher,
+{
+    /// Same as [`iter_with_proofs`](JellyfishMerkleTree::iter_with_proofs), yielding each leaf
+    /// with the preimage of its key hash as well, or `None` if the reader has none.
+    ///
+    /// A missing preimage is not an error, since nothing requires a writer to record them: the
+    /// leaves without one are exported all the same, by key hash only. Use
+    /// [`iter_with_proofs_and_preimages_strict`](JellyfishMerkleTree::iter_with_proofs_and_preimages_strict)
+    /// for exports that must be readable by key.
+    pub fn iter_with_proofs_and_preimages(
+        &self,
+        version: Version,
+        start_key: KeyHash,
+        end_key: KeyHash,
+    ) -> impl Iterator<Item = Result<ProvenLeaf<H>>> + '_ {
+        self.iter_with_proofs(version, start_key, end_key)
+            .map(move |leaf| {
+                let (key_hash, value, proof) = leaf?;
+                let preimage = self.reader.preimage(key_hash)?;
+                Ok((key_hash, value, preimage, proof))
+            })
+    }
+
+    /// Same as
+    /// [`iter_with_proofs_and_preimages`](JellyfishMerkleTree::iter_with_proofs_and_preimages),
+    /// yielding a [`MissingPreimageError`] in place of every leaf whose preimage the reader does
+    /// not have, so that the preimages of the leaves yielded are never `None`.
+    pub fn iter_with_proofs_and_preimages_strict(
+        &self,
+        version: Version,
+        start_key: KeyHash,
+        end_key: KeyHash,
+    ) -> impl Iterator<Item = Result<ProvenLeaf<H>>> + '_ {
+        self.iter_with_proofs_and_preimages(version, start_key, end_key)
+            .map(|leaf| {
+                let leaf = leaf?;
+                ensure!(leaf.2.is_some(), MissingPreimageError { key_hash: leaf.0 });
+                Ok(leaf)
+            })
+    }
+}
+
+/// How a key changed between two versions, as reported by [`JellyfishMerkleTree::diff`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The key has a value at the newer version but not at the older one.
+    Added,
+    /// The key has a different value at each version.
+    Updated,
+    /// The key has a value at the older version but not at the newer one.
+    Removed,
+}
+
+/// The depths of the leaves of a tree, as returned by [`JellyfishMerkleTree::depth_stats`].
+///
+/// The depths are all zero for an empty tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DepthStats {
+    /// The number of leaves whose depth was measured.
+    pub leaves: usize,
+    /// The depth of the shallowest leaf.
+    pub min_depth: usize,
+    /// The depth of the deepest leaf.
+    pub max_depth: usize,
+    /// The mean depth of the leaves.
+    pub mean_depth: f64,
+}
+
+impl DepthStats {
+    /// Accounts for a leaf at `depth`.
+    fn add(&mut self, depth: usize) {
+        if self.leaves == 0 || depth < self.min_depth {
+            self.min_depth = depth;
+        }
+        self.max_depth = self.max_depth.max(depth);
+        self.leaves += 1;
+        self.mean_depth += (depth as f64 - self.mean_depth) / self.leaves as f64;
+    }
 }
 
 /// The result of putting a single key-value pair into the tree, or deleting a key.
@@ -89,7 +89,7 @@ where
                 leftmost_right_proof,
             } => {
                 let key_hash = leftmost_right_proof
-                    .leaf()
+                    .leaf_node()
                     .expect("must have leaf")
                     .key_hash();
                 let key_left_proof = self
@@ -118,7 +118,7 @@ where
                 rightmost_left_proof,
             } => {
                 let leftmost_key_hash = leftmost_right_proof
-                    .leaf()
+                    .leaf_node()
                     .expect("must have leaf")
                     .key_hash();
                 let value_leftmost = self
@@ -135,7 +135,7 @@ where
                 );
 
                 let rightmost_key_hash = rightmost_left_proof
-                    .leaf()
+                    .leaf_node()
                     .expect("must have leaf")
                     .key_hash();
                 let value_rightmost = self
@@ -161,7 +161,7 @@ where
                 rightmost_left_proof,
             } => {
                 let rightmost_key_hash = rightmost_left_proof
-                    .leaf()
+                    .leaf_node()
                     .expect("must have leaf")
                     .key_hash();
                 let value_rightmost = self
@@ -460,7 +460,7 @@ mod tests {
                 } => {
                     if leftmost_right_proof.root_hash() != new_root_hash {
                         let good_proof = tree
-                            .get_with_proof(leftmost_right_proof.leaf().unwrap().key_hash(), 0)
+                            .get_with_proof(leftmost_right_proof.leaf_node().unwrap().key_hash(), 0)
                             .unwrap();
                         panic!(
                             "root hash mismatch. bad proof: {:?}, good proof: {:?}",
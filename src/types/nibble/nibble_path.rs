@@ -13,7 +13,10 @@ use mirai_annotations::*;
 use proptest::{collection::vec, prelude::*};
 use serde::{Deserialize, Serialize};
 
-use crate::types::nibble::{Nibble, ROOT_NIBBLE_HEIGHT};
+use crate::{
+    types::nibble::{Nibble, ROOT_NIBBLE_HEIGHT},
+    KeyHash,
+};
 
 /// NibblePath defines a path in Merkle tree in the unit of nibble (4 bits).
 #[derive(
@@ -203,6 +206,16 @@ impl NibblePath {
     }
 }
 
+/// Returns the full path of `key` in the tree, its 64 nibbles from the root down, high nibble of
+/// each byte first.
+///
+/// A leaf sits at the shortest prefix of this path no other key shares, and the subtree under
+/// the child `n` of the root holds exactly the keys whose path starts with `n`, so that keys can
+/// be assigned to shards consistently with the layout of the tree.
+pub fn nibble_path_of(key: KeyHash) -> NibblePath {
+    NibblePath::new(key.0.to_vec())
+}
+
 pub trait Peekable: Iterator {
     /// Returns the `next()` value without advancing the iterator.
     fn peek(&self) -> Option<Self::Item>;
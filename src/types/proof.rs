@@ -15,7 +15,10 @@ use crate::{
 #[cfg(all(test, feature = "std"))]
 use proptest_derive::Arbitrary;
 
-pub use self::definition::{SparseMerkleProof, SparseMerkleRangeProof, UpdateMerkleProof};
+pub use self::definition::{
+    verify_batch, KeyProof, ProofItem, SparseMerkleProof, SparseMerkleRangeProof,
+    UpdateMerkleProof, VersionRangeProof,
+};
 use crate::{KeyHash, ValueHash, SPARSE_MERKLE_PLACEHOLDER_HASH};
 use borsh::{BorshDeserialize, BorshSerialize};
 use serde::{Deserialize, Serialize};
@@ -147,6 +150,10 @@ impl SparseMerkleLeafNode {
         self.key_hash
     }
 
+    pub(crate) fn value_hash(&self) -> ValueHash {
+        self.value_hash
+    }
+
     pub(crate) fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
         let mut hasher = H::new();
         hasher.update(LEAF_DOMAIN_SEPARATOR);
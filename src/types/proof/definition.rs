@@ -8,10 +8,11 @@ use super::{SparseMerkleInternalNode, SparseMerkleLeafNode, SparseMerkleNode};
 use crate::{
     storage::Node,
     types::nibble::nibble_path::{skip_common_prefix, NibblePath},
-    Bytes32Ext, KeyHash, RootHash, SimpleHasher, ValueHash, SPARSE_MERKLE_PLACEHOLDER_HASH,
+    Bytes32Ext, KeyHash, OwnedValue, RootHash, SimpleHasher, ValueHash,
+    SPARSE_MERKLE_PLACEHOLDER_HASH,
 };
-use alloc::vec::Vec;
-use anyhow::{bail, ensure, format_err, Result};
+use alloc::{format, vec::Vec};
+use anyhow::{bail, ensure, format_err, Context, Result};
 use serde::{Deserialize, Serialize};
 
 /// A proof that can be used to authenticate an element in a Sparse Merkle Tree given trusted root
@@ -53,6 +54,78 @@ impl<H: SimpleHasher> core::fmt::Debug for SparseMerkleProof<H> {
     }
 }
 
+/// The types of the siblings of a proof in the bitmap of
+/// [`SparseMerkleProof::to_compact_bytes`].
+const COMPACT_NULL: u8 = 0;
+const COMPACT_INTERNAL: u8 = 1;
+const COMPACT_LEAF: u8 = 2;
+
+/// The JSON form of a [`SparseMerkleProof`], see [`SparseMerkleProof::to_json`].
+#[cfg(feature = "json")]
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonProof {
+    leaf: Option<JsonLeaf>,
+    siblings: Vec<JsonSibling>,
+}
+
+/// The leaf of a [`JsonProof`], or one of its leaf siblings.
+#[cfg(feature = "json")]
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonLeaf {
+    key_hash: alloc::string::String,
+    value_hash: alloc::string::String,
+}
+
+/// A sibling of a [`JsonProof`], tagged with its type, and with its hash unless it is a
+/// placeholder.
+#[cfg(feature = "json")]
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+enum JsonSibling {
+    Placeholder,
+    Internal {
+        hash: alloc::string::String,
+        left_child: alloc::string::String,
+        right_child: alloc::string::String,
+    },
+    Leaf {
+        hash: alloc::string::String,
+        key_hash: alloc::string::String,
+        value_hash: alloc::string::String,
+    },
+}
+
+/// Decodes the hex string `hex` of the field `field` of a [`JsonProof`] as a hash.
+#[cfg(feature = "json")]
+fn decode_json_hash(field: &str, hex: &str) -> Result<[u8; 32]> {
+    let mut hash = [0u8; 32];
+    hex::decode_to_slice(hex, &mut hash)
+        .with_context(|| format!("Invalid {} {:?} in JSON proof.", field, hex))?;
+    Ok(hash)
+}
+
+/// Reads the fields of a proof off the front of its compact encoding.
+struct CompactReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> CompactReader<'a> {
+    /// Takes the next `len` bytes, failing if fewer are left.
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(self.bytes.len() >= len, "Compact proof is truncated.");
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    /// Takes the next `N` bytes as an array.
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+}
+
 // Manually implement PartialEq to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
 // TODO: Switch back to #[derive] once the perfect_derive feature lands
 impl<H: SimpleHasher> PartialEq for SparseMerkleProof<H> {
@@ -84,10 +157,40 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
     }
 
     /// Returns the leaf node in this proof.
-    pub fn leaf(&self) -> Option<SparseMerkleLeafNode> {
+    pub(crate) fn leaf_node(&self) -> Option<SparseMerkleLeafNode> {
         self.leaf.clone()
     }
 
+    /// Returns the key hash and value hash of the leaf this proof commits to, without needing the
+    /// value itself.
+    ///
+    /// For an inclusion proof this is the queried key and the hash of its value. For a
+    /// non-inclusion proof the leaf, if any, is the one occupying the queried key's slot, and
+    /// `None` means the slot is empty.
+    pub fn leaf(&self) -> Option<(KeyHash, ValueHash)> {
+        self.leaf
+            .as_ref()
+            .map(|leaf| (leaf.key_hash(), leaf.value_hash()))
+    }
+
+    /// For a non-inclusion proof of `element_key`, returns the index of the first nibble of the
+    /// key that can no longer be followed in the tree, or `None` if this proves inclusion of
+    /// `element_key`.
+    ///
+    /// If the proof ends in a conflicting leaf, this is the length in nibbles of the prefix the
+    /// key shares with that leaf. If it ends in an empty subtree, this is the nibble whose slot in
+    /// the deepest internal node on the key's path is empty. Nothing is verified here; call
+    /// [`verify_nonexistence`](SparseMerkleProof::verify_nonexistence) first to trust the answer.
+    pub fn nonexistence_divergence_depth(&self, element_key: KeyHash) -> Option<usize> {
+        match &self.leaf {
+            Some(leaf) if leaf.key_hash == element_key => None,
+            Some(leaf) => Some(element_key.0.common_prefix_nibbles_len(&leaf.key_hash.0)),
+            // The empty subtree sits right below the last sibling, in the nibble that sibling
+            // belongs to.
+            None => Some(self.siblings.len().saturating_sub(1) / 4),
+        }
+    }
+
     /// Returns the list of siblings in this proof.
     pub(crate) fn siblings(&self) -> &[SparseMerkleNode] {
         &self.siblings
@@ -127,6 +230,50 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
         expected_root_hash: RootHash,
         element_key: KeyHash,
         element_value: Option<V>,
+    ) -> Result<()> {
+        self.verify_by_value_hash(
+            expected_root_hash,
+            element_key,
+            element_value.map(|value| ValueHash::with::<H>(value)),
+        )
+    }
+
+    /// Verifies an element whose key is `element_key` and whose value hashes to
+    /// `element_value_hash` exists in the Sparse Merkle Tree using the provided proof.
+    ///
+    /// This is [`verify_existence`](SparseMerkleProof::verify_existence) for callers that only
+    /// know the hash of the value, such as the one returned by
+    /// [`JellyfishMerkleTree::get_proof_for_value_hash`](crate::JellyfishMerkleTree::get_proof_for_value_hash).
+    pub fn verify_inclusion_with_value_hash(
+        &self,
+        expected_root_hash: RootHash,
+        element_key: KeyHash,
+        element_value_hash: ValueHash,
+    ) -> Result<()> {
+        self.verify_by_value_hash(expected_root_hash, element_key, Some(element_value_hash))
+    }
+
+    /// Same as [`verify`](SparseMerkleProof::verify), with the value given by its hash.
+    fn verify_by_value_hash(
+        &self,
+        expected_root_hash: RootHash,
+        element_key: KeyHash,
+        element_value_hash: Option<ValueHash>,
+    ) -> Result<()> {
+        self.verify_subtree_by_value_hash(expected_root_hash.0, 0, element_key, element_value_hash)
+    }
+
+    /// Same as [`verify_by_value_hash`](SparseMerkleProof::verify_by_value_hash), against the
+    /// hash of the subtree `depth` bits below the root on the path of `element_key` instead of
+    /// the root hash: only the siblings below that subtree are hashed.
+    ///
+    /// Fails if the proof ends above `depth`.
+    pub(crate) fn verify_subtree_by_value_hash(
+        &self,
+        expected_subtree_hash: [u8; 32],
+        depth: usize,
+        element_key: KeyHash,
+        element_value_hash: Option<ValueHash>,
     ) -> Result<()> {
         ensure!(
             self.siblings.len() <= 256,
@@ -135,8 +282,8 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
             self.siblings.len(),
         );
 
-        match (element_value, self.leaf.clone()) {
-            (Some(value), Some(leaf)) => {
+        match (element_value_hash, self.leaf.clone()) {
+            (Some(hash), Some(leaf)) => {
                 // This is an inclusion proof, so the key and value hash provided in the proof
                 // should match element_key and element_value_hash. `siblings` should prove the
                 // route from the leaf node to the root.
@@ -146,7 +293,6 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
                     leaf.key_hash,
                     element_key
                 );
-                let hash: ValueHash = ValueHash::with::<H>(value);
                 ensure!(
                     hash == leaf.value_hash,
                     "Value hashes do not match. Value hash in proof: {:?}. \
@@ -155,7 +301,7 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
                     hash,
                 );
             }
-            (Some(_value), None) => bail!("Expected inclusion proof. Found non-inclusion proof."),
+            (Some(_hash), None) => bail!("Expected inclusion proof. Found non-inclusion proof."),
             (None, Some(leaf)) => {
                 // This is a non-inclusion proof. The proof intends to show that if a leaf node
                 // representing `element_key` is inserted, it will break a currently existing leaf
@@ -179,13 +325,22 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
             }
         }
 
+        ensure!(
+            depth <= self.siblings.len(),
+            "Sparse Merkle Tree proof of {} siblings ends above depth {}.",
+            self.siblings.len(),
+            depth,
+        );
+
         let current_hash = self
             .leaf
             .clone()
             .map_or(SPARSE_MERKLE_PLACEHOLDER_HASH, |leaf| leaf.hash::<H>());
-        let actual_root_hash = self
+        // The siblings go from the leaf up, so those below `depth` come first.
+        let actual_hash = self
             .siblings
             .iter()
+            .take(self.siblings.len() - depth)
             .zip(
                 element_key
                     .0
@@ -201,16 +356,296 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
                 }
             });
 
+        if depth == 0 {
+            ensure!(
+                actual_hash == expected_subtree_hash,
+                "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
+                actual_hash,
+                expected_subtree_hash,
+            );
+        } else {
+            ensure!(
+                actual_hash == expected_subtree_hash,
+                "Hashes of the subtree at depth {} do not match. Actual hash: {:?}. Expected \
+                 hash: {:?}.",
+                depth,
+                actual_hash,
+                expected_subtree_hash,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Verifies that the subtree of the keys starting with `prefix` hashes to `subtree_hash` in
+    /// the tree whose root hash is `expected_root_hash`, using a proof returned by
+    /// [`JellyfishMerkleTree::get_subtree_proof`](crate::JellyfishMerkleTree::get_subtree_proof).
+    ///
+    /// A proof ending above `prefix` shows that the subtree there holds a single leaf, given by
+    /// the proof, or no key at all: the subtree of `prefix` is then that leaf if its key starts
+    /// with `prefix`, and empty otherwise.
+    pub fn verify_subtree(
+        &self,
+        expected_root_hash: RootHash,
+        prefix: &NibblePath,
+        subtree_hash: [u8; 32],
+    ) -> Result<()> {
+        let prefix_bits: Vec<bool> = prefix.bits().collect();
         ensure!(
-            actual_root_hash == expected_root_hash.0,
+            self.siblings.len() <= prefix_bits.len(),
+            "Subtree proof has more siblings ({}) than its prefix has bits ({}).",
+            self.siblings.len(),
+            prefix_bits.len(),
+        );
+
+        let current_hash = match &self.leaf {
+            Some(leaf) => {
+                let common_bits = leaf
+                    .key_hash
+                    .0
+                    .iter_bits()
+                    .zip(&prefix_bits)
+                    .take_while(|(bit, prefix_bit)| bit == *prefix_bit)
+                    .count();
+                ensure!(
+                    common_bits >= self.siblings.len(),
+                    "Key in proof {:?} is not under the path of the proof.",
+                    leaf.key_hash,
+                );
+                let expected_subtree_hash = if common_bits == prefix_bits.len() {
+                    leaf.hash::<H>()
+                } else {
+                    SPARSE_MERKLE_PLACEHOLDER_HASH
+                };
+                ensure!(
+                    subtree_hash == expected_subtree_hash,
+                    "Subtree hashes do not match. Subtree hash from proof: {:?}. Expected \
+                     subtree hash: {:?}.",
+                    expected_subtree_hash,
+                    subtree_hash,
+                );
+                leaf.hash::<H>()
+            }
+            None => {
+                // Only an empty subtree can end the proof above the prefix without a leaf.
+                ensure!(
+                    self.siblings.len() == prefix_bits.len()
+                        || subtree_hash == SPARSE_MERKLE_PLACEHOLDER_HASH,
+                    "Subtree proof of {} siblings ends above its prefix of {} bits.",
+                    self.siblings.len(),
+                    prefix_bits.len(),
+                );
+                subtree_hash
+            }
+        };
+        // The siblings go from the subtree up, along the first bits of the prefix.
+        let actual_hash = self
+            .siblings
+            .iter()
+            .zip(prefix_bits[..self.siblings.len()].iter().rev())
+            .fold(current_hash, |hash, (sibling_node, &bit)| {
+                if bit {
+                    SparseMerkleInternalNode::new(sibling_node.hash::<H>(), hash).hash::<H>()
+                } else {
+                    SparseMerkleInternalNode::new(hash, sibling_node.hash::<H>()).hash::<H>()
+                }
+            });
+        ensure!(
+            actual_hash == expected_root_hash.0,
             "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
-            actual_root_hash,
-            expected_root_hash.0,
+            actual_hash,
+            expected_root_hash,
         );
 
         Ok(())
     }
 
+    /// Encodes the proof in a compact binary format, much smaller than its bincode encoding,
+    /// decoded by [`from_compact_bytes`](SparseMerkleProof::from_compact_bytes).
+    ///
+    /// The encoding is a flag byte, 1 if the proof has a leaf and 0 otherwise, the key hash and
+    /// value hash of the leaf if any, the big-endian `u16` number of siblings, a bitmap of two
+    /// bits per sibling giving its type, null, internal or leaf, from the most significant bits
+    /// of the first byte on, and the two hashes of each sibling that is not null, in order.
+    /// Placeholder siblings, which make up most of the siblings of a proof deep in a sparse
+    /// subtree, thus take two bits each instead of a whole hash.
+    pub fn to_compact_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(3 + 64 + self.siblings.len().div_ceil(4));
+        match &self.leaf {
+            Some(leaf) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&leaf.key_hash.0);
+                bytes.extend_from_slice(&leaf.value_hash.0);
+            }
+            None => bytes.push(0),
+        }
+        bytes.extend_from_slice(&(self.siblings.len() as u16).to_be_bytes());
+        let mut bitmap = alloc::vec![0u8; self.siblings.len().div_ceil(4)];
+        for (i, sibling) in self.siblings.iter().enumerate() {
+            let kind = match sibling {
+                SparseMerkleNode::Null => COMPACT_NULL,
+                SparseMerkleNode::Internal(_) => COMPACT_INTERNAL,
+                SparseMerkleNode::Leaf(_) => COMPACT_LEAF,
+            };
+            bitmap[i / 4] |= kind << (6 - 2 * (i % 4));
+        }
+        bytes.extend_from_slice(&bitmap);
+        for sibling in &self.siblings {
+            match sibling {
+                SparseMerkleNode::Null => {}
+                SparseMerkleNode::Internal(node) => {
+                    bytes.extend_from_slice(&node.left_child);
+                    bytes.extend_from_slice(&node.right_child);
+                }
+                SparseMerkleNode::Leaf(node) => {
+                    bytes.extend_from_slice(&node.key_hash.0);
+                    bytes.extend_from_slice(&node.value_hash.0);
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Decodes a proof encoded by [`to_compact_bytes`](SparseMerkleProof::to_compact_bytes).
+    ///
+    /// Fails if `bytes` is not exactly the encoding of a proof.
+    pub fn from_compact_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = CompactReader { bytes };
+        let leaf = match reader.take(1)?[0] {
+            0 => None,
+            1 => Some(SparseMerkleLeafNode::new(
+                KeyHash(reader.array()?),
+                ValueHash(reader.array()?),
+            )),
+            flag => bail!("Invalid leaf flag {} in compact proof.", flag),
+        };
+        let num_siblings = u16::from_be_bytes(reader.array()?) as usize;
+        ensure!(
+            num_siblings <= 256,
+            "Compact proof has {} siblings, more than the height of the tree.",
+            num_siblings,
+        );
+        let bitmap = reader.take(num_siblings.div_ceil(4))?;
+        let siblings = (0..num_siblings)
+            .map(|i| match (bitmap[i / 4] >> (6 - 2 * (i % 4))) & 0b11 {
+                COMPACT_NULL => Ok(SparseMerkleNode::Null),
+                COMPACT_INTERNAL => Ok(SparseMerkleNode::Internal(SparseMerkleInternalNode::new(
+                    reader.array()?,
+                    reader.array()?,
+                ))),
+                COMPACT_LEAF => Ok(SparseMerkleNode::Leaf(SparseMerkleLeafNode::new(
+                    KeyHash(reader.array()?),
+                    ValueHash(reader.array()?),
+                ))),
+                kind => bail!("Invalid sibling type {} in compact proof.", kind),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ensure!(
+            reader.bytes.is_empty(),
+            "Compact proof has {} trailing bytes.",
+            reader.bytes.len(),
+        );
+        Ok(Self::new(leaf, siblings))
+    }
+
+    /// Encodes the proof as JSON, for verifiers that do not read bincode, to be decoded by
+    /// [`from_json`](SparseMerkleProof::from_json).
+    ///
+    /// The proof is an object with a `leaf`, `null` or an object with the hex `key_hash` and
+    /// `value_hash` of the leaf, and the `siblings`, ordered from the bottom level to the root as
+    /// in [`siblings`](SparseMerkleProof::siblings). Each sibling is an object whose `kind` is
+    /// `placeholder`, `internal` or `leaf`: placeholders carry nothing else, the hash of which is
+    /// [`SPARSE_MERKLE_PLACEHOLDER_HASH`], internal siblings carry their hex `hash`, `left_child`
+    /// and `right_child`, and leaf siblings their hex `hash`, `key_hash` and `value_hash`.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> alloc::string::String {
+        let json_leaf = |leaf: &SparseMerkleLeafNode| JsonLeaf {
+            key_hash: hex::encode(leaf.key_hash.0),
+            value_hash: hex::encode(leaf.value_hash.0),
+        };
+        let json = JsonProof {
+            leaf: self.leaf.as_ref().map(json_leaf),
+            siblings: self
+                .siblings
+                .iter()
+                .map(|sibling| match sibling {
+                    SparseMerkleNode::Null => JsonSibling::Placeholder,
+                    SparseMerkleNode::Internal(node) => JsonSibling::Internal {
+                        hash: hex::encode(node.hash::<H>()),
+                        left_child: hex::encode(node.left_child),
+                        right_child: hex::encode(node.right_child),
+                    },
+                    SparseMerkleNode::Leaf(node) => {
+                        let leaf = json_leaf(node);
+                        JsonSibling::Leaf {
+                            hash: hex::encode(node.hash::<H>()),
+                            key_hash: leaf.key_hash,
+                            value_hash: leaf.value_hash,
+                        }
+                    }
+                })
+                .collect(),
+        };
+        serde_json::to_string(&json).expect("proofs encode as JSON")
+    }
+
+    /// Decodes a proof encoded by [`to_json`](SparseMerkleProof::to_json).
+    ///
+    /// Fails if `json` is not the encoding of a proof, or if the hash of a sibling is not that of
+    /// its contents under `H`, which a proof encoded with another hasher fails with.
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<Self> {
+        let json: JsonProof =
+            serde_json::from_str(json).map_err(|err| format_err!("Invalid JSON proof: {}", err))?;
+        let leaf = |key_hash: &str, value_hash: &str| -> Result<SparseMerkleLeafNode> {
+            Ok(SparseMerkleLeafNode::new(
+                KeyHash(decode_json_hash("key_hash", key_hash)?),
+                ValueHash(decode_json_hash("value_hash", value_hash)?),
+            ))
+        };
+        ensure!(
+            json.siblings.len() <= 256,
+            "JSON proof has {} siblings, more than the height of the tree.",
+            json.siblings.len(),
+        );
+        let siblings = json
+            .siblings
+            .iter()
+            .map(|sibling| {
+                let (node, hash) = match sibling {
+                    JsonSibling::Placeholder => return Ok(SparseMerkleNode::Null),
+                    JsonSibling::Internal {
+                        hash,
+                        left_child,
+                        right_child,
+                    } => (
+                        SparseMerkleNode::Internal(SparseMerkleInternalNode::new(
+                            decode_json_hash("left_child", left_child)?,
+                            decode_json_hash("right_child", right_child)?,
+                        )),
+                        hash,
+                    ),
+                    JsonSibling::Leaf {
+                        hash,
+                        key_hash,
+                        value_hash,
+                    } => (SparseMerkleNode::Leaf(leaf(key_hash, value_hash)?), hash),
+                };
+                ensure!(
+                    decode_json_hash("hash", hash)? == node.hash::<H>(),
+                    "Sibling hash {} in JSON proof does not match its contents.",
+                    hash,
+                );
+                Ok(node)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let json_leaf = json
+            .leaf
+            .map(|json_leaf| leaf(&json_leaf.key_hash, &json_leaf.value_hash))
+            .transpose()?;
+        Ok(Self::new(json_leaf, siblings))
+    }
+
     /// This function computes a new merkle path on split insertion (ie when inserting a new value creates
     /// a key split).
     ///
@@ -491,7 +926,7 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
             .siblings
             .iter()
             .zip(
-                self.leaf()
+                self.leaf_node()
                     .expect("need leaf hash for root_hash")
                     .key_hash
                     .0
@@ -511,6 +946,187 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
     }
 }
 
+/// A key with its value, or `None` for an absent key, the proof of it and the root hash to check
+/// it against, as verified by [`verify_batch`].
+pub type ProofItem<H> = (KeyHash, Option<OwnedValue>, SparseMerkleProof<H>, RootHash);
+
+/// Verifies every item of `items` as [`SparseMerkleProof::verify`] does, returning the result of
+/// each in the order of `items`.
+///
+/// An invalid proof only fails its own result, so that a batch of proofs received from an
+/// untrusted peer can be screened at once, keeping the valid ones. With the `parallel_verify`
+/// feature, the proofs are verified on the rayon thread pool.
+pub fn verify_batch<H: SimpleHasher + Sync>(items: &[ProofItem<H>]) -> Vec<Result<()>> {
+    #[cfg(feature = "parallel_verify")]
+    {
+        use rayon::prelude::*;
+        items.par_iter().map(verify_item).collect()
+    }
+    #[cfg(not(feature = "parallel_verify"))]
+    {
+        items.iter().map(verify_item).collect()
+    }
+}
+
+/// Verifies one item of [`verify_batch`].
+fn verify_item<H: SimpleHasher>((key, value, proof, root_hash): &ProofItem<H>) -> Result<()> {
+    proof.verify(*root_hash, *key, value.as_ref())
+}
+
+/// The value of a key with the proof of it, tagged with whether the key is present, as returned
+/// by [`JellyfishMerkleTree::get_with_tagged_proof`](crate::JellyfishMerkleTree::get_with_tagged_proof).
+///
+/// Unlike an `Option<OwnedValue>`, the tag keeps a present key with an empty value apart from
+/// an absent key, and pairs each case with the kind of proof it needs.
+pub enum KeyProof<H: SimpleHasher> {
+    /// The key holds the value, which the inclusion proof proves.
+    Present(OwnedValue, SparseMerkleProof<H>),
+    /// The key is absent, which the non-inclusion proof proves.
+    Absent(SparseMerkleProof<H>),
+}
+
+// Manually implement Debug to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> core::fmt::Debug for KeyProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            KeyProof::Present(value, proof) => {
+                f.debug_tuple("Present").field(value).field(proof).finish()
+            }
+            KeyProof::Absent(proof) => f.debug_tuple("Absent").field(proof).finish(),
+        }
+    }
+}
+
+// Manually implement PartialEq to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> PartialEq for KeyProof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (KeyProof::Present(value, proof), KeyProof::Present(other_value, other_proof)) => {
+                value == other_value && proof == other_proof
+            }
+            (KeyProof::Absent(proof), KeyProof::Absent(other_proof)) => proof == other_proof,
+            _ => false,
+        }
+    }
+}
+
+// Manually implement Clone to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> Clone for KeyProof<H> {
+    fn clone(&self) -> Self {
+        match self {
+            KeyProof::Present(value, proof) => KeyProof::Present(value.clone(), proof.clone()),
+            KeyProof::Absent(proof) => KeyProof::Absent(proof.clone()),
+        }
+    }
+}
+
+impl<H: SimpleHasher> KeyProof<H> {
+    /// Verifies the proof of `element_key` against `expected_root_hash`: existence with the value
+    /// for [`Present`](KeyProof::Present), non-existence for [`Absent`](KeyProof::Absent).
+    pub fn verify(&self, expected_root_hash: RootHash, element_key: KeyHash) -> Result<()> {
+        match self {
+            KeyProof::Present(value, proof) => {
+                proof.verify_existence(expected_root_hash, element_key, value)
+            }
+            KeyProof::Absent(proof) => proof.verify_nonexistence(expected_root_hash, element_key),
+        }
+    }
+
+    /// Returns the proof, whichever its kind.
+    pub fn proof(&self) -> &SparseMerkleProof<H> {
+        match self {
+            KeyProof::Present(_, proof) | KeyProof::Absent(proof) => proof,
+        }
+    }
+}
+
+/// The values of a key at two versions of the tree, with the proofs of both against the root
+/// hashes of these versions, as returned by
+/// [`JellyfishMerkleTree::get_version_range_proof`](crate::JellyfishMerkleTree::get_version_range_proof).
+///
+/// Once [verified](VersionRangeProof::verify), and the root hashes checked against trusted ones,
+/// this shows what the key held at each endpoint, which a light client can compare without the
+/// tree. Versions in between are not covered: a value changed and then restored within the range
+/// looks unchanged.
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct VersionRangeProof<H: SimpleHasher> {
+    /// The first version of the range.
+    pub from_version: crate::Version,
+    /// The root hash of the tree at `from_version`.
+    pub from_root_hash: RootHash,
+    /// The value of the key at `from_version`, or `None` if it was absent.
+    pub from_value: Option<OwnedValue>,
+    /// The proof of `from_value` against `from_root_hash`.
+    pub from_proof: SparseMerkleProof<H>,
+    /// The last version of the range.
+    pub to_version: crate::Version,
+    /// The root hash of the tree at `to_version`.
+    pub to_root_hash: RootHash,
+    /// The value of the key at `to_version`, or `None` if it was absent.
+    pub to_value: Option<OwnedValue>,
+    /// The proof of `to_value` against `to_root_hash`.
+    pub to_proof: SparseMerkleProof<H>,
+}
+
+// Manually implement Debug to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> core::fmt::Debug for VersionRangeProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("VersionRangeProof")
+            .field("from_version", &self.from_version)
+            .field("from_root_hash", &self.from_root_hash)
+            .field("from_value", &self.from_value)
+            .field("from_proof", &self.from_proof)
+            .field("to_version", &self.to_version)
+            .field("to_root_hash", &self.to_root_hash)
+            .field("to_value", &self.to_value)
+            .field("to_proof", &self.to_proof)
+            .finish()
+    }
+}
+
+// Manually implement Clone to circumvent [incorrect auto-bounds](https://github.com/rust-lang/rust/issues/26925)
+// TODO: Switch back to #[derive] once the perfect_derive feature lands
+impl<H: SimpleHasher> Clone for VersionRangeProof<H> {
+    fn clone(&self) -> Self {
+        Self {
+            from_version: self.from_version,
+            from_root_hash: self.from_root_hash,
+            from_value: self.from_value.clone(),
+            from_proof: self.from_proof.clone(),
+            to_version: self.to_version,
+            to_root_hash: self.to_root_hash,
+            to_value: self.to_value.clone(),
+            to_proof: self.to_proof.clone(),
+        }
+    }
+}
+
+impl<H: SimpleHasher> VersionRangeProof<H> {
+    /// Verifies the value of `element_key` at each endpoint against the root hash of that
+    /// endpoint, an absent value by a non-inclusion proof.
+    ///
+    /// The root hashes are those claimed by the prover: a verifier must also check them against
+    /// root hashes it trusts for both versions.
+    pub fn verify(&self, element_key: KeyHash) -> Result<()> {
+        self.from_proof
+            .verify(self.from_root_hash, element_key, self.from_value.as_ref())
+            .with_context(|| format!("invalid proof at version {}", self.from_version))?;
+        self.to_proof
+            .verify(self.to_root_hash, element_key, self.to_value.as_ref())
+            .with_context(|| format!("invalid proof at version {}", self.to_version))
+    }
+
+    /// Returns whether the key holds the same value, or is absent, at both endpoints.
+    pub fn is_unchanged(&self) -> bool {
+        self.from_value == self.to_value
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, borsh::BorshSerialize, borsh::BorshDeserialize)]
 pub struct UpdateMerkleProof<H: SimpleHasher>(
     #[borsh(bound(serialize = "", deserialize = ""))] Vec<SparseMerkleProof<H>>,
@@ -1,7 +1,7 @@
 use alloc::collections::{BTreeMap, BTreeSet};
 
 use alloc::vec::Vec;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use borsh::{BorshDeserialize, BorshSerialize};
 #[cfg(any(test))]
 use proptest_derive::Arbitrary;
@@ -20,6 +20,67 @@ pub trait TreeWriter {
     fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()>;
 }
 
+/// Defines the interface used to write a whole [`TreeUpdateBatch`], its stale node index
+/// included, to storage that keeps one.
+pub trait TreeUpdateWriter {
+    /// Writes the node batch of `batch` and its stale node indices into storage.
+    fn write_tree_update_batch(&self, batch: &TreeUpdateBatch) -> Result<()>;
+}
+
+/// A [`TreeWriter`] forwarding every node batch to a primary and a secondary writer, to keep a
+/// standby copy of a store up to date.
+///
+/// The primary is written first, and the secondary only once the primary write succeeded; the
+/// error of either write is returned, naming the writer that failed. The two writes are not
+/// transactionally linked: when the secondary fails, the primary already holds the batch, and the
+/// secondary has to be brought back in sync before it is used. Writing a whole
+/// [`TreeUpdateBatch`] through [`TreeUpdateWriter`] mirrors its stale node indices too, so that
+/// both stores can prune the same nodes.
+#[derive(Debug)]
+pub struct MirrorTreeWriter<P, S> {
+    primary: P,
+    secondary: S,
+}
+
+impl<P: TreeWriter, S: TreeWriter> MirrorTreeWriter<P, S> {
+    /// Creates a writer mirroring the writes of `primary` to `secondary`.
+    pub fn new(primary: P, secondary: S) -> Self {
+        MirrorTreeWriter { primary, secondary }
+    }
+
+    /// Returns the primary writer.
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    /// Returns the secondary writer.
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+}
+
+impl<P: TreeWriter, S: TreeWriter> TreeWriter for MirrorTreeWriter<P, S> {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        self.primary
+            .write_node_batch(node_batch)
+            .context("failed to write the node batch to the primary")?;
+        self.secondary
+            .write_node_batch(node_batch)
+            .context("failed to write the node batch to the secondary")
+    }
+}
+
+impl<P: TreeUpdateWriter, S: TreeUpdateWriter> TreeUpdateWriter for MirrorTreeWriter<P, S> {
+    fn write_tree_update_batch(&self, batch: &TreeUpdateBatch) -> Result<()> {
+        self.primary
+            .write_tree_update_batch(batch)
+            .context("failed to write the tree update batch to the primary")?;
+        self.secondary
+            .write_tree_update_batch(batch)
+            .context("failed to write the tree update batch to the secondary")
+    }
+}
+
 /// Node batch that will be written into db atomically with other batches.
 #[derive(Debug, Clone, PartialEq, Default, Eq, borsh::BorshSerialize, borsh::BorshDeserialize)]
 pub struct NodeBatch {